@@ -0,0 +1,349 @@
+//! An async API that downloads payloads with reqwest on the caller's executor,
+//! which must be a tokio runtime. Unpacking and splatting are CPU bound, so
+//! they are still done on the rayon thread pool, each payload being handed off
+//! to it once it has been downloaded.
+
+use crate::{util::ErrorKind, Ctx, Error, Path, WorkItem};
+use anyhow::Context as _;
+use std::sync::Arc;
+
+/// A request from the rayon side of [`Ctx::execute_async`] for the contents of
+/// a payload
+struct DownloadRequest {
+    item: WorkItem,
+    contents: crossbeam_channel::Sender<Result<crate::download::PayloadContents, Error>>,
+}
+
+impl Ctx {
+    /// The async equivalent of [`Self::get_and_validate`], which downloads
+    /// the file with the reqwest client if it isn't already cached
+    pub async fn get_and_validate_async<P>(
+        &self,
+        client: &reqwest::Client,
+        url: impl AsRef<str>,
+        path: &P,
+        checksum: Option<crate::util::Sha256>,
+        expected_len: Option<u64>,
+        progress: indicatif::ProgressBar,
+    ) -> Result<bytes::Bytes, Error>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        let url = url.as_ref();
+        let expected_len = expected_len.unwrap_or_default();
+        let cache_path = self.work_dir.join("dl").join(path.as_ref());
+
+        if let Some(contents) =
+            self.read_cached(&cache_path, checksum.as_ref(), expected_len, &progress)
+        {
+            return Ok(contents);
+        }
+
+        anyhow::ensure!(
+            !self.offline,
+            "{cache_path} is not cached, or failed validation, and network access is disabled"
+        );
+
+        let tries = self.http_retry + 1;
+        // The bytes that were already added to the progress by a previous try
+        let mut counted = 0;
+
+        for attempt in 1..=tries {
+            let mut req = client.get(url);
+            // See get_and_validate
+            if checksum.is_some() {
+                req = req.header("accept-encoding", "identity");
+            }
+
+            let mut res = req
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .with_context(|| format!("HTTP GET request for {url} failed"))?;
+
+            // reqwest transparently decodes gzip, removing the header, but any
+            // other encoding would fail the checksum with a misleading error
+            if let Some(encoding) = res
+                .headers()
+                .get("content-encoding")
+                .and_then(|header| header.to_str().ok())
+                .map(|enc| enc.trim().to_ascii_lowercase())
+                .filter(|enc| !enc.is_empty() && enc != "identity")
+            {
+                anyhow::bail!(
+                    "{url} was served with unsupported content-encoding '{encoding}', the server or mirror must serve it uncompressed or gzip encoded"
+                );
+            }
+
+            // The content-length is unknown if the body is encoded, in which
+            // case we just keep the expected length, if any
+            let content_length = res.content_length().unwrap_or_default();
+            if attempt == 1 && content_length > 0 {
+                crate::ctx::adjust_length(&progress, expected_len, content_length);
+            }
+
+            let mut body = bytes::BytesMut::with_capacity(content_length as usize);
+            let read = loop {
+                match res.chunk().await {
+                    Ok(Some(chunk)) => {
+                        body.extend_from_slice(&chunk);
+
+                        if body.len() > counted {
+                            progress.inc((body.len() - counted) as u64);
+                            counted = body.len();
+                        }
+                    }
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            };
+
+            match read {
+                Ok(()) => return self.write_cached(url, &cache_path, checksum, body.freeze()),
+                Err(err) if err.is_body() || err.is_decode() => {
+                    if attempt < tries {
+                        tracing::warn!(url, "HTTP GET failed to retrieve entire body, retrying");
+                    }
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        ErrorKind::Network.msg(format!("failed to retrieve body for {url}"))
+                    });
+                }
+            }
+        }
+
+        Err(ErrorKind::Network.msg(format!("failed to retrieve {url} after {tries} tries due to I/O failures reading the response body, try using --http-retries to increase the retry count")).into())
+    }
+
+    /// The async equivalent of [`Self::execute`]. The payloads are downloaded
+    /// with the reqwest client as the rayon thread pool gets to them, so only
+    /// as many are held in memory at once as with [`Self::execute`].
+    ///
+    /// If the operation panics, the panic is resumed when the future is
+    /// polled.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn execute_async(
+        self: Arc<Self>,
+        client: reqwest::Client,
+        packages: std::collections::BTreeMap<String, crate::manifest::ManifestItem>,
+        payloads: Vec<WorkItem>,
+        crt_version: String,
+        sdk_version: String,
+        arches: u32,
+        variants: u32,
+        ops: crate::Ops,
+    ) -> Result<Vec<crate::Diagnostic>, Error> {
+        use futures::StreamExt as _;
+
+        let (requests, pending) = futures::channel::mpsc::unbounded::<DownloadRequest>();
+        let (result_tx, result) = futures::channel::oneshot::channel();
+
+        let ctx = self.clone();
+        rayon::spawn(move || {
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                // The requests are closed once the closure is dropped, which
+                // completes the downloads on the async side
+                ctx.execute_with(
+                    payloads,
+                    crt_version,
+                    sdk_version,
+                    arches,
+                    variants,
+                    ops,
+                    move |item| {
+                        let (contents, rx) = crossbeam_channel::bounded(1);
+                        requests
+                            .unbounded_send(DownloadRequest {
+                                item: item.clone(),
+                                contents,
+                            })
+                            .ok()
+                            .and_then(|()| rx.recv().ok())
+                            .with_context(|| {
+                                format!("the download of {} was cancelled", item.payload.filename)
+                            })?
+                    },
+                )
+            }));
+
+            // The future was dropped if this fails, so there is no one to
+            // report the result to
+            let _ = result_tx.send(res);
+        });
+
+        let packages = Arc::new(packages);
+        let downloads = pending.for_each_concurrent(None, |req| {
+            let (ctx, client, packages) = (self.clone(), client.clone(), packages.clone());
+
+            async move {
+                let contents =
+                    crate::download::download_async(&ctx, &client, &packages, &req.item).await;
+                // The operation failed if the payload is no longer needed,
+                // which is reported by it instead
+                let _ = req.contents.send(contents);
+            }
+        });
+
+        let ((), result) = futures::future::join(downloads, result).await;
+
+        match result {
+            Ok(Ok(result)) => result,
+            Ok(Err(panic)) => std::panic::resume_unwind(panic),
+            Err(_cancelled) => anyhow::bail!("the operation was cancelled"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{util::ProgressTarget, util::Sha256, Ctx, PathBuf};
+    use std::io::{Read as _, Write as _};
+
+    /// Serves each response to a single request for its path, in order
+    fn serve(mut responses: Vec<(&'static str, Vec<u8>)>) -> (String, std::thread::JoinHandle<()>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            while !responses.is_empty() {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0; 4096];
+                let read = stream.read(&mut buf).unwrap();
+                let request = String::from_utf8_lossy(&buf[..read]);
+                let path = request.split(' ').nth(1).unwrap();
+
+                let i = responses.iter().position(|(p, _)| *p == path).unwrap();
+                stream.write_all(&responses.remove(i).1).unwrap();
+            }
+        });
+
+        (format!("http://{addr}"), server)
+    }
+
+    fn response(body: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+
+    fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(fut)
+    }
+
+    #[test]
+    fn downloads() {
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let mut ctx =
+            Ctx::with_dir(work_dir.clone(), ProgressTarget::Hidden, ureq::agent(), 1).unwrap();
+        let client = reqwest::Client::new();
+        let expected = Sha256::digest(b"payload");
+
+        // The first response is cut short, so it is retried
+        let truncated =
+            b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\npay".to_vec();
+        let (url, server) = serve(vec![
+            ("/payload.vsix", truncated),
+            ("/payload.vsix", response("payload")),
+        ]);
+
+        let progress = indicatif::ProgressBar::hidden();
+        let get = |ctx: &Ctx, url: &str| {
+            block_on(ctx.get_and_validate_async(
+                &client,
+                format!("{url}/payload.vsix"),
+                &"payload.vsix",
+                Some(expected.clone()),
+                Some(7),
+                progress.clone(),
+            ))
+        };
+
+        assert_eq!(get(&ctx, &url).unwrap(), "payload");
+        server.join().unwrap();
+        // The retry doesn't count the bytes of the first try again
+        assert_eq!(progress.position(), 7);
+        assert_eq!(
+            std::fs::read_to_string(work_dir.join("dl/payload.vsix")).unwrap(),
+            "payload"
+        );
+
+        // The cached file is used without any request
+        ctx.offline = true;
+        assert_eq!(get(&ctx, &url).unwrap(), "payload");
+
+        // And a mismatch is not cached
+        ctx.offline = false;
+        std::fs::remove_file(work_dir.join("dl/payload.vsix")).unwrap();
+        let (url, server) = serve(vec![("/payload.vsix", response("altered"))]);
+        let err = get(&ctx, &url).unwrap_err();
+        server.join().unwrap();
+        assert_eq!(
+            crate::util::ErrorKind::of(&err),
+            crate::util::ErrorKind::ChecksumMismatch
+        );
+        assert!(!work_dir.join("dl/payload.vsix").exists());
+    }
+
+    #[test]
+    fn execute_async() {
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let ctx = std::sync::Arc::new(
+            Ctx::with_dir(work_dir.clone(), ProgressTarget::Hidden, ureq::agent(), 0).unwrap(),
+        );
+
+        let (url, server) = serve(vec![
+            ("/first.vsix", response("first")),
+            ("/second.vsix", response("second")),
+        ]);
+        let payloads = ["first", "second"]
+            .into_iter()
+            .map(|name| crate::WorkItem {
+                progress: indicatif::ProgressBar::hidden(),
+                payload: std::sync::Arc::new(crate::Payload {
+                    filename: format!("{name}.vsix").into(),
+                    sha256: Sha256::digest(name.as_bytes()),
+                    url: format!("{url}/{name}.vsix"),
+                    size: name.len() as u64,
+                    install_size: None,
+                    kind: crate::PayloadKind::SdkHeaders,
+                    target_arch: None,
+                    variant: None,
+                }),
+            })
+            .collect();
+
+        let fut = ctx.execute_async(
+            reqwest::Client::new(),
+            Default::default(),
+            payloads,
+            "14.40.17.10".to_owned(),
+            "10.0.26100".to_owned(),
+            crate::Arch::X86_64 as u32,
+            crate::Variant::Desktop as u32,
+            crate::Ops::Download,
+        );
+
+        // So that it can be spawned on a multi-threaded runtime
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&fut);
+
+        assert!(block_on(fut).unwrap().is_empty());
+        server.join().unwrap();
+
+        for name in ["first", "second"] {
+            assert_eq!(
+                std::fs::read_to_string(work_dir.join(format!("dl/{name}.vsix"))).unwrap(),
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    splat::{DirLayout, SplatRoots},
+    Arch, Path, PathBuf,
+};
+use anyhow::{Context as _, Error};
+use std::fmt::Write as _;
+
+/// The rule that turns the splat directories into a `cc_toolchain_config`,
+/// compiling with `clang-cl` and linking with `lld-link`
+const TOOLCHAIN_CONFIG_RULE: &str = r#"
+_COMPILE_ACTIONS = [
+    ACTION_NAMES.assemble,
+    ACTION_NAMES.preprocess_assemble,
+    ACTION_NAMES.c_compile,
+    ACTION_NAMES.cpp_compile,
+    ACTION_NAMES.cpp_header_parsing,
+    ACTION_NAMES.cpp_module_compile,
+]
+
+_LINK_ACTIONS = [
+    ACTION_NAMES.cpp_link_executable,
+    ACTION_NAMES.cpp_link_dynamic_library,
+    ACTION_NAMES.cpp_link_nodeps_dynamic_library,
+]
+
+def _xwin_cc_toolchain_config_impl(ctx):
+    triple = XWIN_TRIPLES[ctx.attr.arch]
+
+    features = [
+        feature(
+            name = "xwin_sysroot",
+            enabled = True,
+            flag_sets = [
+                flag_set(
+                    actions = _COMPILE_ACTIONS,
+                    flag_groups = [flag_group(flags = ["--target=" + triple] + ["/imsvc" + d for d in XWIN_INCLUDE_DIRECTORIES])],
+                ),
+                flag_set(
+                    actions = _LINK_ACTIONS,
+                    flag_groups = [flag_group(flags = ["--target=" + triple, "-fuse-ld=lld-link"])],
+                ),
+            ],
+            # lld-link searches the directories in LIB for libraries
+            env_sets = [
+                env_set(
+                    actions = _LINK_ACTIONS,
+                    env_entries = [env_entry(key = "LIB", value = ";".join(XWIN_LIB_DIRECTORIES[ctx.attr.arch]))],
+                ),
+            ],
+        ),
+    ]
+
+    return cc_common.create_cc_toolchain_config_info(
+        ctx = ctx,
+        features = features,
+        cxx_builtin_include_directories = XWIN_INCLUDE_DIRECTORIES,
+        toolchain_identifier = "xwin-" + ctx.attr.arch,
+        host_system_name = "local",
+        target_system_name = triple,
+        target_cpu = ctx.attr.arch,
+        target_libc = "msvcrt",
+        compiler = "clang-cl",
+        abi_version = "local",
+        abi_libc_version = "local",
+        tool_paths = [
+            tool_path(name = "gcc", path = XWIN_TOOLS["clang-cl"]),
+            tool_path(name = "cpp", path = XWIN_TOOLS["clang-cl"]),
+            tool_path(name = "ld", path = XWIN_TOOLS["lld-link"]),
+            tool_path(name = "ar", path = XWIN_TOOLS["llvm-lib"]),
+            tool_path(name = "nm", path = XWIN_TOOLS["llvm-nm"]),
+            tool_path(name = "objdump", path = XWIN_TOOLS["llvm-objdump"]),
+            tool_path(name = "strip", path = XWIN_TOOLS["llvm-strip"]),
+            tool_path(name = "gcov", path = XWIN_TOOLS["llvm-cov"]),
+        ],
+    )
+
+xwin_cc_toolchain_config = rule(
+    implementation = _xwin_cc_toolchain_config_impl,
+    attrs = {
+        "arch": attr.string(mandatory = True, values = XWIN_TRIPLES.keys()),
+    },
+    provides = [CcToolchainConfigInfo],
+)
+"#;
+
+/// The Bazel `@platforms//cpu` constraint for an architecture
+fn bazel_cpu(arch: Arch) -> &'static str {
+    match arch {
+        Arch::X86 => "x86_32",
+        Arch::X86_64 => "x86_64",
+        Arch::Aarch => "armv7",
+        Arch::Aarch64 => "aarch64",
+    }
+}
+
+/// Quotes a string for Starlark, which uses the same escapes as JSON for
+/// anything that can appear in a path
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).expect("strings are always serializable")
+}
+
+/// Writes a `xwin.bzl` with the include and library directories of the splat,
+/// and a rule for a `cc_toolchain_config` that uses them with `clang-cl` and
+/// `lld-link`, and a `BUILD.bazel` with a toolchain for each architecture, so
+/// that the splat can be registered as a Bazel C++ toolchain with
+/// `register_toolchains("//<dir>:all")`
+///
+/// The /winsysroot layout isn't supported.
+pub(crate) fn emit_bazel(
+    dir: &Path,
+    roots: &SplatRoots,
+    arches: u32,
+    layout: &DirLayout<'_>,
+    crt_version: &str,
+    sdk_version: &str,
+) -> Result<(), Error> {
+    let root = crate::util::canonicalize(&roots.root)?;
+
+    // Only reference the directories that were actually splatted, eg. there
+    // are no headers if --libs-only was used
+    let absolute = |dirs: Vec<PathBuf>| -> Vec<PathBuf> {
+        dirs.into_iter()
+            .filter(|dir| dir.exists())
+            .map(|dir| match dir.strip_prefix(&roots.root) {
+                Ok(rel) => root.join(rel),
+                Err(_) => dir,
+            })
+            .collect()
+    };
+
+    let includes = absolute(roots.include_dirs(layout));
+
+    let mut bzl = String::new();
+
+    writeln!(bzl, "# Generated by xwin {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(bzl)?;
+    writeln!(
+        bzl,
+        r#"load("@bazel_tools//tools/build_defs/cc:action_names.bzl", "ACTION_NAMES")"#
+    )?;
+    writeln!(
+        bzl,
+        r#"load("@bazel_tools//tools/cpp:cc_toolchain_config_lib.bzl", "env_entry", "env_set", "feature", "flag_group", "flag_set", "tool_path")"#
+    )?;
+    writeln!(bzl)?;
+    writeln!(bzl, "XWIN_CRT_VERSION = {}", quote(crt_version))?;
+    writeln!(bzl, "XWIN_SDK_VERSION = {}", quote(sdk_version))?;
+    writeln!(bzl)?;
+
+    writeln!(bzl, "XWIN_INCLUDE_DIRECTORIES = [")?;
+    for include in &includes {
+        writeln!(bzl, "    {},", quote(include.as_str()))?;
+    }
+    writeln!(bzl, "]")?;
+    writeln!(bzl)?;
+
+    writeln!(bzl, "XWIN_LIB_DIRECTORIES = {{")?;
+    for arch in Arch::iter(arches) {
+        writeln!(bzl, "    {}: [", quote(arch.as_str()))?;
+        for lib_dir in absolute(roots.lib_dirs(layout, arch).to_vec()) {
+            writeln!(bzl, "        {},", quote(lib_dir.as_str()))?;
+        }
+        writeln!(bzl, "    ],")?;
+    }
+    writeln!(bzl, "}}")?;
+    writeln!(bzl)?;
+
+    writeln!(bzl, "XWIN_TRIPLES = {{")?;
+    for arch in Arch::iter(arches) {
+        writeln!(
+            bzl,
+            "    {}: {},",
+            quote(arch.as_str()),
+            quote(arch.as_triple())
+        )?;
+    }
+    writeln!(bzl, "}}")?;
+    writeln!(bzl)?;
+
+    // Bazel requires absolute paths for tools outside of the workspace
+    writeln!(bzl, "XWIN_TOOLS = {{")?;
+    for tool in [
+        "clang-cl",
+        "lld-link",
+        "llvm-lib",
+        "llvm-nm",
+        "llvm-objdump",
+        "llvm-strip",
+        "llvm-cov",
+    ] {
+        let path = crate::util::find_in_path(tool)
+            .and_then(|path| path.to_str().map(String::from))
+            .unwrap_or_else(|| {
+                tracing::warn!("unable to find '{tool}' in PATH, the Bazel toolchain will need to be updated with its location");
+                tool.to_owned()
+            });
+
+        writeln!(bzl, "    {}: {},", quote(tool), quote(&path))?;
+    }
+    writeln!(bzl, "}}")?;
+
+    bzl.push_str(TOOLCHAIN_CONFIG_RULE);
+
+    let mut build = String::new();
+
+    writeln!(build, "# Generated by xwin {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(build)?;
+    writeln!(build, r#"load(":xwin.bzl", "xwin_cc_toolchain_config")"#)?;
+    writeln!(build)?;
+    writeln!(
+        build,
+        r#"package(default_visibility = ["//visibility:public"])"#
+    )?;
+    writeln!(build)?;
+    writeln!(build, r#"filegroup(name = "empty")"#)?;
+
+    for arch in Arch::iter(arches) {
+        let name = arch.as_str();
+        let cpu = bazel_cpu(arch);
+
+        write!(
+            build,
+            r#"
+xwin_cc_toolchain_config(
+    name = "{name}_config",
+    arch = "{name}",
+)
+
+cc_toolchain(
+    name = "{name}_cc_toolchain",
+    all_files = ":empty",
+    compiler_files = ":empty",
+    dwp_files = ":empty",
+    linker_files = ":empty",
+    objcopy_files = ":empty",
+    strip_files = ":empty",
+    toolchain_config = ":{name}_config",
+)
+
+toolchain(
+    name = "{name}_toolchain",
+    target_compatible_with = [
+        "@platforms//os:windows",
+        "@platforms//cpu:{cpu}",
+    ],
+    toolchain = ":{name}_cc_toolchain",
+    toolchain_type = "@bazel_tools//tools/cpp:toolchain_type",
+)
+"#
+        )?;
+    }
+
+    std::fs::create_dir_all(dir).with_context(|| format!("unable to create {dir}"))?;
+
+    let bzl_path = dir.join("xwin.bzl");
+    std::fs::write(&bzl_path, bzl).with_context(|| format!("unable to write {bzl_path}"))?;
+
+    let build_path = dir.join("BUILD.bazel");
+    std::fs::write(&build_path, build).with_context(|| format!("unable to write {build_path}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn follows_layout() {
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = crate::splat::test::test_roots(&root);
+
+        for dir in [
+            "crt/include",
+            "crt/lib/spectre/x86_64",
+            "sdk/include/ucrt",
+            "sdk/include/um",
+            "sdk/Include/10.0.22621.0/ucrt",
+            "sdk/Include/10.0.22621.0/um",
+            "sdk/lib/um/x86_64",
+            "sdk/lib/ucrt/x86_64",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let emit = |layout: &DirLayout<'_>| {
+            let dir = root.join("bazel");
+            emit_bazel(
+                &dir,
+                &roots,
+                Arch::X86_64 as u32,
+                layout,
+                "14.40.33807",
+                "10.0.22621.0",
+            )
+            .unwrap();
+            std::fs::read_to_string(dir.join("xwin.bzl")).unwrap()
+        };
+
+        let root = crate::util::canonicalize(&root).unwrap();
+        let dirs = |bzl: &str, name: &str| -> Vec<String> {
+            let start = bzl.find(name).unwrap();
+            bzl[start..]
+                .lines()
+                .take_while(|line| !matches!(*line, "]" | "}"))
+                .filter_map(|line| line.trim().strip_suffix(','))
+                .filter_map(|quoted| serde_json::from_str::<String>(quoted).ok())
+                .map(|dir| dir.strip_prefix(root.as_str()).unwrap().to_owned())
+                .collect()
+        };
+
+        let mut layout = DirLayout {
+            winsysroot: false,
+            map: false,
+            merge_ucrt_include: false,
+            preserve_ms_arch_notation: false,
+            spectre: true,
+            sdk_version: "10.0.22621.0",
+        };
+
+        let bzl = emit(&layout);
+        assert_eq!(
+            dirs(&bzl, "XWIN_INCLUDE_DIRECTORIES"),
+            ["/crt/include", "/sdk/include/ucrt", "/sdk/include/um"]
+        );
+        assert_eq!(
+            dirs(&bzl, "XWIN_LIB_DIRECTORIES"),
+            [
+                "/crt/lib/spectre/x86_64",
+                "/sdk/lib/um/x86_64",
+                "/sdk/lib/ucrt/x86_64"
+            ]
+        );
+        assert!(!bzl.contains("/bin/false"));
+
+        layout.map = true;
+        layout.spectre = false;
+        let bzl = emit(&layout);
+        assert_eq!(
+            dirs(&bzl, "XWIN_INCLUDE_DIRECTORIES"),
+            [
+                "/crt/include",
+                "/sdk/Include/10.0.22621.0/ucrt",
+                "/sdk/Include/10.0.22621.0/um"
+            ]
+        );
+        // The non-spectre CRT libs weren't splatted
+        assert_eq!(
+            dirs(&bzl, "XWIN_LIB_DIRECTORIES"),
+            ["/sdk/lib/um/x86_64", "/sdk/lib/ucrt/x86_64"]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     splat::SdkHeaders,
-    util::{ProgressTarget, Sha256},
+    util::{ErrorKind, ProgressTarget, Sha256},
     Path, PathBuf, WorkItem,
 };
 use anyhow::{Context as _, Error};
@@ -16,12 +16,121 @@ pub enum Unpack {
     Needed(PathBuf),
 }
 
+/// Written next to a cached download once its checksum has been verified, so
+/// that it doesn't need to be hashed again on subsequent runs unless the file
+/// is modified
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Verified {
+    sha256: Sha256,
+    size: u64,
+    modified: std::time::SystemTime,
+}
+
+impl Verified {
+    #[inline]
+    fn path(cache_path: &Path) -> PathBuf {
+        format!("{cache_path}.verified").into()
+    }
+
+    fn is_valid(cache_path: &Path, expected: &Sha256) -> bool {
+        let Ok(metadata) = std::fs::metadata(cache_path) else {
+            return false;
+        };
+        let Ok(verified) = std::fs::read(Self::path(cache_path)) else {
+            return false;
+        };
+        let Ok(verified) = serde_json::from_slice::<Self>(&verified) else {
+            return false;
+        };
+
+        verified.sha256 == *expected
+            && verified.size == metadata.len()
+            && matches!(metadata.modified(), Ok(modified) if modified == verified.modified)
+    }
+
+    fn write(cache_path: &Path, sha256: Sha256) {
+        let write = || -> Result<(), Error> {
+            let metadata = std::fs::metadata(cache_path)?;
+            let verified = serde_json::to_vec(&Self {
+                sha256,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            })?;
+            std::fs::write(Self::path(cache_path), verified)?;
+            Ok(())
+        };
+
+        // This is purely an optimization, so failing to write it is fine
+        if let Err(err) = write() {
+            tracing::debug!("failed to write verification for {cache_path}: {err:#}");
+        }
+    }
+}
+
 pub struct Ctx {
     pub work_dir: PathBuf,
     pub tempdir: Option<tempfile::TempDir>,
     pub client: ureq::Agent,
     pub draw_target: ProgressTarget,
     pub http_retry: u8,
+    /// Whether the source files in packages are unpacked and splatted, which
+    /// are normally skipped
+    pub include_sources: bool,
+    /// Whether cached downloads are always hashed to verify their checksum,
+    /// rather than only when they have been modified since they were last
+    /// verified
+    pub verify: bool,
+    /// The number of threads used to download payloads, defaults to the
+    /// global rayon thread pool if not specified
+    pub download_threads: Option<usize>,
+    /// The maximum number of CABs of a single MSI that are downloaded
+    /// concurrently, which are otherwise only limited by the download threads
+    pub cab_download_threads: Option<usize>,
+    /// The number of threads used to unpack payloads, defaults to the
+    /// global rayon thread pool if not specified
+    pub unpack_threads: Option<usize>,
+    /// The number of threads used to splat payloads, defaults to the
+    /// global rayon thread pool if not specified
+    pub splat_threads: Option<usize>,
+    /// If true, network access is forbidden and every resource must already
+    /// be present in the cache
+    pub offline: bool,
+    /// If true, the raw tables from each MSI that is unpacked are written as
+    /// JSON to `msi-tables/<payload>` for debugging
+    pub keep_unpacked_raw: bool,
+    /// If true, a CAB that fails to extract is removed from the cache and
+    /// downloaded again, and the extraction retried once before failing
+    pub retry_cab_extraction: bool,
+    /// If true, the splat output is used even if it contains files that
+    /// weren't created by xwin, which normally fails as the existing CRT and
+    /// SDK directories in it are deleted
+    pub allow_non_empty_output: bool,
+    /// If true, a payload that fails to be downloaded, unpacked, or splatted
+    /// is logged and the remaining payloads are still processed, with the
+    /// failures only being returned as an error once everything else is done
+    pub keep_going: bool,
+    /// If true, the time spent in each phase of [`Self::execute`] is logged
+    /// once it has finished
+    pub timings: bool,
+    /// If true, payloads whose checksum doesn't match the one in the manifest
+    /// are used anyways, with a warning, rather than failing, eg. for mirrors
+    /// or proxies that alter the payloads
+    pub ignore_checksum_mismatches: bool,
+    /// If specified, a cached channel manifest that was retrieved or
+    /// confirmed to be current within this duration is used without making
+    /// any request
+    pub manifest_cache_ttl: Option<std::time::Duration>,
+    /// Tracks the estimated work across the download, unpack, and splat
+    /// phases of every payload, hidden by default
+    pub overall_progress: indicatif::ProgressBar,
+}
+
+/// The validators from a previous response, used to make conditional requests
+/// for resources that change over time
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HttpValidators {
+    etag: Option<String>,
+    last_modified: Option<String>,
 }
 
 impl Ctx {
@@ -40,6 +149,21 @@ impl Ctx {
             client,
             draw_target: dt,
             http_retry,
+            include_sources: false,
+            verify: false,
+            download_threads: None,
+            cab_download_threads: None,
+            unpack_threads: None,
+            splat_threads: None,
+            offline: false,
+            keep_unpacked_raw: false,
+            retry_cab_extraction: false,
+            allow_non_empty_output: false,
+            keep_going: false,
+            timings: false,
+            ignore_checksum_mismatches: false,
+            manifest_cache_ttl: None,
+            overall_progress: indicatif::ProgressBar::hidden(),
         })
     }
 
@@ -62,59 +186,285 @@ impl Ctx {
             client,
             draw_target: dt,
             http_retry,
+            include_sources: false,
+            verify: false,
+            download_threads: None,
+            cab_download_threads: None,
+            unpack_threads: None,
+            splat_threads: None,
+            offline: false,
+            keep_unpacked_raw: false,
+            retry_cab_extraction: false,
+            allow_non_empty_output: false,
+            keep_going: false,
+            timings: false,
+            ignore_checksum_mismatches: false,
+            manifest_cache_ttl: None,
+            overall_progress: indicatif::ProgressBar::hidden(),
         })
     }
 
-    pub fn get_and_validate<P>(
+    /// Populates the cache from a tar stream, eg. an artifact of the cache from
+    /// a previous run. Entries must be relative to the root of the cache, ie.
+    /// `dl/...` or `unpack/...`, optionally prefixed with `./`, any other
+    /// entries are ignored.
+    ///
+    /// Returns the number of files that were written to the cache
+    pub fn populate_cache(&self, archive: impl std::io::Read) -> Result<u32, Error> {
+        let mut archive = tar::Archive::new(archive);
+        let mut count = 0;
+
+        for entry in archive
+            .entries()
+            .context("failed to read cache archive entries")?
+        {
+            let mut entry = entry.context("failed to read cache archive entry")?;
+
+            let path = entry
+                .path()
+                .context("failed to read path of cache archive entry")?
+                .into_owned();
+            // Archives created with eg. `tar -C <cache> -cf cache.tar .` prefix
+            // every entry with `./`
+            let path = path
+                .strip_prefix(".")
+                .map(std::path::Path::to_path_buf)
+                .unwrap_or(path);
+
+            if !path.starts_with("dl") && !path.starts_with("unpack") {
+                tracing::warn!(
+                    "ignoring cache archive entry '{}' which is not in 'dl' or 'unpack'",
+                    path.display()
+                );
+                continue;
+            }
+
+            let is_file = entry.header().entry_type().is_file();
+
+            // unpack_in refuses to write outside of the work directory
+            if entry
+                .unpack_in(&self.work_dir)
+                .with_context(|| format!("failed to unpack cache entry '{}'", path.display()))?
+                && is_file
+            {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Retrieves a resource that changes over time and thus can't be verified
+    /// with a checksum, eg. the channel manifest.
+    ///
+    /// If the resource was previously cached, a conditional request is made
+    /// using the `ETag` and/or `Last-Modified` of the previous response so that
+    /// the cached version is reused if it hasn't changed, in which case its
+    /// modification time is updated to record when it was last confirmed to be
+    /// current. The cached version is also used, with a warning, if the
+    /// request fails with an error that may be temporary, eg. a timeout or a
+    /// server error, but not if the resource is eg. no longer found.
+    pub fn get_conditional<P>(
         &self,
         url: impl AsRef<str>,
         path: &P,
-        checksum: Option<Sha256>,
-        mut progress: indicatif::ProgressBar,
+        progress: indicatif::ProgressBar,
     ) -> Result<bytes::Bytes, Error>
     where
         P: AsRef<Path> + std::fmt::Debug,
     {
-        let short_path = path.as_ref();
+        let url = url.as_ref();
         let cache_path = {
             let mut cp = self.work_dir.clone();
             cp.push("dl");
-            cp.push(short_path);
+            cp.push(path.as_ref());
             cp
         };
+        let validators_path = PathBuf::from(format!("{cache_path}.http"));
 
-        if cache_path.exists() {
-            tracing::debug!("verifying existing cached dl file");
+        let cached = std::fs::read(&cache_path).ok();
+        let validators = cached
+            .as_ref()
+            .and_then(|_| std::fs::read(&validators_path).ok())
+            .and_then(|v| serde_json::from_slice::<HttpValidators>(&v).ok());
 
-            match std::fs::read(&cache_path) {
-                Ok(contents) => match &checksum {
-                    Some(expected) => {
-                        let chksum = Sha256::digest(&contents);
+        if self.offline {
+            let cached = cached.with_context(|| {
+                format!("{cache_path} is not cached, and network access is disabled")
+            })?;
+
+            progress.inc_length(cached.len() as u64);
+            progress.inc(cached.len() as u64);
+            return Ok(cached.into());
+        }
+
+        let mut req = self.client.get(url);
+
+        if let Some(validators) = &validators {
+            if let Some(etag) = &validators.etag {
+                req = req.header("if-none-match", etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                req = req.header("if-modified-since", last_modified);
+            }
+        }
 
-                        if chksum != *expected {
-                            tracing::warn!(
-                                "checksum mismatch, expected {} != actual {}",
-                                expected,
-                                chksum
+        let res = match (req.call(), cached) {
+            (Ok(res), cached) => {
+                if res.status() == 304 {
+                    if let Some(cached) = cached {
+                        tracing::debug!("{url} has not been modified, using cached version");
+
+                        // This only affects how long the cached version is
+                        // considered fresh, so failing to update it is fine
+                        if let Err(err) = std::fs::File::options()
+                            .write(true)
+                            .open(&cache_path)
+                            .and_then(|f| f.set_modified(std::time::SystemTime::now()))
+                        {
+                            tracing::debug!(
+                                "failed to update modification time of {cache_path}: {err}"
                             );
-                        } else {
-                            progress.inc_length(contents.len() as u64);
-                            progress.inc(contents.len() as u64);
-                            return Ok(contents.into());
                         }
+
+                        progress.inc_length(cached.len() as u64);
+                        progress.inc(cached.len() as u64);
+                        return Ok(cached.into());
                     }
-                    None => {
-                        progress.inc_length(contents.len() as u64);
-                        progress.inc(contents.len() as u64);
-                        return Ok(contents.into());
-                    }
-                },
-                Err(e) => {
-                    tracing::warn!(error = %e, "failed to read cached file");
+
+                    anyhow::bail!("{url} responded with 304 for an unconditional request");
+                }
+
+                res
+            }
+            (Err(err), Some(cached)) => {
+                let err = Error::from(err);
+
+                // Only fall back to the cached version if the request may
+                // succeed later, eg. a 404 means the url is no longer valid
+                if crate::util::ErrorKind::of(&err) != crate::util::ErrorKind::Network {
+                    return Err(err.context(format!("HTTP GET request for {url} failed")));
+                }
+
+                let age = std::fs::metadata(&cache_path)
+                    .and_then(|md| md.modified())
+                    .ok()
+                    .and_then(|modified| modified.elapsed().ok())
+                    .map_or_else(|| "an unknown time".to_owned(), format_age);
+
+                tracing::warn!(
+                    "failed to retrieve {url}, using the version cached {age} ago: {err:#}"
+                );
+                progress.inc_length(cached.len() as u64);
+                progress.inc(cached.len() as u64);
+                return Ok(cached.into());
+            }
+            (Err(err), None) => {
+                return Err(err).with_context(|| format!("HTTP GET request for {url} failed"));
+            }
+        };
+
+        let header = |name: &str| {
+            res.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        let validators = HttpValidators {
+            etag: header("etag"),
+            last_modified: header("last-modified"),
+        };
+
+        let mut body = Vec::new();
+        std::io::Read::read_to_end(&mut res.into_body().as_reader(), &mut body).with_context(
+            || ErrorKind::Network.msg(format!("failed to retrieve body for {url}")),
+        )?;
+
+        progress.inc_length(body.len() as u64);
+        progress.inc(body.len() as u64);
+
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(&cache_path, &body)
+            .with_context(|| format!("failed to write {cache_path}"))?;
+
+        if validators.etag.is_some() || validators.last_modified.is_some() {
+            std::fs::write(&validators_path, serde_json::to_vec(&validators)?)
+                .with_context(|| format!("failed to write {validators_path}"))?;
+        } else if validators_path.exists() {
+            let _ = std::fs::remove_file(&validators_path);
+        }
+
+        Ok(body.into())
+    }
+
+    /// Removes the file from the cache and retrieves it from the url again,
+    /// eg. because it passed validation but its contents are still unusable
+    pub(crate) fn redownload<P>(
+        &self,
+        url: impl AsRef<str>,
+        path: &P,
+        checksum: Option<Sha256>,
+        progress: indicatif::ProgressBar,
+    ) -> Result<bytes::Bytes, Error>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        let cache_path = self.work_dir.join("dl").join(path.as_ref());
+
+        // Don't throw away the cached file if it can't be replaced
+        anyhow::ensure!(
+            !self.offline,
+            "{cache_path} needs to be downloaded again, but network access is disabled"
+        );
+
+        for path in [Verified::path(&cache_path), cache_path.clone()] {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err).with_context(|| format!("unable to remove {path}"));
                 }
             }
         }
 
+        self.get_and_validate(url, path, checksum, None, progress)
+    }
+
+    /// Retrieves the file, either from the cache or the url, validating it
+    /// against the checksum if one is provided
+    ///
+    /// `expected_len` is the size the progress bar's length was already set to
+    /// for this file, eg. the size from the manifest, so that the length is only
+    /// adjusted if the actual size differs, rather than added to
+    pub fn get_and_validate<P>(
+        &self,
+        url: impl AsRef<str>,
+        path: &P,
+        checksum: Option<Sha256>,
+        expected_len: Option<u64>,
+        mut progress: indicatif::ProgressBar,
+    ) -> Result<bytes::Bytes, Error>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        let expected_len = expected_len.unwrap_or_default();
+        let cache_path = self.work_dir.join("dl").join(path.as_ref());
+
+        if let Some(contents) =
+            self.read_cached(&cache_path, checksum.as_ref(), expected_len, &progress)
+        {
+            return Ok(contents);
+        }
+
+        anyhow::ensure!(
+            !self.offline,
+            "{cache_path} is not cached, or failed validation, and network access is disabled"
+        );
+
         use bytes::BufMut;
 
         struct ProgressCopy {
@@ -145,23 +495,50 @@ impl Ctx {
         enum DownloadError {
             Ureq(ureq::Error),
             Io(std::io::Error),
+            Encoding(String),
             Retry((bytes::BytesMut, indicatif::ProgressBar)),
         }
 
+        // Every payload is already compressed (MSI, CAB, VSIX), so there is
+        // nothing to gain from compressing them in transit, and since we need
+        // the exact bytes to validate the checksum, we explicitly ask for them
+        // as-is in case a mirror would otherwise compress them
+        let identity = checksum.is_some();
+
         let try_download = |mut body: bytes::BytesMut,
                             progress: indicatif::ProgressBar|
          -> Result<bytes::BytesMut, DownloadError> {
-            let res = self
-                .client
-                .get(url.as_ref())
-                .call()
-                .map_err(DownloadError::Ureq)?;
+            let mut req = self.client.get(url.as_ref());
+            if identity {
+                req = req.header("accept-encoding", "identity");
+            }
+
+            let res = req.call().map_err(DownloadError::Ureq)?;
 
-            let content_length = res
+            // ureq transparently decodes gzip, but any other encoding would
+            // just give us the encoded bytes which would then fail the checksum
+            // with a misleading error, and in both cases the content-length is
+            // the encoded length, not the length of the body we actually read
+            let encoded = match res
                 .headers()
-                .get("content-length")
-                .and_then(|header| header.to_str().ok()?.parse().ok())
-                .unwrap_or_default();
+                .get("content-encoding")
+                .and_then(|header| header.to_str().ok())
+                .map(|enc| enc.trim().to_ascii_lowercase())
+                .as_deref()
+            {
+                None | Some("" | "identity") => false,
+                Some("gzip") => true,
+                Some(other) => return Err(DownloadError::Encoding(other.to_owned())),
+            };
+
+            let content_length = if encoded {
+                0
+            } else {
+                res.headers()
+                    .get("content-length")
+                    .and_then(|header| header.to_str().ok()?.parse().ok())
+                    .unwrap_or_default()
+            };
 
             if body.capacity() > 0 {
                 if body.capacity() as u64 != content_length {
@@ -175,7 +552,12 @@ impl Ctx {
                 }
             } else {
                 body.reserve(content_length as usize);
-                progress.inc_length(content_length);
+
+                // The content-length is unknown if the body is encoded, in
+                // which case we just keep the expected length, if any
+                if content_length > 0 {
+                    adjust_length(&progress, expected_len, content_length);
+                }
             }
 
             let failed = body.len();
@@ -207,23 +589,7 @@ impl Ctx {
         while tries > 0 {
             match try_download(body, progress) {
                 Ok(body) => {
-                    let body = body.freeze();
-
-                    if let Some(expected) = checksum {
-                        let chksum = Sha256::digest(&body);
-
-                        anyhow::ensure!(
-                            chksum == expected,
-                            "checksum mismatch, expected {expected} != actual {chksum}"
-                        );
-                    }
-
-                    if let Some(parent) = cache_path.parent() {
-                        std::fs::create_dir_all(parent)?;
-                    }
-
-                    std::fs::write(cache_path, &body)?;
-                    return Ok(body);
+                    return self.write_cached(url.as_ref(), &cache_path, checksum, body.freeze());
                 }
                 Err(DownloadError::Retry((b, prog))) => {
                     tries -= 1;
@@ -244,13 +610,132 @@ impl Ctx {
                         .with_context(|| format!("HTTP GET request for {} failed", url.as_ref()));
                 }
                 Err(DownloadError::Io(err)) => {
-                    return Err(err)
-                        .with_context(|| format!("failed to retrieve body for {}", url.as_ref()));
+                    return Err(err).with_context(|| {
+                        ErrorKind::Network
+                            .msg(format!("failed to retrieve body for {}", url.as_ref()))
+                    });
+                }
+                Err(DownloadError::Encoding(encoding)) => {
+                    anyhow::bail!(
+                        "{} was served with unsupported content-encoding '{encoding}', the server or mirror must serve it uncompressed or gzip encoded",
+                        url.as_ref()
+                    );
                 }
             }
         }
 
-        anyhow::bail!("failed to retrieve {} after {total} tries due to I/O failures reading the response body, try using --http-retries to increase the retry count", url.as_ref());
+        Err(ErrorKind::Network.msg(format!("failed to retrieve {} after {total} tries due to I/O failures reading the response body, try using --http-retries to increase the retry count", url.as_ref())).into())
+    }
+
+    /// Reads the file from the cache if it is present and, if a checksum is
+    /// provided, matches it
+    pub(crate) fn read_cached(
+        &self,
+        cache_path: &Path,
+        checksum: Option<&Sha256>,
+        expected_len: u64,
+        progress: &indicatif::ProgressBar,
+    ) -> Option<bytes::Bytes> {
+        if !cache_path.exists() {
+            return None;
+        }
+
+        tracing::debug!("verifying existing cached dl file");
+
+        // The file is hashed while it is being read, rather than being
+        // read a second time once it has been verified
+        let contents = match checksum {
+            Some(expected) if self.verify || !Verified::is_valid(cache_path, expected) => {
+                let mut contents = Vec::new();
+                std::fs::File::open(cache_path)
+                    .and_then(|file| Sha256::read_digest(file, &mut contents))
+                    .map(|chksum| {
+                        if chksum == *expected {
+                            Verified::write(cache_path, chksum);
+                            return Some(contents);
+                        }
+
+                        tracing::warn!(
+                            "checksum mismatch, expected {} != actual {}",
+                            expected,
+                            chksum
+                        );
+
+                        // The mismatch is never written as verified, so
+                        // this is checked again on every run
+                        if self.ignore_checksum_mismatches {
+                            tracing::warn!("using {cache_path} despite the checksum mismatch");
+                            Some(contents)
+                        } else {
+                            None
+                        }
+                    })
+            }
+            Some(_) => {
+                tracing::debug!("cached dl file was previously verified");
+                std::fs::read(cache_path).map(Some)
+            }
+            None => std::fs::read(cache_path).map(Some),
+        };
+
+        match contents {
+            Ok(Some(contents)) => {
+                adjust_length(progress, expected_len, contents.len() as u64);
+                progress.inc(contents.len() as u64);
+                Some(contents.into())
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to read cached file");
+                None
+            }
+        }
+    }
+
+    /// Validates the body that was downloaded from the url against the
+    /// checksum, if one is provided, and writes it to the cache
+    pub(crate) fn write_cached(
+        &self,
+        url: &str,
+        cache_path: &Path,
+        checksum: Option<Sha256>,
+        body: bytes::Bytes,
+    ) -> Result<bytes::Bytes, Error> {
+        let verified = if let Some(expected) = checksum {
+            let chksum = Sha256::digest(&body);
+
+            if chksum == expected {
+                Some(chksum)
+            } else {
+                if !self.ignore_checksum_mismatches {
+                    return Err(ErrorKind::ChecksumMismatch
+                        .msg(format!(
+                            "checksum mismatch, expected {expected} != actual {chksum}"
+                        ))
+                        .into());
+                }
+
+                tracing::warn!(
+                    url,
+                    "checksum mismatch, expected {expected} != actual {chksum}, using it anyways"
+                );
+                None
+            }
+        } else {
+            None
+        };
+
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(cache_path, &body)?;
+
+        if let Some(chksum) = verified {
+            Verified::write(cache_path, chksum);
+        }
+
+        Ok(body)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -263,32 +748,149 @@ impl Ctx {
         arches: u32,
         variants: u32,
         ops: crate::Ops,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<crate::Diagnostic>, Error> {
+        let packages = std::sync::Arc::new(packages);
+        let ctx = self.clone();
+
+        self.execute_with(
+            payloads,
+            crt_version,
+            sdk_version,
+            arches,
+            variants,
+            ops,
+            |wi| crate::download::download(ctx.clone(), packages.clone(), wi),
+        )
+    }
+
+    /// Executes the operation, using `download` to retrieve the contents of
+    /// each payload that isn't skipped
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn execute_with(
+        self: std::sync::Arc<Self>,
+        payloads: Vec<WorkItem>,
+        crt_version: String,
+        sdk_version: String,
+        arches: u32,
+        variants: u32,
+        ops: crate::Ops,
+        download: impl Fn(&WorkItem) -> Result<crate::download::PayloadContents, Error> + Sync,
+    ) -> Result<Vec<crate::Diagnostic>, Error> {
         use rayon::prelude::*;
 
-        let packages = std::sync::Arc::new(packages);
+        let start = std::time::Instant::now();
+        let timings = Timings::default();
+        // The context is consumed when minimizing
+        let report_timings = self.timings;
 
         let mut results = Vec::new();
+        // The results are in the same order as the payloads
+        let names: Vec<_> = payloads
+            .iter()
+            .map(|wi| wi.payload.filename.clone())
+            .collect();
         let crt_ft = parking_lot::Mutex::new(None);
+        // Only used to report what was cached when only downloading
+        let cached_files = std::sync::atomic::AtomicU64::new(0);
+        let cached_bytes = std::sync::atomic::AtomicU64::new(0);
         let atl_ft = parking_lot::Mutex::new(None);
 
+        if let crate::Ops::Minimize(config) = &ops {
+            crate::minimize::preflight(config)?;
+        }
+
+        // The total work is estimated up front so that the overall progress
+        // doesn't jump backwards as each payload moves on to the next phase
+        let phases = match ops {
+            crate::Ops::Download => 1,
+            crate::Ops::Unpack => 2,
+            _ => 3,
+        };
+        let overall = &self.overall_progress;
+        overall.reset();
+        overall.set_length(
+            payloads
+                .iter()
+                .map(|wi| estimate_work(&wi.payload).iter().take(phases).sum::<u64>())
+                .sum(),
+        );
+
         let mut splat_config = match &ops {
             crate::Ops::Splat(config) => {
+                // Everything that affects the contents of the splat, so that we
+                // only resume a splat that was done with the same configuration
+                let fingerprint = config.resume.then(|| {
+                    let map = config
+                        .map
+                        .as_ref()
+                        .and_then(|map| std::fs::read(map).ok())
+                        .map(|map| Sha256::digest(&map).to_string());
+                    let payloads = Sha256::digest(
+                        payloads
+                            .iter()
+                            .map(|wi| wi.payload.filename.as_str())
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                            .as_bytes(),
+                    );
+
+                    format!(
+                        "crt={crt_version}\nsdk={sdk_version}\narches={arches}\nvariants={variants}\n\
+                        debug_libs={}\ndebug_symbols={}\nsymlinks={}\nms_arch={}\nwinsysroot={}\n\
+                        headers_only={}\nlibs_only={}\nsources={}\nmap={}\npayloads={payloads}\n\
+                        crt_dir={}\nsdk_dir={}\nsdk_libs={}\nfallback_copy={}\nmerge_ucrt_include={}\n\
+                        separate_tlbs={}\ncrt_lib_casings={}\n",
+                        config.include_debug_libs,
+                        config.include_debug_symbols,
+                        config.enable_symlinks,
+                        config.preserve_ms_arch_notation,
+                        config.use_winsysroot_style,
+                        config.headers_only,
+                        config.libs_only,
+                        self.include_sources,
+                        map.as_deref().unwrap_or("none"),
+                        config.crt_dir_name.as_deref().unwrap_or("crt"),
+                        config.sdk_dir_name.as_deref().unwrap_or("sdk"),
+                        config.sdk_libs.join(","),
+                        config.symlink_fallback_copy,
+                        config.merge_ucrt_include,
+                        config.separate_tlbs,
+                        config
+                            .crt_lib_casings
+                            .as_ref()
+                            .map_or_else(|| "all".to_owned(), |casings| casings.join(",")),
+                    )
+                });
+
                 let splat_roots = crate::splat::prep_splat(
-                    self.clone(),
+                    self.work_dir.join("unpack"),
+                    self.allow_non_empty_output,
                     &config.output,
+                    config.prefix.as_deref(),
                     config.use_winsysroot_style.then_some(&crt_version),
+                    (
+                        config.crt_dir_name.as_deref(),
+                        config.sdk_dir_name.as_deref(),
+                    ),
+                    fingerprint.as_deref(),
+                    config.add_arch,
                 )?;
                 let mut config = config.clone();
                 config.output = splat_roots.root.clone();
+                config.prefix = None;
 
                 Some((splat_roots, config))
             }
             crate::Ops::Minimize(config) => {
                 let splat_roots = crate::splat::prep_splat(
-                    self.clone(),
+                    self.work_dir.join("unpack"),
+                    self.allow_non_empty_output,
                     &config.splat_output,
+                    config.splat_prefix.as_deref(),
                     config.use_winsysroot_style.then_some(&crt_version),
+                    (None, None),
+                    None,
+                    false,
                 )?;
 
                 let config = crate::SplatConfig {
@@ -300,6 +902,7 @@ impl Ctx {
                     output: splat_roots.root.clone(),
                     map: Some(config.map.clone()),
                     copy: config.copy,
+                    ..Default::default()
                 };
 
                 Some((splat_roots, config))
@@ -309,37 +912,48 @@ impl Ctx {
 
         // Detect if the output root directory is case sensitive or not,
         // if it's not, disable symlinks as they won't work
-        let enable_symlinks = if let Some((root, sc_enable_symlinks)) =
-            splat_config.as_mut().and_then(|(sr, c)| {
-                c.enable_symlinks
-                    .then_some((&sr.root, &mut c.enable_symlinks))
-            }) {
+        if let Some((root, config)) = splat_config
+            .as_mut()
+            .and_then(|(sr, c)| c.link_kind().is_some().then_some((&sr.root, c)))
+        {
             let test_path = root.join("BIG.xwin");
             std::fs::write(&test_path, "").with_context(|| {
                 format!("failed to write case-sensitivity test file {test_path}")
             })?;
 
-            let enable_symlinks = if std::fs::read(root.join("big.xwin")).is_ok() {
-                tracing::warn!("detected splat root '{root}' is on a case-sensitive file system, disabling symlinks");
-                false
-            } else {
-                true
-            };
+            let is_insensitive = std::fs::read(root.join("big.xwin")).is_ok();
 
             // Will be ugly but won't harm anything if file is left
             let _ = std::fs::remove_file(test_path);
-            *sc_enable_symlinks = enable_symlinks;
-            enable_symlinks
-        } else {
-            false
-        };
 
-        let map = if let Some(map) = splat_config.as_ref().and_then(|(_, sp)| sp.map.as_ref()) {
+            if is_insensitive {
+                anyhow::ensure!(
+                    !config.symlink_fallback_copy,
+                    "splat root '{root}' is on a case-insensitive file system, so alternate casings can't be created as copies"
+                );
+
+                tracing::warn!("detected splat root '{root}' is on a case-sensitive file system, disabling symlinks");
+                config.enable_symlinks = false;
+            }
+        }
+
+        let map = if let Some((map, sp)) = splat_config
+            .as_ref()
+            .and_then(|(_, sp)| sp.map.as_ref().map(|map| (map, sp)))
+        {
             match std::fs::read_to_string(map) {
-                Ok(m) => Some(
-                    toml::from_str::<crate::Map>(&m)
-                        .with_context(|| format!("failed to deserialize '{map}'"))?,
-                ),
+                Ok(m) => {
+                    let mut map = toml::from_str::<crate::Map>(&m)
+                        .with_context(|| format!("failed to deserialize '{map}'"))?;
+                    map.translate(
+                        crate::MapLayout {
+                            winsysroot: sp.use_winsysroot_style,
+                            ms_arch_notation: sp.preserve_ms_arch_notation,
+                        },
+                        &sdk_version,
+                    );
+                    Some(map)
+                }
                 Err(err) => {
                     if !matches!(err.kind(), std::io::ErrorKind::NotFound) {
                         tracing::error!("unable to read mapping from '{map}': {err}");
@@ -351,42 +965,156 @@ impl Ctx {
             None
         };
 
+        // Each phase has a different resource profile, so they can optionally
+        // be run in their own thread pool rather than the global one
+        let build_pool = |threads: Option<usize>,
+                          phase: &'static str|
+         -> Result<Option<rayon::ThreadPool>, Error> {
+            threads
+                .map(|num_threads| {
+                    rayon::ThreadPoolBuilder::new()
+                        .num_threads(num_threads)
+                        .thread_name(move |i| format!("xwin-{phase}-{i}"))
+                        .build()
+                        .with_context(|| format!("failed to build {phase} thread pool"))
+                })
+                .transpose()
+        };
+
+        // The unpacked and splatted payloads take up roughly their installed
+        // size, which is used to give a rough idea of the space needed if
+        // the disk fills up
+        let installed_size: u64 = payloads
+            .iter()
+            .map(|wi| wi.payload.install_size.unwrap_or(wi.payload.size))
+            .sum();
+        let unpack_root = self.work_dir.join("unpack");
+
+        let download_pool = build_pool(self.download_threads, "download")?;
+        let unpack_pool = build_pool(self.unpack_threads, "unpack")?;
+        let splat_pool = build_pool(self.splat_threads, "splat")?;
+
+        fn in_pool<R: Send>(pool: Option<&rayon::ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+            match pool {
+                Some(pool) => pool.install(op),
+                None => op(),
+            }
+        }
+
         payloads
             .into_par_iter()
             .map(|wi| -> Result<Option<SdkHeaders>, Error> {
-                let payload_contents =
-                    crate::download::download(self.clone(), packages.clone(), &wi)?;
+                let checkpoints = splat_config
+                    .as_ref()
+                    .and_then(|(roots, _)| roots.checkpoints.as_deref());
+
+                // If the payload was already splatted by a previous run we can
+                // skip it entirely, we just need to restore the state that is
+                // needed to add the symlinks
+                if let Some(cp) = checkpoints
+                    .and_then(|cp_dir| crate::splat::Checkpoint::read(cp_dir, &wi.payload))
+                {
+                    match wi.payload.kind {
+                        crate::PayloadKind::CrtHeaders => *crt_ft.lock() = cp.tree,
+                        crate::PayloadKind::AtlHeaders => *atl_ft.lock() = cp.tree,
+                        _ => {}
+                    }
+
+                    wi.progress.finish_with_message("📦 splatted (checkpoint)");
+                    overall.inc(estimate_work(&wi.payload).iter().sum());
+                    return Ok(cp.sdk_headers);
+                }
+
+                let [download_work, unpack_work, splat_work] = estimate_work(&wi.payload);
+
+                let payload_contents = timings.record(Phase::Download, || {
+                    in_pool(download_pool.as_ref(), || download(&wi))
+                })?;
+                overall.inc(download_work);
 
                 if let crate::Ops::Download = ops {
+                    let (files, bytes) = match &payload_contents {
+                        crate::download::PayloadContents::Vsix(vsix) => (1, vsix.len()),
+                        crate::download::PayloadContents::Msi { msi, cabs } => (
+                            1 + cabs.len(),
+                            msi.len() + cabs.iter().map(|cab| cab.content.len()).sum::<usize>(),
+                        ),
+                    };
+
+                    cached_files.fetch_add(files as u64, std::sync::atomic::Ordering::Relaxed);
+                    cached_bytes.fetch_add(bytes as u64, std::sync::atomic::Ordering::Relaxed);
                     return Ok(None);
                 }
 
-                let ft = crate::unpack::unpack(self.clone(), &wi, payload_contents)?;
+                let ft = timings
+                    .record(Phase::Unpack, || {
+                        in_pool(unpack_pool.as_ref(), || {
+                            crate::unpack::unpack(
+                                self.clone(),
+                                &wi,
+                                payload_contents,
+                                crate::unpack::default_libs_only(&wi.payload, variants),
+                                false,
+                            )
+                        })
+                    })
+                    .map_err(|err| out_of_space(err, &unpack_root, installed_size))?;
+                overall.inc(unpack_work);
 
                 if let crate::Ops::Unpack = ops {
                     return Ok(None);
                 }
 
                 let sdk_headers = if let Some((splat_roots, config)) = &splat_config {
-                    crate::splat::splat(
-                        config,
-                        splat_roots,
-                        &wi,
-                        &ft,
-                        map.as_ref()
-                            .filter(|_m| !matches!(ops, crate::Ops::Minimize(_))),
-                        &sdk_version,
-                        arches,
-                        variants,
-                    )
-                    .with_context(|| format!("failed to splat {}", wi.payload.filename))?
+                    timings
+                        .record(Phase::Splat, || {
+                            in_pool(splat_pool.as_ref(), || {
+                                crate::splat::splat(
+                                    config,
+                                    splat_roots,
+                                    &wi,
+                                    &ft,
+                                    map.as_ref()
+                                        .filter(|_m| !matches!(ops, crate::Ops::Minimize(_))),
+                                    &sdk_version,
+                                    arches,
+                                    variants,
+                                    self.include_sources,
+                                )
+                            })
+                        })
+                        .with_context(|| format!("failed to splat {}", wi.payload.filename))
+                        .map_err(|err| out_of_space(err, &splat_roots.root, installed_size))?
                 } else {
                     None
                 };
+                overall.inc(splat_work);
+
+                let is_headers = matches!(
+                    wi.payload.kind,
+                    crate::PayloadKind::CrtHeaders | crate::PayloadKind::AtlHeaders
+                );
+                let mut tree = Some(ft);
+
+                // Record that the payload was successfully splatted so that it
+                // can be skipped if the splat is resumed
+                let sdk_headers = if let Some(cp_dir) = checkpoints {
+                    let cp = crate::splat::Checkpoint {
+                        sha256: wi.payload.sha256.clone(),
+                        sdk_headers,
+                        tree: tree.take().filter(|_| is_headers),
+                    };
+
+                    cp.write(cp_dir, &wi.payload)?;
+                    tree = cp.tree;
+                    cp.sdk_headers
+                } else {
+                    sdk_headers
+                };
 
                 match wi.payload.kind {
-                    crate::PayloadKind::CrtHeaders => *crt_ft.lock() = Some(ft),
-                    crate::PayloadKind::AtlHeaders => *atl_ft.lock() = Some(ft),
+                    crate::PayloadKind::CrtHeaders => *crt_ft.lock() = tree,
+                    crate::PayloadKind::AtlHeaders => *atl_ft.lock() = tree,
                     _ => {}
                 }
 
@@ -394,36 +1122,68 @@ impl Ctx {
             })
             .collect_into_vec(&mut results);
 
-        let sdk_headers = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let (sdk_headers, failed) = partition_failed(results, names, self.keep_going)?;
+        overall.finish();
+
+        // The CABs of every MSI are downloaded as well, so the cache can be
+        // used to unpack and splat offline
+        if let crate::Ops::Download = ops {
+            tracing::info!(
+                "cached {} files ({}) in {}",
+                cached_files.into_inner(),
+                indicatif::HumanBytes(cached_bytes.into_inner()),
+                self.work_dir.join("dl")
+            );
+        }
+
         let sdk_headers = sdk_headers.into_iter().flatten().collect();
 
         let Some((roots, sc)) = splat_config else {
-            return Ok(());
+            if report_timings {
+                timings.report(start, None);
+            }
+            return check_failed(&failed).map(|()| Vec::new());
         };
 
-        let splat_links = || -> anyhow::Result<()> {
-            if enable_symlinks {
-                let crt_ft = crt_ft.lock().take();
-                let atl_ft = atl_ft.lock().take();
-
-                crate::splat::finalize_splat(
-                    &self,
-                    sc.use_winsysroot_style.then_some(&sdk_version),
-                    &roots,
-                    sdk_headers,
-                    crt_ft,
-                    atl_ft,
-                )?;
-            }
+        let finalize_start = std::time::Instant::now();
 
-            Ok(())
+        let splat_links = || -> anyhow::Result<Vec<crate::Diagnostic>> {
+            let Some(link_kind) = sc.link_kind() else {
+                return Ok(Vec::new());
+            };
+
+            let crt_ft = crt_ft.lock().take();
+            let atl_ft = atl_ft.lock().take();
+
+            crate::splat::finalize_splat(
+                self.draw_target,
+                sc.use_winsysroot_style.then_some(&sdk_version),
+                &roots,
+                sdk_headers,
+                crt_ft,
+                atl_ft,
+                link_kind,
+            )
         };
 
+        let mut diagnostics = Vec::new();
+
         match ops {
             crate::Ops::Minimize(config) => {
-                splat_links()?;
+                // The build would fail, or worse, succeed with a map that is
+                // missing the files of the failed payloads
+                check_failed(&failed)?;
+
+                diagnostics = splat_links()?;
+                let results_json = config.results_json.clone();
                 let results = crate::minimize::minimize(self, config, roots, &sdk_version)?;
 
+                if let Some(path) = results_json {
+                    let serialized = serde_json::to_vec_pretty(&results)?;
+                    std::fs::write(&path, serialized)
+                        .with_context(|| format!("unable to write minimize results to {path}"))?;
+                }
+
                 fn emit(name: &str, num: crate::minimize::FileNumbers) {
                     fn hb(bytes: u64) -> String {
                         let mut bytes = bytes as f64;
@@ -455,18 +1215,159 @@ impl Ctx {
                 emit("sdk headers", results.sdk_headers);
                 emit("sdk libs", results.sdk_libs);
             }
-            crate::Ops::Splat(_config) => {
-                if map.is_none() {
-                    splat_links()?;
+            crate::Ops::Splat(config) => {
+                // The map may only specify additional casings, in which case
+                // the normal symlinks are still needed
+                if !map.as_ref().is_some_and(|map| map.has_filters()) {
+                    diagnostics = splat_links()?;
+                }
+
+                if config.link_arch_dirs {
+                    crate::splat::link_arch_dirs(&roots, arches, config.preserve_ms_arch_notation)?;
+                }
+
+                if config.use_winsysroot_style && config.winsysroot_bin {
+                    crate::splat::create_winsysroot_bin(&roots, arches)?;
+                }
+
+                if config.absolute_symlinks {
+                    crate::splat::make_symlinks_absolute(&roots)?;
+                }
+
+                if let Some(mtime) = config.mtime {
+                    crate::splat::set_mtimes(&roots, mtime)?;
+                }
+
+                if let Some(dir) = &config.emit_bazel {
+                    crate::bazel::emit_bazel(
+                        dir,
+                        &roots,
+                        arches,
+                        &config.dir_layout(variants, &sdk_version),
+                        &crt_version,
+                        &sdk_version,
+                    )?;
+                }
+
+                if let Some(layer) = &config.emit_oci_layer {
+                    crate::oci::emit_oci_layer(
+                        layer,
+                        &roots,
+                        arches,
+                        &config.dir_layout(variants, &sdk_version),
+                        &crt_version,
+                        &sdk_version,
+                    )?;
+                }
+
+                if let Some(path) = &config.emit_layout_json {
+                    crate::layout::emit_layout_json(
+                        path,
+                        &config,
+                        &roots,
+                        arches,
+                        variants,
+                        &crt_version,
+                        &sdk_version,
+                    )?;
+                }
+
+                if let Some(path) = &config.emit_gn_args {
+                    crate::gn::emit_gn_args(path, &roots, arches, &crt_version, &sdk_version)?;
+                }
+
+                if let Some(path) = &config.symlink_report {
+                    // The casing symlinks are only created on unix, and a map
+                    // may not specify any
+                    crate::splat::write_symlink_report(
+                        path,
+                        &roots,
+                        cfg!(unix) && sc.enable_symlinks && map.is_none(),
+                    )?;
+                }
+
+                if let Some(command) = &config.post_splat {
+                    if failed.is_empty() {
+                        crate::splat::run_post_splat(command, &roots, &crt_version, &sdk_version)?;
+                    } else {
+                        tracing::warn!("not running post splat command as the splat is incomplete");
+                    }
                 }
             }
             _ => {}
         }
 
-        Ok(())
+        if report_timings {
+            timings.report(start, Some(finalize_start));
+        }
+
+        check_failed(&failed)?;
+        Ok(diagnostics)
     }
 
-    pub(crate) fn prep_unpack(&self, payload: &crate::Payload) -> Result<Unpack, Error> {
+    /// Downloads and unpacks the specified payloads, eg. from
+    /// [`crate::package_payloads`], and copies the unpacked contents of each
+    /// one to `<output>/<payload filename>`
+    pub fn extract(
+        self: std::sync::Arc<Self>,
+        packages: std::collections::BTreeMap<String, crate::manifest::ManifestItem>,
+        payloads: Vec<WorkItem>,
+        output: &Path,
+    ) -> Result<(), Error> {
+        use rayon::prelude::*;
+
+        let packages = std::sync::Arc::new(packages);
+
+        payloads
+            .into_par_iter()
+            .try_for_each(|wi| -> Result<(), Error> {
+                let contents = crate::download::download(self.clone(), packages.clone(), &wi)?;
+                crate::unpack::unpack(self.clone(), &wi, contents, false, true)?;
+
+                let unpack_dir = self.work_dir.join("unpack").join(&wi.payload.filename);
+                let dest_dir = output.join(&wi.payload.filename);
+
+                wi.progress.set_message("📋 copying...");
+
+                for entry in walkdir::WalkDir::new(&unpack_dir) {
+                    let entry = entry.with_context(|| format!("unable to read {unpack_dir}"))?;
+                    let src = Path::from_path(entry.path()).with_context(|| {
+                        format!("{} is not a utf-8 path", entry.path().display())
+                    })?;
+                    let rel = src.strip_prefix(&unpack_dir)?;
+
+                    // The unpack metadata is only meaningful in the cache
+                    if rel == ".unpack" {
+                        continue;
+                    }
+
+                    let dest = dest_dir.join(rel);
+
+                    if entry.file_type().is_dir() {
+                        std::fs::create_dir_all(&dest)
+                            .with_context(|| format!("unable to create {dest}"))?;
+                    } else {
+                        std::fs::copy(src, &dest)
+                            .with_context(|| format!("unable to copy {src} to {dest}"))?;
+                    }
+                }
+
+                wi.progress.finish_with_message("📦 extracted");
+                Ok(())
+            })
+    }
+
+    /// Checks whether the payload was already unpacked, and if not, prepares
+    /// its unpack directory. A payload that was unpacked with only its default
+    /// libraries is unpacked again if all of its contents are needed. A payload
+    /// that was unpacked unfiltered is laid out differently, so is only reused
+    /// when unpacking unfiltered again, and vice versa.
+    pub(crate) fn prep_unpack(
+        &self,
+        payload: &crate::Payload,
+        default_libs_only: bool,
+        unfiltered: bool,
+    ) -> Result<Unpack, Error> {
         let mut unpack_dir = {
             let mut pb = self.work_dir.clone();
             pb.push("unpack");
@@ -478,7 +1379,13 @@ impl Ctx {
 
         if let Ok(unpack) = std::fs::read(&unpack_dir) {
             if let Ok(um) = serde_json::from_slice::<crate::unpack::UnpackMeta>(&unpack) {
-                if payload.sha256 == um.sha256 {
+                // Sources are skipped by default, so if they weren't unpacked
+                // previously we need to unpack again
+                if payload.sha256 == um.sha256
+                    && (um.sources || !self.include_sources)
+                    && (!um.default_libs_only || default_libs_only)
+                    && um.unfiltered == unfiltered
+                {
                     tracing::debug!("already unpacked");
                     unpack_dir.pop();
                     return Ok(Unpack::Present {
@@ -534,3 +1441,322 @@ impl Drop for Ctx {
         }
     }
 }
+
+/// Adds a prominent explanation to an error that was caused by running out of
+/// disk space, along with an estimate of how much space is needed
+fn out_of_space(err: Error, dir: &Path, needed: u64) -> Error {
+    if !crate::util::is_out_of_space(&err) {
+        return err;
+    }
+
+    let gib = needed as f64 / (1024.0 * 1024.0 * 1024.0);
+    err.context(format!(
+        "ran out of disk space while writing to {dir}, roughly {gib:.1} GiB of free space is needed"
+    ))
+}
+
+#[derive(Copy, Clone)]
+enum Phase {
+    Download,
+    Unpack,
+    Splat,
+}
+
+/// The time spent in the download, unpack, and splat phases of every payload
+#[derive(Default)]
+struct Timings {
+    /// When each phase was first started and last finished by any payload
+    spans: parking_lot::Mutex<[Option<(std::time::Instant, std::time::Instant)>; 3]>,
+    /// The time spent in each phase summed across every payload, which is
+    /// longer than the wall time when payloads are processed in parallel
+    busy: [std::sync::atomic::AtomicU64; 3],
+}
+
+impl Timings {
+    fn record<R>(&self, phase: Phase, op: impl FnOnce() -> R) -> R {
+        let start = std::time::Instant::now();
+        let res = op();
+        let end = std::time::Instant::now();
+
+        self.busy[phase as usize].fetch_add(
+            (end - start).as_nanos() as u64,
+            std::sync::atomic::Ordering::Relaxed,
+        );
+
+        let mut spans = self.spans.lock();
+        let (first, last) = spans[phase as usize].get_or_insert((start, end));
+        *first = (*first).min(start);
+        *last = (*last).max(end);
+
+        res
+    }
+
+    /// Logs the time spent in each phase, as well as the time spent finalizing
+    /// the splat, eg. adding the symlinks, which is done once every payload
+    /// has been processed
+    fn report(&self, start: std::time::Instant, finalize_start: Option<std::time::Instant>) {
+        let spans = self.spans.lock();
+
+        for (i, name) in ["download", "unpack", "splat"].into_iter().enumerate() {
+            let Some((first, last)) = spans[i] else {
+                continue;
+            };
+
+            let busy = std::time::Duration::from_nanos(
+                self.busy[i].load(std::sync::atomic::Ordering::Relaxed),
+            );
+            tracing::info!(
+                "{name}: {:.2?} wall time, {busy:.2?} summed across payloads",
+                last - first
+            );
+        }
+
+        if let Some(finalize_start) = finalize_start {
+            tracing::info!("finalize: {:.2?} wall time", finalize_start.elapsed());
+        }
+
+        tracing::info!("total: {:.2?}", start.elapsed());
+    }
+}
+
+/// Estimates the number of bytes processed by the download, unpack, and splat
+/// phases of a payload. The manifest only has an installed size for packages
+/// with a single payload, and doesn't include the CABs referenced by MSIs, so
+/// this is only ever an approximation.
+fn estimate_work(payload: &crate::Payload) -> [u64; 3] {
+    let installed = payload.install_size.unwrap_or(payload.size);
+    [payload.size, installed, installed]
+}
+
+/// Adjusts the length of the progress bar, which already accounts for
+/// `expected` bytes, to the `actual` number of bytes
+pub(crate) fn adjust_length(progress: &indicatif::ProgressBar, expected: u64, actual: u64) {
+    if actual >= expected {
+        progress.inc_length(actual - expected);
+    } else {
+        let length = progress.length().unwrap_or_default();
+        progress.set_length(length.saturating_sub(expected - actual));
+    }
+}
+
+/// Separates the results of processing each payload from the names of the
+/// payloads that failed, which are only collected with `--keep-going`, otherwise
+/// the first failure is returned
+fn partition_failed<T>(
+    results: Vec<Result<T, Error>>,
+    names: Vec<PathBuf>,
+    keep_going: bool,
+) -> Result<(Vec<T>, Vec<PathBuf>), Error> {
+    let mut succeeded = Vec::with_capacity(results.len());
+    let mut failed = Vec::new();
+    for (result, name) in results.into_iter().zip(names) {
+        match result {
+            Ok(res) => succeeded.push(res),
+            Err(err) if keep_going => {
+                tracing::error!("failed to process {name}: {err:#}");
+                failed.push(name);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok((succeeded, failed))
+}
+
+/// Fails if any payload failed, which is only done once everything that could
+/// be done has been
+fn check_failed(failed: &[PathBuf]) -> Result<(), Error> {
+    anyhow::ensure!(
+        failed.is_empty(),
+        "{} payload(s) failed: {}",
+        failed.len(),
+        failed
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+/// Formats how long ago something happened in the largest whole unit, eg.
+/// `3 days`
+fn format_age(age: std::time::Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+
+    format!("{count} {unit}{}", if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn populates_cache_from_archive() {
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let ctx =
+            Ctx::with_dir(work_dir.clone(), ProgressTarget::Hidden, ureq::agent(), 0).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in [
+            ("./dl/a.cab", "cab"),
+            ("unpack/b/.unpack", "unpack"),
+            ("./other/c.txt", "other"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            // set_path strips the leading `./`, so write the name as is
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, contents.as_bytes()).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+
+        assert_eq!(ctx.populate_cache(archive.as_slice()).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(work_dir.join("dl/a.cab")).unwrap(),
+            "cab"
+        );
+        assert!(work_dir.join("unpack/b/.unpack").exists());
+        assert!(!work_dir.join("other").exists());
+    }
+
+    #[test]
+    fn verified_sidecar() {
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let mut ctx =
+            Ctx::with_dir(work_dir.clone(), ProgressTarget::Hidden, ureq::agent(), 0).unwrap();
+        ctx.offline = true;
+
+        // The sidecar records a checksum that doesn't match the contents, so
+        // the contents are only returned if the file isn't hashed again
+        let cache_path = work_dir.join("dl/a.cab");
+        std::fs::write(&cache_path, "actual").unwrap();
+        let expected = Sha256::digest(b"expected");
+        Verified::write(&cache_path, expected.clone());
+
+        let get = |ctx: &Ctx| {
+            ctx.get_and_validate(
+                "https://example.com/a.cab",
+                &"a.cab",
+                Some(expected.clone()),
+                None,
+                indicatif::ProgressBar::hidden(),
+            )
+        };
+
+        assert_eq!(get(&ctx).unwrap(), "actual");
+
+        // A different checksum isn't covered by the sidecar
+        assert!(ctx
+            .get_and_validate(
+                "https://example.com/a.cab",
+                &"a.cab",
+                Some(Sha256::digest(b"other")),
+                None,
+                indicatif::ProgressBar::hidden(),
+            )
+            .is_err());
+
+        // Nor is the file once it has changed
+        std::fs::write(&cache_path, "changed").unwrap();
+        assert!(get(&ctx).is_err());
+
+        // And --verify ignores it entirely
+        std::fs::write(&cache_path, "actual").unwrap();
+        Verified::write(&cache_path, expected.clone());
+        assert_eq!(get(&ctx).unwrap(), "actual");
+        ctx.verify = true;
+        assert!(get(&ctx).is_err());
+    }
+
+    #[test]
+    fn keep_going() {
+        let results = || {
+            vec![
+                Ok(1),
+                Err(anyhow::anyhow!("a failed")),
+                Ok(3),
+                Err(anyhow::anyhow!("b failed")),
+            ]
+        };
+        let names = || -> Vec<PathBuf> {
+            ["a.vsix", "b.msi", "c.vsix", "d.msi"]
+                .map(PathBuf::from)
+                .into()
+        };
+
+        // The first failure is returned as is
+        let err = partition_failed(results(), names(), false).unwrap_err();
+        assert_eq!(err.to_string(), "a failed");
+
+        // Or every payload is processed and the failures collected
+        let (succeeded, failed) = partition_failed(results(), names(), true).unwrap();
+        assert_eq!(succeeded, [1, 3]);
+        assert_eq!(failed, ["b.msi", "d.msi"]);
+        assert_eq!(
+            check_failed(&failed).unwrap_err().to_string(),
+            "2 payload(s) failed: b.msi, d.msi"
+        );
+
+        check_failed(&[]).unwrap();
+    }
+
+    #[test]
+    fn conditional_fallback() {
+        use std::io::{Read as _, Write as _};
+
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let ctx =
+            Ctx::with_dir(work_dir.clone(), ProgressTarget::Hidden, ureq::agent(), 0).unwrap();
+        std::fs::write(work_dir.join("dl/manifest.json"), "cached").unwrap();
+
+        let get = |url: &str| {
+            ctx.get_conditional(url, &"manifest.json", indicatif::ProgressBar::hidden())
+        };
+
+        // Nothing is listening, which may not be the case later
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert_eq!(get(&format!("http://{addr}/channel")).unwrap(), "cached");
+
+        // But the url no longer existing won't change
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4096];
+            let _ = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+        });
+
+        let err = get(&format!("http://{addr}/channel")).unwrap_err();
+        server.join().unwrap();
+        assert_eq!(
+            crate::util::ErrorKind::of(&err),
+            crate::util::ErrorKind::Other
+        );
+
+        assert_eq!(format_age(std::time::Duration::from_secs(59)), "0 minutes");
+        assert_eq!(format_age(std::time::Duration::from_secs(3600)), "1 hour");
+        assert_eq!(
+            format_age(std::time::Duration::from_secs(3 * 86400 + 5)),
+            "3 days"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{util::Sha256, Path, PathBuf};
+use anyhow::{Context as _, Error};
+use std::collections::BTreeMap;
+
+/// A single entry in a splat tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// A regular file and the hash of its contents
+    File(Sha256),
+    /// A symlink and the path it points to
+    Symlink(PathBuf),
+}
+
+/// A single difference between two splat trees
+#[derive(Debug)]
+pub enum Difference {
+    /// The path is only present in the second tree
+    Added(Entry),
+    /// The path is only present in the first tree
+    Removed(Entry),
+    /// The path is present in both trees, but with different contents or
+    /// link targets, or changed from a file to a symlink or vice versa
+    Changed { old: Entry, new: Entry },
+}
+
+/// The differences between two splat trees, keyed by their path relative to
+/// the root of each tree
+pub struct TreeDiff {
+    pub differences: BTreeMap<PathBuf, Difference>,
+}
+
+fn gather(root: &Path) -> Result<BTreeMap<PathBuf, Entry>, Error> {
+    use rayon::prelude::*;
+
+    let mut entries = Vec::new();
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("failed to walk {root}"))?;
+
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let path = PathBuf::from_path_buf(entry.path().to_owned())
+            .map_err(|pb| anyhow::anyhow!("path {} is not utf-8", pb.display()))?;
+
+        let link = if entry.path_is_symlink() {
+            let link =
+                std::fs::read_link(&path).with_context(|| format!("failed to read link {path}"))?;
+            Some(
+                PathBuf::from_path_buf(link)
+                    .map_err(|pb| anyhow::anyhow!("link {} is not utf-8", pb.display()))?,
+            )
+        } else {
+            None
+        };
+
+        entries.push((path, link));
+    }
+
+    entries
+        .into_par_iter()
+        .map(|(path, link)| -> Result<(PathBuf, Entry), Error> {
+            let entry = match link {
+                Some(link) => Entry::Symlink(link),
+                None => Entry::File(
+                    std::fs::File::open(&path)
+                        .and_then(Sha256::digest_reader)
+                        .with_context(|| format!("failed to read {path}"))?,
+                ),
+            };
+
+            let rel_path = path
+                .strip_prefix(root)
+                .with_context(|| format!("{path} is not a child of {root}"))?
+                .to_owned();
+
+            Ok((rel_path, entry))
+        })
+        .collect()
+}
+
+/// Walks both trees, hashing every file and reading every symlink, and
+/// returns the set of paths that were added, removed, or changed going
+/// from `old` to `new`
+pub fn diff_trees(old: &Path, new: &Path) -> Result<TreeDiff, Error> {
+    let (old_entries, new_entries) = rayon::join(|| gather(old), || gather(new));
+    let mut old_entries = old_entries?;
+    let new_entries = new_entries?;
+
+    let mut differences = BTreeMap::new();
+
+    for (path, new) in new_entries {
+        match old_entries.remove(&path) {
+            Some(old) => {
+                if old != new {
+                    differences.insert(path, Difference::Changed { old, new });
+                }
+            }
+            None => {
+                differences.insert(path, Difference::Added(new));
+            }
+        }
+    }
+
+    for (path, old) in old_entries {
+        differences.insert(path, Difference::Removed(old));
+    }
+
+    Ok(TreeDiff { differences })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn differences() {
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let (old, new) = (root.join("old"), root.join("new"));
+
+        for (tree, files) in [
+            (
+                &old,
+                &[
+                    ("same.h", "same"),
+                    ("changed.h", "old"),
+                    ("removed.h", "removed"),
+                ],
+            ),
+            (
+                &new,
+                &[
+                    ("same.h", "same"),
+                    ("changed.h", "new"),
+                    ("added.h", "added"),
+                ],
+            ),
+        ] {
+            std::fs::create_dir_all(tree.join("include")).unwrap();
+            for (name, contents) in files {
+                std::fs::write(tree.join("include").join(name), contents).unwrap();
+            }
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("same.h", old.join("include/Same.h")).unwrap();
+            std::os::unix::fs::symlink("added.h", new.join("include/Same.h")).unwrap();
+        }
+
+        let diff = diff_trees(&old, &new).unwrap();
+        let file = |contents: &str| Entry::File(Sha256::digest(contents.as_bytes()));
+
+        assert!(matches!(
+            &diff.differences[Path::new("include/added.h")],
+            Difference::Added(new) if *new == file("added")
+        ));
+        assert!(matches!(
+            &diff.differences[Path::new("include/removed.h")],
+            Difference::Removed(old) if *old == file("removed")
+        ));
+        assert!(matches!(
+            &diff.differences[Path::new("include/changed.h")],
+            Difference::Changed { old, new } if *old == file("old") && *new == file("new")
+        ));
+        assert!(!diff.differences.contains_key(Path::new("include/same.h")));
+
+        #[cfg(unix)]
+        {
+            assert!(matches!(
+                &diff.differences[Path::new("include/Same.h")],
+                Difference::Changed {
+                    old: Entry::Symlink(old),
+                    new: Entry::Symlink(new),
+                } if old == "same.h" && new == "added.h"
+            ));
+            assert_eq!(diff.differences.len(), 4);
+        }
+    }
+}
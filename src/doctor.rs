@@ -0,0 +1,193 @@
+use crate::{Arch, Path, PathBuf};
+use anyhow::{Context as _, Error};
+
+/// A tiny program that needs both the SDK headers and libraries
+const TEST_PROGRAM: &str = r#"#include <windows.h>
+
+int main(void) {
+    return GetCurrentProcessId() == 0;
+}
+"#;
+
+/// The outcome of compiling and linking a test program against a splat
+pub struct DoctorReport {
+    /// The full `clang-cl` command line that was used, so that it can be
+    /// reproduced
+    pub command: String,
+    /// Whether the test program was successfully compiled and linked
+    pub success: bool,
+    /// The combined stdout and stderr from `clang-cl`
+    pub output: String,
+}
+
+/// Compiles and links a program that includes `windows.h` and calls a function
+/// from `kernel32.lib` with `clang-cl` and `lld-link`, using only the headers
+/// and libraries from the splat directory, to check that it is actually usable.
+///
+/// Only the default splat layout is supported, ie. `<root>/crt` and `<root>/sdk`.
+pub fn doctor(root: &Path, target: &str, arch: Arch) -> Result<DoctorReport, Error> {
+    let root = crate::util::canonicalize(root)?;
+    let roots = crate::splat::SplatRoots::default_layout(&root);
+
+    anyhow::ensure!(
+        roots.crt.exists() && roots.sdk.exists(),
+        "{root} doesn't contain the 'crt' and 'sdk' directories of the default splat layout"
+    );
+
+    // The architecture directories depend on whether --preserve-ms-arch-notation
+    // was used when splatting
+    let layout = roots.detect_layout(Some(arch))?;
+
+    anyhow::ensure!(
+        crate::splat::resolve_include(&root, "windows.h").is_some(),
+        "windows.h can't be resolved in {}/include, the splat doesn't have the SDK headers",
+        roots.sdk
+    );
+
+    for tool in ["clang-cl", "lld-link"] {
+        anyhow::ensure!(
+            crate::util::find_in_path(tool).is_some(),
+            "unable to find '{tool}' in PATH"
+        );
+    }
+
+    let td = tempfile::tempdir().context("unable to create temporary directory")?;
+    let td_path = PathBuf::from_path_buf(td.path().to_owned())
+        .map_err(|pb| anyhow::anyhow!("temp dir {} is not utf-8", pb.display()))?;
+
+    let src = td_path.join("doctor.c");
+    std::fs::write(&src, TEST_PROGRAM).with_context(|| format!("unable to write {src}"))?;
+
+    let args = clang_cl_args(&roots, &layout, target, arch, &td_path);
+    let command = display_command(&args);
+
+    let output = std::process::Command::new("clang-cl")
+        .args(&args)
+        .output()
+        .context("unable to run clang-cl")?;
+
+    let mut combined = String::from_utf8_lossy(&output.stdout).into_owned();
+    combined.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    Ok(DoctorReport {
+        command,
+        success: output.status.success(),
+        output: combined,
+    })
+}
+
+/// The `clang-cl` arguments for compiling the test program in the directory,
+/// which only use the include and library directories of the splat
+fn clang_cl_args(
+    roots: &crate::splat::SplatRoots,
+    layout: &crate::splat::DirLayout<'_>,
+    target: &str,
+    arch: Arch,
+    dir: &Path,
+) -> Vec<String> {
+    let mut args = vec![
+        format!("--target={target}"),
+        "/nologo".to_owned(),
+        "-fuse-ld=lld-link".to_owned(),
+    ];
+
+    args.extend(
+        roots
+            .include_dirs(layout)
+            .into_iter()
+            .filter(|dir| dir.exists())
+            .map(|dir| format!("/imsvc{dir}")),
+    );
+    args.extend([
+        dir.join("doctor.c").to_string(),
+        format!("/Fo{}", dir.join("doctor.obj")),
+        format!("/Fe{}", dir.join("doctor.exe")),
+        "/link".to_owned(),
+    ]);
+    args.extend(
+        roots
+            .lib_dirs(layout, arch)
+            .iter()
+            .map(|dir| format!("/libpath:{dir}")),
+    );
+    args.push("kernel32.lib".to_owned());
+    args
+}
+
+/// The command line that can be copied to run `clang-cl` with the arguments
+fn display_command(args: &[String]) -> String {
+    std::iter::once("clang-cl")
+        .chain(args.iter().map(String::as_str))
+        .map(|arg| {
+            if arg.contains(' ') {
+                format!("\"{arg}\"")
+            } else {
+                arg.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clang_cl_command() {
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned())
+            .unwrap()
+            .join("my splat");
+        let roots = crate::splat::SplatRoots::default_layout(&root);
+
+        for dir in [
+            "crt/include",
+            "crt/lib/arm64",
+            "sdk/include/ucrt",
+            "sdk/include/um",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let layout = roots.detect_layout(Some(Arch::Aarch64)).unwrap();
+        let args = clang_cl_args(
+            &roots,
+            &layout,
+            "aarch64-pc-windows-msvc",
+            Arch::Aarch64,
+            Path::new("/tmp/doctor"),
+        );
+
+        // Only the include directories that exist are passed, but every
+        // library directory is, in the detected arch notation
+        assert_eq!(
+            args,
+            [
+                "--target=aarch64-pc-windows-msvc".to_owned(),
+                "/nologo".to_owned(),
+                "-fuse-ld=lld-link".to_owned(),
+                format!("/imsvc{root}/crt/include"),
+                format!("/imsvc{root}/sdk/include/ucrt"),
+                format!("/imsvc{root}/sdk/include/um"),
+                "/tmp/doctor/doctor.c".to_owned(),
+                "/Fo/tmp/doctor/doctor.obj".to_owned(),
+                "/Fe/tmp/doctor/doctor.exe".to_owned(),
+                "/link".to_owned(),
+                format!("/libpath:{root}/crt/lib/arm64"),
+                format!("/libpath:{root}/sdk/lib/um/arm64"),
+                format!("/libpath:{root}/sdk/lib/ucrt/arm64"),
+                "kernel32.lib".to_owned(),
+            ]
+        );
+
+        // Arguments with spaces are quoted so the command can be copied
+        let command = display_command(&args);
+        assert!(command.starts_with(
+            "clang-cl --target=aarch64-pc-windows-msvc /nologo -fuse-ld=lld-link \"/imsvc"
+        ));
+        assert!(command.ends_with(&format!(
+            "\"/libpath:{root}/sdk/lib/ucrt/arm64\" kernel32.lib"
+        )));
+    }
+}
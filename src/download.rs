@@ -8,7 +8,6 @@ struct Cab {
     filename: PathBuf,
     sha256: Sha256,
     url: String,
-    #[allow(dead_code)]
     size: u64,
 }
 
@@ -16,6 +15,10 @@ pub(crate) struct CabContents {
     pub(crate) path: PathBuf,
     pub(crate) content: bytes::Bytes,
     pub(crate) sequence: u32,
+    /// The url and checksum, so the CAB can be downloaded again if it fails
+    /// to extract
+    pub(crate) url: String,
+    pub(crate) sha256: Sha256,
 }
 
 pub(crate) enum PayloadContents {
@@ -32,42 +35,21 @@ pub(crate) fn download(
     item: &crate::WorkItem,
 ) -> Result<PayloadContents, Error> {
     item.progress.set_message("📥 downloading..");
+    ensure_sized(&item.payload)?;
 
     let contents = ctx.get_and_validate(
         &item.payload.url,
         &item.payload.filename,
         Some(item.payload.sha256.clone()),
+        Some(item.payload.size),
         item.progress.clone(),
     )?;
 
+    ensure_not_empty(&item.payload, &contents)?;
+
     let pc = match item.payload.filename.extension() {
         Some("msi") => {
-            let cabs: Vec<_> = match pkgs.values().find(|mi| {
-                mi.payloads
-                    .iter()
-                    .any(|mi_payload| mi_payload.sha256 == item.payload.sha256)
-            }) {
-                Some(mi) => mi
-                    .payloads
-                    .iter()
-                    .filter(|pay| pay.file_name.ends_with(".cab"))
-                    .map(|pay| Cab {
-                        filename: pay
-                            .file_name
-                            .strip_prefix("Installers\\")
-                            .unwrap_or(&pay.file_name)
-                            .into(),
-                        sha256: pay.sha256.clone(),
-                        url: pay.url.clone(),
-                        size: pay.size,
-                    })
-                    .collect(),
-                None => anyhow::bail!(
-                    "unable to find manifest parent for {}",
-                    item.payload.filename
-                ),
-            };
-
+            let cabs = manifest_cabs(&pkgs, &item.payload)?;
             download_cabs(ctx, &cabs, item, contents)
         }
         Some("vsix") => Ok(PayloadContents::Vsix(contents)),
@@ -79,25 +61,156 @@ pub(crate) fn download(
     pc
 }
 
-/// Each SDK MSI has 1 or more cab files associated with it containing the actual
-/// data we need that must be downloaded separately and indexed from the MSI
-fn download_cabs(
-    ctx: Arc<Ctx>,
-    cabs: &[Cab],
-    msi: &crate::WorkItem,
-    msi_content: bytes::Bytes,
+/// The async equivalent of [`download`], which downloads the payload and, for
+/// MSIs, the CABs it references, with the reqwest client
+#[cfg(feature = "async")]
+pub(crate) async fn download_async(
+    ctx: &Ctx,
+    client: &reqwest::Client,
+    pkgs: &std::collections::BTreeMap<String, manifest::ManifestItem>,
+    item: &crate::WorkItem,
 ) -> Result<PayloadContents, Error> {
-    use rayon::prelude::*;
+    use futures::{StreamExt as _, TryStreamExt as _};
+
+    item.progress.set_message("📥 downloading..");
+    ensure_sized(&item.payload)?;
+
+    let contents = ctx
+        .get_and_validate_async(
+            client,
+            &item.payload.url,
+            &item.payload.filename,
+            Some(item.payload.sha256.clone()),
+            Some(item.payload.size),
+            item.progress.clone(),
+        )
+        .await?;
+
+    ensure_not_empty(&item.payload, &contents)?;
+
+    let pc = match item.payload.filename.extension() {
+        Some("msi") => {
+            let cabs = manifest_cabs(pkgs, &item.payload)?;
+            let cab_files = msi_cab_files(contents.clone(), &item.payload.filename, &cabs)?;
+
+            let concurrency = ctx.cab_download_threads.unwrap_or(cab_files.len()).max(1);
 
-    let msi_filename = &msi.payload.filename;
+            let cabs = futures::stream::iter(cab_files)
+                .map(|(cab_name, chksum, url, sequence)| async move {
+                    let content = ctx
+                        .get_and_validate_async(
+                            client,
+                            &url,
+                            &cab_name,
+                            Some(chksum.clone()),
+                            None,
+                            item.progress.clone(),
+                        )
+                        .await?;
+
+                    Ok::<_, Error>(CabContents {
+                        path: cab_name,
+                        content,
+                        sequence,
+                        url,
+                        sha256: chksum,
+                    })
+                })
+                .buffered(concurrency)
+                .try_collect()
+                .await?;
+
+            Ok(PayloadContents::Msi {
+                msi: contents,
+                cabs,
+            })
+        }
+        Some("vsix") => Ok(PayloadContents::Vsix(contents)),
+        ext => anyhow::bail!("unknown extension {ext:?}"),
+    };
+
+    item.progress.finish_with_message("downloaded");
+
+    pc
+}
+
+/// Every payload we care about has actual content, so a size of 0 means the
+/// manifest entry is a placeholder or otherwise bogus, and would only result
+/// in a confusing failure later when splatting
+fn ensure_sized(payload: &crate::Payload) -> Result<(), Error> {
+    anyhow::ensure!(
+        payload.size > 0,
+        "payload {} ({}) has a size of 0 in the manifest",
+        payload.filename,
+        payload.url
+    );
+    Ok(())
+}
+
+fn ensure_not_empty(payload: &crate::Payload, contents: &[u8]) -> Result<(), Error> {
+    anyhow::ensure!(
+        !contents.is_empty(),
+        "payload {} ({}) was empty",
+        payload.filename,
+        payload.url
+    );
+    Ok(())
+}
+
+/// Finds the manifest item that an MSI payload belongs to, which also lists
+/// the CAB files the MSI references
+pub(crate) fn msi_parent<'pkgs>(
+    pkgs: &'pkgs std::collections::BTreeMap<String, manifest::ManifestItem>,
+    payload: &crate::Payload,
+) -> Option<&'pkgs manifest::ManifestItem> {
+    pkgs.values().find(|mi| {
+        mi.payloads
+            .iter()
+            .any(|mi_payload| mi_payload.sha256 == payload.sha256)
+    })
+}
 
-    let mut msi_pkg = msi::Package::open(std::io::Cursor::new(msi_content.clone()))
+/// The CAB payloads listed in the manifest item that the MSI belongs to, which
+/// is a superset of the CABs the MSI actually references
+fn manifest_cabs(
+    pkgs: &std::collections::BTreeMap<String, manifest::ManifestItem>,
+    payload: &crate::Payload,
+) -> Result<Vec<Cab>, Error> {
+    let Some(mi) = msi_parent(pkgs, payload) else {
+        anyhow::bail!("unable to find manifest parent for {}", payload.filename);
+    };
+
+    Ok(mi
+        .payloads
+        .iter()
+        .filter(|pay| pay.file_name.ends_with(".cab"))
+        .map(|pay| Cab {
+            filename: pay
+                .file_name
+                .strip_prefix("Installers\\")
+                .unwrap_or(&pay.file_name)
+                .into(),
+            sha256: pay.sha256.clone(),
+            url: pay.url.clone(),
+            size: pay.size,
+        })
+        .collect())
+}
+
+/// Reads the CABs the MSI references from its `Media` table, along with the
+/// last sequence number of the files in each one
+fn media_cabs<'cabs>(
+    msi_content: bytes::Bytes,
+    msi_filename: &crate::Path,
+    cabs: &'cabs [Cab],
+) -> Result<Vec<(&'cabs Cab, u32)>, Error> {
+    let mut msi_pkg = msi::Package::open(std::io::Cursor::new(msi_content))
         .with_context(|| format!("invalid MSI for {}", msi_filename))?;
 
     // The `Media` table contains the list of cabs by name, which we then need
     // to lookup in the list of payloads.
     // Columns: [DiskId, LastSequence, DiskPrompt, Cabinet, VolumeLabel, Source]
-    let cab_files: Vec<_> = msi_pkg
+    let media_cabs = msi_pkg
         .select_rows(msi::Select::table("Media"))
         .with_context(|| format!("{} does not contain a list of CAB files", msi_filename))?
         .filter_map(|row| {
@@ -116,19 +229,9 @@ fn download_cabs(
                     .and_then(|(name, seq)| {
                         let cab_name = name.trim_matches('"');
 
-                        cabs.iter().find_map(|payload| {
-                            (payload.filename == cab_name).then(|| {
-                                (
-                                    PathBuf::from(format!(
-                                        "{}/{cab_name}",
-                                        msi_filename.file_stem().unwrap(),
-                                    )),
-                                    payload.sha256.clone(),
-                                    payload.url.clone(),
-                                    seq,
-                                )
-                            })
-                        })
+                        cabs.iter()
+                            .find(|payload| payload.filename == cab_name)
+                            .map(|payload| (payload, seq))
                     })
             } else {
                 None
@@ -136,16 +239,82 @@ fn download_cabs(
         })
         .collect();
 
+    Ok(media_cabs)
+}
+
+/// The cache path, checksum, url, and last sequence number of each CAB the MSI
+/// references
+fn msi_cab_files(
+    msi_content: bytes::Bytes,
+    msi_filename: &crate::Path,
+    cabs: &[Cab],
+) -> Result<Vec<(PathBuf, Sha256, String, u32)>, Error> {
+    let media_cabs = media_cabs(msi_content, msi_filename, cabs)?;
+
+    // Only the CABs the MSI actually references matter, the manifest item can
+    // list others that are never downloaded
+    if let Some((cab, _)) = media_cabs.iter().find(|(cab, _)| cab.size == 0) {
+        anyhow::bail!(
+            "CAB {} ({}) for {msi_filename} has a size of 0 in the manifest",
+            cab.filename,
+            cab.url
+        );
+    }
+
+    Ok(media_cabs
+        .into_iter()
+        .map(|(cab, seq)| {
+            (
+                PathBuf::from(format!(
+                    "{}/{}",
+                    msi_filename.file_stem().unwrap(),
+                    cab.filename
+                )),
+                cab.sha256.clone(),
+                cab.url.clone(),
+                seq,
+            )
+        })
+        .collect())
+}
+
+/// Each SDK MSI has 1 or more cab files associated with it containing the actual
+/// data we need that must be downloaded separately and indexed from the MSI
+fn download_cabs(
+    ctx: Arc<Ctx>,
+    cabs: &[Cab],
+    msi: &crate::WorkItem,
+    msi_content: bytes::Bytes,
+) -> Result<PayloadContents, Error> {
+    use rayon::prelude::*;
+
+    let cab_files = msi_cab_files(msi_content.clone(), &msi.payload.filename, cabs)?;
+
+    // The CABs are downloaded in at most `cab_download_threads` sequential
+    // chunks, as otherwise every CAB of every MSI that is being downloaded can
+    // have a connection open at the same time
+    let min_len = ctx
+        .cab_download_threads
+        .map_or(1, |threads| cab_files.len().div_ceil(threads));
+
     let cabs = cab_files
         .into_par_iter()
+        .with_min_len(min_len)
         .map(
             |(cab_name, chksum, url, sequence)| -> Result<CabContents, Error> {
-                let cab_contents =
-                    ctx.get_and_validate(url, &cab_name, Some(chksum), msi.progress.clone())?;
+                let cab_contents = ctx.get_and_validate(
+                    &url,
+                    &cab_name,
+                    Some(chksum.clone()),
+                    None,
+                    msi.progress.clone(),
+                )?;
                 Ok(CabContents {
                     path: cab_name,
                     content: cab_contents,
                     sequence,
+                    url,
+                    sha256: chksum,
                 })
             },
         )
@@ -156,3 +325,64 @@ fn download_cabs(
         cabs,
     })
 }
+
+/// A CAB file referenced by an MSI payload
+#[derive(serde::Serialize)]
+pub struct MsiCab {
+    /// The MSI payload that references the CAB
+    pub msi: PathBuf,
+    /// The file name of the CAB
+    pub name: PathBuf,
+    pub url: String,
+    pub sha256: Sha256,
+    /// The size of the CAB in bytes
+    pub size: u64,
+}
+
+/// Downloads only the MSI payloads and reads the CAB files each one references
+/// from its `Media` table, without downloading the CABs themselves, eg. to
+/// determine the full size of a download for a mirror. VSIX payloads don't
+/// reference any CABs and are skipped.
+pub fn list_cabs(
+    ctx: &Ctx,
+    pkgs: &std::collections::BTreeMap<String, manifest::ManifestItem>,
+    items: &[crate::WorkItem],
+) -> Result<Vec<MsiCab>, Error> {
+    use rayon::prelude::*;
+
+    let listed = items
+        .par_iter()
+        .filter(|item| item.payload.filename.extension() == Some("msi"))
+        .map(|item| -> Result<Vec<MsiCab>, Error> {
+            item.progress.set_message("📥 downloading..");
+            let contents = ctx.get_and_validate(
+                &item.payload.url,
+                &item.payload.filename,
+                Some(item.payload.sha256.clone()),
+                Some(item.payload.size),
+                item.progress.clone(),
+            )?;
+
+            let cabs = manifest_cabs(pkgs, &item.payload)?;
+            let msi_cabs = media_cabs(contents, &item.payload.filename, &cabs)?
+                .into_iter()
+                .map(|(cab, _seq)| MsiCab {
+                    msi: item.payload.filename.clone(),
+                    name: cab.filename.clone(),
+                    url: cab.url.clone(),
+                    sha256: cab.sha256.clone(),
+                    size: cab.size,
+                })
+                .collect();
+
+            item.progress.finish_with_message("listed");
+            Ok(msi_cabs)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Payloads are listed in parallel, so sort to keep the output stable
+    let mut cabs: Vec<_> = listed.into_iter().flatten().collect();
+    cabs.sort_by(|a, b| a.msi.cmp(&b.msi).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(cabs)
+}
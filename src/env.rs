@@ -0,0 +1,88 @@
+use crate::{Arch, Path, PathBuf};
+use anyhow::Error;
+
+/// The directories of a splat needed to compile and link for an architecture
+pub struct SplatDirs {
+    /// The include directories, in the order clang-cl searches them
+    pub include_dirs: Vec<PathBuf>,
+    /// The directories containing the CRT, SDK, and UCRT libraries
+    pub lib_dirs: Vec<PathBuf>,
+}
+
+/// Finds the include and library directories of an existing splat for an
+/// architecture, eg. to pass them to the compiler and linker explicitly.
+///
+/// Only the default splat layout is supported, ie. `<root>/crt` and
+/// `<root>/sdk`, and only the directories that actually exist are returned.
+pub fn splat_dirs(root: &Path, arch: Arch) -> Result<SplatDirs, Error> {
+    let root = crate::util::canonicalize(root)?;
+    let roots = crate::splat::SplatRoots::default_layout(&root);
+
+    anyhow::ensure!(
+        roots.crt.exists() && roots.sdk.exists(),
+        "{root} doesn't contain the 'crt' and 'sdk' directories of the default splat layout"
+    );
+
+    // The architecture directories depend on whether --preserve-ms-arch-notation
+    // was used when splatting
+    let layout = roots.detect_layout(Some(arch))?;
+
+    Ok(SplatDirs {
+        include_dirs: roots
+            .include_dirs(&layout)
+            .into_iter()
+            .filter(|dir| dir.exists())
+            .collect(),
+        lib_dirs: roots
+            .lib_dirs(&layout, arch)
+            .into_iter()
+            .filter(|dir| dir.exists())
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn existing_dirs() {
+        let td = tempfile::tempdir().unwrap();
+        let root =
+            crate::util::canonicalize(&PathBuf::from_path_buf(td.path().to_owned()).unwrap())
+                .unwrap();
+
+        std::fs::create_dir_all(root.join("crt")).unwrap();
+        assert!(splat_dirs(&root, Arch::X86_64).is_err());
+
+        for dir in [
+            "crt/include",
+            "crt/lib/x86_64",
+            "sdk/include/ucrt",
+            "sdk/include/um",
+            "sdk/include/shared",
+            "sdk/lib/um/x86_64",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        // The CRT libraries for the architecture must exist
+        assert!(splat_dirs(&root, Arch::Aarch64).is_err());
+
+        // Only the directories that exist are returned, in search order
+        let dirs = splat_dirs(&root, Arch::X86_64).unwrap();
+        assert_eq!(
+            dirs.include_dirs,
+            [
+                root.join("crt/include"),
+                root.join("sdk/include/ucrt"),
+                root.join("sdk/include/shared"),
+                root.join("sdk/include/um"),
+            ]
+        );
+        assert_eq!(
+            dirs.lib_dirs,
+            [root.join("crt/lib/x86_64"), root.join("sdk/lib/um/x86_64")]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{splat::SplatRoots, Arch, Path};
+use anyhow::{Context as _, Error};
+use std::fmt::Write as _;
+
+/// Quotes a string for GN, which only needs `"`, `$`, and `\` to be escaped
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        if matches!(c, '"' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes a GN args file, eg. `args.gn`, that points the Windows toolchain of
+/// Chromium and other GN based projects at the splat, via the
+/// `visual_studio_path` and `windows_sdk_path` args
+///
+/// Only the /winsysroot layout is supported, as GN expects the same layout as
+/// a Visual Studio installation.
+pub(crate) fn emit_gn_args(
+    path: &Path,
+    roots: &SplatRoots,
+    arches: u32,
+    crt_version: &str,
+    sdk_version: &str,
+) -> Result<(), Error> {
+    let mut args = String::new();
+
+    writeln!(args, "# Generated by xwin {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(args, "# CRT version: {crt_version}")?;
+    writeln!(args)?;
+    writeln!(args, "target_os = \"win\"")?;
+
+    // GN uses the same architecture names as MS, but can only target one of
+    // them at a time
+    let mut iter = Arch::iter(arches);
+    match (iter.next(), iter.next()) {
+        (Some(arch), None) => {
+            writeln!(args, "target_cpu = {}", quote(arch.as_ms_str()))?;
+        }
+        _ => {
+            writeln!(
+                args,
+                "# target_cpu is one of {}",
+                Arch::iter(arches)
+                    .map(|arch| quote(arch.as_ms_str()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+        }
+    }
+
+    writeln!(args)?;
+    writeln!(args, "visual_studio_path = {}", quote(roots.root.as_str()))?;
+    writeln!(args, "windows_sdk_path = {}", quote(roots.sdk.as_str()))?;
+    writeln!(args, "windows_sdk_version = {}", quote(sdk_version))?;
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("unable to create {parent}"))?;
+    }
+
+    std::fs::write(path, args).with_context(|| format!("unable to write {path}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn args_file() {
+        let td = tempfile::tempdir().unwrap();
+        let dir = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = SplatRoots::default_layout(Path::new("/build/$out/winsdk"));
+        let path = dir.join("out/args.gn");
+
+        emit_gn_args(
+            &path,
+            &roots,
+            Arch::Aarch64 as u32,
+            "14.38.17.8",
+            "10.0.22621",
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!(
+                r#"# Generated by xwin {}
+# CRT version: 14.38.17.8
+
+target_os = "win"
+target_cpu = "arm64"
+
+visual_studio_path = "/build/\$out/winsdk"
+windows_sdk_path = "/build/\$out/winsdk/sdk"
+windows_sdk_version = "10.0.22621"
+"#,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        // Only one architecture can be targeted at a time
+        emit_gn_args(
+            &path,
+            &roots,
+            Arch::X86 as u32 | Arch::X86_64 as u32,
+            "14.38.17.8",
+            "10.0.22621",
+        )
+        .unwrap();
+
+        let args = std::fs::read_to_string(&path).unwrap();
+        assert!(args.contains("\n# target_cpu is one of \"x86\", \"x64\"\n"));
+        assert!(!args.contains("\ntarget_cpu"));
+    }
+}
@@ -0,0 +1,451 @@
+use crate::{Arch, Path, PathBuf, PayloadKind, Variant};
+use anyhow::{Context as _, Error};
+
+/// The locally installed CRT and SDK to create a splat from
+pub struct ImportConfig {
+    /// The Visual Studio installation directory, containing `VC/Tools/MSVC`
+    pub vs_dir: PathBuf,
+    /// The `Windows Kits/10` directory, defaults to `<vs_dir>/Windows Kits/10`
+    pub sdk_dir: Option<PathBuf>,
+    /// The CRT version to use, which can be a prefix, eg. `14.38`, defaults to
+    /// the latest installed version
+    pub crt_version: Option<String>,
+    /// The SDK version to use, which can be a prefix, eg. `10.0.22621`,
+    /// defaults to the latest installed version
+    pub sdk_version: Option<String>,
+    /// The architectures to import the libraries for
+    pub arches: u32,
+    /// Whether to import the Active Template Library (ATL) as well
+    pub include_atl: bool,
+    /// Whether to use the MS arch notation for the library directories
+    pub preserve_ms_arch_notation: bool,
+    /// The root output directory
+    pub output: PathBuf,
+    /// Imports to the output even if it contains files that weren't created
+    /// by xwin
+    pub allow_non_empty_output: bool,
+}
+
+/// Finds the first of the directory names that exists, as the casing of the
+/// SDK directories depends on where the installation came from, eg. the
+/// /winsysroot layout uses lowercase names
+fn find_dir(parent: &Path, names: &[&str]) -> Result<PathBuf, Error> {
+    names
+        .iter()
+        .map(|name| parent.join(name))
+        .find(|dir| dir.is_dir())
+        .with_context(|| format!("unable to find any of {names:?} in {parent}"))
+}
+
+/// Finds the latest version directory in `dir`, optionally restricted to the
+/// versions that start with `requested`
+fn find_version(dir: &Path, requested: Option<&str>) -> Result<String, Error> {
+    let parse = |version: &str| -> Option<Vec<u32>> {
+        version.split('.').map(|part| part.parse().ok()).collect()
+    };
+
+    let mut versions = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("unable to read {dir}"))? {
+        let entry = entry.with_context(|| format!("unable to read {dir}"))?;
+        let Some(name) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+
+        if !entry.path().is_dir() || requested.is_some_and(|req| !name.starts_with(req)) {
+            continue;
+        }
+
+        if let Some(parsed) = parse(&name) {
+            versions.push((parsed, name));
+        }
+    }
+
+    versions
+        .into_iter()
+        .max()
+        .map(|(_, name)| name)
+        .with_context(|| match requested {
+            Some(req) => format!("unable to find version '{req}' in {dir}"),
+            None => format!("unable to find any versions in {dir}"),
+        })
+}
+
+/// Copies the files in `src` to `dst`, recursively if specified
+fn copy_dir(src: &Path, dst: &Path, recursive: bool) -> Result<u32, Error> {
+    let mut copied = 0;
+
+    for entry in walkdir::WalkDir::new(src)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .sort_by_file_name()
+    {
+        let entry = entry.with_context(|| format!("failed to walk {src}"))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = Path::from_path(entry.path())
+            .with_context(|| format!("path {} is not utf-8", entry.path().display()))?;
+        let tar = dst.join(path.strip_prefix(src)?);
+
+        if let Some(parent) = tar.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("unable to create {parent}"))?;
+        }
+
+        std::fs::copy(path, &tar).with_context(|| format!("failed to copy {path} to {tar}"))?;
+        copied += 1;
+    }
+
+    Ok(copied)
+}
+
+/// A payload staged from the local installation in the same layout as an
+/// unpacked payload, so that it can be splatted like one
+struct Staged {
+    name: String,
+    kind: PayloadKind,
+    target_arch: Option<Arch>,
+    /// The directories to copy, relative to the staged payload, and whether
+    /// they are copied recursively
+    dirs: Vec<(PathBuf, PathBuf, bool)>,
+}
+
+/// Creates a splat in the default layout from a local Visual Studio and Windows
+/// SDK installation, without downloading anything.
+///
+/// The installation is first staged in the layout of the unpacked payloads,
+/// and then splatted the same way as downloaded payloads, so the splat gets the
+/// same directories and symlinks as a normal splat.
+///
+/// Only the desktop CRT libraries, ie. the files directly in
+/// `lib/<arch>`, are imported. As with a normal splat, any existing `crt` and
+/// `sdk` directories in the output are replaced, but only if the output is a
+/// previous splat or `allow_non_empty_output` is set.
+pub fn import_splat(
+    config: &ImportConfig,
+    draw_target: crate::util::ProgressTarget,
+) -> Result<(), Error> {
+    let msvc = config.vs_dir.join("VC/Tools/MSVC");
+    let crt_version = find_version(&msvc, config.crt_version.as_deref())?;
+    let crt_src = msvc.join(&crt_version);
+
+    let sdk_dir = config
+        .sdk_dir
+        .clone()
+        .unwrap_or_else(|| config.vs_dir.join("Windows Kits/10"));
+    let sdk_include = find_dir(&sdk_dir, &["Include", "include"])?;
+    let sdk_lib = find_dir(&sdk_dir, &["Lib", "lib"])?;
+    let sdk_version = find_version(&sdk_include, config.sdk_version.as_deref())?;
+
+    anyhow::ensure!(
+        sdk_lib.join(&sdk_version).is_dir(),
+        "SDK {sdk_version} has headers but no libraries in {sdk_lib}"
+    );
+
+    let sdk_include = sdk_include.join(&sdk_version);
+    let sdk_lib = sdk_lib.join(&sdk_version);
+
+    let mut staged = vec![Staged {
+        name: "crt_headers".to_owned(),
+        kind: PayloadKind::CrtHeaders,
+        target_arch: None,
+        dirs: vec![(crt_src.join("include"), "include".into(), true)],
+    }];
+
+    if config.include_atl {
+        staged.push(Staged {
+            name: "atl_headers".to_owned(),
+            kind: PayloadKind::AtlHeaders,
+            target_arch: None,
+            dirs: vec![(crt_src.join("atlmfc/include"), "include".into(), true)],
+        });
+    }
+
+    let mut sdk_headers = Staged {
+        name: "sdk_headers".to_owned(),
+        kind: PayloadKind::SdkHeaders,
+        target_arch: None,
+        dirs: Vec::new(),
+    };
+
+    for dir in ["shared", "um", "winrt", "cppwinrt"] {
+        let src = sdk_include.join(dir);
+
+        // The C++/WinRT headers are only present in newer SDKs
+        if dir == "cppwinrt" && !src.exists() {
+            tracing::warn!("SDK {sdk_version} doesn't contain the C++/WinRT headers");
+            continue;
+        }
+
+        sdk_headers
+            .dirs
+            .push((src, PathBuf::from("include").join(dir), true));
+    }
+    staged.push(sdk_headers);
+
+    let mut ucrt = Staged {
+        name: "ucrt".to_owned(),
+        kind: PayloadKind::Ucrt,
+        target_arch: None,
+        dirs: vec![(sdk_include.join("ucrt"), "include/ucrt".into(), true)],
+    };
+
+    for arch in Arch::iter(config.arches) {
+        let ms_arch = arch.as_ms_str();
+
+        staged.push(Staged {
+            name: format!("crt_libs_{arch}"),
+            kind: PayloadKind::CrtLibs,
+            target_arch: Some(arch),
+            dirs: vec![(
+                crt_src.join("lib").join(ms_arch),
+                PathBuf::from("lib").join(ms_arch),
+                false,
+            )],
+        });
+
+        if config.include_atl {
+            staged.push(Staged {
+                name: format!("atl_libs_{arch}"),
+                kind: PayloadKind::AtlLibs,
+                target_arch: Some(arch),
+                dirs: vec![(
+                    crt_src.join("atlmfc/lib").join(ms_arch),
+                    PathBuf::from("lib").join(ms_arch),
+                    false,
+                )],
+            });
+        }
+
+        staged.push(Staged {
+            name: format!("sdk_libs_{arch}"),
+            kind: PayloadKind::SdkLibs,
+            target_arch: Some(arch),
+            dirs: vec![(
+                sdk_lib.join("um").join(ms_arch),
+                PathBuf::from("lib/um").join(ms_arch),
+                false,
+            )],
+        });
+
+        ucrt.dirs.push((
+            sdk_lib.join("ucrt").join(ms_arch),
+            PathBuf::from("lib/ucrt").join(ms_arch),
+            false,
+        ));
+    }
+    staged.push(ucrt);
+
+    let mut roots = crate::splat::prep_splat(
+        PathBuf::new(),
+        config.allow_non_empty_output,
+        &config.output,
+        None,
+        None,
+        (None, None),
+        None,
+        false,
+    )?;
+
+    // The staging directory is in the output so that the staged files can be
+    // moved rather than copied into the splat
+    let staging = tempfile::Builder::new()
+        .prefix(".xwin-import")
+        .tempdir_in(&roots.root)
+        .with_context(|| format!("unable to create staging directory in {}", roots.root))?;
+    roots.src = PathBuf::from_path_buf(staging.path().to_owned())
+        .map_err(|pb| anyhow::anyhow!("staging path {} is not utf-8", pb.display()))?;
+
+    let splat_config = crate::SplatConfig {
+        include_debug_libs: true,
+        include_debug_symbols: true,
+        enable_symlinks: true,
+        preserve_ms_arch_notation: config.preserve_ms_arch_notation,
+        output: roots.root.clone(),
+        ..Default::default()
+    };
+
+    let mut sdk_headers = Vec::new();
+    let (mut crt_headers, mut atl_headers) = (None, None);
+
+    for staged in staged {
+        let payload_dir = roots.src.join(&staged.name);
+
+        for (src, rel, recursive) in &staged.dirs {
+            anyhow::ensure!(src.is_dir(), "expected directory '{src}' does not exist");
+
+            let copied = copy_dir(src, &payload_dir.join(rel), *recursive)?;
+            tracing::info!("copied {copied} files from {src}");
+        }
+
+        let tree = crate::unpack::read_unpack_dir(payload_dir)?;
+        let item = crate::WorkItem {
+            progress: indicatif::ProgressBar::hidden(),
+            payload: std::sync::Arc::new(crate::Payload {
+                filename: staged.name.clone().into(),
+                sha256: crate::util::Sha256([0; 32]),
+                url: String::new(),
+                size: 0,
+                install_size: None,
+                kind: staged.kind,
+                target_arch: staged.target_arch,
+                variant: matches!(staged.kind, PayloadKind::CrtLibs).then_some(Variant::Desktop),
+            }),
+        };
+
+        let headers = crate::splat::splat(
+            &splat_config,
+            &roots,
+            &item,
+            &tree,
+            None,
+            &sdk_version,
+            config.arches,
+            Variant::Desktop as u32,
+            false,
+        )
+        .with_context(|| format!("failed to splat {}", staged.name))?;
+
+        sdk_headers.extend(headers);
+        match staged.kind {
+            PayloadKind::CrtHeaders => crt_headers = Some(tree),
+            PayloadKind::AtlHeaders => atl_headers = Some(tree),
+            _ => {}
+        }
+    }
+
+    crate::splat::finalize_splat(
+        draw_target,
+        None,
+        &roots,
+        sdk_headers,
+        crt_headers,
+        atl_headers,
+        crate::splat::LinkKind::Symlink,
+    )?;
+
+    tracing::info!(
+        "imported CRT {crt_version} and SDK {sdk_version} to {}",
+        roots.root
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn latest_version() {
+        let td = tempfile::tempdir().unwrap();
+        let dir = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        for version in ["14.38.33130", "14.40.33807", "14.9.1", "not-a-version"] {
+            std::fs::create_dir(dir.join(version)).unwrap();
+        }
+
+        // Versions are compared numerically, not lexically
+        assert_eq!(super::find_version(&dir, None).unwrap(), "14.40.33807");
+        assert_eq!(
+            super::find_version(&dir, Some("14.38")).unwrap(),
+            "14.38.33130"
+        );
+        assert!(super::find_version(&dir, Some("14.39")).is_err());
+    }
+
+    #[test]
+    fn imports_installation() {
+        use crate::{Arch, PathBuf};
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let vs_dir = root.join("vs");
+
+        let write = |path: &str, contents: &str| {
+            let path = vs_dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+
+        let crt = "VC/Tools/MSVC/14.40.33807";
+        let sdk = "Windows Kits/10";
+        write(
+            &format!("{crt}/include/vcruntime.h"),
+            "#include <Windows.h>\n",
+        );
+        write(&format!("{crt}/lib/x64/msvcrt.lib"), "");
+        // Only the desktop libs directly in the arch directory are imported
+        write(&format!("{crt}/lib/x64/store/msvcrt.lib"), "");
+        write(&format!("{sdk}/Include/10.0.22621.0/um/Windows.h"), "");
+        write(&format!("{sdk}/Include/10.0.22621.0/shared/sal.h"), "");
+        write(&format!("{sdk}/Include/10.0.22621.0/winrt/roapi.h"), "");
+        write(&format!("{sdk}/Include/10.0.22621.0/ucrt/stdio.h"), "");
+        write(&format!("{sdk}/Lib/10.0.22621.0/um/x64/Kernel32.Lib"), "");
+        write(&format!("{sdk}/Lib/10.0.22621.0/ucrt/x64/ucrt.lib"), "");
+
+        let output = root.join("splat");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(output.join("unrelated.txt"), "").unwrap();
+
+        let mut config = super::ImportConfig {
+            vs_dir: vs_dir.clone(),
+            sdk_dir: None,
+            crt_version: None,
+            sdk_version: None,
+            arches: Arch::X86_64 as u32,
+            include_atl: false,
+            preserve_ms_arch_notation: false,
+            output: output.clone(),
+            allow_non_empty_output: false,
+        };
+
+        // The output isn't a splat, so nothing in it may be deleted
+        std::fs::create_dir_all(output.join("crt")).unwrap();
+        std::fs::write(output.join("crt/keep.txt"), "").unwrap();
+        let err = super::import_splat(&config, crate::util::ProgressTarget::Hidden).unwrap_err();
+        assert!(err.to_string().contains("allow-non-empty-output"), "{err}");
+        assert!(output.join("crt/keep.txt").exists());
+
+        config.allow_non_empty_output = true;
+        super::import_splat(&config, crate::util::ProgressTarget::Hidden).unwrap();
+
+        for path in [
+            "crt/include/vcruntime.h",
+            "crt/lib/x86_64/msvcrt.lib",
+            "sdk/include/um/Windows.h",
+            "sdk/include/shared/sal.h",
+            "sdk/include/winrt/roapi.h",
+            "sdk/include/ucrt/stdio.h",
+            "sdk/lib/um/x86_64/Kernel32.Lib",
+            "sdk/lib/ucrt/x86_64/ucrt.lib",
+            "unrelated.txt",
+        ] {
+            assert!(output.join(path).is_file(), "{path} is missing");
+        }
+
+        assert!(!output.join("crt/keep.txt").exists());
+        assert!(!output.join("crt/lib/x86_64/store").exists());
+        // Nothing is left of the staged payloads
+        assert_eq!(
+            std::fs::read_dir(&output)
+                .unwrap()
+                .filter(|entry| entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(".xwin-import"))
+                .count(),
+            0
+        );
+
+        // The same symlinks as a normal splat are created
+        #[cfg(unix)]
+        {
+            assert!(output.join("sdk/lib/um/x86_64/kernel32.lib").is_file());
+            assert!(output.join("sdk/include/um/windows.h").is_file());
+            assert!(output.join("sdk/include/10.0.22621.0").is_dir());
+        }
+
+        // The source installation is left as it was
+        assert!(vs_dir.join(crt).join("lib/x64/msvcrt.lib").exists());
+    }
+}
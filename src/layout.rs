@@ -0,0 +1,106 @@
+use crate::{splat::SplatRoots, Arch, Path, PathBuf, SplatConfig};
+use anyhow::{Context as _, Error};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The version of the layout JSON, incremented if a field is ever changed or
+/// removed, new fields can be added without changing it
+const LAYOUT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Layout<'a> {
+    version: u32,
+    crt_version: &'a str,
+    sdk_version: &'a str,
+    /// The root of the splat, ie. the output directory including the prefix
+    root: &'a Path,
+    crt: &'a Path,
+    sdk: &'a Path,
+    /// Whether the /winsysroot layout was used
+    winsysroot: bool,
+    /// The include directories, in the order they should be searched
+    include_dirs: Vec<PathBuf>,
+    /// The target specific directories for each architecture
+    arches: BTreeMap<&'static str, ArchLayout>,
+}
+
+#[derive(Serialize)]
+struct ArchLayout {
+    triple: &'static str,
+    /// The name of the architecture directories in the splat, ie. `x64` if
+    /// the MS arch notation was preserved
+    dir_name: &'static str,
+    /// The directories containing the CRT, SDK, and UCRT libraries
+    lib_dirs: Vec<PathBuf>,
+    /// The directory containing the SDK type libraries, if they were placed in
+    /// a directory of their own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tlb_dir: Option<PathBuf>,
+}
+
+/// Writes a JSON description of the splat to `path`, with the resolved CRT and
+/// SDK versions, and the absolute include directories and the library
+/// directories for each architecture, so that any build system or script can
+/// use the splat without knowing how its layout was configured.
+///
+/// Only the directories that were actually splatted are listed, eg. there are
+/// no include directories if `--libs-only` was used
+pub(crate) fn emit_layout_json(
+    path: &Path,
+    config: &SplatConfig,
+    roots: &SplatRoots,
+    arches: u32,
+    variants: u32,
+    crt_version: &str,
+    sdk_version: &str,
+) -> Result<(), Error> {
+    let dir_layout = config.dir_layout(variants, sdk_version);
+
+    let include_dirs = roots
+        .include_dirs(&dir_layout)
+        .into_iter()
+        .filter(|dir| dir.exists())
+        .collect();
+
+    let arches = Arch::iter(arches)
+        .map(|arch| {
+            let lib_dirs = roots
+                .lib_dirs(&dir_layout, arch)
+                .into_iter()
+                .filter(|dir| dir.exists())
+                .collect();
+
+            let tlb_dir = Some(roots.tlb_dir(&dir_layout, arch))
+                .filter(|dir| config.separate_tlbs && dir.exists());
+
+            (
+                arch.as_str(),
+                ArchLayout {
+                    triple: arch.as_triple(),
+                    dir_name: dir_layout.arch_dir(arch),
+                    lib_dirs,
+                    tlb_dir,
+                },
+            )
+        })
+        .collect();
+
+    let layout = Layout {
+        version: LAYOUT_VERSION,
+        crt_version,
+        sdk_version,
+        root: &roots.root,
+        crt: &roots.crt,
+        sdk: &roots.sdk,
+        winsysroot: config.use_winsysroot_style,
+        include_dirs,
+        arches,
+    };
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("unable to create {parent}"))?;
+    }
+
+    std::fs::write(path, serde_json::to_vec_pretty(&layout)?)
+        .with_context(|| format!("unable to write {path}"))
+}
@@ -6,21 +6,44 @@ use std::{
     collections::{BTreeMap, BTreeSet},
     fmt,
 };
+use util::ErrorKind;
 
+#[cfg(feature = "async")]
+mod asynchronous;
+mod bazel;
 mod ctx;
+mod diff;
+mod doctor;
 mod download;
+mod env;
+mod gn;
+mod import;
+mod layout;
 pub mod manifest;
 mod minimize;
+mod oci;
+mod sbom;
 mod splat;
 mod unpack;
 pub mod util;
 
 pub use ctx::Ctx;
+pub use diff::{diff_trees, Difference, Entry, TreeDiff};
+pub use doctor::{doctor, DoctorReport};
+pub use download::{list_cabs, MsiCab};
+pub use env::{splat_dirs, SplatDirs};
+pub use import::{import_splat, ImportConfig};
 pub use minimize::MinimizeConfig;
-pub use splat::SplatConfig;
+pub use oci::OciLayer;
+pub use sbom::Sbom;
+pub use splat::{resolve_include, symlink_splat, SplatConfig, CRT_LIB_CASINGS};
+pub use unpack::{index_payloads, list_unpacked, FileIndex, IndexedFile, Unpacked};
 pub use ureq;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
 pub enum Arch {
     X86 = 0x1,
     X86_64 = 0x2,
@@ -69,6 +92,17 @@ impl Arch {
         }
     }
 
+    /// The Rust target triple for the architecture
+    #[inline]
+    pub fn as_triple(&self) -> &'static str {
+        match self {
+            Self::X86 => "i686-pc-windows-msvc",
+            Self::X86_64 => "x86_64-pc-windows-msvc",
+            Self::Aarch => "thumbv7a-pc-windows-msvc",
+            Self::Aarch64 => "aarch64-pc-windows-msvc",
+        }
+    }
+
     pub fn iter(val: u32) -> impl Iterator<Item = Self> {
         [Self::X86, Self::X86_64, Self::Aarch, Self::Aarch64]
             .iter()
@@ -82,9 +116,13 @@ impl Arch {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
 pub enum Variant {
     Desktop = 0x1,
+    #[serde(rename = "onecore")]
     OneCore = 0x2,
     Store = 0x4,
     /// All of the variants come in a spectre-safe form as well
@@ -148,11 +186,13 @@ pub enum Ops {
 
 #[derive(Clone)]
 pub struct WorkItem {
+    /// The progress of the payload, its length should be initialized to the
+    /// payload's size, which is adjusted if the downloaded size differs
     pub progress: indicatif::ProgressBar,
     pub payload: std::sync::Arc<Payload>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Payload {
     /// The "suggested" filename for the payload when stored on disk
     pub filename: PathBuf,
@@ -173,7 +213,8 @@ pub struct Payload {
     pub variant: Option<Variant>,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PayloadKind {
     AtlHeaders,
     AtlLibs,
@@ -183,146 +224,689 @@ pub enum PayloadKind {
     SdkLibs,
     SdkStoreLibs,
     Ucrt,
+    /// The Debug Interface Access SDK, which contains both headers and the
+    /// libs for every architecture
+    DiaSdk,
+    /// The Windows Runtime metadata (`.winmd`) files used to generate projections
+    SdkMetadata,
+    /// The MSVC compiler and linker binaries that run on a host architecture and
+    /// target an architecture, which are splatted to `bin/Host<host>/<target>`
+    CrtTools,
 }
 
 pub struct PrunedPackageList {
     pub crt_version: String,
     pub sdk_version: String,
     pub payloads: Vec<Payload>,
+    /// Problems that were encountered while pruning that weren't fatal, but
+    /// might mean the payloads are incomplete
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// An expected package was not present in the manifest, and was skipped
+    MissingPackage,
+    /// An expected package was not present in the manifest, but a fallback
+    /// package was used instead
+    UsedFallback,
+    /// A CRT package for one of the selected architectures and variants is
+    /// present in the manifest but wasn't selected, which might mean the
+    /// selection for that architecture is incomplete
+    UnselectedPackage,
+    /// The same SDK header is present in multiple payloads, but with different
+    /// contents, and only one of them was splatted
+    ConflictingHeader,
+}
+
+/// A non-fatal problem encountered while pruning the package list
+#[derive(Debug, serde::Serialize)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// The id of the expected package, or for conflicts, the path of the
+    /// header relative to the splat root
+    pub id: String,
+    /// The id of the package used instead, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+    /// Package ids in the manifest that are similar to the expected one
+    pub suggestions: Vec<String>,
+    /// The payloads that contain conflicting versions of a header
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub payloads: Vec<PathBuf>,
+}
+
+impl Diagnostic {
+    fn new(
+        kind: DiagnosticKind,
+        pkgs: &BTreeMap<String, manifest::ManifestItem>,
+        id: &str,
+        fallback: Option<&str>,
+    ) -> Self {
+        Self {
+            kind,
+            id: id.to_owned(),
+            fallback: fallback.map(String::from),
+            suggestions: similar_ids(pkgs.keys(), id)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            payloads: Vec::new(),
+        }
+    }
+}
+
+/// A fully resolved set of payloads that can be persisted to disk so that
+/// future runs can download exactly the same payloads, bypassing manifest
+/// resolution entirely
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PinnedPayloads {
+    pub crt_version: String,
+    pub sdk_version: String,
+    pub arches: u32,
+    pub variants: u32,
+    pub payloads: Vec<Payload>,
+    /// The subset of the package manifest needed to download the payloads,
+    /// ie. the parent items of MSI payloads, which list their CAB files
+    pub packages: BTreeMap<String, manifest::ManifestItem>,
+}
+
+impl PinnedPayloads {
+    pub fn new(
+        pkgs: &BTreeMap<String, manifest::ManifestItem>,
+        pruned: PrunedPackageList,
+        arches: u32,
+        variants: u32,
+    ) -> Self {
+        let packages = pruned
+            .payloads
+            .iter()
+            .filter(|payload| payload.filename.extension() == Some("msi"))
+            .filter_map(|payload| download::msi_parent(pkgs, payload))
+            .map(|mi| (mi.id.clone(), mi.clone()))
+            .collect();
+
+        Self {
+            crt_version: pruned.crt_version,
+            sdk_version: pruned.sdk_version,
+            arches,
+            variants,
+            payloads: pruned.payloads,
+            packages,
+        }
+    }
+}
+
+/// The optional parts of the package selection done by [`prune_pkg_list`]
+#[derive(Default)]
+pub struct PruneOptions<'t> {
+    /// If specified, only the CRT libraries for these explicit architecture
+    /// and variant pairs are selected, rather than every combination of the
+    /// architectures and variants
+    pub targets: Option<&'t [(Arch, Variant)]>,
+    /// Selects the Active Template Library (ATL)
+    pub include_atl: bool,
+    /// Selects the Debug Interface Access (DIA) SDK
+    pub include_dia: bool,
+    /// Selects the MSVC tools that run on this architecture and target each
+    /// of the selected architectures
+    pub tools_host: Option<Arch>,
+    /// Selects the Windows Runtime metadata
+    pub include_winmd: bool,
+    /// Skips the Store variant of the CRT libraries if it wasn't selected,
+    /// rather than selecting it for the libraries that are linked by default
+    pub skip_store_crt: bool,
+    /// The SDK version to select, defaults to the latest
+    pub sdk_version: Option<String>,
+    /// The CRT version to select, defaults to the latest
+    pub crt_version: Option<String>,
 }
 
 /// Returns the list of packages that are actually needed for cross compilation
+///
+/// By default the CRT libraries for every combination of `arches` and `variants`
+/// are selected, but if [`PruneOptions::targets`] is specified, only the CRT
+/// libraries for the explicit architecture and variant pairs are selected
+/// instead. Note that the spectre variant, if specified, applies to every
+/// architecture.
+///
+/// The Store variant of the CRT libraries is always selected unless
+/// [`PruneOptions::skip_store_crt`] is set, as it contains some libraries that
+/// are linked by default even when not targeting the Store, eg. `oldnames.lib`.
+/// The manifest only provides the entire variant as a single package, but if
+/// the Store variant wasn't one of the `variants`, only those default libraries
+/// are unpacked and splatted from it.
 pub fn prune_pkg_list(
     pkg_manifest: &manifest::PackageManifest,
     arches: u32,
     variants: u32,
-    include_atl: bool,
-    sdk_version: Option<String>,
-    crt_version: Option<String>,
+    options: PruneOptions<'_>,
 ) -> Result<PrunedPackageList, Error> {
     // We only really need 2 core pieces from the manifest, the CRT (headers + libs)
     // and the Windows SDK
     let pkgs = &pkg_manifest.packages;
     let mut payloads = Vec::new();
+    let mut diagnostics = Vec::new();
 
     let crt_version = get_crt(
         pkgs,
         arches,
         variants,
+        &options,
+        &mut payloads,
+        &mut diagnostics,
+    )?;
+    let sdk_version = get_sdk(
+        pkgs,
+        arches,
+        variants,
+        options.sdk_version,
+        options.include_winmd,
         &mut payloads,
-        include_atl,
-        crt_version,
     )?;
-    let sdk_version = get_sdk(pkgs, arches, sdk_version, &mut payloads)?;
+
+    if options.include_dia {
+        get_dia(pkgs, &mut payloads)?;
+    }
+
+    if let Some(host) = options.tools_host {
+        get_tools(
+            pkgs,
+            &crt_version,
+            host,
+            arches,
+            &mut payloads,
+            &mut diagnostics,
+        )?;
+    }
 
     Ok(PrunedPackageList {
         crt_version,
         sdk_version,
         payloads,
+        diagnostics,
     })
 }
 
-fn get_crt(
+/// Returns the payloads for a single package in the manifest, bypassing the
+/// normal CRT and SDK selection, eg. to inspect the contents of a package that
+/// xwin doesn't otherwise use
+///
+/// The kind of each payload is detected the same way as [`explicit_pkg_list`].
+/// Payloads whose kind can't be detected are still returned, as
+/// [`Ctx::extract`] unpacks every file in a payload regardless of its kind.
+///
+/// The CAB files that accompany MSI payloads are not returned as they are
+/// downloaded along with the MSI that references them.
+pub fn package_payloads(
     pkgs: &BTreeMap<String, manifest::ManifestItem>,
-    arches: u32,
-    variants: u32,
-    pruned: &mut Vec<Payload>,
-    include_atl: bool,
+    package_id: &str,
+) -> Result<Vec<Payload>, Error> {
+    let item = pkgs.get(package_id).with_context(|| {
+        ErrorKind::Selection.msg(format!(
+            "unable to find package '{package_id}'{}",
+            did_you_mean(pkgs.keys(), package_id)
+        ))
+    })?;
+
+    let install_size = (item.payloads.len() == 1)
+        .then_some(item)
+        .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive));
+
+    let payloads: Vec<_> = item
+        .payloads
+        .iter()
+        .filter(|payload| !payload.file_name.ends_with(".cab"))
+        .map(|payload| {
+            let mut detected = detect_payload(item, payload).unwrap_or_else(|| {
+                tracing::debug!(
+                    "unable to detect the kind of payload '{}' in package '{package_id}'",
+                    payload.file_name
+                );
+
+                Payload {
+                    filename: payload.file_name.clone().into(),
+                    sha256: payload.sha256.clone(),
+                    url: payload.url.clone(),
+                    size: payload.size,
+                    install_size: None,
+                    // The kind is only used to filter the unpacked files,
+                    // which isn't done when extracting
+                    kind: PayloadKind::SdkHeaders,
+                    target_arch: None,
+                    variant: None,
+                }
+            });
+
+            if let Some(stripped) = detected.filename.as_str().strip_prefix("Installers\\") {
+                detected.filename = stripped.into();
+            }
+            detected.install_size = detected.install_size.or(install_size);
+            detected
+        })
+        .collect();
+
+    anyhow::ensure!(
+        !payloads.is_empty(),
+        "package '{package_id}' doesn't have any payloads that can be unpacked"
+    );
+
+    Ok(payloads)
+}
+
+/// Selects every payload of exactly the specified packages, bypassing the
+/// selection heuristics of [`prune_pkg_list`] for when they don't select what
+/// is needed. The CABs for MSI payloads are still retrieved as usual.
+///
+/// The kind of each payload is detected on a best-effort basis from the package
+/// id and payload name. Payloads whose kind can't be detected are skipped with
+/// a warning as they can't be splatted, [`package_payloads`] can be used to
+/// retrieve any package regardless.
+///
+/// If not specified, the CRT and SDK versions are taken from the ids of the
+/// CRT and SDK packages, or are the latest versions in the manifest.
+pub fn explicit_pkg_list(
+    pkg_manifest: &manifest::PackageManifest,
+    package_ids: &[String],
+    sdk_version: Option<String>,
     crt_version: Option<String>,
-) -> Result<String, Error> {
-    fn to_payload(mi: &manifest::ManifestItem, payload: &manifest::Payload) -> Payload {
-        // These are really the only two we care about
-        let kind = if mi.id.contains("Headers") {
-            PayloadKind::CrtHeaders
-        } else {
-            PayloadKind::CrtLibs
-        };
+) -> Result<PrunedPackageList, Error> {
+    let pkgs = &pkg_manifest.packages;
+    let sdk_regex = regex::Regex::new(r"^Win\d+SDK_(.+)$")?;
 
-        let variant = [
-            // Put this one first as Desktop will match OneCore.Desktop otherwise
-            ("OneCore", Variant::OneCore),
-            ("Desktop", Variant::Desktop),
-            ("Store", Variant::Store),
-        ]
-        .iter()
-        .find_map(|(s, var)| payload.file_name.contains(s).then_some(*var));
-
-        // The "chip" in the manifest means "host architecture" but we never need
-        // to care about that since we only care about host agnostic artifacts, but
-        // we do need to check the name of the payload in case it targets a specific
-        // architecture only (eg libs)
-        let target_arch = [
-            ("x64", Arch::X86_64),
-            // Put this one first otherwise "arm" will match it
-            ("arm64", Arch::Aarch64),
-            ("ARM64", Arch::Aarch64),
-            ("arm", Arch::Aarch),
-            // Put this last as many names also include the host architecture :p
-            ("x86", Arch::X86),
-        ]
-        .iter()
-        .find_map(|(s, arch)| payload.file_name.contains(s).then_some(*arch));
+    let mut crt_version = crt_version;
+    let mut sdk_version = sdk_version;
+    let mut payloads = Vec::new();
+
+    for id in package_ids {
+        let mi = pkgs.get(id).with_context(|| {
+            ErrorKind::Selection.msg(format!(
+                "unable to find package '{id}'{}",
+                did_you_mean(pkgs.keys(), id)
+            ))
+        })?;
 
-        Payload {
-            filename: if let Some(Arch::Aarch64) = target_arch {
-                payload.file_name.replace("ARM", "arm").into()
+        if let Some((version, _)) = crt_package(id) {
+            crt_version.get_or_insert_with(|| version.to_owned());
+        } else if let Some(caps) = sdk_regex.captures(id) {
+            sdk_version.get_or_insert_with(|| caps[1].to_owned());
+        }
+
+        for payload in mi
+            .payloads
+            .iter()
+            .filter(|payload| !payload.file_name.ends_with(".cab"))
+        {
+            if let Some(detected) = detect_payload(mi, payload) {
+                payloads.push(detected);
             } else {
-                payload.file_name.clone().into()
-            },
+                tracing::warn!(
+                    "unable to detect the kind of payload '{}' in package '{id}', skipping it",
+                    payload.file_name
+                );
+            }
+        }
+    }
+
+    anyhow::ensure!(
+        !payloads.is_empty(),
+        "none of the specified packages have payloads that can be splatted"
+    );
+
+    let crt_version = match crt_version {
+        Some(crt_version) => crt_version,
+        None => latest_crt_version(
+            pkgs.get("Microsoft.VisualStudio.Product.BuildTools")
+                .context("unable to find root BuildTools item")?,
+        )?,
+    };
+
+    let sdk_version = match sdk_version {
+        Some(sdk_version) => sdk_version,
+        None => get_latest_sdk_version(pkgs.keys())
+            .context("unable to find latest WinSDK version")?
+            .1
+            .to_string(),
+    };
+
+    Ok(PrunedPackageList {
+        crt_version,
+        sdk_version,
+        payloads,
+        diagnostics: Vec::new(),
+    })
+}
+
+/// Splits the id of a CRT or ATL package into its version, and whether it is
+/// an ATL package, eg. `Microsoft.VC.14.38.17.8.ATL.Headers.base`
+fn crt_package(id: &str) -> Option<(&str, bool)> {
+    id.strip_prefix("Microsoft.VC.").and_then(|rest| {
+        rest.split_once(".CRT.")
+            .map(|(version, _)| (version, false))
+            .or_else(|| rest.split_once(".ATL.").map(|(version, _)| (version, true)))
+    })
+}
+
+/// Detects the kind of a payload on a best-effort basis from the id of its
+/// package and its name
+fn detect_payload(mi: &manifest::ManifestItem, payload: &manifest::Payload) -> Option<Payload> {
+    match crt_package(&mi.id) {
+        Some((_, false)) => Some(crt_payload(mi, payload)),
+        Some((_, true)) => Some(atl_payload(mi, payload)),
+        None if mi.id == "Microsoft.VisualCpp.DIA.SDK" => Some(Payload {
+            filename: payload.file_name.clone().into(),
             sha256: payload.sha256.clone(),
             url: payload.url.clone(),
             size: payload.size,
-            kind,
-            target_arch,
-            variant,
-            install_size: (mi.payloads.len() == 1)
-                .then_some(mi)
-                .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive)),
+            install_size: None,
+            kind: PayloadKind::DiaSdk,
+            variant: None,
+            target_arch: None,
+        }),
+        None => sdk_payload(payload),
+    }
+}
+
+/// Detects the kind of an SDK or Universal CRT payload from its name
+fn sdk_payload(payload: &manifest::Payload) -> Option<Payload> {
+    let file_name = payload
+        .file_name
+        .strip_prefix("Installers\\")
+        .unwrap_or(&payload.file_name);
+    let name = file_name.strip_suffix("-x86_en-us.msi")?;
+    let find_arch = |ms: &str| Arch::iter(u32::MAX).find(|arch| arch.as_ms_str() == ms);
+
+    let (kind, target_arch, variant) = if name == "Universal CRT Headers Libraries and Sources" {
+        (PayloadKind::Ucrt, None, None)
+    } else {
+        let name = name.strip_prefix("Windows SDK ")?;
+
+        if let Some((kind, rest)) = name.split_once("Headers") {
+            (
+                PayloadKind::SdkHeaders,
+                find_arch(rest.trim()).filter(|arch| *arch != Arch::X86),
+                kind.contains("Store").then_some(Variant::Store),
+            )
+        } else if let Some(arch) = name.strip_prefix("Desktop Libs ") {
+            (PayloadKind::SdkLibs, Some(find_arch(arch)?), None)
+        } else if name == "for Windows Store Apps Libs" {
+            (PayloadKind::SdkStoreLibs, None, None)
+        } else if name == "for Windows Store Apps Metadata" {
+            (PayloadKind::SdkMetadata, None, None)
+        } else {
+            return None;
+        }
+    };
+
+    Some(Payload {
+        filename: file_name.into(),
+        sha256: payload.sha256.clone(),
+        url: payload.url.clone(),
+        size: payload.size,
+        install_size: None,
+        kind,
+        target_arch,
+        variant,
+    })
+}
+
+/// A named selection of payloads that is applied on top of the payloads
+/// returned by [`prune_pkg_list`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// The smallest set of payloads that can still be used to compile and link
+    /// a program for a single architecture, eg. for CI smoke tests. This is
+    /// the UCRT, the CRT headers and non-Store libs, the SDK headers, and the
+    /// SDK libs, for only the first selected architecture. ATL, DIA, the
+    /// Windows Runtime metadata, and the Store libs are all skipped.
+    ///
+    /// Note that the Store SDK headers are still needed as they contain some
+    /// of the core headers.
+    Minimal,
+}
+
+impl std::str::FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "minimal" => Self::Minimal,
+            o => anyhow::bail!("unknown preset '{o}'"),
+        })
+    }
+}
+
+impl Preset {
+    /// Removes the payloads that aren't part of the preset, returning the
+    /// architectures that remain
+    pub fn apply(self, payloads: &mut Vec<Payload>, arches: u32) -> Result<u32, Error> {
+        match self {
+            Self::Minimal => {
+                let arch = Arch::iter(arches)
+                    .next()
+                    .context("no architecture was selected")?;
+                let is_arch = |pay: &Payload| !matches!(pay.target_arch, Some(ta) if ta != arch);
+
+                payloads.retain(|pay| match pay.kind {
+                    PayloadKind::Ucrt | PayloadKind::CrtHeaders => true,
+                    PayloadKind::SdkHeaders | PayloadKind::SdkLibs => is_arch(pay),
+                    PayloadKind::CrtLibs => is_arch(pay) && pay.variant != Some(Variant::Store),
+                    PayloadKind::AtlHeaders
+                    | PayloadKind::AtlLibs
+                    | PayloadKind::SdkStoreLibs
+                    | PayloadKind::SdkMetadata
+                    | PayloadKind::DiaSdk
+                    | PayloadKind::CrtTools => false,
+                });
+
+                Ok(arch as u32)
+            }
         }
     }
+}
+
+/// Determines the architecture a CRT or ATL payload targets from its file name,
+/// eg. `Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.base.vsix`
+///
+/// The "chip" in the manifest means "host architecture" but we never need
+/// to care about that since we only care about host agnostic artifacts, but
+/// we do need to check the name of the payload in case it targets a specific
+/// architecture only (eg libs). The name is matched by whole `.` separated
+/// tokens, ignoring case, so that eg. `ARM`, `ARM64`, and `ARM64EC` are all
+/// distinct.
+fn payload_target_arch(file_name: &str) -> Option<Arch> {
+    [
+        ("x64", Arch::X86_64),
+        ("arm64", Arch::Aarch64),
+        ("arm", Arch::Aarch),
+        // Put this last as many names also include the host architecture :p
+        ("x86", Arch::X86),
+    ]
+    .iter()
+    .find_map(|(token, arch)| {
+        file_name
+            .split('.')
+            .any(|part| part.eq_ignore_ascii_case(token))
+            .then_some(*arch)
+    })
+}
+
+/// The file name a CRT or ATL payload is stored as, which lowercases the
+/// `ARM64` token for consistency with the other architectures
+fn payload_filename(file_name: &str, target_arch: Option<Arch>) -> PathBuf {
+    if target_arch != Some(Arch::Aarch64) {
+        return file_name.into();
+    }
+
+    file_name
+        .split('.')
+        .map(|part| {
+            if part.eq_ignore_ascii_case("arm64") {
+                "arm64"
+            } else {
+                part
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+        .into()
+}
+
+/// Converts a CRT package's payload, detecting its kind, architecture, and
+/// variant from the package id and payload name
+fn crt_payload(mi: &manifest::ManifestItem, payload: &manifest::Payload) -> Payload {
+    // These are really the only two we care about
+    let kind = if mi.id.contains("Headers") {
+        PayloadKind::CrtHeaders
+    } else {
+        PayloadKind::CrtLibs
+    };
+
+    let variant = [
+        // Put this one first as Desktop will match OneCore.Desktop otherwise
+        ("OneCore", Variant::OneCore),
+        ("Desktop", Variant::Desktop),
+        ("Store", Variant::Store),
+    ]
+    .iter()
+    .find_map(|(s, var)| payload.file_name.contains(s).then_some(*var));
+
+    let target_arch = payload_target_arch(&payload.file_name);
+
+    Payload {
+        filename: payload_filename(&payload.file_name, target_arch),
+        sha256: payload.sha256.clone(),
+        url: payload.url.clone(),
+        size: payload.size,
+        kind,
+        target_arch,
+        variant,
+        install_size: (mi.payloads.len() == 1)
+            .then_some(mi)
+            .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive)),
+    }
+}
+
+/// Finds the latest CRT version that the build tools depend on
+fn latest_crt_version(build_tools: &manifest::ManifestItem) -> Result<String, Error> {
+    let crt_version_rs_versions = build_tools
+        .dependencies
+        .keys()
+        .filter_map(|key| {
+            key.strip_prefix("Microsoft.VisualStudio.Component.VC.")
+                .and_then(|s| s.strip_suffix(".x86.x64"))
+                .and_then(versions::Version::new)
+        })
+        .max()
+        .context("unable to find latest CRT version")?;
+
+    Ok(crt_version_rs_versions.to_string())
+}
 
+fn get_crt(
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+    arches: u32,
+    variants: u32,
+    options: &PruneOptions<'_>,
+    pruned: &mut Vec<Payload>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<String, Error> {
     let build_tools = pkgs
         .get("Microsoft.VisualStudio.Product.BuildTools")
         .context("unable to find root BuildTools item")?;
 
-    let crt_version = if let Some(user) = crt_version {
+    let crt_version = if let Some(user) = options.crt_version.clone() {
         // Ensure it is a valid version and that it actually exists in the manifest
-        versions::Version::new(&user)
-            .with_context(|| format!("invalid CRT version '{user}' specified"))?;
+        versions::Version::new(&user).with_context(|| {
+            ErrorKind::Selection.msg(format!("invalid CRT version '{user}' specified"))
+        })?;
 
         build_tools
             .dependencies
             .get(&format!(
                 "Microsoft.VisualStudio.Component.VC.{user}.x86.x64"
             ))
-            .with_context(|| format!("CRT version '{user}' does not exist in the manifest"))?;
+            .with_context(|| {
+                ErrorKind::Selection.msg(format!(
+                    "CRT version '{user}' does not exist in the manifest"
+                ))
+            })?;
 
         user
     } else {
-        let crt_version_rs_versions = build_tools
-            .dependencies
-            .keys()
-            .filter_map(|key| {
-                key.strip_prefix("Microsoft.VisualStudio.Component.VC.")
-                    .and_then(|s| s.strip_suffix(".x86.x64"))
-                    .and_then(versions::Version::new)
-            })
-            .max()
-            .context("unable to find latest CRT version")?;
-
-        crt_version_rs_versions.to_string()
+        latest_crt_version(build_tools)?
     };
 
+    // Older toolsets don't necessarily provide libs for every architecture, eg.
+    // ARM64, and rather than producing a splat that can't link, fail early
+    for arch in Arch::iter(arches) {
+        let component = match arch {
+            Arch::X86 | Arch::X86_64 => "x86.x64",
+            Arch::Aarch => "ARM",
+            Arch::Aarch64 => "ARM64",
+        };
+
+        let component_id = format!("Microsoft.VisualStudio.Component.VC.{crt_version}.{component}");
+        // Fallback to checking for the libs themselves, which only uppercase ARM64
+        let crt_libs_prefix = format!(
+            "Microsoft.VC.{crt_version}.CRT.{}.",
+            if arch == Arch::Aarch64 {
+                "ARM64"
+            } else {
+                arch.as_ms_str()
+            }
+        );
+
+        if !build_tools.dependencies.contains_key(&component_id)
+            && !pkgs
+                .range(crt_libs_prefix.clone()..)
+                .next()
+                .is_some_and(|(id, _)| id.starts_with(&crt_libs_prefix))
+        {
+            return Err(ErrorKind::Selection.msg(format!(
+                "CRT version '{crt_version}' does not provide libraries for '{arch}', '{component_id}' was not found in the manifest"
+            )).into());
+        }
+    }
+
     // The CRT headers are in the "base" package
-    // `Microsoft.VC.<ridiculous_version_numbers>.CRT.Headers.base`
+    // `Microsoft.VC.<ridiculous_version_numbers>.CRT.Headers.base`, but older
+    // toolsets used different ids for the same package, so fallback to those
+    // before giving up
     {
-        let header_key = format!("Microsoft.VC.{crt_version}.CRT.Headers.base");
+        let header_keys = [
+            format!("Microsoft.VC.{crt_version}.CRT.Headers.base"),
+            format!("Microsoft.VC.{crt_version}.CRT.Headers"),
+            // VS 2017 toolsets weren't versioned in the id at all
+            "Microsoft.VisualCpp.CRT.Headers".to_owned(),
+        ];
+
+        let (header_key, crt_headers) = header_keys
+            .iter()
+            .find_map(|key| pkgs.get(key).map(|mi| (key, mi)))
+            .with_context(|| {
+                format!(
+                    "unable to find CRT headers item '{}'{}",
+                    header_keys[0],
+                    did_you_mean(pkgs.keys(), &header_keys[0])
+                )
+            })?;
 
-        let crt_headers = pkgs
-            .get(&header_key)
-            .with_context(|| format!("unable to find CRT headers item '{header_key}'"))?;
+        if *header_key != header_keys[0] {
+            tracing::warn!(
+                "unable to find CRT headers item '{}', using '{header_key}' instead",
+                header_keys[0]
+            );
+            diagnostics.push(Diagnostic::new(
+                DiagnosticKind::UsedFallback,
+                pkgs,
+                &header_keys[0],
+                Some(header_key),
+            ));
+        }
 
-        pruned.push(to_payload(crt_headers, &crt_headers.payloads[0]));
+        pruned.push(crt_payload(crt_headers, &crt_headers.payloads[0]));
     }
 
     {
@@ -332,13 +916,26 @@ fn get_crt(
         // The spectre versions include both the regular and spectre version of every lib
         let spectre = (variants & Variant::Spectre as u32) != 0;
 
-        // We need to force include the Store version as well, as they
-        // include some libraries that are often linked by default, eg oldnames.lib
-        let variants = variants | Variant::Store as u32;
-
         let mut crt_lib_id = String::new();
+        let mut selected = BTreeSet::new();
 
         for arch in Arch::iter(arches) {
+            let variants = options.targets.map_or(variants, |targets| {
+                targets
+                    .iter()
+                    .filter(|(ta, _)| *ta == arch)
+                    .fold(0, |acc, (_, var)| acc | *var as u32)
+            });
+
+            // We need to force include the Store version as well, as they
+            // include some libraries that are often linked by default, eg oldnames.lib,
+            // unless the user has opted out of them
+            let variants = if !options.skip_store_crt {
+                variants | Variant::Store as u32
+            } else {
+                variants
+            };
+
             for variant in Variant::iter(variants) {
                 crt_lib_id.clear();
 
@@ -362,84 +959,176 @@ fn get_crt(
                 )
                 .unwrap();
 
-                match pkgs.get(&crt_lib_id) {
-                    Some(crt_libs) => {
-                        pruned.push(to_payload(crt_libs, &crt_libs.payloads[0]));
-                    }
-                    None => {
-                        tracing::warn!("Unable to locate '{crt_lib_id}'");
+                if let Some(crt_libs) = pkgs.get(&crt_lib_id) {
+                    pruned.push(crt_payload(crt_libs, &crt_libs.payloads[0]));
+                    selected.insert(crt_lib_id.clone());
+                } else {
+                    tracing::warn!(
+                        "Unable to locate '{crt_lib_id}'{}",
+                        did_you_mean(pkgs.keys(), &crt_lib_id)
+                    );
+                    diagnostics.push(Diagnostic::new(
+                        DiagnosticKind::MissingPackage,
+                        pkgs,
+                        &crt_lib_id,
+                        None,
+                    ));
+                }
+
+                // Some toolsets split the ARM64 libs for the floating point
+                // variants into their own packages alongside the base one, eg.
+                // `Microsoft.VC.<version>.CRT.ARM64.Desktop.neon.base`, which
+                // are only present in some toolsets
+                if arch == Arch::Aarch64 {
+                    let base_id = crt_lib_id.strip_suffix(".base").unwrap_or(&crt_lib_id);
+
+                    for piece in AARCH64_CRT_PIECES {
+                        let piece_id = format!("{base_id}.{piece}.base");
+
+                        if let Some(crt_libs) = pkgs.get(&piece_id) {
+                            pruned.push(crt_payload(crt_libs, &crt_libs.payloads[0]));
+                            selected.insert(piece_id);
+                        }
                     }
                 }
             }
+
+            // The ids above are constructed from the naming scheme the
+            // toolsets have used so far, which is brittle, so report any other
+            // packages for the same architecture and variants, eg. if a toolset
+            // splits the libs for an architecture into multiple packages
+            audit_crt_libs(
+                pkgs,
+                &crt_version,
+                arch,
+                variants,
+                spectre,
+                &selected,
+                diagnostics,
+            );
         }
-        if include_atl {
-            get_atl(pkgs, arches, spectre, pruned, &crt_version)?;
+        if options.include_atl {
+            get_atl(pkgs, arches, spectre, pruned, diagnostics, &crt_version)?;
         }
     }
 
     Ok(crt_version)
 }
 
-fn get_atl(
+/// The ARM64 CRT libs that some toolsets place in their own packages, in
+/// addition to the base package for each variant
+const AARCH64_CRT_PIECES: &[&str] = &["neon", "softfp"];
+
+/// Finds the CRT lib packages in the manifest for the architecture and variants
+/// that weren't selected
+fn audit_crt_libs(
     pkgs: &BTreeMap<String, manifest::ManifestItem>,
-    arches: u32,
-    spectre: bool,
-    pruned: &mut Vec<Payload>,
     crt_version: &str,
-) -> Result<(), Error> {
-    fn to_payload(mi: &manifest::ManifestItem, payload: &manifest::Payload) -> Payload {
-        // These are really the only two we care about
-        let kind = if mi.id.contains("Headers") {
-            PayloadKind::AtlHeaders
+    arch: Arch,
+    variants: u32,
+    spectre: bool,
+    selected: &BTreeSet<String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let prefix = format!(
+        "Microsoft.VC.{crt_version}.CRT.{}.",
+        if arch == Arch::Aarch64 {
+            "ARM64"
         } else {
-            PayloadKind::AtlLibs
-        };
+            arch.as_ms_str()
+        }
+    );
 
-        let filename = payload.file_name.to_lowercase();
-
-        // The "chip" in the manifest means "host architecture" but we never need
-        // to care about that since we only care about host agnostic artifacts, but
-        // we do need to check the name of the payload in case it targets a specific
-        // architecture only (eg libs)
-        let target_arch = [
-            ("x64", Arch::X86_64),
-            // Put this one first otherwise "arm" will match it
-            ("arm64", Arch::Aarch64),
-            ("arm", Arch::Aarch),
-            // Put this last as many names also include the host architecture :p
-            ("x86", Arch::X86),
-        ]
-        .iter()
-        .find_map(|(s, arch)| filename.contains(s).then_some(*arch));
+    for (id, _) in pkgs
+        .range(prefix.clone()..)
+        .take_while(|(id, _)| id.starts_with(&prefix))
+    {
+        if selected.contains(id) || !id.ends_with(".base") {
+            continue;
+        }
 
-        Payload {
-            filename: if let Some(Arch::Aarch64) = target_arch {
-                payload.file_name.replace("ARM", "arm").into()
-            } else {
-                payload.file_name.clone().into()
-            },
-            sha256: payload.sha256.clone(),
-            url: payload.url.clone(),
-            size: payload.size,
-            kind,
-            target_arch,
-            variant: None,
-            install_size: (mi.payloads.len() == 1)
-                .then_some(mi)
-                .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive)),
+        let rest = &id[prefix.len()..];
+
+        let Some(variant_rest) = Variant::iter(variants)
+            .find_map(|variant| rest.strip_prefix(variant)?.strip_prefix('.'))
+        else {
+            continue;
+        };
+
+        // Spectre versions are only wanted if they were requested, in which
+        // case the regular version isn't
+        if variant_rest.starts_with("spectre.") != spectre {
+            continue;
         }
+
+        tracing::info!(
+            "CRT package '{id}' for '{arch}' is present in the manifest but was not selected"
+        );
+        diagnostics.push(Diagnostic {
+            kind: DiagnosticKind::UnselectedPackage,
+            id: id.clone(),
+            fallback: None,
+            suggestions: Vec::new(),
+            payloads: Vec::new(),
+        });
+    }
+}
+
+/// Converts an ATL package's payload, detecting its kind and architecture from
+/// the package id and payload name
+fn atl_payload(mi: &manifest::ManifestItem, payload: &manifest::Payload) -> Payload {
+    // These are really the only two we care about
+    let kind = if mi.id.contains("Headers") {
+        PayloadKind::AtlHeaders
+    } else {
+        PayloadKind::AtlLibs
+    };
+
+    let target_arch = payload_target_arch(&payload.file_name);
+
+    Payload {
+        filename: payload_filename(&payload.file_name, target_arch),
+        sha256: payload.sha256.clone(),
+        url: payload.url.clone(),
+        size: payload.size,
+        kind,
+        target_arch,
+        variant: None,
+        install_size: (mi.payloads.len() == 1)
+            .then_some(mi)
+            .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive)),
     }
+}
 
+fn get_atl(
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+    arches: u32,
+    spectre: bool,
+    pruned: &mut Vec<Payload>,
+    diagnostics: &mut Vec<Diagnostic>,
+    crt_version: &str,
+) -> Result<(), Error> {
     // The ATL headers are in the "base" package
-    // `Microsoft.VC.<ridiculous_version_numbers>.ATL.Headers.base`
+    // `Microsoft.VC.<ridiculous_version_numbers>.ATL.Headers.base`, but the
+    // packaging differs for some toolset versions, so we still want the libs
+    // even if the headers can't be found
     {
         let header_key = format!("Microsoft.VC.{crt_version}.ATL.Headers.base");
 
-        let atl_headers = pkgs
-            .get(&header_key)
-            .with_context(|| format!("unable to find ATL headers item '{header_key}'"))?;
-
-        pruned.push(to_payload(atl_headers, &atl_headers.payloads[0]));
+        if let Some(atl_headers) = pkgs.get(&header_key) {
+            pruned.push(atl_payload(atl_headers, &atl_headers.payloads[0]));
+        } else {
+            tracing::warn!(
+                "Unable to locate ATL headers '{header_key}'{}",
+                did_you_mean(pkgs.keys(), &header_key)
+            );
+            diagnostics.push(Diagnostic::new(
+                DiagnosticKind::MissingPackage,
+                pkgs,
+                &header_key,
+                None,
+            ));
+        }
     }
 
     {
@@ -463,13 +1152,20 @@ fn get_atl(
                 )
                 .unwrap();
 
-                match pkgs.get(&crt_lib_id) {
-                    Some(crt_libs) => {
-                        pruned.push(to_payload(crt_libs, &crt_libs.payloads[0]));
-                    }
-                    None => {
-                        tracing::warn!("Unable to locate '{}'", crt_lib_id);
-                    }
+                if let Some(crt_libs) = pkgs.get(&crt_lib_id) {
+                    pruned.push(atl_payload(crt_libs, &crt_libs.payloads[0]));
+                } else {
+                    tracing::warn!(
+                        "Unable to locate '{}'{}",
+                        crt_lib_id,
+                        did_you_mean(pkgs.keys(), &crt_lib_id)
+                    );
+                    diagnostics.push(Diagnostic::new(
+                        DiagnosticKind::MissingPackage,
+                        pkgs,
+                        &crt_lib_id,
+                        None,
+                    ));
                 }
             }
         }
@@ -478,43 +1174,212 @@ fn get_atl(
     Ok(())
 }
 
-fn get_latest_sdk_version<'keys>(
-    keys: impl Iterator<Item = &'keys String>,
-) -> Option<(String, versions::Version)> {
-    // Normally I would consider regex overkill for this, but we already use
-    // it for include scanning so...meh, this is only called once so there is
-    // no need to do one time initialization or the like (except in tests where it doesn't matter)
-    let regex = regex::Regex::new(r"^Win(\d+)SDK_(.+)").ok()?;
-    let (major, full) = keys
-        .filter_map(|key| {
-            let caps = regex.captures(key)?;
-            // So the SDK versions are, as usual for Microsoft, fucking stupid.
-            // A Win11 SDK still (currently) have a 10.* version...so we can't just
-            // assume that they will actually be ordered above a Win10 SDK? (though
-            // probably...but better to NOT assume, never trust Microsoft versions numbers)
-            let sdk_major: u8 = caps[1].parse().ok()?;
-            let version = versions::Version::new(&caps[2])?;
-            Some((sdk_major, version))
-        })
-        .max()?;
+fn get_dia(
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+    pruned: &mut Vec<Payload>,
+) -> Result<(), Error> {
+    // Unlike the CRT and ATL, the DIA SDK is a single VSIX that contains the
+    // headers as well as the libs for every architecture, so we just splat out
+    // the architectures that were actually requested
+    let dia = pkgs.get("Microsoft.VisualCpp.DIA.SDK").with_context(|| {
+        format!(
+            "unable to find DIA SDK item 'Microsoft.VisualCpp.DIA.SDK'{}",
+            did_you_mean(pkgs.keys(), "Microsoft.VisualCpp.DIA.SDK")
+        )
+    })?;
+
+    let payload = dia
+        .payloads
+        .first()
+        .with_context(|| format!("DIA SDK item '{}' has no payloads", dia.id))?;
+
+    pruned.push(Payload {
+        filename: payload.file_name.clone().into(),
+        sha256: payload.sha256.clone(),
+        url: payload.url.clone(),
+        size: payload.size,
+        install_size: (dia.payloads.len() == 1)
+            .then_some(dia)
+            .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive)),
+        kind: PayloadKind::DiaSdk,
+        variant: None,
+        target_arch: None,
+    });
 
-    Some((format!("Win{major}SDK_{full}"), full))
+    Ok(())
 }
 
-fn get_sdk(
+/// Selects the MSVC tools, ie. `cl.exe`, `link.exe` etc, that run on `host` and
+/// target each of the `arches`. The tools targeting the host itself are always
+/// selected, as the cross tools depend on them
+fn get_tools(
     pkgs: &BTreeMap<String, manifest::ManifestItem>,
+    crt_version: &str,
+    host: Arch,
     arches: u32,
+    pruned: &mut Vec<Payload>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<(), Error> {
+    // The tools packages only use uppercase architecture names, eg.
+    // `Microsoft.VC.14.38.17.8.Tools.HostX64.TargetARM64.base`
+    let upper = |arch: Arch| arch.as_ms_str().to_ascii_uppercase();
+
+    for target in Arch::iter(arches | host as u32) {
+        let prefix = format!(
+            "Microsoft.VC.{crt_version}.Tools.Host{}.Target{}",
+            upper(host),
+            upper(target)
+        );
+
+        // The resources, ie. the localized messages, are nice to have, but the
+        // tools work without them
+        for (id, required) in [
+            (format!("{prefix}.base"), true),
+            (format!("{prefix}.Res.base"), false),
+        ] {
+            let Some(mi) = pkgs.get(&id) else {
+                anyhow::ensure!(
+                    !required,
+                    "unable to find tools item '{id}'{}",
+                    did_you_mean(pkgs.keys(), &id)
+                );
+
+                tracing::warn!(
+                    "unable to find tools item '{id}'{}",
+                    did_you_mean(pkgs.keys(), &id)
+                );
+                diagnostics.push(Diagnostic::new(
+                    DiagnosticKind::MissingPackage,
+                    pkgs,
+                    &id,
+                    None,
+                ));
+                continue;
+            };
+
+            for payload in &mi.payloads {
+                pruned.push(Payload {
+                    filename: payload.file_name.clone().into(),
+                    sha256: payload.sha256.clone(),
+                    url: payload.url.clone(),
+                    size: payload.size,
+                    install_size: (mi.payloads.len() == 1)
+                        .then_some(mi)
+                        .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive)),
+                    kind: PayloadKind::CrtTools,
+                    // The tools targeting the host are needed no matter which
+                    // target is being splatted
+                    target_arch: (arches & target as u32 != 0).then_some(target),
+                    variant: None,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Microsoft periodically renames package ids, sometimes just by changing the
+/// casing, sometimes by changing the scheme entirely, so when an id we expect
+/// is missing we try to find the closest ids that actually exist in the manifest
+/// so that it is (hopefully) obvious what changed
+fn similar_ids<'keys>(
+    keys: impl Iterator<Item = &'keys String>,
+    expected: &str,
+) -> Vec<&'keys str> {
+    const MAX_SUGGESTIONS: usize = 3;
+
+    // Simple levenshtein distance, this is only ever used in error paths
+    // so there's no need to be clever
+    fn distance(a: &[u8], b: &[u8]) -> usize {
+        let mut prev: Vec<usize> = (0..=b.len()).collect();
+        let mut cur = vec![0; b.len() + 1];
+
+        for (i, ac) in a.iter().enumerate() {
+            cur[0] = i + 1;
+            for (j, bc) in b.iter().enumerate() {
+                let cost = usize::from(ac != bc);
+                cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+            }
+            std::mem::swap(&mut prev, &mut cur);
+        }
+
+        prev[b.len()]
+    }
+
+    let expected = expected.to_ascii_lowercase();
+    // Allow roughly a quarter of the id to differ, any more than that and the
+    // suggestions are mostly noise
+    let max_distance = (expected.len() / 4).max(2);
+
+    let mut similar: Vec<_> = keys
+        .filter_map(|key| {
+            let dist = distance(expected.as_bytes(), key.to_ascii_lowercase().as_bytes());
+            (dist <= max_distance).then_some((dist, key.as_str()))
+        })
+        .collect();
+
+    similar.sort();
+    similar
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, key)| key)
+        .collect()
+}
+
+/// Formats the ids similar to the one that was expected for use in a diagnostic
+fn did_you_mean<'keys>(keys: impl Iterator<Item = &'keys String>, expected: &str) -> String {
+    let similar = similar_ids(keys, expected);
+
+    if similar.is_empty() {
+        String::new()
+    } else {
+        format!(", did you mean one of: {}?", similar.join(", "))
+    }
+}
+
+fn get_latest_sdk_version<'keys>(
+    keys: impl Iterator<Item = &'keys String>,
+) -> Option<(String, versions::Version)> {
+    // Normally I would consider regex overkill for this, but we already use
+    // it for include scanning so...meh, this is only called once so there is
+    // no need to do one time initialization or the like (except in tests where it doesn't matter)
+    let regex = regex::Regex::new(r"^Win(\d+)SDK_(.+)").ok()?;
+    let (major, full) = keys
+        .filter_map(|key| {
+            let caps = regex.captures(key)?;
+            // So the SDK versions are, as usual for Microsoft, fucking stupid.
+            // A Win11 SDK still (currently) have a 10.* version...so we can't just
+            // assume that they will actually be ordered above a Win10 SDK? (though
+            // probably...but better to NOT assume, never trust Microsoft versions numbers)
+            let sdk_major: u8 = caps[1].parse().ok()?;
+            let version = versions::Version::new(&caps[2])?;
+            Some((sdk_major, version))
+        })
+        .max()?;
+
+    Some((format!("Win{major}SDK_{full}"), full))
+}
+
+fn get_sdk(
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+    arches: u32,
+    variants: u32,
     sdk_version: Option<String>,
+    include_winmd: bool,
     pruned: &mut Vec<Payload>,
 ) -> Result<String, Error> {
     let (sdk, sdk_version) = if let Some(sdk_version) = sdk_version {
-        let sv = versions::Version::new(&sdk_version)
-            .with_context(|| format!("invalid SDK version '{sdk_version}'"))?;
+        let sv = versions::Version::new(&sdk_version).with_context(|| {
+            ErrorKind::Selection.msg(format!("invalid SDK version '{sdk_version}'"))
+        })?;
 
         let (_, mi) = pkgs
             .iter()
             .find(|(key, _)| key.ends_with(&sdk_version))
-            .with_context(|| format!("unable to locate SDK '{sdk_version}'"))?;
+            .with_context(|| {
+                ErrorKind::Selection.msg(format!("unable to locate SDK '{sdk_version}'"))
+            })?;
 
         (mi, sv)
     } else {
@@ -533,118 +1398,94 @@ fn get_sdk(
     // the vast majority of the actual needed headers. However, it also doesn't
     // have all of them, as there are even more required headers in the completely
     // separate `Windows Store Apps Headers-x86` package as well. Incredibly annoying.
+    //
+    // On top of that, headers have moved between the different header packages
+    // across SDK versions (https://github.com/Jake-Shadle/xwin/pull/134,
+    // https://github.com/Jake-Shadle/xwin/issues/128), so rather than matching
+    // exact names, we take every headers package other than those for an
+    // architecture that wasn't requested
     {
-        let header_payload = sdk
-            .payloads
-            .iter()
-            .find(|payload| {
-                payload
-                    .file_name
-                    .ends_with("Windows SDK Desktop Headers x86-x86_en-us.msi")
-            })
-            .with_context(|| format!("unable to find headers for {}", sdk.id))?;
-
-        pruned.push(Payload {
-            filename: format!("{}_headers.msi", sdk.id).into(),
-            sha256: header_payload.sha256.clone(),
-            url: header_payload.url.clone(),
-            size: header_payload.size,
-            install_size: None,
-            kind: PayloadKind::SdkHeaders,
-            variant: None,
-            target_arch: None,
-        });
-
-        // https://github.com/Jake-Shadle/xwin/pull/134
-        // This was previously just part of the regular SDK headers
-        let header_payload = sdk.payloads.iter().find(|payload| {
-            payload
-                .file_name
-                .ends_with("Windows SDK OnecoreUap Headers x86-x86_en-us.msi")
-        });
-
-        if let Some(header_payload) = header_payload {
-            pruned.push(Payload {
-                filename: format!("{}_uap_headers.msi", sdk.id).into(),
-                sha256: header_payload.sha256.clone(),
-                url: header_payload.url.clone(),
-                size: header_payload.size,
-                install_size: None,
-                kind: PayloadKind::SdkHeaders,
-                variant: None,
-                target_arch: None,
-            });
-        }
-
-        let header_payload = sdk
-            .payloads
-            .iter()
-            .find(|payload| {
-                payload
-                    .file_name
-                    .ends_with("Windows SDK for Windows Store Apps Headers-x86_en-us.msi")
-            })
-            .with_context(|| format!("unable to find Windows SDK for Windows Store Apps Headers-x86_en-us.msi for {}", sdk.id))?;
-
-        pruned.push(Payload {
-            filename: format!("{}_store_headers.msi", sdk.id).into(),
-            sha256: header_payload.sha256.clone(),
-            url: header_payload.url.clone(),
-            size: header_payload.size,
-            install_size: None,
-            kind: PayloadKind::SdkHeaders,
-            variant: Some(Variant::Store),
-            target_arch: None,
-        });
+        let mut has_desktop = false;
+        let mut has_store = false;
 
-        // https://github.com/Jake-Shadle/xwin/issues/128
-        let header_payload = sdk.payloads.iter().find(|payload| {
-            payload
+        for header_payload in &sdk.payloads {
+            let Some((kind, rest)) = header_payload
                 .file_name
-                .ends_with("Windows SDK for Windows Store Apps Headers OnecoreUap-x86_en-us.msi")
-        });
+                .strip_prefix("Installers\\")
+                .unwrap_or(&header_payload.file_name)
+                .strip_prefix("Windows SDK ")
+                .and_then(|fname| fname.strip_suffix("-x86_en-us.msi"))
+                .and_then(|fname| fname.split_once("Headers"))
+            else {
+                continue;
+            };
 
-        if let Some(header_payload) = header_payload {
-            pruned.push(Payload {
-                filename: format!("{}_store_headers_onecoreuap.msi", sdk.id).into(),
-                sha256: header_payload.sha256.clone(),
-                url: header_payload.url.clone(),
-                size: header_payload.size,
-                install_size: None,
-                kind: PayloadKind::SdkHeaders,
-                variant: Some(Variant::Store),
-                target_arch: None,
-            });
-        }
+            let kind = kind.trim();
+            let rest = rest.trim();
 
-        for arch in Arch::iter(arches) {
-            if arch == Arch::X86 {
+            // The x86 package is the one that has the bulk of the headers, the
+            // other architectures only have a few architecture specific ones
+            let target_arch = Arch::iter(u32::MAX).find(|arch| arch.as_ms_str() == rest);
+            if target_arch.is_some_and(|arch| arch != Arch::X86 && arches & arch as u32 == 0) {
                 continue;
             }
 
-            let header_payload = sdk
-                .payloads
-                .iter()
-                .find(|payload| {
-                    payload
-                        .file_name
-                        .strip_prefix("Installers\\Windows SDK Desktop Headers ")
-                        .and_then(|fname| fname.strip_suffix("-x86_en-us.msi"))
-                        .map_or(false, |fname| fname == arch.as_ms_str())
-                })
-                .with_context(|| format!("unable to find {} headers for {}", arch, sdk.id))?;
+            // Keep the names for the packages that were previously matched
+            // exactly so that they are still found in the cache
+            let filename = match (kind, rest) {
+                ("Desktop", "x86") => {
+                    has_desktop = true;
+                    format!("{}_headers.msi", sdk.id)
+                }
+                ("OnecoreUap", "x86") => format!("{}_uap_headers.msi", sdk.id),
+                ("for Windows Store Apps", "") => {
+                    has_store = true;
+                    format!("{}_store_headers.msi", sdk.id)
+                }
+                ("for Windows Store Apps", "OnecoreUap") => {
+                    format!("{}_store_headers_onecoreuap.msi", sdk.id)
+                }
+                ("Desktop", arch) => format!("{}_{arch}_headers.msi", sdk.id),
+                (kind, rest) => {
+                    let name = format!("{kind} {rest}")
+                        .split(|c: char| !c.is_ascii_alphanumeric())
+                        .filter(|s| !s.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("_")
+                        .to_ascii_lowercase();
+
+                    format!("{}_{name}_headers.msi", sdk.id)
+                }
+            };
 
             pruned.push(Payload {
-                filename: format!("{}_{}_headers.msi", sdk.id, arch.as_ms_str()).into(),
+                filename: filename.into(),
                 sha256: header_payload.sha256.clone(),
                 url: header_payload.url.clone(),
                 size: header_payload.size,
                 install_size: None,
                 kind: PayloadKind::SdkHeaders,
-                variant: None,
-                target_arch: Some(arch),
+                variant: kind.contains("Store").then_some(Variant::Store),
+                target_arch: target_arch.filter(|arch| *arch != Arch::X86),
             });
         }
+
+        anyhow::ensure!(has_desktop, "unable to find headers for {}", sdk.id);
+
+        // The Store headers are only strictly needed when targeting the Store,
+        // so manifests that lack them can still be used for Desktop builds
+        if !has_store {
+            anyhow::ensure!(
+                variants & Variant::Store as u32 == 0,
+                "unable to find Windows SDK for Windows Store Apps Headers-x86_en-us.msi for {}",
+                sdk.id
+            );
+
+            tracing::warn!(
+                "unable to find Windows SDK for Windows Store Apps Headers-x86_en-us.msi for {}, some headers may be missing",
+                sdk.id
+            );
+        }
     }
 
     // Each target architecture has its own separate installer. Oh, and we also
@@ -703,11 +1544,46 @@ fn get_sdk(
         });
     }
 
+    // The WinRT metadata is only needed by people generating projections, eg.
+    // windows-rs or C++/WinRT, so it's opt-in
+    if include_winmd {
+        let winmd_payload = sdk
+            .payloads
+            .iter()
+            .find(|payload| {
+                payload
+                    .file_name
+                    .ends_with("Windows SDK for Windows Store Apps Metadata-x86_en-us.msi")
+            })
+            .with_context(|| {
+                format!(
+                    "unable to find Windows SDK for Windows Store Apps Metadata-x86_en-us.msi for {}",
+                    sdk.id
+                )
+            })?;
+
+        pruned.push(Payload {
+            filename: format!("{}_winmd.msi", sdk.id).into(),
+            sha256: winmd_payload.sha256.clone(),
+            url: winmd_payload.url.clone(),
+            size: winmd_payload.size,
+            install_size: None,
+            kind: PayloadKind::SdkMetadata,
+            variant: None,
+            target_arch: None,
+        });
+    }
+
     // We also need the Universal CRT, which is luckily all just in a single MSI
     {
-        let ucrt = pkgs
-            .get("Microsoft.Windows.UniversalCRT.HeadersLibsSources.Msi")
-            .context("unable to find Universal CRT")?;
+        const UCRT_ID: &str = "Microsoft.Windows.UniversalCRT.HeadersLibsSources.Msi";
+
+        let ucrt = pkgs.get(UCRT_ID).with_context(|| {
+            format!(
+                "unable to find Universal CRT '{UCRT_ID}'{}",
+                did_you_mean(pkgs.keys(), UCRT_ID)
+            )
+        })?;
 
         let msi = ucrt
             .payloads
@@ -734,15 +1610,120 @@ fn get_sdk(
 
 #[derive(serde::Serialize, serde::Deserialize, Default)]
 pub struct Map {
+    /// The splat layout the paths in the map are relative to, maps without
+    /// one are assumed to match the layout they are used with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<MapLayout>,
+    #[serde(default)]
     pub crt: Block,
+    #[serde(default)]
     pub sdk: Block,
+    /// Additional casings to create as symlinks for any splatted file, keyed
+    /// by the file name as it is on disk, eg. `"libcmt.lib" = ["LIBCMT.lib"]`
+    ///
+    /// Unlike the rest of the map, this doesn't filter the splatted files
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub casings: BTreeMap<String, Vec<String>>,
 }
 
 impl Map {
+    /// Whether the map actually filters the splatted files, or only specifies
+    /// additional casings
+    pub(crate) fn has_filters(&self) -> bool {
+        [
+            &self.crt.headers,
+            &self.crt.libs,
+            &self.sdk.headers,
+            &self.sdk.libs,
+        ]
+        .iter()
+        .any(|section| !section.filter.is_empty())
+    }
+
     fn clear(&mut self) {
         self.crt.clear();
         self.sdk.clear();
     }
+
+    /// Translates the library paths from the layout the map was generated
+    /// for to another layout, so that a map can be used regardless of the
+    /// layout it was generated with. The header paths are the same in every
+    /// layout.
+    pub(crate) fn translate(&mut self, to: MapLayout, sdk_version: &str) {
+        let Some(from) = self.layout else {
+            return;
+        };
+
+        if from == to {
+            return;
+        }
+
+        let arch_name = |arch: Arch, ms: bool| {
+            if ms {
+                arch.as_ms_str()
+            } else {
+                arch.as_str()
+            }
+        };
+
+        let translate_arch = |comp: &str| -> String {
+            Arch::iter(u32::MAX)
+                .find(|arch| arch_name(*arch, from.ms_arch_notation) == comp)
+                .map_or(comp, |arch| arch_name(arch, to.ms_arch_notation))
+                .to_owned()
+        };
+
+        // CRT lib paths are `<arch>/<lib>`, SDK lib paths are
+        // `[<sdk_version>/]<um|ucrt>/<arch>/<lib>`
+        let translate = |path: &str, sdk: bool| -> String {
+            let (glob, path) = match path.strip_prefix(Section::GLOB_PREFIX) {
+                Some(pattern) => (Section::GLOB_PREFIX, pattern),
+                None => ("", path),
+            };
+
+            let mut comps: Vec<_> = path.split('/').map(String::from).collect();
+
+            if sdk && from.winsysroot && comps.len() > 1 {
+                comps.remove(0);
+            }
+
+            let arch_index = usize::from(sdk);
+            if comps.len() > arch_index + 1 {
+                comps[arch_index] = translate_arch(&comps[arch_index]);
+            }
+
+            if sdk && to.winsysroot {
+                comps.insert(0, sdk_version.to_owned());
+            }
+
+            format!("{glob}{}", comps.join("/"))
+        };
+
+        for (section, sdk) in [(&mut self.crt.libs, false), (&mut self.sdk.libs, true)] {
+            section.filter = std::mem::take(&mut section.filter)
+                .into_iter()
+                .map(|path| translate(&path, sdk))
+                .collect();
+            section.symlinks = std::mem::take(&mut section.symlinks)
+                .into_iter()
+                .map(|(path, links)| (translate(&path, sdk), links))
+                .collect();
+        }
+
+        self.layout = Some(to);
+    }
+}
+
+/// The splat layout a [`Map`] was generated for
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MapLayout {
+    /// Whether the map is for the /winsysroot layout, where the SDK libs are
+    /// in a directory named after the SDK version
+    #[serde(default)]
+    pub winsysroot: bool,
+    /// Whether the architecture directories use the MS notation, eg. `x64`
+    #[serde(default)]
+    pub ms_arch_notation: bool,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default)]
@@ -775,15 +1756,61 @@ pub struct Section {
 }
 
 impl Section {
+    /// The prefix for filter entries that are glob patterns rather than exact
+    /// relative paths, eg. `glob:um/*`
+    pub const GLOB_PREFIX: &'static str = "glob:";
+
+    /// Whether the relative path is kept by the filter, either because it is
+    /// listed exactly, or because it matches one of the glob patterns
+    pub(crate) fn keeps(&self, path: &str) -> bool {
+        if self.filter.contains(path) {
+            return true;
+        }
+
+        // The patterns all share the same prefix, so they are contiguous in
+        // the sorted set and we don't need to check every entry
+        self.filter
+            .range::<str, _>((
+                std::ops::Bound::Included(Self::GLOB_PREFIX),
+                std::ops::Bound::Unbounded,
+            ))
+            .map_while(|entry| entry.strip_prefix(Self::GLOB_PREFIX))
+            .any(|pattern| util::glob_match(pattern, path))
+    }
+
     fn clear(&mut self) {
         self.filter.clear();
         self.symlinks.clear();
     }
 }
 
+/// Removes `link` if it is already a symlink, so that it can be recreated,
+/// eg. when splatting over the output of a previous run that was interrupted.
+/// Regular files and directories are never removed.
+fn remove_existing_link(link: &Path) -> Result<(), Error> {
+    if !link.is_symlink() {
+        return Ok(());
+    }
+
+    // Directory symlinks on Windows have to be removed as directories
+    #[cfg(windows)]
+    let res = std::fs::remove_dir(link).or_else(|_| std::fs::remove_file(link));
+    #[cfg(not(windows))]
+    let res = std::fs::remove_file(link);
+
+    res.with_context(|| format!("unable to remove existing symlink {link}"))
+}
+
+/// Creates a symlink, replacing any existing symlink at the same path.
+///
+/// `original` is relative to the directory of `link`, and every symlink in a
+/// splat is created this way so that it can be moved or archived, the only
+/// exceptions being `--winsysroot-bin`, which links to `lld-link` outside of
+/// the splat, and `--absolute-symlinks`
 #[cfg(unix)]
 #[inline]
 fn symlink(original: &str, link: &Path) -> Result<(), Error> {
+    remove_existing_link(link)?;
     std::os::unix::fs::symlink(original, link)
         .with_context(|| format!("unable to symlink from {link} to {original}"))
 }
@@ -794,6 +1821,75 @@ fn symlink(_original: &str, _link: &Path) -> Result<(), Error> {
     Ok(())
 }
 
+/// Copies `original`, relative to the parent directory of `link`, to `link`,
+/// which is used to create alternate casings when symlinks can't be used. This
+/// fails if `link` is the same file as `original`, ie. the file system is
+/// case-insensitive, as the alternate casing can't be a distinct file.
+fn copy_link(original: &str, link: &Path) -> Result<(), Error> {
+    let src = link
+        .parent()
+        .with_context(|| format!("{link} has no parent directory"))?
+        .join(original);
+    let src_md = std::fs::metadata(&src).with_context(|| format!("unable to read {src}"))?;
+
+    remove_existing_link(link)?;
+
+    anyhow::ensure!(
+        !same_file(&src, link),
+        "unable to create {link} as a copy of {src}, as they are the same file on this (case-insensitive) file system"
+    );
+
+    if src_md.is_file() {
+        std::fs::copy(&src, link).with_context(|| format!("failed to copy {src} to {link}"))?;
+        return Ok(());
+    }
+
+    for entry in walkdir::WalkDir::new(&src) {
+        let entry = entry.with_context(|| format!("failed to walk {src}"))?;
+        let rel = entry
+            .path()
+            .strip_prefix(&src)
+            .with_context(|| format!("{} is not a child of {src}", entry.path().display()))?;
+        let target = link.as_std_path().join(rel);
+
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)
+                .with_context(|| format!("unable to create {}", target.display()))?;
+        } else {
+            std::fs::copy(entry.path(), &target).with_context(|| {
+                format!(
+                    "failed to copy {} to {}",
+                    entry.path().display(),
+                    target.display()
+                )
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether both paths exist and are the same file, eg. because they only
+/// differ in casing on a case-insensitive file system
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+        _ => false,
+    }
+}
+
+#[cfg(windows)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    // The canonical path has the casing of the file that actually exists
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
 #[inline]
 fn symlink_on_windows_too(original: &str, link: &Path) -> Result<(), Error> {
     #[cfg(unix)]
@@ -805,6 +1901,7 @@ fn symlink_on_windows_too(original: &str, link: &Path) -> Result<(), Error> {
     {
         let full_path = link.parent().unwrap().join(original);
         if full_path.is_dir() {
+            remove_existing_link(link)?;
             std::os::windows::fs::symlink_dir(original, link)
                 .with_context(|| format!("unable to symlink from {link} to {original}"))
         } else {
@@ -842,4 +1939,674 @@ mod test {
             glsv(just_11.iter().chain(just_10.iter())).unwrap().0
         );
     }
+
+    #[test]
+    fn similar_ids() {
+        let ids = [
+            "Microsoft.VC.14.38.17.8.CRT.Headers".to_owned(),
+            "Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base".to_owned(),
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.base".to_owned(),
+            "Microsoft.Windows.UniversalCRT.HeadersLibsSources.Msi".to_owned(),
+        ];
+
+        // Suffix was dropped
+        assert_eq!(
+            super::similar_ids(ids.iter(), "Microsoft.VC.14.38.17.8.CRT.Headers.base")[0],
+            "Microsoft.VC.14.38.17.8.CRT.Headers"
+        );
+
+        // Casing changed
+        assert_eq!(
+            super::similar_ids(ids.iter(), "Microsoft.VC.14.38.17.8.CRT.arm64.Desktop.base")[0],
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.base"
+        );
+
+        assert!(super::similar_ids(ids.iter(), "Win10SDK_10.0.22621").is_empty());
+    }
+
+    #[test]
+    fn payload_arches() {
+        use super::{payload_filename as pf, payload_target_arch as pta, Arch};
+
+        let arm64 = "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.base.vsix";
+        assert_eq!(pta(arm64), Some(Arch::Aarch64));
+        assert_eq!(
+            pf(arm64, pta(arm64)),
+            "Microsoft.VC.14.38.17.8.CRT.arm64.Desktop.base.vsix"
+        );
+
+        let arm = "Microsoft.VC.14.38.17.8.CRT.arm.Desktop.base.vsix";
+        assert_eq!(pta(arm), Some(Arch::Aarch));
+        assert_eq!(pf(arm, pta(arm)), arm);
+
+        assert_eq!(
+            pta("Microsoft.VC.14.38.17.8.ATL.x64.Spectre.base.vsix"),
+            Some(Arch::X86_64)
+        );
+        assert_eq!(pta("Microsoft.VC.14.38.17.8.CRT.Headers.base.vsix"), None);
+
+        // ARM64EC isn't supported yet, but mustn't be mistaken for, or mangled
+        // like, ARM64
+        let arm64ec = "Microsoft.VC.14.38.17.8.CRT.ARM64EC.Desktop.base.vsix";
+        assert_eq!(pta(arm64ec), None);
+        assert_eq!(pf(arm64ec, Some(Arch::Aarch64)), arm64ec);
+    }
+
+    #[test]
+    fn unselected_crt_libs() {
+        use super::{Arch, DiagnosticKind, Variant};
+
+        let pkgs: super::BTreeMap<_, _> = [
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.spectre.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.neon.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.OneCore.Desktop.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Store.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64EC.Desktop.base",
+            "Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base",
+        ]
+        .into_iter()
+        .map(|id| {
+            (
+                id.to_owned(),
+                crate::manifest::ManifestItem {
+                    id: id.to_owned(),
+                    version: "14.38.33130".to_owned(),
+                    kind: crate::manifest::ItemKind::Vsix,
+                    chip: None,
+                    payloads: Vec::new(),
+                    dependencies: Default::default(),
+                    install_sizes: None,
+                    localized_resources: Vec::new(),
+                },
+            )
+        })
+        .collect();
+
+        let selected = [
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.base".to_owned(),
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Store.base".to_owned(),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut diagnostics = Vec::new();
+        super::audit_crt_libs(
+            &pkgs,
+            "14.38.17.8",
+            Arch::Aarch64,
+            Variant::Desktop as u32 | Variant::Store as u32,
+            false,
+            &selected,
+            &mut diagnostics,
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::UnselectedPackage);
+        assert_eq!(
+            diagnostics[0].id,
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.neon.base"
+        );
+    }
+
+    #[test]
+    fn aarch64_crt_pieces() {
+        use super::{Arch, PayloadKind, Variant};
+
+        let item = |id: &str| crate::manifest::ManifestItem {
+            id: id.to_owned(),
+            version: "14.38.33130".to_owned(),
+            kind: crate::manifest::ItemKind::Vsix,
+            chip: None,
+            payloads: vec![crate::manifest::Payload {
+                file_name: format!("{id}.vsix"),
+                sha256: crate::util::Sha256([0; 32]),
+                size: 1,
+                url: String::new(),
+            }],
+            dependencies: Default::default(),
+            install_sizes: None,
+            localized_resources: Vec::new(),
+        };
+
+        let mut build_tools = item("Microsoft.VisualStudio.Product.BuildTools");
+        build_tools.dependencies.insert(
+            "Microsoft.VisualStudio.Component.VC.14.38.17.8.x86.x64".to_owned(),
+            serde_json::Value::Null,
+        );
+
+        let mut pkgs: super::BTreeMap<_, _> = [
+            "Microsoft.VC.14.38.17.8.CRT.Headers.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.neon.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.softfp.base",
+            "Microsoft.VC.14.38.17.8.CRT.ARM64.Desktop.spectre.neon.base",
+            "Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base",
+        ]
+        .into_iter()
+        .map(|id| (id.to_owned(), item(id)))
+        .collect();
+        pkgs.insert(build_tools.id.clone(), build_tools);
+
+        let select = |arch: Arch| {
+            let mut pruned = Vec::new();
+            let mut diagnostics = Vec::new();
+            super::get_crt(
+                &pkgs,
+                arch as u32,
+                Variant::Desktop as u32,
+                &super::PruneOptions {
+                    skip_store_crt: true,
+                    ..Default::default()
+                },
+                &mut pruned,
+                &mut diagnostics,
+            )
+            .unwrap();
+
+            assert!(diagnostics.is_empty());
+            pruned
+                .into_iter()
+                .filter(|payload| payload.kind == PayloadKind::CrtLibs)
+                .map(|payload| payload.filename.into_string())
+                .collect::<Vec<_>>()
+        };
+
+        // The pieces are selected alongside the base package, but not the
+        // spectre piece as the spectre libs weren't requested
+        assert_eq!(
+            select(Arch::Aarch64),
+            [
+                "Microsoft.VC.14.38.17.8.CRT.arm64.Desktop.base.vsix",
+                "Microsoft.VC.14.38.17.8.CRT.arm64.Desktop.neon.base.vsix",
+                "Microsoft.VC.14.38.17.8.CRT.arm64.Desktop.softfp.base.vsix",
+            ]
+        );
+        assert_eq!(
+            select(Arch::X86_64),
+            ["Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base.vsix"]
+        );
+    }
+
+    #[test]
+    fn sdk_payload_kinds() {
+        use super::{Arch, PayloadKind, Variant};
+
+        let detect = |file_name: &str| {
+            super::sdk_payload(&crate::manifest::Payload {
+                file_name: file_name.to_owned(),
+                sha256: crate::util::Sha256([0; 32]),
+                size: 1,
+                url: String::new(),
+            })
+            .map(|payload| (payload.kind, payload.target_arch, payload.variant))
+        };
+
+        assert_eq!(
+            detect("Installers\\Windows SDK Desktop Headers x86-x86_en-us.msi"),
+            Some((PayloadKind::SdkHeaders, None, None))
+        );
+        assert_eq!(
+            detect("Installers\\Windows SDK Desktop Headers arm64-x86_en-us.msi"),
+            Some((PayloadKind::SdkHeaders, Some(Arch::Aarch64), None))
+        );
+        assert_eq!(
+            detect("Installers\\Windows SDK for Windows Store Apps Headers-x86_en-us.msi"),
+            Some((PayloadKind::SdkHeaders, None, Some(Variant::Store)))
+        );
+        assert_eq!(
+            detect("Installers\\Windows SDK Desktop Libs x64-x86_en-us.msi"),
+            Some((PayloadKind::SdkLibs, Some(Arch::X86_64), None))
+        );
+        assert_eq!(
+            detect("Installers\\Windows SDK for Windows Store Apps Libs-x86_en-us.msi"),
+            Some((PayloadKind::SdkStoreLibs, None, None))
+        );
+        assert_eq!(
+            detect("Installers\\Windows SDK for Windows Store Apps Metadata-x86_en-us.msi"),
+            Some((PayloadKind::SdkMetadata, None, None))
+        );
+        assert_eq!(
+            detect("Universal CRT Headers Libraries and Sources-x86_en-us.msi"),
+            Some((PayloadKind::Ucrt, None, None))
+        );
+        assert_eq!(
+            detect("Installers\\Windows SDK Signing Tools-x86_en-us.msi"),
+            None
+        );
+    }
+
+    #[test]
+    fn dia_selection() {
+        use super::PayloadKind;
+
+        let mut pkgs = super::BTreeMap::new();
+        let mut pruned = Vec::new();
+        assert!(super::get_dia(&pkgs, &mut pruned).is_err());
+
+        pkgs.insert(
+            "Microsoft.VisualCpp.DIA.SDK".to_owned(),
+            crate::manifest::ManifestItem {
+                id: "Microsoft.VisualCpp.DIA.SDK".to_owned(),
+                version: "17.8.34205.153".to_owned(),
+                kind: crate::manifest::ItemKind::Vsix,
+                chip: None,
+                payloads: vec![crate::manifest::Payload {
+                    file_name: "Microsoft.VisualCpp.DIA.SDK.vsix".to_owned(),
+                    sha256: crate::util::Sha256([0; 32]),
+                    size: 1,
+                    url: String::new(),
+                }],
+                dependencies: Default::default(),
+                install_sizes: None,
+                localized_resources: Vec::new(),
+            },
+        );
+
+        super::get_dia(&pkgs, &mut pruned).unwrap();
+
+        // The single payload has the libs for every architecture
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].kind, PayloadKind::DiaSdk);
+        assert_eq!(pruned[0].filename, "Microsoft.VisualCpp.DIA.SDK.vsix");
+        assert_eq!(pruned[0].target_arch, None);
+    }
+
+    #[test]
+    fn minimal_preset() {
+        use super::{Arch, Payload, PayloadKind, Preset, Variant};
+
+        let payload = |kind, target_arch, variant| Payload {
+            filename: format!("{kind:?}-{target_arch:?}-{variant:?}").into(),
+            sha256: crate::util::Sha256([0; 32]),
+            url: String::new(),
+            size: 0,
+            install_size: None,
+            kind,
+            target_arch,
+            variant,
+        };
+
+        let all = || {
+            let mut payloads = vec![
+                payload(PayloadKind::Ucrt, None, None),
+                payload(PayloadKind::CrtHeaders, None, None),
+                payload(PayloadKind::AtlHeaders, None, None),
+                payload(PayloadKind::SdkHeaders, None, None),
+                payload(PayloadKind::SdkMetadata, None, None),
+                payload(PayloadKind::DiaSdk, None, None),
+            ];
+            for arch in [Arch::X86_64, Arch::Aarch64] {
+                payloads.extend([
+                    payload(PayloadKind::CrtLibs, Some(arch), Some(Variant::Desktop)),
+                    payload(PayloadKind::CrtLibs, Some(arch), Some(Variant::Store)),
+                    payload(PayloadKind::AtlLibs, Some(arch), None),
+                    payload(PayloadKind::SdkHeaders, Some(arch), None),
+                    payload(PayloadKind::SdkLibs, Some(arch), None),
+                    payload(PayloadKind::SdkStoreLibs, Some(arch), None),
+                    payload(PayloadKind::CrtTools, Some(arch), None),
+                ]);
+            }
+            payloads
+        };
+
+        // Only the first selected architecture is kept
+        let mut payloads = all();
+        let arches = Preset::Minimal
+            .apply(&mut payloads, Arch::X86_64 as u32 | Arch::Aarch64 as u32)
+            .unwrap();
+        assert_eq!(arches, Arch::X86_64 as u32);
+
+        let kept: Vec<_> = payloads.iter().map(|pay| pay.filename.as_str()).collect();
+        assert_eq!(
+            kept,
+            [
+                "Ucrt-None-None",
+                "CrtHeaders-None-None",
+                "SdkHeaders-None-None",
+                "CrtLibs-Some(X86_64)-Some(Desktop)",
+                "SdkHeaders-Some(X86_64)-None",
+                "SdkLibs-Some(X86_64)-None",
+            ]
+        );
+
+        let mut payloads = all();
+        assert!(Preset::Minimal.apply(&mut payloads, 0).is_err());
+        assert_eq!("minimal".parse::<Preset>().unwrap(), Preset::Minimal);
+        assert!("full".parse::<Preset>().is_err());
+    }
+
+    #[test]
+    fn tools_selection() {
+        use super::{Arch, DiagnosticKind, PayloadKind};
+
+        let mut pkgs = super::BTreeMap::new();
+        for id in [
+            "Microsoft.VC.14.38.17.8.Tools.HostX64.TargetX64.base",
+            "Microsoft.VC.14.38.17.8.Tools.HostX64.TargetX64.Res.base",
+            "Microsoft.VC.14.38.17.8.Tools.HostX64.TargetARM64.base",
+        ] {
+            pkgs.insert(
+                id.to_owned(),
+                crate::manifest::ManifestItem {
+                    id: id.to_owned(),
+                    version: "14.38.33130".to_owned(),
+                    kind: crate::manifest::ItemKind::Vsix,
+                    chip: None,
+                    payloads: vec![crate::manifest::Payload {
+                        file_name: format!("{id}.vsix"),
+                        sha256: crate::util::Sha256([0; 32]),
+                        size: 1,
+                        url: String::new(),
+                    }],
+                    dependencies: Default::default(),
+                    install_sizes: None,
+                    localized_resources: Vec::new(),
+                },
+            );
+        }
+
+        let mut pruned = Vec::new();
+        let mut diagnostics = Vec::new();
+        super::get_tools(
+            &pkgs,
+            "14.38.17.8",
+            Arch::X86_64,
+            Arch::Aarch64 as u32,
+            &mut pruned,
+            &mut diagnostics,
+        )
+        .unwrap();
+
+        // The host tools are always selected, but aren't specific to a target
+        let selected: Vec<_> = pruned
+            .iter()
+            .map(|pay| {
+                assert_eq!(pay.kind, PayloadKind::CrtTools);
+                (pay.filename.as_str(), pay.target_arch)
+            })
+            .collect();
+        assert_eq!(
+            selected,
+            [
+                (
+                    "Microsoft.VC.14.38.17.8.Tools.HostX64.TargetX64.base.vsix",
+                    None
+                ),
+                (
+                    "Microsoft.VC.14.38.17.8.Tools.HostX64.TargetX64.Res.base.vsix",
+                    None
+                ),
+                (
+                    "Microsoft.VC.14.38.17.8.Tools.HostX64.TargetARM64.base.vsix",
+                    Some(Arch::Aarch64)
+                ),
+            ]
+        );
+
+        // The resources are optional
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::MissingPackage);
+        assert_eq!(
+            diagnostics[0].id,
+            "Microsoft.VC.14.38.17.8.Tools.HostX64.TargetARM64.Res.base"
+        );
+
+        // But the tools aren't
+        assert!(super::get_tools(
+            &pkgs,
+            "14.38.17.8",
+            Arch::X86_64,
+            Arch::X86 as u32,
+            &mut pruned,
+            &mut diagnostics,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn package_payload_kinds() {
+        use super::{Arch, PayloadKind, Variant};
+
+        let item = |id: &str, file_names: &[&str]| {
+            (
+                id.to_owned(),
+                crate::manifest::ManifestItem {
+                    id: id.to_owned(),
+                    version: "1.0".to_owned(),
+                    kind: crate::manifest::ItemKind::Vsix,
+                    chip: None,
+                    payloads: file_names
+                        .iter()
+                        .map(|file_name| crate::manifest::Payload {
+                            file_name: (*file_name).to_owned(),
+                            sha256: crate::util::Sha256([0; 32]),
+                            size: 1,
+                            url: String::new(),
+                        })
+                        .collect(),
+                    dependencies: Default::default(),
+                    install_sizes: None,
+                    localized_resources: Vec::new(),
+                },
+            )
+        };
+
+        let pkgs: super::BTreeMap<_, _> = [
+            item(
+                "Microsoft.VC.14.38.17.8.CRT.ARM64.Store.base",
+                &["Microsoft.VC.14.38.17.8.CRT.ARM64.Store.base.vsix"],
+            ),
+            item(
+                "Microsoft.VC.14.38.17.8.ATL.Headers.base",
+                &["Microsoft.VC.14.38.17.8.ATL.Headers.base.vsix"],
+            ),
+            item(
+                "Win11SDK_10.0.22621",
+                &[
+                    "Installers\\Windows SDK Desktop Libs x64-x86_en-us.msi",
+                    "Installers\\16ab2ea2187acffa6435e334796c8c89.cab",
+                ],
+            ),
+            item(
+                "Microsoft.VisualCpp.DIA.SDK",
+                &["Microsoft.VisualCpp.DIA.SDK.vsix"],
+            ),
+            item("Microsoft.Unknown", &["Installers\\unknown.msi"]),
+        ]
+        .into_iter()
+        .collect();
+
+        let kinds = |id: &str| {
+            super::package_payloads(&pkgs, id)
+                .unwrap()
+                .into_iter()
+                .map(|payload| {
+                    (
+                        payload.filename.into_string(),
+                        payload.kind,
+                        payload.target_arch,
+                        payload.variant,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            kinds("Microsoft.VC.14.38.17.8.CRT.ARM64.Store.base"),
+            [(
+                "Microsoft.VC.14.38.17.8.CRT.arm64.Store.base.vsix".to_owned(),
+                PayloadKind::CrtLibs,
+                Some(Arch::Aarch64),
+                Some(Variant::Store)
+            )]
+        );
+        assert_eq!(
+            kinds("Microsoft.VC.14.38.17.8.ATL.Headers.base"),
+            [(
+                "Microsoft.VC.14.38.17.8.ATL.Headers.base.vsix".to_owned(),
+                PayloadKind::AtlHeaders,
+                None,
+                None
+            )]
+        );
+        // The CABs are downloaded along with the MSI
+        assert_eq!(
+            kinds("Win11SDK_10.0.22621"),
+            [(
+                "Windows SDK Desktop Libs x64-x86_en-us.msi".to_owned(),
+                PayloadKind::SdkLibs,
+                Some(Arch::X86_64),
+                None
+            )]
+        );
+        assert_eq!(
+            kinds("Microsoft.VisualCpp.DIA.SDK"),
+            [(
+                "Microsoft.VisualCpp.DIA.SDK.vsix".to_owned(),
+                PayloadKind::DiaSdk,
+                None,
+                None
+            )]
+        );
+        // Payloads whose kind can't be detected can still be extracted
+        assert_eq!(kinds("Microsoft.Unknown")[0].0, "unknown.msi");
+        assert!(super::package_payloads(&pkgs, "Microsoft.Missing").is_err());
+    }
+
+    #[test]
+    fn map_layout_translation() {
+        use super::{Map, MapLayout};
+
+        let default = MapLayout {
+            winsysroot: false,
+            ms_arch_notation: false,
+        };
+        let winsysroot = MapLayout {
+            winsysroot: true,
+            ms_arch_notation: true,
+        };
+
+        let mut map = Map {
+            layout: Some(default),
+            ..Default::default()
+        };
+        map.crt.libs.filter.insert("x86_64/libcmt.lib".to_owned());
+        map.crt.libs.symlinks.insert(
+            "x86_64/libcmt.lib".to_owned(),
+            vec!["LIBCMT.lib".to_owned()],
+        );
+        map.sdk
+            .libs
+            .filter
+            .insert("um/x86_64/kernel32.lib".to_owned());
+        map.sdk.libs.filter.insert("glob:ucrt/aarch64/*".to_owned());
+        map.sdk.headers.filter.insert("um/x86_64/fake.h".to_owned());
+
+        map.translate(winsysroot, "10.0.22621");
+
+        assert_eq!(map.layout, Some(winsysroot));
+        assert!(map.crt.libs.filter.contains("x64/libcmt.lib"));
+        assert!(map.crt.libs.symlinks.contains_key("x64/libcmt.lib"));
+        assert!(map
+            .sdk
+            .libs
+            .filter
+            .contains("10.0.22621/um/x64/kernel32.lib"));
+        assert!(map.sdk.libs.filter.contains("glob:10.0.22621/ucrt/arm64/*"));
+        // Headers are the same in every layout
+        assert!(map.sdk.headers.filter.contains("um/x86_64/fake.h"));
+
+        map.translate(default, "10.0.22621");
+
+        assert!(map.crt.libs.filter.contains("x86_64/libcmt.lib"));
+        assert!(map.sdk.libs.filter.contains("um/x86_64/kernel32.lib"));
+        assert!(map.sdk.libs.filter.contains("glob:ucrt/aarch64/*"));
+    }
+
+    #[test]
+    fn map_filter_globs() {
+        let section = super::Section {
+            filter: [
+                "glob:um/*",
+                "glob:shared/d3d*.h",
+                "shared/winapifamily.h",
+                "winrt/wrl.h",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            symlinks: Default::default(),
+        };
+
+        assert!(section.keeps("um/Windows.h"));
+        assert!(section.keeps("um/gl/GL.h"));
+        assert!(section.keeps("shared/d3d9types.h"));
+        assert!(section.keeps("shared/winapifamily.h"));
+        assert!(section.keeps("winrt/wrl.h"));
+
+        assert!(!section.keeps("shared/dxgi.h"));
+        assert!(!section.keeps("winrt/roapi.h"));
+        assert!(!section.keeps("ucrt/stdio.h"));
+    }
+
+    /// Re-running a splat over existing output needs to replace the symlinks
+    /// it created previously, but never real files
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_replaced() {
+        use crate::PathBuf;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        std::fs::write(root.join("Windows.h"), "").unwrap();
+        std::fs::write(root.join("WinSock2.h"), "").unwrap();
+
+        let link = root.join("windows.h");
+        super::symlink("Windows.h", &link).unwrap();
+        super::symlink("WinSock2.h", &link).unwrap();
+        assert_eq!(
+            std::fs::read_link(&link).unwrap().to_str(),
+            Some("WinSock2.h")
+        );
+
+        assert!(super::symlink("Windows.h", &root.join("WinSock2.h")).is_err());
+    }
+
+    #[test]
+    fn copy_links() {
+        let td = tempfile::tempdir().unwrap();
+        let dir = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        std::fs::write(dir.join("Windows.h"), "windows").unwrap();
+        std::fs::create_dir_all(dir.join("Include/um")).unwrap();
+        std::fs::write(dir.join("Include/um/WinSock2.h"), "winsock").unwrap();
+
+        // The alternate casings can't be distinct files
+        if dir.join("WINDOWS.H").exists() {
+            assert!(super::copy_link("Windows.h", &dir.join("windows.h")).is_err());
+            return;
+        }
+
+        super::copy_link("Windows.h", &dir.join("windows.h")).unwrap();
+        super::copy_link("Include", &dir.join("include")).unwrap();
+
+        for (path, contents) in [
+            ("windows.h", "windows"),
+            ("include/um/WinSock2.h", "winsock"),
+        ] {
+            let path = dir.join(path);
+            assert!(!path.is_symlink());
+            assert_eq!(std::fs::read_to_string(path).unwrap(), contents);
+        }
+
+        // Copying again, eg. when resuming, replaces the copy
+        std::fs::write(dir.join("Windows.h"), "updated").unwrap();
+        super::copy_link("Windows.h", &dir.join("windows.h")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("windows.h")).unwrap(),
+            "updated"
+        );
+    }
 }
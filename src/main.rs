@@ -5,7 +5,7 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 use anyhow::{Context as _, Error};
 use camino::Utf8PathBuf as PathBuf;
 use clap::builder::{PossibleValuesParser, TypedValueParser as _};
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory as _, FromArgMatches as _, Parser, Subcommand};
 use indicatif as ia;
 use std::time::Duration;
 use tracing_subscriber::filter::LevelFilter;
@@ -65,6 +65,11 @@ pub struct SplatOptions {
     /// and --disable-symlinks for use with clang-cl on Windows.
     #[arg(long)]
     use_winsysroot_style: bool,
+    /// A relative path under the output directory in which the CRT and SDK
+    /// are placed, eg. `--prefix 17.8.3`, allowing multiple toolchains to
+    /// be splatted side by side in the same output directory
+    #[arg(long)]
+    prefix: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -74,18 +79,78 @@ pub enum Command {
     /// Note that this is not a full list as the SDK uses MSI files for many
     /// packages, so they would need to be downloaded and inspected to determine
     /// which CAB files must also be downloaded to get the content needed.
-    List,
+    List {
+        /// Adds a column with the sha256 checksum of each payload
+        #[arg(long)]
+        show_hashes: bool,
+        /// Only prints the number of payloads and their total download and
+        /// install sizes in bytes, one per line, instead of the full table
+        #[arg(long, conflicts_with = "show_hashes")]
+        summary: bool,
+    },
+    /// Prints the effective configuration, ie. the cache directory, the
+    /// manifest, the resolved CRT and SDK versions, and the selected
+    /// architectures and variants, without downloading any payloads, eg. to
+    /// include in a bug report
+    Info,
     /// Downloads all the selected packages that aren't already present in
     /// the download cache
-    Download,
+    Download {
+        /// Writes a `CycloneDX` SBOM of the downloaded payloads to the path
+        #[arg(long)]
+        sbom: Option<PathBuf>,
+        /// Prints the resolved CRT and SDK versions and the packages that will
+        /// be downloaded, along with the total download and install size, and
+        /// asks for confirmation before downloading them
+        #[arg(long)]
+        plan: bool,
+        /// Skips the confirmation when using `--plan`
+        #[arg(long, requires = "plan")]
+        yes: bool,
+    },
+    /// Lists every CAB file referenced by the selected MSI packages, along
+    /// with its size, which aren't included by `list`.
+    ///
+    /// Only the MSIs are downloaded to read the CABs they reference, not the
+    /// CABs themselves.
+    ListCabs {
+        /// Prints the CABs as JSON, including their url and sha256 checksum,
+        /// rather than as a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Writes a JSON index of every file in the selected packages, and the
+    /// payload(s) each one is unpacked from.
+    ///
+    /// Only the packages themselves are downloaded, the CAB files that contain
+    /// the actual contents of MSI packages are not needed, and nothing is
+    /// unpacked.
+    Index {
+        /// The path to write the index to. Defaults to stdout if not specified.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
     /// Unpacks all of the downloaded packages to disk
     Unpack,
+    /// Downloads and unpacks a single package from the manifest, by its id,
+    /// to a directory, bypassing the normal CRT and SDK selection.
+    ///
+    /// Each of the package's payloads is unpacked to its own subdirectory,
+    /// including the files that are skipped when splatting. This is mainly
+    /// useful for investigating the layout of a package.
+    Extract {
+        /// The id of the package in the manifest, eg.
+        /// `Microsoft.VC.14.38.17.8.CRT.Headers.base`
+        package: String,
+        /// The directory to unpack the package to
+        output: PathBuf,
+    },
     /// Fixes the packages to prune unneeded files and adds symlinks to address
     /// file casing issues and then spalts the final artifacts into directories
     Splat {
         #[command(flatten)]
         options: SplatOptions,
-        /// The root output directory. Defaults to `./.xwin-cache/splat` if not
+        /// The root output directory. Defaults to `<cache-dir>/splat` if not
         /// specified.
         #[arg(long)]
         output: Option<PathBuf>,
@@ -98,6 +163,142 @@ pub enum Command {
         /// increases overall time and disk usage
         #[arg(long)]
         copy: bool,
+        /// Only downloads and splats headers, skipping all libraries
+        #[arg(long, conflicts_with = "libs_only")]
+        headers_only: bool,
+        /// Only downloads and splats libraries, skipping all headers
+        #[arg(long)]
+        libs_only: bool,
+        /// Resumes a previous splat to the same output with the same options,
+        /// skipping the payloads that were already successfully splatted,
+        /// rather than deleting the output and starting from scratch
+        #[arg(long)]
+        resume: bool,
+        /// Creates the `bin/Host<arch>/<arch>` directories that clang-cl
+        /// expects in the /winsysroot layout, with `link.exe` symlinked to
+        /// `lld-link` if it is found in `PATH`. As `lld-link` is outside of
+        /// the splat, the symlink is to its absolute path, so it can't be used
+        /// with `--emit-oci-layer`
+        #[arg(
+            long,
+            requires = "use_winsysroot_style",
+            conflicts_with = "emit_oci_layer"
+        )]
+        winsysroot_bin: bool,
+        /// Symlinks each architecture directory of the libraries to the other
+        /// notation, eg. `lib/x64` -> `x86_64`, or `lib/x86_64` -> `x64` with
+        /// `--preserve-ms-arch-notation`, for tools that expect the other one
+        #[arg(long)]
+        link_arch_dirs: bool,
+        /// The name of the directory the CRT is splatted to. Defaults to `crt`
+        #[arg(long, conflicts_with = "use_winsysroot_style")]
+        crt_dir_name: Option<String>,
+        /// The name of the directory the SDK is splatted to. Defaults to `sdk`
+        #[arg(long, conflicts_with = "use_winsysroot_style")]
+        sdk_dir_name: Option<String>,
+        /// Writes a `CycloneDX` SBOM of the splatted payloads to the path
+        #[arg(long)]
+        sbom: Option<PathBuf>,
+        /// A comma separated list of glob patterns, eg. `d3d12,dxgi,user*`.
+        /// If specified, only the SDK libs that match one of the patterns, as
+        /// well as the libs required by the Rust standard library, are splatted
+        #[arg(long, value_delimiter = ',', conflicts_with = "map")]
+        sdk_libs: Vec<String>,
+        /// If symlinks are disabled, either via `--disable-symlinks` or
+        /// because they aren't supported, create copies of the files with
+        /// alternate casings instead of omitting them. Fails if the file
+        /// system is case-insensitive, as the copies can't be distinct files
+        #[arg(long)]
+        symlink_fallback_copy: bool,
+        /// A command to run with the system shell once the splat has finished
+        /// successfully, eg. to package or sign the output. The splat root, CRT
+        /// and SDK directories, and the CRT and SDK versions are available in
+        /// the `XWIN_SPLAT_ROOT`, `XWIN_CRT_DIR`, `XWIN_SDK_DIR`,
+        /// `XWIN_CRT_VERSION`, and `XWIN_SDK_VERSION` environment variables
+        #[arg(long)]
+        post_splat: Option<String>,
+        /// Writes a `xwin.bzl` and `BUILD.bazel` to the directory with a Bazel
+        /// C++ toolchain for each architecture, using the splat with
+        /// `clang-cl` and `lld-link`
+        #[arg(long, conflicts_with = "use_winsysroot_style")]
+        emit_bazel: Option<PathBuf>,
+        /// Writes an OCI image layout to the directory, with the splat as its
+        /// only layer and the environment variables for using it with `cc`
+        /// and cargo in the image config, eg. to use as a base image or to
+        /// `COPY --from` in a container build
+        #[arg(long, conflicts_with = "use_winsysroot_style")]
+        emit_oci_layer: Option<PathBuf>,
+        /// The absolute path in the OCI image the splat is placed at
+        #[arg(long, default_value = "/xwin", value_parser = parse_oci_root)]
+        oci_root: PathBuf,
+        /// Writes a JSON description of the splat to the path, with the
+        /// resolved CRT and SDK versions, the include directories, and the
+        /// library directories for each architecture, for use by other build
+        /// systems or scripts
+        #[arg(long)]
+        emit_layout_json: Option<PathBuf>,
+        /// Writes a GN args file, eg. `args.gn`, to the path, with the
+        /// `visual_studio_path` and `windows_sdk_path` pointing at the splat,
+        /// for Chromium and other GN based projects
+        #[arg(long, requires = "use_winsysroot_style")]
+        emit_gn_args: Option<PathBuf>,
+        /// A comma separated list of the CRT libs that get an uppercase
+        /// casing, eg. `libcmt,oldnames` if only linking the static CRT.
+        /// Defaults to all of them, specify without any libs for none
+        #[arg(
+            long,
+            num_args = 0..,
+            value_delimiter = ',',
+            value_parser = clap::builder::PossibleValuesParser::new(
+                xwin::CRT_LIB_CASINGS.iter().map(|(name, _)| *name)
+            )
+        )]
+        crt_lib_casings: Option<Vec<String>>,
+        /// Writes every symlink in the splat and its target to the path once
+        /// the splat has finished, one `<link> -> <target>` per line, so that
+        /// a copy or archive of the splat can be checked to have preserved
+        /// the symlinks rather than dereferencing them
+        #[arg(long)]
+        symlink_report: Option<PathBuf>,
+        /// Adds the selected architectures to an existing splat in the output,
+        /// eg. `--arch aarch64` to a splat of `x86_64`, rather than deleting
+        /// it first. Only files that aren't already present, ie. the new
+        /// libraries and any missing headers, are splatted, so the layout
+        /// options must be the same as the existing splat. Can't be used with
+        /// `--emit-bazel` or `--emit-layout-json`, as they would only describe
+        /// the added architectures
+        #[arg(long, conflicts_with_all = ["resume", "emit_bazel", "emit_layout_json"])]
+        add_arch: bool,
+        /// Makes every symlink in the splat point at the absolute path of its
+        /// target. By default every symlink is relative, so that the splat
+        /// can be moved, with the exception of the `link.exe` created by
+        /// `--winsysroot-bin`
+        #[arg(long, conflicts_with = "emit_oci_layer")]
+        absolute_symlinks: bool,
+        /// Sets the modification time of every file in the splat to the Unix
+        /// timestamp, in seconds, once the splat has finished, so that the
+        /// timestamps are stable for build systems that rely on them
+        #[arg(long, value_name = "SECONDS", env = "SOURCE_DATE_EPOCH")]
+        mtime: Option<u64>,
+        /// Places the UCRT headers directly in the SDK include directory,
+        /// alongside `um` and `shared`, rather than in `include/ucrt`, so that
+        /// one less include directory is needed. clang-cl's `/winsdkdir`
+        /// expects the `ucrt` directory, so the include directories must be
+        /// specified explicitly
+        #[arg(long, conflicts_with_all = ["map", "use_winsysroot_style"])]
+        merge_ucrt_include: bool,
+        /// Places the type libraries (`.tlb`) from the SDK in their own
+        /// `sdk/tlb/<arch>` directory, with lowercase symlinks like the
+        /// libraries, rather than alongside the libraries. They are always
+        /// splatted, even if they don't match `--sdk-libs`
+        #[arg(long, conflicts_with = "map")]
+        separate_tlbs: bool,
+        /// A Windows MSVC target triple, eg. `aarch64-pc-windows-msvc`, to
+        /// splat into its own `<output>/<triple>` directory. May be specified
+        /// multiple times, in which case every target shares the same download
+        /// and unpack cache. Takes precedence over `--arch`.
+        #[arg(long = "target", value_name = "TRIPLE", value_parser = parse_target_triple)]
+        targets: Vec<(String, xwin::Arch)>,
         // Splits the CRT and SDK into architecture and variant specific
         // directories. The shared headers in the CRT and SDK are duplicated
         // for each output so that each combination is self-contained.
@@ -115,10 +316,10 @@ pub enum Command {
     Minimize {
         #[command(flatten)]
         options: SplatOptions,
-        /// The path of the filter file that is generated. Defaults to ./.xwin-cache/xwin-map.toml
+        /// The path of the filter file that is generated. Defaults to `<cache-dir>/xwin-map.toml`
         #[arg(long)]
         map: Option<PathBuf>,
-        /// The root splat output directory. Defaults to `./.xwin-cache/splat` if not
+        /// The root splat output directory. Defaults to `<cache-dir>/splat` if not
         /// specified.
         #[arg(long)]
         output: Option<PathBuf>,
@@ -138,11 +339,121 @@ pub enum Command {
         /// The path of the manifest to compile. Defaults to Cargo.toml if not specified
         #[arg(long)]
         manifest_path: Option<PathBuf>,
+        /// Compiles in release mode, so that the headers and libraries used
+        /// by an optimized build are captured
+        #[arg(long)]
+        release: bool,
+        /// Additional whitespace separated arguments passed to `cargo build`,
+        /// eg. `--cargo-args "--features simd --locked"`, so that the traced
+        /// build matches the real one
+        #[arg(long, allow_hyphen_values = true)]
+        cargo_args: Option<String>,
         /// If supplied, the strace output is persisted to disk rather than being
         /// deleted once the compilation has finished
         #[arg(long)]
         preserve_strace: bool,
+        /// The directory in which the strace output, which can be gigabytes
+        /// for large builds, is written. Defaults to the system temp directory
+        #[arg(long)]
+        strace_tmp_dir: Option<PathBuf>,
+        /// Writes the number and size of the used and total files for the CRT
+        /// and SDK headers and libs as JSON to the path, eg. to track the size
+        /// of the minimized sysroot over time
+        #[arg(long)]
+        results_json: Option<PathBuf>,
+        /// Uses the strace output of a previous minimize, eg. one kept with
+        /// `--preserve-strace`, rather than building the crate again. The
+        /// splat output must be the same as the one that was traced
+        #[arg(long, conflicts_with_all = ["release", "cargo_args", "preserve_strace", "strace_tmp_dir"])]
+        strace_input: Option<PathBuf>,
+    },
+    /// Compares two splat output directories, reporting every file and symlink
+    /// that was added, removed, or changed between them
+    ///
+    /// This is useful to see the impact of changing eg. `--sdk-version` or
+    /// `--crt-version` before committing to it
+    Diff {
+        /// The original splat directory
+        old: PathBuf,
+        /// The new splat directory
+        new: PathBuf,
+    },
+    /// Adds the symlinks that address file casing issues to an existing splat
+    /// directory, eg. one that was extracted from an archive, without
+    /// downloading or unpacking anything
+    ///
+    /// Only the default splat layout is supported, and any existing symlinks
+    /// in the directory are removed and recreated
+    Symlink {
+        /// The root splat directory, containing the `crt` and `sdk` directories
+        dir: PathBuf,
+    },
+    /// Creates a splat from a local Visual Studio installation, eg. to get the
+    /// symlinks that address file casing issues, without downloading anything
+    ///
+    /// The architectures are selected with `--arch`, the versions with
+    /// `--crt-version` and `--sdk-version`, and ATL with `--include-atl`. Only
+    /// the default splat layout is supported
+    Import {
+        /// The Visual Studio installation directory, containing `VC/Tools/MSVC`
+        vs_dir: PathBuf,
+        /// The `Windows Kits/10` directory containing the SDK. Defaults to
+        /// `<vs-dir>/Windows Kits/10`, eg. if `<vs-dir>` is a /winsysroot
+        #[arg(long)]
+        sdk_dir: Option<PathBuf>,
+        /// The root output directory. Defaults to `splat` in the cache
+        /// directory if not specified
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Uses the MS arch notation for the library directories, eg. `x64`
+        /// instead of `x86_64`
+        #[arg(long)]
+        preserve_ms_arch_notation: bool,
+    },
+    /// Checks that an existing splat directory is usable by compiling and
+    /// linking a tiny program that includes `windows.h` and links
+    /// `kernel32.lib` with `clang-cl` and `lld-link`
+    ///
+    /// Only the default splat layout is supported
+    Doctor {
+        /// The root splat directory, containing the `crt` and `sdk` directories
+        dir: PathBuf,
+        /// The target triple to compile for
+        #[arg(
+            long,
+            default_value = "x86_64-pc-windows-msvc",
+            value_parser = parse_target_triple
+        )]
+        target: (String, xwin::Arch),
+    },
+    /// Prints the environment variables for compiling and linking against an
+    /// existing splat directory, one `NAME=value` per line
+    ///
+    /// Only the default splat layout is supported
+    Env {
+        /// The root splat directory, containing the `crt` and `sdk` directories
+        dir: PathBuf,
+        /// The target triple to print the variables for
+        #[arg(
+            long,
+            default_value = "x86_64-pc-windows-msvc",
+            value_parser = parse_target_triple
+        )]
+        target: (String, xwin::Arch),
+        /// `cargo` prints the `cc` and cargo variables for the target, eg.
+        /// `CFLAGS_<triple>` and `CARGO_TARGET_<TRIPLE>_RUSTFLAGS`, `single`
+        /// prints a single `XWIN_FLAGS` with every include and library flag
+        /// for `clang-cl`, to splice into any command line
+        #[arg(
+            long,
+            default_value = "cargo",
+            value_parser = PossibleValuesParser::new(["cargo", "single"])
+        )]
+        format: String,
     },
+    /// Lists the payloads that have been unpacked in the cache directory,
+    /// without needing to retrieve the manifest
+    ListUnpacked,
 }
 
 const ARCHES: &[&str] = &["x86", "x86_64", "aarch", "aarch64"];
@@ -154,7 +465,93 @@ fn parse_level(s: &str) -> Result<LevelFilter, Error> {
         .map_err(|_e| anyhow::anyhow!("failed to parse level '{s}'"))
 }
 
+fn parse_target_config(src: &str) -> anyhow::Result<(xwin::Arch, xwin::Variant)> {
+    let (arch, variant) = src
+        .split_once(':')
+        .with_context(|| format!("target config '{src}' is not in the form <arch>:<variant>"))?;
+
+    anyhow::ensure!(
+        ARCHES.contains(&arch),
+        "unknown architecture '{arch}', possible values: {}",
+        ARCHES.join(", ")
+    );
+    anyhow::ensure!(
+        VARIANTS.contains(&variant),
+        "unknown variant '{variant}', possible values: {}",
+        VARIANTS.join(", ")
+    );
+
+    Ok((arch.parse()?, variant.parse()?))
+}
+
+fn parse_target_triple(src: &str) -> anyhow::Result<(String, xwin::Arch)> {
+    let (arch, rest) = src
+        .split_once('-')
+        .with_context(|| format!("'{src}' is not a target triple"))?;
+
+    anyhow::ensure!(
+        rest.ends_with("-windows-msvc"),
+        "target '{src}' is not a Windows MSVC target"
+    );
+
+    let arch = match arch {
+        "i586" | "i686" => xwin::Arch::X86,
+        "x86_64" => xwin::Arch::X86_64,
+        "thumbv7a" => xwin::Arch::Aarch,
+        "aarch64" => xwin::Arch::Aarch64,
+        other => anyhow::bail!("unsupported architecture '{other}' in target '{src}'"),
+    };
+
+    Ok((src.to_owned(), arch))
+}
+
+/// The CRT and SDK versions specified via `--toolchain`
+#[derive(Clone, Default)]
+struct Toolchain {
+    crt_version: Option<String>,
+    sdk_version: Option<String>,
+}
+
+fn parse_toolchain(src: &str) -> anyhow::Result<Toolchain> {
+    let mut toolchain = Toolchain::default();
+
+    for component in src.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        let (key, version) = component
+            .split_once('=')
+            .with_context(|| format!("'{component}' is not in the form <crt|sdk>=<version>"))?;
+
+        let slot = match key.trim() {
+            "crt" => &mut toolchain.crt_version,
+            "sdk" => &mut toolchain.sdk_version,
+            other => {
+                anyhow::bail!("unknown toolchain component '{other}', expected 'crt' or 'sdk'")
+            }
+        };
+
+        let version = version.trim();
+        anyhow::ensure!(!version.is_empty(), "'{key}' has an empty version");
+        anyhow::ensure!(slot.is_none(), "'{key}' was specified more than once");
+        *slot = Some(version.to_owned());
+    }
+
+    anyhow::ensure!(
+        toolchain.crt_version.is_some() || toolchain.sdk_version.is_some(),
+        "toolchain '{src}' doesn't specify a crt or sdk version"
+    );
+
+    Ok(toolchain)
+}
+
 #[allow(clippy::indexing_slicing)]
+fn parse_oci_root(src: &str) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        src.starts_with('/') && !src.trim_matches('/').is_empty(),
+        "the OCI root '{src}' must be an absolute path other than '/'"
+    );
+
+    Ok(PathBuf::from(src.trim_end_matches('/')))
+}
+
 fn parse_duration(src: &str) -> anyhow::Result<Duration> {
     let suffix_pos = src.find(char::is_alphabetic).unwrap_or(src.len());
 
@@ -200,13 +597,73 @@ pub struct Args {
     #[arg(long)]
     temp: bool,
     /// Specifies the cache directory used to persist downloaded items to disk.
-    /// Defaults to `./.xwin-cache` if not specified.
+    ///
+    /// Defaults to `xwin` in the platform cache directory, eg.
+    /// `~/.cache/xwin` on Linux, so that it is shared between projects. If
+    /// `./.xwin-cache` exists in the current directory it is used instead, for
+    /// compatibility with previous versions.
     #[arg(long)]
     cache_dir: Option<PathBuf>,
+    /// A TOML file with the defaults for the common options, eg. the
+    /// architectures, versions, and splat layout, which are used unless the
+    /// equivalent flag is specified. Defaults to `xwin.toml` in the current
+    /// directory if it exists
+    #[arg(long, env = "XWIN_CONFIG")]
+    config: Option<PathBuf>,
     /// Specifies a VS manifest to use from a file, rather than downloading it
     /// from the Microsoft site.
     #[arg(long, conflicts_with_all = &["manifest_version", "channel"])]
     manifest: Option<PathBuf>,
+    /// Retrieves the VS manifest from the url rather than the one for the
+    /// manifest version and channel, eg. an `aka.ms` or
+    /// `go.microsoft.com/fwlink` link to a pinned manifest. Redirects are
+    /// followed to the actual manifest
+    #[arg(long, conflicts_with_all = &["manifest", "manifest_version", "channel"])]
+    manifest_url: Option<String>,
+    /// Specifies a pinned set of payloads, previously written with
+    /// `--export-pinned`, to use instead of resolving them from a manifest.
+    #[arg(
+        long,
+        conflicts_with_all = &[
+            "manifest",
+            "manifest_url",
+            "manifest_version",
+            "channel",
+            "sdk_version",
+            "crt_version",
+            "toolchain",
+            "include_atl",
+            "include_dia",
+            "include_tools",
+            "include_winmd",
+            "no_store_crt",
+            "preset",
+            "arch",
+            "variant",
+            "target_config",
+        ],
+    )]
+    from_pinned: Option<PathBuf>,
+    /// Writes the fully resolved set of payloads, including their urls and
+    /// checksums, to the specified path so that it can be used with
+    /// `--from-pinned` to download exactly the same payloads in the future
+    #[arg(long)]
+    export_pinned: Option<PathBuf>,
+    /// Populates the cache from a tar archive before doing anything else, eg.
+    /// an archive of the `dl` directory from a previous run. `-` reads the
+    /// archive from stdin.
+    ///
+    /// Entries in the archive must be relative to the cache root, ie. `dl/...`
+    /// or `unpack/...`
+    #[arg(long)]
+    cache_from: Option<PathBuf>,
+    /// Writes a JSON file with every non-fatal problem encountered when
+    /// resolving the packages, eg. packages that were expected but missing
+    /// from the manifest, or when splatting, eg. SDK headers with different
+    /// contents in multiple payloads, so that CI can check the selection is
+    /// complete
+    #[arg(long, conflicts_with = "from_pinned")]
+    diagnostics_json: Option<PathBuf>,
     /// The manifest version to retrieve
     #[arg(long, default_value = "17")]
     manifest_version: String,
@@ -221,19 +678,155 @@ pub struct Args {
     /// instead of defaulting to the latest MSVCRT available in the the manifest
     #[arg(long)]
     crt_version: Option<String>,
+    /// Fails if the resolved SDK version isn't exactly this version, eg. to
+    /// catch a newer SDK being published and silently used
+    #[arg(long, value_name = "VERSION")]
+    expect_sdk: Option<String>,
+    /// Fails if the resolved CRT version isn't exactly this version, eg. to
+    /// catch a newer CRT being published and silently used
+    #[arg(long, value_name = "VERSION")]
+    expect_crt: Option<String>,
+    /// Specifies the CRT and/or SDK versions in a single string, eg.
+    /// `crt=14.38,sdk=10.0.22621`, as an alternative to `--crt-version` and
+    /// `--sdk-version`, which take precedence if also specified
+    #[arg(long, env = "XWIN_TOOLCHAIN", value_parser = parse_toolchain)]
+    toolchain: Option<Toolchain>,
     /// Whether to include the Active Template Library (ATL) in the installation
     #[arg(long)]
     include_atl: bool,
+    /// Whether to include the Debug Interface Access (DIA) SDK in the
+    /// installation, which is splatted to its own `dia` directory
+    #[arg(long)]
+    include_dia: bool,
+    /// Includes the MSVC compiler and linker binaries, eg. `cl.exe`, that run
+    /// on the specified host architecture and target each selected
+    /// architecture, which are splatted to `bin/Host<host>/<target>` in the
+    /// CRT directory, the same layout as a Visual Studio installation
+    #[arg(
+        long,
+        value_name = "HOST",
+        value_parser = PossibleValuesParser::new(ARCHES).map(|s| s.parse::<xwin::Arch>().unwrap()),
+    )]
+    include_tools: Option<xwin::Arch>,
+    /// Whether to include the Windows Runtime metadata (`.winmd`) files needed to
+    /// generate projections, eg. with windows-rs or C++/WinRT, which are
+    /// splatted to `UnionMetadata/<sdk version>` in the SDK directory
+    #[arg(long)]
+    include_winmd: bool,
+    /// Don't download the Store variant of the CRT libraries, which is otherwise
+    /// always included as it contains some libraries that are linked by default,
+    /// eg. `oldnames.lib`, even when not targeting the Store. When not targeting
+    /// the Store, only those libraries are unpacked and splatted from it, but
+    /// the whole package still needs to be downloaded. Only use this if nothing
+    /// you build links them, or they are provided some other way.
+    #[arg(long)]
+    no_store_crt: bool,
+    /// A named selection of payloads applied on top of the normal selection.
+    ///
+    /// `minimal` only selects the UCRT, the CRT headers and libs, and the SDK
+    /// headers and libs for the first selected architecture, skipping ATL,
+    /// DIA, the Windows Runtime metadata, and the Store libs, which is useful
+    /// for quick experiments and CI smoke tests
+    #[arg(
+        long,
+        value_parser = PossibleValuesParser::new(["minimal"]).map(|s| s.parse::<xwin::Preset>().unwrap()),
+        conflicts_with_all = &["include_atl", "include_dia", "include_tools", "include_winmd"],
+    )]
+    preset: Option<xwin::Preset>,
+    /// Selects every payload of exactly the specified manifest package ids,
+    /// eg. `Microsoft.VC.14.40.17.10.CRT.Headers.base`, instead of the normal
+    /// selection of the CRT and SDK, for when it doesn't select what is
+    /// needed. The kind of each payload is detected from the package id and
+    /// payload name so that it can be splatted, payloads whose kind can't be
+    /// detected are skipped.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with_all = &["from_pinned", "preset", "target_config", "include_atl", "include_dia", "include_tools", "include_winmd", "no_store_crt"],
+    )]
+    packages: Vec<String>,
+    /// Whether to include the source files that are shipped with some packages,
+    /// eg. the Universal CRT sources, which are splatted to `sdk/src`
+    #[arg(long)]
+    include_sources: bool,
+    /// Always hash cached downloads to verify their checksums, rather than
+    /// only when they have changed since they were last verified
+    #[arg(long)]
+    verify: bool,
+    /// Forbids all network access, every download, including the manifests,
+    /// must already be present in the cache, and it is an error if any are missing
+    #[arg(long)]
+    offline: bool,
+    /// Writes the raw Directory, Component, File, and Media tables from each
+    /// MSI that is unpacked as JSON to `msi-tables/<payload>` in the cache
+    /// directory, to help diagnose why a file was unpacked where it was
+    #[arg(long)]
+    keep_unpacked_raw: bool,
+    /// If a file fails to extract from a CAB, removes the CAB from the cache
+    /// and downloads it again, then retries the extraction once before failing
+    #[arg(long)]
+    retry_cab_extraction: bool,
+    /// Splats to the output directory even if it contains files that weren't
+    /// created by xwin. Note that the existing CRT and SDK directories in it
+    /// are still deleted
+    #[arg(long)]
+    allow_non_empty_output: bool,
+    /// Continues processing the remaining payloads if one fails to be
+    /// downloaded, unpacked, or splatted, eg. to create a partial splat when
+    /// a payload is missing upstream, still exiting with an error once done
+    #[arg(long)]
+    keep_going: bool,
+    /// Logs the time spent downloading, unpacking, and splatting the payloads,
+    /// as well as finalizing the splat, once everything is done, to see where
+    /// the time goes, eg. to decide which thread count to adjust
+    #[arg(long)]
+    timings: bool,
+    /// Uses payloads whose checksum doesn't match the manifest, with a warning,
+    /// rather than failing. This is only meant as a workaround for mirrors or
+    /// proxies that alter the payloads, and means the payloads can't be
+    /// trusted to be the ones Microsoft published
+    #[arg(long)]
+    no_verify_checksums: bool,
+    /// Uses the cached channel manifest without making any request if it was
+    /// retrieved or confirmed to be current within this duration, eg. `24h`
+    #[arg(long, value_parser = parse_duration, conflicts_with = "manifest")]
+    manifest_cache_ttl: Option<Duration>,
     /// Specifies a timeout for how long a single download is allowed to take.
     #[arg(short, long, value_parser = parse_duration, default_value = "60s")]
     timeout: Duration,
     /// An HTTPS proxy to use
     #[arg(long, env = "HTTPS_PROXY")]
     https_proxy: Option<String>,
+    /// The User-Agent header sent with every HTTP request, for proxies or
+    /// gateways that block or throttle requests that aren't identifiable.
+    /// Defaults to `xwin/<version>`
+    #[arg(long, env = "XWIN_USER_AGENT")]
+    user_agent: Option<String>,
     /// The number of times an HTTP get will be retried if it fails due to I/O
     /// failures
     #[arg(long, env = "XWIN_HTTP_RETRY", default_value = "0")]
     http_retry: u8,
+    /// Shows a single progress bar with the total bytes across all payloads,
+    /// rather than one progress bar per payload
+    #[arg(long)]
+    single_progress: bool,
+    /// The number of threads used to download payloads. Defaults to the
+    /// number of logical CPUs
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    download_threads: Option<u16>,
+    /// The maximum number of CABs of a single MSI that are downloaded
+    /// concurrently. Each SDK MSI can have dozens of CABs, which are otherwise
+    /// all downloaded at once, limited only by the download threads
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    cab_download_threads: Option<u16>,
+    /// The number of threads used to unpack payloads. Defaults to the
+    /// number of logical CPUs
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    unpack_threads: Option<u16>,
+    /// The number of threads used to splat payloads. Defaults to the
+    /// number of logical CPUs
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    splat_threads: Option<u16>,
     /// The architectures to include
     #[arg(
         long,
@@ -250,14 +843,165 @@ pub struct Args {
         default_values_t = vec![xwin::Variant::Desktop],
     )]
     variant: Vec<xwin::Variant>,
+    /// An explicit `<arch>:<variant>` pair to include, eg. `x86_64:desktop`.
+    ///
+    /// Can be specified multiple times, and unlike `--arch` and `--variant`,
+    /// only the CRT libraries for the specified pairs are included rather than
+    /// every combination of them. Note that the spectre variant applies to
+    /// every architecture if specified.
+    #[arg(
+        long,
+        value_parser = parse_target_config,
+        conflicts_with_all = &["arch", "variant"],
+    )]
+    target_config: Vec<(xwin::Arch, xwin::Variant)>,
     #[command(subcommand)]
     cmd: Command,
 }
 
-fn main() -> Result<(), Error> {
-    let args = Args::parse();
+/// The exit code for each [`xwin::util::ErrorKind`], so that eg. CI can decide
+/// whether it is worth retrying a failure. 2 is used by clap for usage errors.
+fn exit_code(kind: xwin::util::ErrorKind) -> u8 {
+    use xwin::util::ErrorKind;
+
+    match kind {
+        ErrorKind::Other => 1,
+        ErrorKind::Network => 3,
+        ErrorKind::ChecksumMismatch => 4,
+        ErrorKind::Selection => 5,
+        ErrorKind::OutOfSpace => 6,
+    }
+}
+
+fn main() -> std::process::ExitCode {
+    match real_main() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            // Same output as returning the error from main
+            eprintln!("Error: {err:?}");
+            std::process::ExitCode::from(exit_code(xwin::util::ErrorKind::of(&err)))
+        }
+    }
+}
+
+fn real_main() -> Result<(), Error> {
+    let (args, config) = match parse_args(std::env::args_os().collect()) {
+        Ok(parsed) => parsed,
+        Err(err) => match err.downcast::<clap::Error>() {
+            Ok(err) => err.exit(),
+            Err(err) => return Err(err),
+        },
+    };
     setup_logger(args.json, args.level)?;
 
+    if let Some(config) = config {
+        tracing::debug!("using options from {config}");
+    }
+
+    if let Command::Diff { old, new } = &args.cmd {
+        let diff = xwin::diff_trees(old, new)?;
+        print_diff(&diff);
+        return Ok(());
+    }
+
+    if let Command::Symlink { dir } = &args.cmd {
+        return xwin::symlink_splat(dir, xwin::util::ProgressTarget::Stdout);
+    }
+
+    if let Command::Import {
+        vs_dir,
+        sdk_dir,
+        output,
+        preserve_ms_arch_notation,
+    } = &args.cmd
+    {
+        let output = match output {
+            Some(output) => output.clone(),
+            None => match &args.cache_dir {
+                Some(cd) => cd.join("splat"),
+                None => default_cache_dir()?.join("splat"),
+            },
+        };
+
+        let toolchain = args.toolchain.as_ref();
+        return xwin::import_splat(
+            &xwin::ImportConfig {
+                vs_dir: vs_dir.clone(),
+                sdk_dir: sdk_dir.clone(),
+                crt_version: args
+                    .crt_version
+                    .clone()
+                    .or_else(|| toolchain.and_then(|tc| tc.crt_version.clone())),
+                sdk_version: args
+                    .sdk_version
+                    .clone()
+                    .or_else(|| toolchain.and_then(|tc| tc.sdk_version.clone())),
+                arches: args.arch.iter().fold(0, |acc, arch| acc | *arch as u32),
+                include_atl: args.include_atl,
+                preserve_ms_arch_notation: *preserve_ms_arch_notation,
+                output,
+                allow_non_empty_output: args.allow_non_empty_output,
+            },
+            xwin::util::ProgressTarget::Stdout,
+        );
+    }
+
+    if let Command::Doctor {
+        dir,
+        target: (triple, arch),
+    } = &args.cmd
+    {
+        let report = xwin::doctor(dir, triple, *arch)?;
+        println!("{}", report.command);
+
+        if !report.output.is_empty() {
+            println!("{}", report.output.trim_end());
+        }
+
+        anyhow::ensure!(report.success, "{dir} is not usable for {triple}");
+        println!("{dir} is usable for {triple}");
+        return Ok(());
+    }
+
+    if let Command::Env {
+        dir,
+        target: (triple, arch),
+        format,
+    } = &args.cmd
+    {
+        let dirs = xwin::splat_dirs(dir, *arch)?;
+        print_env(triple, &dirs, format == "single");
+        return Ok(());
+    }
+
+    if let Command::ListUnpacked = &args.cmd {
+        let cache_dir = match &args.cache_dir {
+            Some(cd) => cd.clone(),
+            None => default_cache_dir()?,
+        };
+
+        print_unpacked(&xwin::list_unpacked(&cache_dir)?);
+        return Ok(());
+    }
+
+    // The license prompt is read from stdin, which would conflict with the
+    // cache archive being streamed in
+    anyhow::ensure!(
+        args.accept_license || !matches!(&args.cache_from, Some(cf) if cf == "-"),
+        "--accept-license must be passed when reading the cache archive from stdin"
+    );
+    anyhow::ensure!(
+        !matches!(
+            &args.cmd,
+            Command::Download {
+                plan: true,
+                yes: false,
+                ..
+            }
+        ) || !matches!(&args.cache_from, Some(cf) if cf == "-"),
+        "--yes must be passed with --plan when reading the cache archive from stdin"
+    );
+
     if !args.accept_license {
         // The license link is the same for every locale, but we should probably
         // retrieve it from the manifest in the future
@@ -273,14 +1017,15 @@ fn main() -> Result<(), Error> {
         }
     }
 
-    let cwd = PathBuf::from_path_buf(std::env::current_dir().context("unable to retrieve cwd")?)
-        .map_err(|pb| anyhow::anyhow!("cwd {} is not a valid utf-8 path", pb.display()))?;
-
     let draw_target = xwin::util::ProgressTarget::Stdout;
 
     let client = {
         let mut builder = ureq::Config::new();
         builder.timeouts.recv_body = Some(args.timeout);
+        builder.user_agent = Some(
+            args.user_agent
+                .unwrap_or_else(|| concat!("xwin/", env!("CARGO_PKG_VERSION")).to_owned()),
+        );
 
         if let Some(proxy) = args.https_proxy {
             let proxy = ureq::Proxy::new(&proxy).context("failed to parse https proxy address")?;
@@ -290,63 +1035,457 @@ fn main() -> Result<(), Error> {
         ureq::Agent::new_with_config(builder)
     };
 
-    let ctx = if args.temp {
+    let mut ctx = if args.temp {
         xwin::Ctx::with_temp(draw_target, client, args.http_retry)?
     } else {
         let cache_dir = match &args.cache_dir {
             Some(cd) => cd.clone(),
-            None => cwd.join(".xwin-cache"),
+            None => default_cache_dir()?,
         };
         xwin::Ctx::with_dir(cache_dir, draw_target, client, args.http_retry)?
     };
 
+    ctx.include_sources = args.include_sources;
+    ctx.verify = args.verify;
+    ctx.offline = args.offline;
+    ctx.keep_unpacked_raw = args.keep_unpacked_raw;
+    ctx.retry_cab_extraction = args.retry_cab_extraction;
+    ctx.allow_non_empty_output = args.allow_non_empty_output;
+    ctx.keep_going = args.keep_going;
+    ctx.timings = args.timings;
+    ctx.ignore_checksum_mismatches = args.no_verify_checksums;
+    ctx.manifest_cache_ttl = args.manifest_cache_ttl;
+
+    if args.no_verify_checksums {
+        tracing::warn!("checksum verification is disabled, payloads that don't match the checksums in the manifest will be used anyways");
+    }
+    ctx.download_threads = args.download_threads.map(usize::from);
+    ctx.cab_download_threads = args.cab_download_threads.map(usize::from);
+    ctx.unpack_threads = args.unpack_threads.map(usize::from);
+    ctx.splat_threads = args.splat_threads.map(usize::from);
+
+    // The overall bar is redundant if there is only a single bar anyway
+    if !args.single_progress {
+        ctx.overall_progress = ia::ProgressBar::with_draw_target(Some(0), draw_target.into())
+            .with_prefix("Overall")
+            .with_style(
+                ia::ProgressStyle::default_bar()
+                    .template("{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.cyan} {percent}% ETA {eta}")
+                    .unwrap()
+                    .progress_chars("█▇▆▅▄▃▂▁  "),
+            );
+    }
+
+    if let Some(cache_from) = &args.cache_from {
+        let count = if cache_from == "-" {
+            ctx.populate_cache(std::io::stdin().lock())
+        } else {
+            let archive = std::fs::File::open(cache_from)
+                .with_context(|| format!("failed to open cache archive '{cache_from}'"))?;
+            ctx.populate_cache(std::io::BufReader::new(archive))
+        }
+        .context("failed to populate cache")?;
+
+        tracing::info!("populated cache with {count} files from '{cache_from}'");
+    }
+
     let ctx = std::sync::Arc::new(ctx);
 
-    let pkg_manifest = load_manifest(
-        &ctx,
-        args.manifest.as_ref(),
-        &args.manifest_version,
-        &args.channel,
-        draw_target,
-    )?;
-
-    let arches = args.arch.into_iter().fold(0, |acc, arch| acc | arch as u32);
-    let variants = args
-        .variant
-        .into_iter()
-        .fold(0, |acc, var| acc | var as u32);
+    if let Command::Extract { package, output } = &args.cmd {
+        let pkg_manifest = load_manifest(
+            &ctx,
+            args.manifest.as_ref(),
+            args.manifest_url.as_deref(),
+            &args.manifest_version,
+            &args.channel,
+            draw_target,
+        )?;
+
+        let payloads = xwin::package_payloads(&pkg_manifest.packages, package)?;
+
+        let mp = ia::MultiProgress::with_draw_target(draw_target.into());
+        let items = work_items(payloads, &mp, args.single_progress, draw_target);
+        mp.set_move_cursor(true);
+
+        // The prefixes are based on the payload kind, which arbitrary packages
+        // don't have
+        for item in &items {
+            item.progress.set_prefix(item.payload.filename.to_string());
+        }
+
+        ctx.extract(pkg_manifest.packages, items, output)?;
+        println!("extracted {package} to {output}");
+        return Ok(());
+    }
 
-    let pruned = xwin::prune_pkg_list(
-        &pkg_manifest,
+    let splat_targets = match &args.cmd {
+        Command::Splat { targets, .. } => targets.clone(),
+        _ => Vec::new(),
+    };
+    let target_arches = splat_targets
+        .iter()
+        .fold(0, |acc, (_, arch)| acc | *arch as u32);
+
+    anyhow::ensure!(
+        splat_targets.is_empty() || args.target_config.is_empty(),
+        "--target can't be used with --target-config"
+    );
+
+    let mut diagnostics = Vec::new();
+
+    let pinned = if let Some(pinned_path) = &args.from_pinned {
+        let pinned = std::fs::read_to_string(pinned_path)
+            .with_context(|| format!("failed to read path '{pinned_path}'"))?;
+        serde_json::from_str::<xwin::PinnedPayloads>(&pinned)
+            .with_context(|| format!("failed to deserialize pinned payloads in '{pinned_path}'"))?
+    } else {
+        let pkg_manifest = load_manifest(
+            &ctx,
+            args.manifest.as_ref(),
+            args.manifest_url.as_deref(),
+            &args.manifest_version,
+            &args.channel,
+            draw_target,
+        )?;
+
+        let (arches, variants) = if args.target_config.is_empty() {
+            (
+                if target_arches != 0 {
+                    target_arches
+                } else {
+                    args.arch.into_iter().fold(0, |acc, arch| acc | arch as u32)
+                },
+                args.variant
+                    .into_iter()
+                    .fold(0, |acc, var| acc | var as u32),
+            )
+        } else {
+            args.target_config
+                .iter()
+                .fold((0, 0), |(arches, variants), (arch, var)| {
+                    (arches | *arch as u32, variants | *var as u32)
+                })
+        };
+
+        let sdk_version = args.sdk_version.or_else(|| {
+            args.toolchain
+                .as_ref()
+                .and_then(|tc| tc.sdk_version.clone())
+        });
+        let crt_version = args.crt_version.or_else(|| {
+            args.toolchain
+                .as_ref()
+                .and_then(|tc| tc.crt_version.clone())
+        });
+
+        let mut pruned = if args.packages.is_empty() {
+            xwin::prune_pkg_list(
+                &pkg_manifest,
+                arches,
+                variants,
+                xwin::PruneOptions {
+                    targets: (!args.target_config.is_empty())
+                        .then_some(args.target_config.as_slice()),
+                    include_atl: args.include_atl,
+                    include_dia: args.include_dia,
+                    tools_host: args.include_tools,
+                    include_winmd: args.include_winmd,
+                    skip_store_crt: args.no_store_crt,
+                    sdk_version,
+                    crt_version,
+                },
+            )?
+        } else {
+            xwin::explicit_pkg_list(&pkg_manifest, &args.packages, sdk_version, crt_version)?
+        };
+
+        let arches = match args.preset {
+            Some(preset) => preset.apply(&mut pruned.payloads, arches)?,
+            None => arches,
+        };
+
+        if let Some(diag_path) = &args.diagnostics_json {
+            let serialized = serde_json::to_vec_pretty(&pruned.diagnostics)?;
+            std::fs::write(diag_path, serialized)
+                .with_context(|| format!("failed to write diagnostics to '{diag_path}'"))?;
+        }
+
+        diagnostics = std::mem::take(&mut pruned.diagnostics);
+
+        xwin::PinnedPayloads::new(&pkg_manifest.packages, pruned, arches, variants)
+    };
+
+    for (name, expected, resolved) in [
+        ("SDK", &args.expect_sdk, &pinned.sdk_version),
+        ("CRT", &args.expect_crt, &pinned.crt_version),
+    ] {
+        check_expected_version(name, expected.as_deref(), resolved)?;
+    }
+
+    if let Some(export_path) = &args.export_pinned {
+        let serialized = serde_json::to_vec_pretty(&pinned)?;
+        std::fs::write(export_path, serialized)
+            .with_context(|| format!("failed to write pinned payloads to '{export_path}'"))?;
+    }
+
+    let xwin::PinnedPayloads {
+        crt_version,
+        sdk_version,
         arches,
         variants,
-        args.include_atl,
-        args.sdk_version,
-        args.crt_version,
-    )?;
+        mut payloads,
+        packages: pkgs,
+    } = pinned;
+
+    anyhow::ensure!(
+        arches & target_arches == target_arches,
+        "the pinned payloads don't include every architecture needed by the specified targets"
+    );
 
+    let mut sbom_path = None;
     let op = match args.cmd {
-        Command::List => {
-            print_packages(&pruned.payloads);
+        Command::List {
+            show_hashes,
+            summary,
+        } => {
+            if summary {
+                let (dl, install) = payload_totals(&payloads);
+                println!("payloads: {}", payloads.len());
+                println!("download: {dl}");
+                println!("install: {install}");
+            } else {
+                print_packages(&payloads, show_hashes);
+            }
+            return Ok(());
+        }
+        Command::Info => {
+            println!("xwin version: {}", env!("CARGO_PKG_VERSION"));
+            println!(
+                "cache directory: {}{}",
+                ctx.work_dir,
+                if args.temp { " (temporary)" } else { "" }
+            );
+
+            if let Some(pinned) = &args.from_pinned {
+                println!("pinned payloads: {pinned}");
+            } else if let Some(manifest) = &args.manifest {
+                println!("manifest: {manifest}");
+            } else if let Some(url) = &args.manifest_url {
+                println!("manifest: {url}");
+            } else {
+                println!(
+                    "manifest: version {}, channel {}",
+                    args.manifest_version, args.channel
+                );
+            }
+
+            println!("CRT version: {crt_version}");
+            println!("SDK version: {sdk_version}");
+            println!(
+                "architectures: {}",
+                xwin::Arch::iter(arches)
+                    .map(|arch| arch.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            println!(
+                "variants: {}",
+                [
+                    xwin::Variant::Desktop,
+                    xwin::Variant::OneCore,
+                    xwin::Variant::Store,
+                    xwin::Variant::Spectre,
+                ]
+                .into_iter()
+                .filter(|var| variants & *var as u32 != 0)
+                .map(|var| var.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+            );
+
+            let (dl, install) = payload_totals(&payloads);
+            println!(
+                "payloads: {} ({} download, {} installed)",
+                payloads.len(),
+                indicatif::HumanBytes(dl),
+                indicatif::HumanBytes(install)
+            );
+
+            let options: Vec<_> = [
+                ("include-atl", args.include_atl),
+                ("include-dia", args.include_dia),
+                ("include-winmd", args.include_winmd),
+                ("include-sources", args.include_sources),
+                ("no-store-crt", args.no_store_crt),
+                ("offline", args.offline),
+            ]
+            .into_iter()
+            .filter_map(|(name, enabled)| enabled.then_some(name))
+            .chain(args.include_tools.map(|_| "include-tools"))
+            .chain(args.preset.map(|_| "preset"))
+            .chain((!args.packages.is_empty()).then_some("packages"))
+            .collect();
+            println!(
+                "options: {}",
+                if options.is_empty() {
+                    "none".to_owned()
+                } else {
+                    options.join(", ")
+                }
+            );
+
+            println!(
+                "default splat output: {} (default layout)",
+                ctx.work_dir.join("splat")
+            );
+            return Ok(());
+        }
+        Command::Diff { .. }
+        | Command::Symlink { .. }
+        | Command::Import { .. }
+        | Command::Doctor { .. }
+        | Command::Env { .. }
+        | Command::Extract { .. }
+        | Command::ListUnpacked => unreachable!(),
+        Command::Download { sbom, plan, yes } => {
+            if plan {
+                println!("CRT version: {crt_version}");
+                println!("SDK version: {sdk_version}");
+                print_packages(&payloads, false);
+
+                if !yes {
+                    let (dl, install) = payload_totals(&payloads);
+                    println!(
+                        "Download {} ({} installed)? (yes | no)",
+                        indicatif::HumanBytes(dl),
+                        indicatif::HumanBytes(install)
+                    );
+
+                    let mut confirm = String::new();
+                    std::io::stdin().read_line(&mut confirm)?;
+
+                    match confirm.trim() {
+                        "yes" => {}
+                        "no" => {
+                            println!("download cancelled");
+                            return Ok(());
+                        }
+                        other => anyhow::bail!("unknown response to confirmation {other}"),
+                    }
+                }
+            }
+
+            sbom_path = sbom;
+            xwin::Ops::Download
+        }
+        Command::ListCabs { json } => {
+            let mp = ia::MultiProgress::with_draw_target(draw_target.into());
+            let items = work_items(payloads, &mp, args.single_progress, draw_target);
+            mp.set_move_cursor(true);
+
+            let cabs = xwin::list_cabs(&ctx, &pkgs, &items)?;
+
+            if json {
+                use std::io::Write;
+                std::io::stdout()
+                    .write_all(&serde_json::to_vec_pretty(&cabs)?)
+                    .context("failed to write CABs to stdout")?;
+            } else {
+                print_cabs(&cabs);
+            }
+
+            return Ok(());
+        }
+        Command::Index { output } => {
+            let mp = ia::MultiProgress::with_draw_target(draw_target.into());
+            let items = work_items(payloads, &mp, args.single_progress, draw_target);
+            mp.set_move_cursor(true);
+
+            let index = xwin::index_payloads(&ctx, &items)?;
+            let serialized = serde_json::to_vec_pretty(&index)?;
+
+            if let Some(path) = output {
+                std::fs::write(&path, serialized)
+                    .with_context(|| format!("failed to write index to '{path}'"))?;
+            } else {
+                use std::io::Write;
+                std::io::stdout()
+                    .write_all(&serialized)
+                    .context("failed to write index to stdout")?;
+            }
+
             return Ok(());
         }
-        Command::Download => xwin::Ops::Download,
         Command::Unpack => xwin::Ops::Unpack,
         Command::Splat {
             options,
             copy,
             map,
             output,
-        } => xwin::Ops::Splat(xwin::SplatConfig {
-            include_debug_libs: options.include_debug_libs,
-            include_debug_symbols: options.include_debug_symbols,
-            enable_symlinks: !options.disable_symlinks,
-            preserve_ms_arch_notation: options.preserve_ms_arch_notation,
-            use_winsysroot_style: options.use_winsysroot_style,
-            copy,
-            map,
-            output: output.unwrap_or_else(|| ctx.work_dir.join("splat")),
-        }),
+            headers_only,
+            libs_only,
+            resume,
+            winsysroot_bin,
+            link_arch_dirs,
+            crt_dir_name,
+            sdk_dir_name,
+            sbom,
+            sdk_libs,
+            symlink_fallback_copy,
+            post_splat,
+            emit_bazel,
+            emit_oci_layer,
+            oci_root,
+            emit_layout_json,
+            emit_gn_args,
+            crt_lib_casings,
+            symlink_report,
+            add_arch,
+            absolute_symlinks,
+            mtime,
+            merge_ucrt_include,
+            separate_tlbs,
+            targets: _,
+        } => {
+            sbom_path = sbom;
+            xwin::Ops::Splat(xwin::SplatConfig {
+                include_debug_libs: options.include_debug_libs,
+                include_debug_symbols: options.include_debug_symbols,
+                enable_symlinks: !options.disable_symlinks,
+                preserve_ms_arch_notation: options.preserve_ms_arch_notation,
+                use_winsysroot_style: options.use_winsysroot_style,
+                prefix: options.prefix,
+                copy,
+                map,
+                headers_only,
+                libs_only,
+                resume,
+                winsysroot_bin,
+                link_arch_dirs,
+                crt_dir_name,
+                sdk_dir_name,
+                sdk_libs,
+                symlink_fallback_copy,
+                post_splat,
+                emit_bazel,
+                emit_oci_layer: emit_oci_layer.map(|dir| xwin::OciLayer {
+                    dir,
+                    root: oci_root,
+                }),
+                emit_layout_json,
+                emit_gn_args,
+                crt_lib_casings,
+                symlink_report,
+                add_arch,
+                absolute_symlinks,
+                mtime: mtime.map(|secs| std::time::UNIX_EPOCH + Duration::from_secs(secs)),
+                merge_ucrt_include,
+                separate_tlbs,
+                // Conflicting headers are only reported in the diagnostics
+                detect_conflicting_headers: args.diagnostics_json.is_some(),
+                output: output.unwrap_or_else(|| ctx.work_dir.join("splat")),
+            })
+        }
         Command::Minimize {
             map,
             output,
@@ -355,7 +1494,12 @@ fn main() -> Result<(), Error> {
             options,
             target,
             manifest_path,
+            release,
+            cargo_args,
             preserve_strace,
+            strace_tmp_dir,
+            results_json,
+            strace_input,
         } => xwin::Ops::Minimize(xwin::MinimizeConfig {
             include_debug_libs: options.include_debug_libs,
             include_debug_symbols: options.include_debug_symbols,
@@ -363,42 +1507,472 @@ fn main() -> Result<(), Error> {
             preserve_ms_arch_notation: options.preserve_ms_arch_notation,
             use_winsysroot_style: options.use_winsysroot_style,
             splat_output: output.unwrap_or_else(|| ctx.work_dir.join("splat")),
+            splat_prefix: options.prefix,
             copy,
             minimize_output,
             map: map.unwrap_or_else(|| ctx.work_dir.join("xwin-map.toml")),
             target: target.unwrap_or("x86_64-pc-windows-msvc".to_owned()),
             manifest_path: manifest_path.unwrap_or("Cargo.toml".into()),
+            release,
+            cargo_args: cargo_args
+                .map(|args| args.split_whitespace().map(String::from).collect())
+                .unwrap_or_default(),
             preserve_strace,
+            strace_tmp_dir,
+            results_json,
+            strace_input,
         }),
     };
 
-    let pkgs = pkg_manifest.packages;
+    // Avoid even downloading payloads that only contain headers or libs if
+    // the user doesn't want them. Payloads that contain both are filtered
+    // during the splat
+    if let xwin::Ops::Splat(config) = &op {
+        use xwin::PayloadKind;
 
-    let mp = ia::MultiProgress::with_draw_target(draw_target.into());
-    let work_items: Vec<_> = pruned
-        .payloads
-        .into_iter()
-        .map(|pay| {
-            use xwin::PayloadKind;
+        payloads.retain(|pay| match pay.kind {
+            PayloadKind::CrtHeaders | PayloadKind::AtlHeaders | PayloadKind::SdkHeaders => {
+                !config.libs_only
+            }
+            PayloadKind::CrtLibs
+            | PayloadKind::AtlLibs
+            | PayloadKind::SdkLibs
+            | PayloadKind::SdkStoreLibs
+            | PayloadKind::CrtTools => !config.headers_only,
+            PayloadKind::Ucrt | PayloadKind::DiaSdk | PayloadKind::SdkMetadata => true,
+        });
+    }
 
-            let prefix = match pay.kind {
-                PayloadKind::CrtHeaders => "CRT.headers".to_owned(),
-                PayloadKind::AtlHeaders => "ATL.headers".to_owned(),
-                PayloadKind::CrtLibs => {
-                    format!(
-                        "CRT.libs.{}.{}",
-                        pay.target_arch.map_or("all", |ta| ta.as_str()),
-                        pay.variant.map_or("none", |v| v.as_str())
-                    )
-                }
-                PayloadKind::AtlLibs => {
-                    format!(
-                        "ATL.libs.{}",
-                        pay.target_arch.map_or("all", |ta| ta.as_str()),
-                    )
-                }
-                PayloadKind::SdkHeaders => {
-                    format!(
+    // The SBOM covers every target, so it's written once all of them are done
+    let sbom = sbom_path.map(|path| (path, payloads.clone()));
+
+    // Each target is splatted to its own directory, sharing the same download
+    // and unpack cache, so the unpacked files need to be copied rather than
+    // moved for every target but the last
+    let runs = match op {
+        xwin::Ops::Splat(config) if !splat_targets.is_empty() => {
+            let last = splat_targets.len() - 1;
+
+            splat_targets
+                .into_iter()
+                .enumerate()
+                .map(|(i, (triple, arch))| {
+                    let mut config = config.clone();
+                    config.output.push(&triple);
+                    if let Some(emit_bazel) = &mut config.emit_bazel {
+                        emit_bazel.push(&triple);
+                    }
+                    if let Some(layer) = &mut config.emit_oci_layer {
+                        layer.dir.push(&triple);
+                    }
+                    for path in [
+                        &mut config.emit_layout_json,
+                        &mut config.emit_gn_args,
+                        &mut config.symlink_report,
+                    ]
+                    .into_iter()
+                    .flatten()
+                    {
+                        push_triple_to_file_name(path, &triple);
+                    }
+                    config.copy |= i != last;
+
+                    let payloads = payloads
+                        .iter()
+                        .filter(|pay| !matches!(pay.target_arch, Some(ta) if ta != arch))
+                        .cloned()
+                        .collect();
+
+                    (xwin::Ops::Splat(config), arch as u32, payloads)
+                })
+                .collect()
+        }
+        op => vec![(op, arches, payloads)],
+    };
+
+    let pruned_diagnostics = diagnostics.len();
+
+    for (op, arches, payloads) in runs {
+        diagnostics.extend(run(
+            ctx.clone(),
+            pkgs.clone(),
+            payloads,
+            crt_version.clone(),
+            sdk_version.clone(),
+            arches,
+            variants,
+            op,
+            args.single_progress,
+            draw_target,
+        )?);
+    }
+
+    // Rewrite the diagnostics if problems were found while splatting, the
+    // pruning diagnostics were already written in case the splat failed
+    if let Some(diag_path) = args
+        .diagnostics_json
+        .as_ref()
+        .filter(|_| diagnostics.len() > pruned_diagnostics)
+    {
+        let serialized = serde_json::to_vec_pretty(&diagnostics)?;
+        std::fs::write(diag_path, serialized)
+            .with_context(|| format!("failed to write diagnostics to '{diag_path}'"))?;
+    }
+
+    if let Some((path, payloads)) = sbom {
+        let sbom = xwin::Sbom::new(&crt_version, &sdk_version, &payloads);
+        let serialized = serde_json::to_vec_pretty(&sbom)?;
+        std::fs::write(&path, serialized)
+            .with_context(|| format!("failed to write SBOM to '{path}'"))?;
+    }
+
+    Ok(())
+}
+
+/// Adds the target triple to the name of a file that is written for each
+/// target, eg. `layout.json` -> `layout-x86_64-pc-windows-msvc.json`
+fn push_triple_to_file_name(path: &mut PathBuf, triple: &str) {
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{triple}.{ext}", path.file_stem().unwrap_or_default()),
+        None => format!("{}-{triple}", path.file_name().unwrap_or_default()),
+    };
+    path.set_file_name(name);
+}
+
+/// The options that can be specified in an `xwin.toml`
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    arch: Option<Vec<xwin::Arch>>,
+    variant: Option<Vec<xwin::Variant>>,
+    crt_version: Option<String>,
+    sdk_version: Option<String>,
+    manifest_version: Option<String>,
+    channel: Option<String>,
+    cache_dir: Option<PathBuf>,
+    include_atl: Option<bool>,
+    include_dia: Option<bool>,
+    /// The layout of the output, used by `splat` and `minimize`
+    #[serde(default)]
+    splat: SplatConfigFile,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct SplatConfigFile {
+    output: Option<PathBuf>,
+    prefix: Option<PathBuf>,
+    use_winsysroot_style: Option<bool>,
+    preserve_ms_arch_notation: Option<bool>,
+    disable_symlinks: Option<bool>,
+    include_debug_libs: Option<bool>,
+    include_debug_symbols: Option<bool>,
+}
+
+/// Whether the config file value for an argument can be used, ie. the argument
+/// wasn't specified on the command line or via its environment variable, and
+/// doesn't conflict with one that was
+fn use_config(cmd: &clap::Command, matches: &clap::ArgMatches, id: &str) -> bool {
+    use clap::parser::ValueSource;
+
+    let is_explicit = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+
+    if is_explicit(id) {
+        return false;
+    }
+
+    let Some(arg) = cmd.get_arguments().find(|arg| arg.get_id() == id) else {
+        return true;
+    };
+
+    // Conflicts are only declared on one of the arguments, so check both ways
+    !cmd.get_arg_conflicts_with(arg)
+        .iter()
+        .any(|other| is_explicit(other.get_id().as_str()))
+        && !cmd.get_arguments().any(|other| {
+            is_explicit(other.get_id().as_str())
+                && cmd
+                    .get_arg_conflicts_with(other)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == id)
+        })
+}
+
+/// Parses the command line, applying the values from the config file, either
+/// the one specified via `--config` or `xwin.toml` in the current directory,
+/// to the arguments that weren't explicitly specified.
+///
+/// The config values are passed to clap as if they were specified on the
+/// command line, so that the relationships between arguments, eg. `requires`
+/// and `conflicts_with`, take them into account as well.
+///
+/// Relative paths in the config file are relative to the directory it is in.
+fn parse_args(argv: Vec<std::ffi::OsString>) -> Result<(Args, Option<PathBuf>), Error> {
+    // The first pass is only used to find the config file and which arguments
+    // were explicitly specified, the command line is validated in the second
+    let matches = Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&argv)?;
+
+    let Some((path, top, sub)) = config_args(&matches)? else {
+        let matches = Args::command().try_get_matches_from(argv)?;
+        return Ok((Args::from_arg_matches(&matches)?, None));
+    };
+
+    let mut argv = argv.into_iter();
+    let mut full: Vec<std::ffi::OsString> = argv.next().into_iter().collect();
+    full.extend(top.into_iter().map(Into::into));
+    full.extend(argv);
+
+    // The subcommand's arguments can go anywhere after it, as long as they
+    // are before any trailing arguments
+    let end = full
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(full.len());
+    full.splice(end..end, sub.into_iter().map(Into::into));
+
+    let matches = Args::command().try_get_matches_from(full)?;
+    Ok((Args::from_arg_matches(&matches)?, Some(path)))
+}
+
+/// Loads the config file and converts the values that can be used into the
+/// equivalent top-level and subcommand arguments
+#[allow(clippy::type_complexity)]
+fn config_args(
+    matches: &clap::ArgMatches,
+) -> Result<Option<(PathBuf, Vec<String>, Vec<String>)>, Error> {
+    let path = if let Some(path) = matches.get_one::<PathBuf>("config") {
+        path.clone()
+    } else {
+        let path = PathBuf::from("xwin.toml");
+        if !path.exists() {
+            return Ok(None);
+        }
+        path
+    };
+
+    let contents =
+        std::fs::read_to_string(&path).with_context(|| format!("unable to read {path}"))?;
+    let config: ConfigFile =
+        toml::from_str(&contents).with_context(|| format!("failed to deserialize {path}"))?;
+
+    let resolve = |rel: PathBuf| match path.parent() {
+        Some(parent) if rel.is_relative() => parent.join(rel),
+        _ => rel,
+    };
+
+    fn join<T: std::fmt::Display>(list: &[T]) -> String {
+        list.iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    let cmd = Args::command();
+    let can_use = |id: &str| use_config(&cmd, matches, id);
+
+    let mut top = Vec::new();
+    if let Some(arch) = config.arch.filter(|_| can_use("arch")) {
+        top.push(format!("--arch={}", join(&arch)));
+    }
+    if let Some(variant) = config.variant.filter(|_| can_use("variant")) {
+        top.push(format!("--variant={}", join(&variant)));
+    }
+    if let Some(crt_version) = config.crt_version.filter(|_| can_use("crt_version")) {
+        top.push(format!("--crt-version={crt_version}"));
+    }
+    if let Some(sdk_version) = config.sdk_version.filter(|_| can_use("sdk_version")) {
+        top.push(format!("--sdk-version={sdk_version}"));
+    }
+    if let Some(mv) = config
+        .manifest_version
+        .filter(|_| can_use("manifest_version"))
+    {
+        top.push(format!("--manifest-version={mv}"));
+    }
+    if let Some(channel) = config.channel.filter(|_| can_use("channel")) {
+        top.push(format!("--channel={channel}"));
+    }
+    if let Some(cache_dir) = config.cache_dir.filter(|_| can_use("cache_dir")) {
+        top.push(format!("--cache-dir={}", resolve(cache_dir)));
+    }
+    // Flags are off unless specified, so only true values need to be passed
+    if config.include_atl == Some(true) && can_use("include_atl") {
+        top.push("--include-atl".to_owned());
+    }
+    if config.include_dia == Some(true) && can_use("include_dia") {
+        top.push("--include-dia".to_owned());
+    }
+
+    let mut sub = Vec::new();
+    let Some((name @ ("splat" | "minimize"), sub_matches)) = matches.subcommand() else {
+        return Ok(Some((path, top, sub)));
+    };
+    let sub_cmd = cmd
+        .find_subcommand(name)
+        .context("unable to find subcommand")?;
+    let can_use = |id: &str| use_config(sub_cmd, sub_matches, id);
+
+    let splat = config.splat;
+    if let Some(out) = splat.output.filter(|_| can_use("output")) {
+        sub.push(format!("--output={}", resolve(out)));
+    }
+    if let Some(prefix) = splat.prefix.filter(|_| can_use("prefix")) {
+        sub.push(format!("--prefix={prefix}"));
+    }
+
+    let flags = [
+        (splat.use_winsysroot_style, "use_winsysroot_style"),
+        (splat.preserve_ms_arch_notation, "preserve_ms_arch_notation"),
+        (splat.disable_symlinks, "disable_symlinks"),
+        (splat.include_debug_libs, "include_debug_libs"),
+        (splat.include_debug_symbols, "include_debug_symbols"),
+    ];
+    for (value, id) in flags {
+        if value == Some(true) && can_use(id) {
+            sub.push(format!("--{}", id.replace('_', "-")));
+        }
+    }
+
+    Ok(Some((path, top, sub)))
+}
+
+/// Gets the cache directory to use when one isn't specified
+fn default_cache_dir() -> Result<PathBuf, Error> {
+    let cwd = PathBuf::from_path_buf(std::env::current_dir().context("unable to retrieve cwd")?)
+        .map_err(|pb| anyhow::anyhow!("cwd {} is not a valid utf-8 path", pb.display()))?;
+
+    // Keep using the cache in the current directory if it was created by a
+    // previous version, rather than downloading everything again
+    let local = cwd.join(".xwin-cache");
+    if local.exists() {
+        return Ok(local);
+    }
+
+    let Some(cache_dir) = dirs::cache_dir() else {
+        tracing::warn!("unable to determine the platform cache directory, using {local}");
+        return Ok(local);
+    };
+
+    let cache_dir = PathBuf::from_path_buf(cache_dir).map_err(|pb| {
+        anyhow::anyhow!("cache directory {} is not a valid utf-8 path", pb.display())
+    })?;
+
+    Ok(cache_dir.join("xwin"))
+}
+
+/// Fails if a version was expected with `--expect-sdk` or `--expect-crt`, but
+/// a different version was resolved
+fn check_expected_version(name: &str, expected: Option<&str>, resolved: &str) -> Result<(), Error> {
+    if let Some(expected) = expected.filter(|expected| *expected != resolved) {
+        return Err(xwin::util::ErrorKind::Selection
+            .msg(format!(
+                "the resolved {name} version '{resolved}' doesn't match the expected version '{expected}'"
+            ))
+            .into());
+    }
+
+    Ok(())
+}
+
+/// Executes the operation on the payloads, drawing the progress until it is
+/// finished
+#[allow(clippy::too_many_arguments)]
+fn run(
+    ctx: std::sync::Arc<xwin::Ctx>,
+    pkgs: std::collections::BTreeMap<String, xwin::manifest::ManifestItem>,
+    payloads: Vec<xwin::Payload>,
+    crt_version: String,
+    sdk_version: String,
+    arches: u32,
+    variants: u32,
+    op: xwin::Ops,
+    single_progress: bool,
+    draw_target: xwin::util::ProgressTarget,
+) -> Result<Vec<xwin::Diagnostic>, Error> {
+    let mp = ia::MultiProgress::with_draw_target(draw_target.into());
+    if !single_progress {
+        mp.add(ctx.overall_progress.clone());
+    }
+    let work_items = work_items(payloads, &mp, single_progress, draw_target);
+
+    mp.set_move_cursor(true);
+
+    let item_bars: Vec<_> = work_items.iter().map(|wi| wi.progress.clone()).collect();
+
+    let handle = std::thread::spawn(move || {
+        ctx.execute(
+            pkgs,
+            work_items,
+            crt_version,
+            sdk_version,
+            arches,
+            variants,
+            op,
+        )
+    });
+
+    if single_progress {
+        aggregate_progress(&handle, &item_bars, draw_target);
+    }
+
+    let res = handle.join();
+
+    match res {
+        Ok(res) => res,
+        Err(panic) => {
+            // The panic payload is almost always a string, either from a
+            // literal message, or a formatted one
+            let msg = if let Some(msg) = panic.downcast_ref::<&'static str>() {
+                msg
+            } else if let Some(msg) = panic.downcast_ref::<String>() {
+                msg.as_str()
+            } else {
+                "unknown panic"
+            };
+
+            anyhow::bail!("xwin panicked: {msg}")
+        }
+    }
+}
+
+/// Creates a work item for each payload, with a progress bar that is drawn
+/// unless only the single aggregate bar is used
+fn work_items(
+    payloads: Vec<xwin::Payload>,
+    mp: &ia::MultiProgress,
+    single_progress: bool,
+    draw_target: xwin::util::ProgressTarget,
+) -> Vec<xwin::WorkItem> {
+    payloads
+        .into_iter()
+        .map(|pay| {
+            use xwin::PayloadKind;
+
+            let prefix = match pay.kind {
+                PayloadKind::CrtHeaders => "CRT.headers".to_owned(),
+                PayloadKind::AtlHeaders => "ATL.headers".to_owned(),
+                PayloadKind::CrtLibs => {
+                    format!(
+                        "CRT.libs.{}.{}",
+                        pay.target_arch.map_or("all", |ta| ta.as_str()),
+                        pay.variant.map_or("none", |v| v.as_str())
+                    )
+                }
+                PayloadKind::AtlLibs => {
+                    format!(
+                        "ATL.libs.{}",
+                        pay.target_arch.map_or("all", |ta| ta.as_str()),
+                    )
+                }
+                PayloadKind::SdkHeaders => {
+                    format!(
                         "SDK.headers.{}.{}",
                         pay.target_arch.map_or("all", |v| v.as_str()),
                         pay.variant.map_or("none", |v| v.as_str())
@@ -412,52 +1986,129 @@ fn main() -> Result<(), Error> {
                 }
                 PayloadKind::SdkStoreLibs => "SDK.libs.store.all".to_owned(),
                 PayloadKind::Ucrt => "SDK.ucrt.all".to_owned(),
+                PayloadKind::DiaSdk => "DIA.sdk.all".to_owned(),
+                PayloadKind::CrtTools => {
+                    format!(
+                        "CRT.tools.{}",
+                        pay.target_arch.map_or("host", |ta| ta.as_str())
+                    )
+                }
+                PayloadKind::SdkMetadata => "SDK.winmd.all".to_owned(),
             };
 
-            let pb = mp.add(
-                ia::ProgressBar::with_draw_target(Some(0), draw_target.into()).with_prefix(prefix).with_style(
-                    ia::ProgressStyle::default_bar()
-                        .template("{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {bytes}/{total_bytes} {msg}")
-                        .unwrap()
-                        .progress_chars("█▇▆▅▄▃▂▁  "),
-                ),
-            );
+            // The individual bars still track their progress when the single
+            // bar is used, they just aren't drawn. The length starts out as the
+            // size of the payload in the manifest so the total is known before
+            // the download starts
+            let pb = if single_progress {
+                ia::ProgressBar::hidden().with_prefix(prefix)
+            } else {
+                mp.add(
+                    ia::ProgressBar::with_draw_target(Some(0), draw_target.into()).with_prefix(prefix).with_style(
+                        ia::ProgressStyle::default_bar()
+                            .template("{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {bytes}/{total_bytes} {msg}")
+                            .unwrap()
+                            .progress_chars("█▇▆▅▄▃▂▁  "),
+                    ),
+                )
+            };
+            pb.set_length(pay.size);
+
             xwin::WorkItem {
                 payload: std::sync::Arc::new(pay),
                 progress: pb,
             }
         })
-        .collect();
+        .collect()
+}
 
-    mp.set_move_cursor(true);
+/// Draws a single bar with the sum of every payload's progress until the
+/// execution thread finishes
+fn aggregate_progress<T>(
+    handle: &std::thread::JoinHandle<T>,
+    item_bars: &[ia::ProgressBar],
+    draw_target: xwin::util::ProgressTarget,
+) {
+    let total = ia::ProgressBar::with_draw_target(Some(0), draw_target.into())
+        .with_prefix("Total")
+        .with_style(
+            ia::ProgressStyle::default_bar()
+                .template("{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {bytes}/{total_bytes} {msg}")
+                .unwrap()
+                .progress_chars("█▇▆▅▄▃▂▁  "),
+        );
 
-    let res = std::thread::spawn(move || {
-        ctx.execute(
-            pkgs,
-            work_items,
-            pruned.crt_version,
-            pruned.sdk_version,
-            arches,
-            variants,
-            op,
-        )
-    })
-    .join();
+    let update = || {
+        let (mut pos, mut len, mut finished) = (0, 0, 0);
+        for pb in item_bars {
+            pos += pb.position();
+            len += pb.length().unwrap_or_default();
+            if pb.is_finished() {
+                finished += 1;
+            }
+        }
 
-    res.unwrap()
+        total.set_length(len);
+        total.set_position(pos);
+        total.set_message(format!("{finished}/{} payloads", item_bars.len()));
+    };
+
+    while !handle.is_finished() {
+        update();
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    update();
+    total.finish();
 }
 
-fn print_packages(payloads: &[xwin::Payload]) {
-    use cli_table::{format::Justify, Cell, Style, Table};
+fn print_diff(diff: &xwin::TreeDiff) {
+    use xwin::{Difference, Entry};
+
+    fn entry(e: &Entry) -> String {
+        match e {
+            Entry::File(hash) => format!("@ {hash}"),
+            Entry::Symlink(target) => format!("=> {target}"),
+        }
+    }
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for (path, difference) in &diff.differences {
+        match difference {
+            Difference::Added(new) => {
+                added += 1;
+                println!("+ {path} {}", entry(new));
+            }
+            Difference::Removed(old) => {
+                removed += 1;
+                println!("- {path} {}", entry(old));
+            }
+            Difference::Changed { old, new } => {
+                changed += 1;
+                println!("~ {path} {} -> {}", entry(old), entry(new));
+            }
+        }
+    }
 
-    let (dl, install) = payloads.iter().fold((0, 0), |(dl, install), payload| {
+    println!("{added} added, {removed} removed, {changed} changed");
+}
+
+/// The total download and install size of the payloads
+fn payload_totals(payloads: &[xwin::Payload]) -> (u64, u64) {
+    payloads.iter().fold((0, 0), |(dl, install), payload| {
         (
             dl + payload.size,
             install + payload.install_size.unwrap_or_default(),
         )
-    });
+    })
+}
 
-    let totals = vec![
+fn print_packages(payloads: &[xwin::Payload], show_hashes: bool) {
+    use cli_table::{format::Justify, Cell, Style, Table};
+
+    let (dl, install) = payload_totals(payloads);
+
+    let mut totals = vec![
         "Total".cell().bold(true).justify(Justify::Right),
         "".cell(),
         "".cell(),
@@ -465,10 +2116,14 @@ fn print_packages(payloads: &[xwin::Payload]) {
         indicatif::HumanBytes(install).cell().bold(true),
     ];
 
+    if show_hashes {
+        totals.push("".cell());
+    }
+
     let table = payloads
         .iter()
         .map(|payload| {
-            vec![
+            let mut row = vec![
                 payload.filename.clone().cell().justify(Justify::Right),
                 payload
                     .target_arch
@@ -482,6 +2137,157 @@ fn print_packages(payloads: &[xwin::Payload]) {
                     .cell(),
                 indicatif::HumanBytes(payload.size).cell(),
                 indicatif::HumanBytes(payload.install_size.unwrap_or_default()).cell(),
+            ];
+
+            if show_hashes {
+                row.push(payload.sha256.to_string().cell());
+            }
+
+            row
+        })
+        .chain(std::iter::once(totals))
+        .collect::<Vec<_>>()
+        .table()
+        .title({
+            let mut title = vec![
+                "Name".cell(),
+                "Target".cell(),
+                "Variant".cell(),
+                "Download Size".cell(),
+                "Install Size".cell(),
+            ];
+
+            if show_hashes {
+                title.push("SHA-256".cell());
+            }
+
+            title
+        });
+
+    let _ = cli_table::print_stdout(table);
+}
+
+fn print_cabs(cabs: &[xwin::MsiCab]) {
+    use cli_table::{format::Justify, Cell, Style, Table};
+
+    let totals = vec![
+        "Total".cell().bold(true).justify(Justify::Right),
+        cabs.len().cell().bold(true),
+        indicatif::HumanBytes(cabs.iter().map(|cab| cab.size).sum())
+            .cell()
+            .bold(true),
+    ];
+
+    let table = cabs
+        .iter()
+        .map(|cab| {
+            vec![
+                cab.msi.clone().cell().justify(Justify::Right),
+                cab.name.clone().cell(),
+                indicatif::HumanBytes(cab.size).cell(),
+            ]
+        })
+        .chain(std::iter::once(totals))
+        .collect::<Vec<_>>()
+        .table()
+        .title(vec!["MSI".cell(), "CAB".cell(), "Size".cell()]);
+
+    let _ = cli_table::print_stdout(table);
+}
+
+/// Quotes an argument with spaces so that it survives being split on spaces
+fn quote_arg(arg: &str) -> String {
+    if arg.contains(' ') {
+        format!("\"{arg}\"")
+    } else {
+        arg.to_owned()
+    }
+}
+
+fn print_env(triple: &str, dirs: &xwin::SplatDirs, single: bool) {
+    for (name, value) in env_vars(triple, dirs, single) {
+        println!("{name}={value}");
+    }
+}
+
+/// The environment variables for compiling and linking for the target with
+/// the splat directories
+fn env_vars(triple: &str, dirs: &xwin::SplatDirs, single: bool) -> Vec<(String, String)> {
+    let includes = dirs
+        .include_dirs
+        .iter()
+        .map(|dir| quote_arg(&format!("/imsvc{dir}")));
+
+    if single {
+        // clang-cl passes everything after /link to the linker, so it has to
+        // be at the end of the command line
+        let flags = [format!("--target={triple}"), "-fuse-ld=lld-link".to_owned()]
+            .into_iter()
+            .chain(includes)
+            .chain(std::iter::once("/link".to_owned()))
+            .chain(
+                dirs.lib_dirs
+                    .iter()
+                    .map(|dir| quote_arg(&format!("/libpath:{dir}"))),
+            )
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        return vec![("XWIN_FLAGS".to_owned(), flags)];
+    }
+
+    let cflags = ["-Wno-unused-command-line-argument".to_owned()]
+        .into_iter()
+        .chain(includes)
+        .collect::<Vec<_>>()
+        .join(" ");
+    // cargo splits the flags on spaces regardless of any quotes
+    let rust_flags = dirs
+        .lib_dirs
+        .iter()
+        .map(|dir| format!("-Lnative={dir}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let triple = triple.replace('-', "_");
+    let cargo_triple = triple.to_uppercase();
+
+    vec![
+        (format!("CC_{triple}"), "clang-cl".to_owned()),
+        (format!("CXX_{triple}"), "clang-cl".to_owned()),
+        (format!("AR_{triple}"), "llvm-lib".to_owned()),
+        (format!("CFLAGS_{triple}"), cflags.clone()),
+        (format!("CXXFLAGS_{triple}"), cflags),
+        (
+            format!("CARGO_TARGET_{cargo_triple}_LINKER"),
+            "lld-link".to_owned(),
+        ),
+        (format!("CARGO_TARGET_{cargo_triple}_RUSTFLAGS"), rust_flags),
+    ]
+}
+
+fn print_unpacked(unpacked: &[xwin::Unpacked]) {
+    use cli_table::{format::Justify, Cell, Style, Table};
+
+    let (files, size) = unpacked.iter().fold((0, 0), |(files, size), up| {
+        (files + up.num_files, size + up.decompressed)
+    });
+
+    let totals = vec![
+        "Total".cell().bold(true).justify(Justify::Right),
+        "".cell(),
+        files.cell().bold(true),
+        indicatif::HumanBytes(size).cell().bold(true),
+    ];
+
+    let table = unpacked
+        .iter()
+        .map(|up| {
+            vec![
+                up.filename.clone().cell().justify(Justify::Right),
+                up.sha256.to_string().cell(),
+                up.num_files.cell(),
+                indicatif::HumanBytes(up.decompressed).cell(),
             ]
         })
         .chain(std::iter::once(totals))
@@ -489,10 +2295,9 @@ fn print_packages(payloads: &[xwin::Payload]) {
         .table()
         .title(vec![
             "Name".cell(),
-            "Target".cell(),
-            "Variant".cell(),
-            "Download Size".cell(),
-            "Install Size".cell(),
+            "SHA-256".cell(),
+            "Files".cell(),
+            "Unpacked Size".cell(),
         ]);
 
     let _ = cli_table::print_stdout(table);
@@ -501,6 +2306,7 @@ fn print_packages(payloads: &[xwin::Payload]) {
 fn load_manifest(
     ctx: &xwin::Ctx,
     manifest: Option<&PathBuf>,
+    manifest_url: Option<&str>,
     manifest_version: &str,
     channel: &str,
     dt: xwin::util::ProgressTarget,
@@ -523,7 +2329,12 @@ fn load_manifest(
             serde_json::from_str(&manifest_content)
                 .with_context(|| format!("failed to deserialize manifest in '{}'", manifest_path))?
         }
-        None => xwin::manifest::get_manifest(ctx, manifest_version, channel, manifest_pb.clone())?,
+        None => match manifest_url {
+            Some(url) => xwin::manifest::get_manifest_from_url(ctx, url, manifest_pb.clone())?,
+            None => {
+                xwin::manifest::get_manifest(ctx, manifest_version, channel, manifest_pb.clone())?
+            }
+        },
     };
 
     let pkg_manifest = xwin::manifest::get_package_manifest(ctx, &manifest, manifest_pb.clone())?;
@@ -554,6 +2365,183 @@ mod test {
         });
     }
 
+    #[test]
+    fn config_precedence() {
+        let td = tempfile::tempdir().unwrap();
+        let dir = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let config = dir.join("xwin.toml");
+        std::fs::write(
+            &config,
+            "arch = [\"x86_64\", \"aarch64\"]\ncrt-version = \"14.38\"\ncache-dir = \"cache\"\n\n[splat]\nuse-winsysroot-style = true\n",
+        )
+        .unwrap();
+
+        let parse = |cli: &[&str]| {
+            super::parse_args(
+                ["xwin", "--config", config.as_str()]
+                    .iter()
+                    .chain(cli.iter())
+                    .map(Into::into)
+                    .collect(),
+            )
+        };
+
+        let (args, path) = parse(&["splat"]).unwrap();
+        assert_eq!(path.as_ref(), Some(&config));
+        assert_eq!(args.arch, [xwin::Arch::X86_64, xwin::Arch::Aarch64]);
+        assert_eq!(args.crt_version.as_deref(), Some("14.38"));
+        assert_eq!(args.cache_dir, Some(dir.join("cache")));
+        let super::Command::Splat { options, .. } = &args.cmd else {
+            unreachable!()
+        };
+        assert!(options.use_winsysroot_style);
+
+        // Flags take precedence, as do flags that conflict with the config
+        let (args, _) = parse(&[
+            "--arch",
+            "x86",
+            "--crt-version",
+            "14.40",
+            "splat",
+            "--emit-bazel",
+            "bazel",
+        ])
+        .unwrap();
+        assert_eq!(args.arch, [xwin::Arch::X86]);
+        assert_eq!(args.crt_version.as_deref(), Some("14.40"));
+        let super::Command::Splat { options, .. } = &args.cmd else {
+            unreachable!()
+        };
+        assert!(!options.use_winsysroot_style);
+
+        // Config values satisfy the requirements of flags
+        let (args, _) = parse(&["splat", "--emit-gn-args", "gn"]).unwrap();
+        let super::Command::Splat {
+            options,
+            emit_gn_args,
+            ..
+        } = &args.cmd
+        else {
+            unreachable!()
+        };
+        assert!(options.use_winsysroot_style);
+        assert_eq!(emit_gn_args.as_deref(), Some("gn".into()));
+
+        // But the requirements are still checked
+        std::fs::write(&config, "").unwrap();
+        let err = parse(&["splat", "--emit-gn-args", "gn"])
+            .err()
+            .expect("--emit-gn-args requires --use-winsysroot-style");
+        assert_eq!(
+            err.downcast_ref::<clap::Error>().unwrap().kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn expected_versions() {
+        use super::check_expected_version as check;
+
+        check("SDK", None, "10.0.22621").unwrap();
+        check("SDK", Some("10.0.22621"), "10.0.22621").unwrap();
+
+        // The versions must match exactly, eg. a prefix isn't enough
+        for (expected, resolved) in [("10.0.22621", "10.0.26100"), ("14.38", "14.38.17.8")] {
+            let err = check("CRT", Some(expected), resolved).unwrap_err();
+            assert_eq!(
+                xwin::util::ErrorKind::of(&err),
+                xwin::util::ErrorKind::Selection
+            );
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "the resolved CRT version '{resolved}' doesn't match the expected version '{expected}'"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn env_output() {
+        let dirs = xwin::SplatDirs {
+            include_dirs: vec!["/xwin/crt/include".into(), "/my xwin/sdk/include/um".into()],
+            lib_dirs: vec!["/xwin/crt/lib/x86_64".into()],
+        };
+
+        let vars = super::env_vars("x86_64-pc-windows-msvc", &dirs, false);
+        let vars: Vec<_> = vars.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        assert_eq!(
+            vars,
+            [
+                "CC_x86_64_pc_windows_msvc=clang-cl",
+                "CXX_x86_64_pc_windows_msvc=clang-cl",
+                "AR_x86_64_pc_windows_msvc=llvm-lib",
+                "CFLAGS_x86_64_pc_windows_msvc=-Wno-unused-command-line-argument /imsvc/xwin/crt/include \"/imsvc/my xwin/sdk/include/um\"",
+                "CXXFLAGS_x86_64_pc_windows_msvc=-Wno-unused-command-line-argument /imsvc/xwin/crt/include \"/imsvc/my xwin/sdk/include/um\"",
+                "CARGO_TARGET_X86_64_PC_WINDOWS_MSVC_LINKER=lld-link",
+                "CARGO_TARGET_X86_64_PC_WINDOWS_MSVC_RUSTFLAGS=-Lnative=/xwin/crt/lib/x86_64",
+            ]
+        );
+
+        // The linker flags have to come after /link
+        assert_eq!(
+            super::env_vars("aarch64-pc-windows-msvc", &dirs, true),
+            [(
+                "XWIN_FLAGS".to_owned(),
+                "--target=aarch64-pc-windows-msvc -fuse-ld=lld-link /imsvc/xwin/crt/include \"/imsvc/my xwin/sdk/include/um\" /link /libpath:/xwin/crt/lib/x86_64".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn winsysroot_bin_conflicts() {
+        let err = super::parse_args(
+            [
+                "xwin",
+                "splat",
+                "--use-winsysroot-style",
+                "--winsysroot-bin",
+                "--emit-oci-layer",
+                "oci",
+            ]
+            .iter()
+            .map(Into::into)
+            .collect(),
+        )
+        .err()
+        .expect("link.exe is an absolute symlink");
+        let err = err.downcast_ref::<clap::Error>().unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert!(err.to_string().contains("--winsysroot-bin"));
+    }
+
+    #[test]
+    fn add_arch_conflicts() {
+        let parse = |cli: &[&str]| {
+            super::parse_args(
+                ["xwin", "splat", "--add-arch"]
+                    .iter()
+                    .chain(cli.iter())
+                    .map(Into::into)
+                    .collect(),
+            )
+        };
+
+        assert!(parse(&["--emit-oci-layer", "oci"]).is_ok());
+
+        // The files describing the layout would only contain the added arches
+        for cli in [
+            ["--emit-bazel", "bazel"],
+            ["--emit-layout-json", "layout.json"],
+        ] {
+            let err = parse(&cli).err().expect("conflicts with --add-arch");
+            assert_eq!(
+                err.downcast_ref::<clap::Error>().unwrap().kind(),
+                clap::error::ErrorKind::ArgumentConflict
+            );
+        }
+    }
+
     use clap::{ColorChoice, Command};
 
     pub struct SnapshotTestDesc {
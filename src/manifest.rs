@@ -1,10 +1,10 @@
 use anyhow::{ensure, Context as _};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{cmp, collections::BTreeMap};
 
 use crate::Ctx;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Payload {
     #[serde(rename = "fileName")]
     pub file_name: String,
@@ -13,7 +13,7 @@ pub struct Payload {
     pub url: String,
 }
 
-#[derive(Copy, Clone, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, Deserialize, Serialize, PartialEq, Eq, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum Chip {
     X86,
@@ -23,7 +23,7 @@ pub enum Chip {
     Neutral,
 }
 
-#[derive(Copy, Clone, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, Deserialize, Serialize, PartialEq, Eq, Debug)]
 pub enum ItemKind {
     /// Unused.
     Bootstrapper,
@@ -57,13 +57,25 @@ pub enum ItemKind {
     Zip,
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
 #[serde(rename_all = "camelCase")]
 pub struct InstallSizes {
     pub target_drive: Option<u64>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// Localized, human readable information for an item
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalizedResource {
+    /// The locale, eg. `en-us`
+    pub language: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Link to the license for the item
+    pub license: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ManifestItem {
     pub id: String,
@@ -76,6 +88,8 @@ pub struct ManifestItem {
     #[serde(default)]
     pub dependencies: BTreeMap<String, serde_json::Value>,
     pub install_sizes: Option<InstallSizes>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub localized_resources: Vec<LocalizedResource>,
 }
 
 impl PartialEq for ManifestItem {
@@ -103,26 +117,114 @@ impl cmp::PartialOrd for ManifestItem {
 
 #[derive(Deserialize, Debug)]
 pub struct Manifest {
+    /// The items in the channel, eg. the products, as well as the item for
+    /// the package manifest itself
     #[serde(rename = "channelItems")]
-    channel_items: Vec<ManifestItem>,
+    pub channel_items: Vec<ManifestItem>,
+}
+
+impl Manifest {
+    /// The product whose license applies to the packages that are retrieved
+    pub const BUILD_TOOLS_ID: &'static str = "Microsoft.VisualStudio.Product.BuildTools";
+
+    /// Retrieves the license link for the Build Tools product, preferring the
+    /// `en-us` locale if it is available
+    pub fn license_url(&self) -> Option<&str> {
+        let product = self
+            .channel_items
+            .iter()
+            .find(|ci| ci.kind == ItemKind::ChannelProduct && ci.id == Self::BUILD_TOOLS_ID)?;
+
+        product
+            .localized_resources
+            .iter()
+            .find(|lr| lr.language.eq_ignore_ascii_case("en-us"))
+            .or_else(|| product.localized_resources.first())
+            .and_then(|lr| lr.license.as_deref())
+    }
 }
 
 /// Retrieves the top-level manifest which contains license links as well as the
 /// link to the actual package manifest which describes all of the contents
+///
+/// If [`Ctx::manifest_cache_ttl`] is set and the cached manifest was retrieved
+/// or confirmed to be current within it, the cached manifest is used without
+/// making any request.
 pub fn get_manifest(
     ctx: &Ctx,
     version: &str,
     channel: &str,
     progress: indicatif::ProgressBar,
 ) -> Result<Manifest, anyhow::Error> {
-    let manifest_bytes = ctx.get_and_validate(
-        format!("https://aka.ms/vs/{version}/{channel}/channel"),
-        &format!("manifest_{version}.json"),
-        None,
+    // Keep the original name for the release channel so existing caches are
+    // still used, but other channels need their own name so that they aren't
+    // mistaken for the release manifest when it is used without a request
+    let cache_name = if channel == "release" {
+        format!("manifest_{version}.json")
+    } else {
+        format!("manifest_{version}_{channel}.json")
+    };
+
+    fetch_manifest(
+        ctx,
+        &format!("https://aka.ms/vs/{version}/{channel}/channel"),
+        &cache_name,
         progress,
-    )?;
+    )
+}
+
+/// Retrieves the top-level manifest from an arbitrary url, eg. an `aka.ms` or
+/// `go.microsoft.com/fwlink` link to a pinned manifest, following any
+/// redirects to the actual manifest
+///
+/// The manifest is cached by the url that was specified rather than the one
+/// that was redirected to, as the link is what stays the same between runs.
+pub fn get_manifest_from_url(
+    ctx: &Ctx,
+    url: &str,
+    progress: indicatif::ProgressBar,
+) -> Result<Manifest, anyhow::Error> {
+    fetch_manifest(ctx, url, &url_cache_name(url), progress)
+}
 
-    let manifest: Manifest = serde_json::from_slice(&manifest_bytes)?;
+/// The name the manifest retrieved from the url is cached as
+fn url_cache_name(url: &str) -> String {
+    let hash = crate::util::Sha256::digest(url.as_bytes()).to_string();
+    format!("manifest_url_{}.json", &hash[..16])
+}
+
+fn fetch_manifest(
+    ctx: &Ctx,
+    url: &str,
+    cache_name: &str,
+    progress: indicatif::ProgressBar,
+) -> Result<Manifest, anyhow::Error> {
+    let fresh = ctx.manifest_cache_ttl.and_then(|ttl| {
+        let cache_path = ctx.work_dir.join("dl").join(cache_name);
+        let age = std::fs::metadata(&cache_path)
+            .and_then(|md| md.modified())
+            .ok()?
+            .elapsed()
+            .ok()?;
+
+        if age >= ttl {
+            return None;
+        }
+
+        std::fs::read(&cache_path).ok()
+    });
+
+    let manifest_bytes = if let Some(fresh) = fresh {
+        tracing::debug!("using cached manifest {cache_name}, it is within the cache TTL");
+        progress.inc_length(fresh.len() as u64);
+        progress.inc(fresh.len() as u64);
+        fresh.into()
+    } else {
+        ctx.get_conditional(url, &cache_name, progress)?
+    };
+
+    let manifest: Manifest = serde_json::from_slice(&manifest_bytes)
+        .with_context(|| format!("failed to deserialize the manifest retrieved from {url}"))?;
 
     Ok(manifest)
 }
@@ -154,6 +256,7 @@ pub fn get_package_manifest(
         payload.url.clone(),
         &format!("pkg_manifest_{}.vsman", payload.sha256),
         None,
+        None,
         progress,
     )?;
 
@@ -166,14 +269,84 @@ pub fn get_package_manifest(
         serde_json::from_slice(&manifest_bytes).context("unable to parse manifest")?;
 
     let mut packages = BTreeMap::new();
+    let mut duplicates = BTreeMap::<String, Vec<String>>::new();
 
     for pkg in manifest.packages {
-        packages.insert(pkg.id.clone(), pkg);
+        if let Some(prev) = packages.insert(pkg.id.clone(), pkg) {
+            duplicates.entry(prev.id).or_default().push(prev.version);
+        }
+    }
+
+    // Duplicate ids are common in the manifest, eg. for localized packages,
+    // but only the last item with a given id is kept, so warn if that could
+    // mean we select the wrong payload
+    for (id, versions) in duplicates.into_iter().filter(|(id, _)| is_selectable(id)) {
+        tracing::warn!(
+            "package '{id}' appears {} times in the package manifest, using version '{}', discarded version(s): {}",
+            versions.len() + 1,
+            packages[&id].version,
+            versions.join(", ")
+        );
     }
 
     Ok(PackageManifest { packages })
 }
 
+/// Whether the package id is one that is looked up when selecting the CRT,
+/// ATL, SDK, UCRT, or DIA SDK payloads
+fn is_selectable(id: &str) -> bool {
+    const PREFIXES: &[&str] = &[
+        "Microsoft.VC.",
+        "Microsoft.VisualStudio.Component.VC.",
+        "Win10SDK_",
+        "Win11SDK_",
+    ];
+    const IDS: &[&str] = &[
+        Manifest::BUILD_TOOLS_ID,
+        "Microsoft.Windows.UniversalCRT.HeadersLibsSources.Msi",
+        "Microsoft.VisualCpp.DIA.SDK",
+    ];
+
+    PREFIXES.iter().any(|prefix| id.starts_with(prefix)) || IDS.contains(&id)
+}
+
 pub struct PackageManifest {
     pub packages: BTreeMap<String, ManifestItem>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn url_cache_key() {
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let mut ctx = Ctx::with_dir(
+            work_dir.clone(),
+            crate::util::ProgressTarget::Hidden,
+            ureq::agent(),
+            0,
+        )
+        .unwrap();
+        ctx.offline = true;
+
+        let pinned = "https://aka.ms/vs/17/release.ltsc.17.8/channel";
+        let other = "https://aka.ms/vs/17/release.ltsc.17.10/channel";
+
+        let name = url_cache_name(pinned);
+        assert_eq!(name, url_cache_name(pinned));
+        assert_ne!(name, url_cache_name(other));
+        assert!(name.starts_with("manifest_url_") && name.ends_with(".json"));
+
+        let get = |url| get_manifest_from_url(&ctx, url, indicatif::ProgressBar::hidden());
+        assert!(get(pinned).is_err());
+
+        // The manifest is cached by the url that was specified, so it can be
+        // used offline, but is never mistaken for a different url or channel
+        std::fs::write(work_dir.join("dl").join(&name), r#"{"channelItems":[]}"#).unwrap();
+        assert!(get(pinned).unwrap().channel_items.is_empty());
+        assert!(get(other).is_err());
+        assert!(get_manifest(&ctx, "17", "release", indicatif::ProgressBar::hidden()).is_err());
+    }
+}
@@ -8,20 +8,36 @@ pub struct MinimizeConfig {
     pub use_winsysroot_style: bool,
     pub preserve_ms_arch_notation: bool,
     pub splat_output: PathBuf,
+    pub splat_prefix: Option<PathBuf>,
     pub copy: bool,
     pub minimize_output: Option<PathBuf>,
     pub map: PathBuf,
     pub target: String,
     pub manifest_path: PathBuf,
+    /// Builds in release mode, which can use different headers than a debug
+    /// build
+    pub release: bool,
+    /// Additional arguments passed to `cargo build`, eg. `--features`
+    pub cargo_args: Vec<String>,
     pub preserve_strace: bool,
+    /// The directory in which the strace output is written, defaults to the
+    /// system temp directory
+    pub strace_tmp_dir: Option<PathBuf>,
+    /// If specified, the [`MinimizeResults`] are written as JSON to the path
+    pub results_json: Option<PathBuf>,
+    /// If specified, the strace output from a previous minimize is used
+    /// instead of building, which requires that the splat output is the same
+    /// as the one that was traced
+    pub strace_input: Option<PathBuf>,
 }
 
-#[derive(Default)]
+#[derive(Default, serde::Serialize)]
 pub struct FileCounts {
     pub bytes: u64,
     pub count: u32,
 }
 
+#[derive(serde::Serialize)]
 pub struct FileNumbers {
     /// The counts for the total set of files
     pub total: FileCounts,
@@ -29,6 +45,7 @@ pub struct FileNumbers {
     pub used: FileCounts,
 }
 
+#[derive(serde::Serialize)]
 pub struct MinimizeResults {
     pub crt_headers: FileNumbers,
     pub crt_libs: FileNumbers,
@@ -36,195 +53,345 @@ pub struct MinimizeResults {
     pub sdk_libs: FileNumbers,
 }
 
-pub(crate) fn minimize(
-    _ctx: std::sync::Arc<Ctx>,
-    config: MinimizeConfig,
-    roots: crate::splat::SplatRoots,
-    sdk_version: &str,
-) -> anyhow::Result<MinimizeResults> {
-    let mut used_paths: std::collections::BTreeMap<
-        PathBuf,
-        (SectionKind, std::collections::BTreeSet<String>),
-    > = std::collections::BTreeMap::new();
+/// Ensures all of the tools needed to minimize are available before we do
+/// any actual work, rather than failing in a confusing way deep in the build
+pub(crate) fn preflight(config: &MinimizeConfig) -> anyhow::Result<()> {
+    const TOOLS: &[&str] = &["cargo", "strace", "clang-cl", "lld-link", "llvm-lib"];
 
-    let (used, total) = rayon::join(
-        || -> anyhow::Result<_> {
-            // Clean the output for the package, otherwise we'll miss headers if
-            // C/C++ code has already been built
-            let mut clean = std::process::Command::new("cargo");
-
-            clean.args([
-                "clean",
-                "--target",
-                &config.target,
-                "--manifest-path",
-                config.manifest_path.as_str(),
-            ]);
-            if !clean.status().map_or(false, |s| s.success()) {
-                tracing::error!("failed to clean cargo target directory");
-            }
+    // Nothing is built when using existing strace output
+    if config.strace_input.is_some() {
+        return Ok(());
+    }
 
-            // Use a temporary (hopefully ramdisk) file to store the actual output
-            // from strace, and just let the output from the build itself go
-            // to stderr as normal
-            let td = tempfile::tempdir().context("failed to create strace output file")?;
-            let strace_output_path = td.path().join("strace_output.txt");
+    let missing: Vec<_> = TOOLS
+        .iter()
+        .filter(|tool| crate::util::find_in_path(tool).is_none())
+        .copied()
+        .collect();
 
-            if config.preserve_strace {
-                let path = td.into_path();
-                tracing::info!("strace output {}", path.display());
-            }
+    anyhow::ensure!(
+        missing.is_empty(),
+        "minimize requires the following tools which were not found in PATH: {}",
+        missing.join(", ")
+    );
 
-            let mut strace = std::process::Command::new("strace");
-            strace.args([
-                // Follow forks, cargo spawns clang/lld
-                "-f",
-                // We only care about opens
-                "-e",
-                "trace=openat",
-                "-o",
-            ]);
-            strace.arg(&strace_output_path);
-            strace.args([
-                "cargo",
-                "build",
-                "--target",
-                &config.target,
-                "--manifest-path",
-                config.manifest_path.as_str(),
-            ]);
-
-            let splat_root = canonicalize(&config.splat_output)?;
-
-            let includes = format!(
-                "-Wno-unused-command-line-argument -fuse-ld=lld-link /vctoolsdir {splat_root}/crt /winsdkdir {splat_root}/sdk"
-            );
+    Ok(())
+}
+
+/// Resolves a symlink, following any chain of symlinks, to the file it
+/// ultimately points to. Unlike [`canonicalize`], the directories leading
+/// to the symlink are not resolved, so that the same file is always keyed by
+/// the same path regardless of which symlink it was reached from, or whether
+/// it was reached from the walk of the splat directory or the strace output.
+fn resolve_symlink(path: &Path) -> Option<PathBuf> {
+    // Symlinks created by the splat never chain more than a couple of times,
+    // so this is only to guard against cycles
+    const MAX_DEPTH: usize = 8;
+
+    let mut resolved = path.to_owned();
+
+    for _ in 0..MAX_DEPTH {
+        if !resolved.is_symlink() {
+            return Some(resolved);
+        }
 
-            let mut libs = format!("-C linker=lld-link -Lnative={splat_root}/crt/lib/x86_64 -Lnative={splat_root}/sdk/lib/um/x86_64 -Lnative={splat_root}/sdk/lib/ucrt/x86_64");
+        let target = std::fs::read_link(&resolved).ok()?;
+        // We're the ones creating symlinks and they are always utf-8
+        let target = PathBuf::from_path_buf(target).ok()?;
 
-            let rust_flags_env = format!(
-                "CARGO_TARGET_{}_RUSTFLAGS",
-                config.target.replace('-', "_").to_uppercase()
-            );
+        let joined = resolved.parent()?.join(target);
 
-            // Sigh, some people use RUSTFLAGS to enable hidden library features, incredibly annoying
-            if let Ok(rf) = std::env::var(&rust_flags_env) {
-                libs.push(' ');
-                libs.push_str(&rf);
-            } else if let Ok(rf) = std::env::var("RUSTFLAGS") {
-                libs.push(' ');
-                libs.push_str(&rf);
+        // Remove any `..` components so that the path can be compared with
+        // the paths of regular files
+        resolved = PathBuf::new();
+        for comp in joined.components() {
+            match comp {
+                camino::Utf8Component::ParentDir => {
+                    resolved.pop();
+                }
+                camino::Utf8Component::CurDir => {}
+                comp => resolved.push(comp),
             }
+        }
+    }
 
-            let triple = config.target.replace('-', "_");
+    tracing::warn!("unable to resolve symlink {path}, it is either cyclic or too deeply nested");
+    None
+}
 
-            let cc_env = [
-                (format!("CC_{triple}"), "clang-cl"),
-                (format!("CXX_{triple}"), "clang-cl"),
-                (format!("AR_{triple}"), "llvm-lib"),
-                (format!("CFLAGS_{triple}"), &includes),
-                (format!("CXXFLAGS_{triple}"), &includes),
-                (rust_flags_env, &libs),
-            ];
+/// Gets the metadata for a file, retrying briefly if it can't be read.
+///
+/// Paths that don't exist are only retried if `retry_missing` is true, eg. for
+/// the target of a symlink that was opened, as most paths that don't exist
+/// are just include directories that were probed by the compiler.
+fn file_metadata(path: &Path, retry_missing: bool) -> Option<std::fs::Metadata> {
+    const RETRIES: u32 = 5;
+    const DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+
+    for attempt in 0..RETRIES {
+        match std::fs::metadata(path) {
+            Ok(md) => return Some(md),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !retry_missing => {
+                return None;
+            }
+            Err(err) => {
+                tracing::debug!(attempt, error = %err, "unable to read metadata for {path}");
+                std::thread::sleep(DELAY);
+            }
+        }
+    }
 
-            strace.envs(cc_env);
+    None
+}
 
-            tracing::info!("compiling {}", config.manifest_path);
+/// Parses the path of a file that was opened from a line of strace output
+fn opened_path(line: &str) -> Option<&str> {
+    let i = line.find("openat(AT_FDCWD, \"")?;
+    let (path, rest) = line[i + 18..].split_once('"')?;
 
-            let mut child = strace.spawn().context("unable to start strace")?;
+    // We can immediately skip file that were unable to be opened,
+    // but many file opens will be asynchronous so this won't
+    // catch all of them, but that's fine since we check for
+    // the existence when consuming the paths
+    (!rest.contains("-1 NOENT (")).then_some(path)
+}
 
-            let (tx, rx) = crossbeam_channel::unbounded();
+/// Compiles the crate under strace, sending the path of every file that is
+/// opened by the build as it is traced
+fn trace_build(
+    config: &MinimizeConfig,
+    roots: &crate::splat::SplatRoots,
+    sdk_version: &str,
+    tx: crossbeam_channel::Sender<String>,
+) -> anyhow::Result<()> {
+    use std::io::BufRead;
+
+    // Clean the output for the package, otherwise we'll miss headers if
+    // C/C++ code has already been built
+    let mut clean = std::process::Command::new("cargo");
+
+    clean.args([
+        "clean",
+        "--target",
+        &config.target,
+        "--manifest-path",
+        config.manifest_path.as_str(),
+    ]);
+    if !clean.status().map_or(false, |s| s.success()) {
+        tracing::error!("failed to clean cargo target directory");
+    }
 
-            // This should happen quickly
-            let strace_output = {
-                let start = std::time::Instant::now();
-                let max = std::time::Duration::from_secs(10);
-                loop {
-                    match std::fs::File::open(&strace_output_path) {
-                        Ok(f) => break f,
-                        Err(err) => {
-                            if start.elapsed() > max {
-                                anyhow::bail!("failed to open strace output '{}' after waiting for {max:?}: {err}", strace_output_path.display());
-                            }
+    // Use a temporary (hopefully ramdisk) file to store the actual output
+    // from strace, and just let the output from the build itself go
+    // to stderr as normal
+    let td = if let Some(dir) = &config.strace_tmp_dir {
+        tempfile::tempdir_in(dir)
+            .with_context(|| format!("failed to create strace output dir in {dir}"))?
+    } else {
+        tempfile::tempdir().context("failed to create strace output dir")?
+    };
+    let strace_output_path = td.path().join("strace_output.txt");
+
+    if config.preserve_strace {
+        let path = td.into_path();
+        tracing::info!("strace output {}", path.display());
+    }
 
-                            std::thread::sleep(std::time::Duration::from_millis(10));
-                        }
+    let mut strace = std::process::Command::new("strace");
+    strace.args([
+        // Follow forks, cargo spawns clang/lld
+        "-f",
+        // We only care about opens
+        "-e",
+        "trace=openat",
+        "-o",
+    ]);
+    strace.arg(&strace_output_path);
+    strace.args([
+        "cargo",
+        "build",
+        "--target",
+        &config.target,
+        "--manifest-path",
+        config.manifest_path.as_str(),
+    ]);
+    if config.release {
+        strace.arg("--release");
+    }
+    strace.args(&config.cargo_args);
+
+    let splat_root = canonicalize(&roots.root)?;
+    let in_splat = |dir: &Path| match dir.strip_prefix(&roots.root) {
+        Ok(rel) => splat_root.join(rel),
+        Err(_) => dir.to_owned(),
+    };
+
+    let includes = format!(
+        "-Wno-unused-command-line-argument -fuse-ld=lld-link /vctoolsdir {} /winsdkdir {}",
+        in_splat(&roots.crt),
+        in_splat(&roots.sdk),
+    );
+
+    // The splat is always filtered by the map that is generated
+    let layout = crate::splat::DirLayout {
+        winsysroot: config.use_winsysroot_style,
+        map: true,
+        merge_ucrt_include: false,
+        preserve_ms_arch_notation: config.preserve_ms_arch_notation,
+        spectre: false,
+        sdk_version,
+    };
+
+    let mut libs = "-C linker=lld-link".to_owned();
+    for dir in roots.lib_dirs(&layout, crate::Arch::X86_64) {
+        libs.push_str(" -Lnative=");
+        libs.push_str(in_splat(&dir).as_str());
+    }
+
+    let rust_flags_env = format!(
+        "CARGO_TARGET_{}_RUSTFLAGS",
+        config.target.replace('-', "_").to_uppercase()
+    );
+
+    // Sigh, some people use RUSTFLAGS to enable hidden library features, incredibly annoying
+    if let Ok(rf) = std::env::var(&rust_flags_env) {
+        libs.push(' ');
+        libs.push_str(&rf);
+    } else if let Ok(rf) = std::env::var("RUSTFLAGS") {
+        libs.push(' ');
+        libs.push_str(&rf);
+    }
+
+    let triple = config.target.replace('-', "_");
+
+    let cc_env = [
+        (format!("CC_{triple}"), "clang-cl"),
+        (format!("CXX_{triple}"), "clang-cl"),
+        (format!("AR_{triple}"), "llvm-lib"),
+        (format!("CFLAGS_{triple}"), &includes),
+        (format!("CXXFLAGS_{triple}"), &includes),
+        (rust_flags_env, &libs),
+    ];
+
+    strace.envs(cc_env);
+
+    tracing::info!("compiling {}", config.manifest_path);
+
+    let mut child = strace.spawn().context("unable to start strace")?;
+
+    // This should happen quickly
+    let strace_output = {
+        let start = std::time::Instant::now();
+        let max = std::time::Duration::from_secs(10);
+        loop {
+            match std::fs::File::open(&strace_output_path) {
+                Ok(f) => break f,
+                Err(err) => {
+                    if start.elapsed() > max {
+                        anyhow::bail!(
+                            "failed to open strace output '{}' after waiting for {max:?}: {err}",
+                            strace_output_path.display()
+                        );
                     }
+
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            }
+        }
+    };
+
+    let mut output = std::io::BufReader::new(strace_output);
+    let mut line = String::new();
+
+    // We cannot use read_line/read_until here as Rust's BufRead
+    // will end a line on either the delimiter OR EOF, and since
+    // the file is being written to while we are reading, it is
+    // almost guaranteed we will hit EOF 1 or more times before
+    // an actual line is completed, given a large enough trace,
+    // so we roll our own
+    let mut read_line = |line: &mut String| -> anyhow::Result<bool> {
+        let buf = unsafe { line.as_mut_vec() };
+        loop {
+            let (done, used) = {
+                let available = match output.fill_buf() {
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => anyhow::bail!(e),
+                };
+                if let Some(i) = memchr::memchr(b'\n', available) {
+                    buf.extend_from_slice(&available[..=i]);
+                    (true, i + 1)
+                } else {
+                    buf.extend_from_slice(available);
+                    (false, available.len())
                 }
             };
+            output.consume(used);
+            if done {
+                return Ok(true);
+            } else if used == 0 && child.try_wait().context("compile child failed")?.is_some() {
+                return Ok(false);
+            }
+        }
+    };
 
-            let mut output = std::io::BufReader::new(strace_output);
-
-            let (_, counts) = rayon::join(
-                move || -> anyhow::Result<()> {
-                    use std::io::BufRead;
-                    let mut line = String::new();
-
-                    // We cannot use read_line/read_until here as Rust's BufRead
-                    // will end a line on either the delimiter OR EOF, and since
-                    // the file is being written to while we are reading, it is
-                    // almost guaranteed we will hit EOF 1 or more times before
-                    // an actual line is completed, given a large enough trace,
-                    // so we roll our own
-                    let mut read_line = |line: &mut String| -> anyhow::Result<bool> {
-                        let buf = unsafe { line.as_mut_vec() };
-                        loop {
-                            let (done, used) = {
-                                let available = match output.fill_buf() {
-                                    Ok(n) => n,
-                                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {
-                                        continue
-                                    }
-                                    Err(e) => anyhow::bail!(e),
-                                };
-                                if let Some(i) = memchr::memchr(b'\n', available) {
-                                    buf.extend_from_slice(&available[..=i]);
-                                    (true, i + 1)
-                                } else {
-                                    buf.extend_from_slice(available);
-                                    (false, available.len())
-                                }
-                            };
-                            output.consume(used);
-                            if done {
-                                return Ok(true);
-                            } else if used == 0
-                                && child.try_wait().context("compile child failed")?.is_some()
-                            {
-                                return Ok(false);
-                            }
-                        }
-                    };
+    loop {
+        line.clear();
+        if !read_line(&mut line)? {
+            break;
+        }
 
-                    loop {
-                        line.clear();
-                        if !read_line(&mut line)? {
-                            break;
-                        }
+        if let Some(opened) = opened_path(&line) {
+            let _ = tx.send(opened.to_owned());
+        }
+    }
 
-                        let Some(i) = line.find("openat(AT_FDCWD, \"") else {
-                            continue;
-                        };
-                        let Some(open) = line[i + 18..].split_once('"') else {
-                            continue;
-                        };
+    drop(tx);
+    let status = child.wait()?;
+    anyhow::ensure!(status.success(), "compilation failed");
 
-                        // We can immediately skip file that were unable to be opened,
-                        // but many file opens will be asynchronous so this won't
-                        // catch all of them, but that's fine since we check for
-                        // the existence in the other thread
-                        if open.1.contains("-1 NOENT (") {
-                            continue;
-                        }
+    Ok(())
+}
 
-                        let _ = tx.send(open.0.to_owned());
-                    }
+/// Sends the path of every file that was opened in the strace output of a
+/// previous minimize, eg. one kept with `--preserve-strace`, so that the build
+/// doesn't need to be run again
+fn replay_strace(path: &Path, tx: crossbeam_channel::Sender<String>) -> anyhow::Result<()> {
+    use std::io::BufRead;
+
+    let output = std::fs::File::open(path)
+        .with_context(|| format!("failed to open strace output '{path}'"))?;
 
-                    drop(tx);
-                    let status = child.wait()?;
-                    anyhow::ensure!(status.success(), "compilation failed");
+    for line in std::io::BufReader::new(output).lines() {
+        let line = line.with_context(|| format!("failed to read strace output '{path}'"))?;
 
-                    Ok(())
+        if let Some(opened) = opened_path(&line) {
+            let _ = tx.send(opened.to_owned());
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn minimize(
+    _ctx: std::sync::Arc<Ctx>,
+    config: MinimizeConfig,
+    roots: crate::splat::SplatRoots,
+    sdk_version: &str,
+) -> anyhow::Result<MinimizeResults> {
+    let mut used_paths: std::collections::BTreeMap<
+        PathBuf,
+        (SectionKind, std::collections::BTreeSet<String>),
+    > = std::collections::BTreeMap::new();
+
+    let (used, total) = rayon::join(
+        || -> anyhow::Result<_> {
+            let (tx, rx) = crossbeam_channel::unbounded();
+
+            let (traced, counts) = rayon::join(
+                || match &config.strace_input {
+                    Some(input) => replay_strace(input, tx),
+                    None => trace_build(&config, &roots, sdk_version, tx),
                 },
                 || {
                     let mut crt_headers = FileCounts::default();
@@ -272,7 +439,7 @@ pub(crate) fn minimize(
                                 return;
                             }
 
-                            let Ok(md) = std::fs::metadata(&path) else {
+                            let Some(md) = file_metadata(&path, symlink.is_some()) else {
                                 // clang will probe paths according to the include directories,
                                 // and while we filter on NOENT in the strace output, in many
                                 // cases the opens are async and this split over multiple lines,
@@ -297,11 +464,10 @@ pub(crate) fn minimize(
                         };
 
                         if path.is_symlink() {
-                            // We're the ones creating symlinks and they are always utf-8
-                            let sl = std::fs::read_link(&path).expect("failed to read symlink");
-                            let sl = PathBuf::from_path_buf(sl).expect("symlink path was non-utf8");
+                            let Some(resolved) = resolve_symlink(&path) else {
+                                continue;
+                            };
 
-                            let resolved = path.parent().unwrap().join(sl);
                             insert(resolved, Some(path.file_name().unwrap().to_owned()));
                         } else {
                             insert(path, None);
@@ -312,6 +478,7 @@ pub(crate) fn minimize(
                 },
             );
 
+            traced?;
             Ok(counts)
         },
         || {
@@ -337,20 +504,16 @@ pub(crate) fn minimize(
                     }
 
                     if entry.path_is_symlink() {
-                        let Ok(rp) = std::fs::read_link(path) else {
-                            continue;
-                        };
-
                         if !path.is_file() {
                             continue;
                         }
 
-                        let Ok(real_path) = PathBuf::from_path_buf(rp) else {
+                        let Some(real_path) = resolve_symlink(path) else {
                             continue;
                         };
 
                         symlinks
-                            .entry(path.parent().unwrap().join(real_path))
+                            .entry(real_path)
                             .or_default()
                             .insert(path.file_name().unwrap().to_owned());
                     } else {
@@ -437,6 +600,10 @@ pub(crate) fn minimize(
             // they can just revert the changes if a file that was previously in
             // the list was removed
             map.clear();
+            map.layout = Some(crate::MapLayout {
+                winsysroot: config.use_winsysroot_style,
+                ms_arch_notation: config.preserve_ms_arch_notation,
+            });
 
             let crt_hdr_prefix = roots.crt.join("include");
             let crt_lib_prefix = roots.crt.join("lib");
@@ -0,0 +1,455 @@
+use crate::{
+    splat::{DirLayout, SplatRoots},
+    Arch, Path, PathBuf,
+};
+use anyhow::{Context as _, Error};
+use serde::Serialize;
+use sha2::Digest as _;
+
+/// Where an OCI image with the splat as its only layer is written
+#[derive(Clone, Debug)]
+pub struct OciLayer {
+    /// The directory the OCI image layout is written to
+    pub dir: PathBuf,
+    /// The absolute path in the image the splat is placed at, eg. `/xwin`
+    pub root: PathBuf,
+}
+
+const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar";
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+#[derive(Serialize)]
+struct Descriptor {
+    #[serde(rename = "mediaType")]
+    media_type: &'static str,
+    digest: String,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<std::collections::BTreeMap<&'static str, String>>,
+}
+
+#[derive(Serialize)]
+struct ImageConfig {
+    architecture: &'static str,
+    os: &'static str,
+    config: ContainerConfig,
+    rootfs: RootFs,
+    history: Vec<History>,
+}
+
+#[derive(Serialize)]
+struct ContainerConfig {
+    #[serde(rename = "Env")]
+    env: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct RootFs {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    diff_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct History {
+    created_by: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    media_type: &'static str,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    schema_version: u32,
+    media_type: &'static str,
+    manifests: Vec<Descriptor>,
+}
+
+/// Hashes everything written to the inner writer
+struct HashWriter<W> {
+    inner: W,
+    hasher: sha2::Sha256,
+    len: u64,
+}
+
+impl<W: std::io::Write> std::io::Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes a blob to the layout, returning its descriptor
+fn write_blob(blobs: &Path, media_type: &'static str, blob: &[u8]) -> Result<Descriptor, Error> {
+    let digest = crate::util::Sha256::digest(blob).to_string();
+    let path = blobs.join(&digest);
+    std::fs::write(&path, blob).with_context(|| format!("unable to write {path}"))?;
+
+    Ok(Descriptor {
+        media_type,
+        digest: format!("sha256:{digest}"),
+        size: blob.len() as u64,
+        annotations: None,
+    })
+}
+
+/// The environment variables that configure `cc` and cargo to use the splat
+/// at `root` in the image for each architecture
+fn image_env(roots: &SplatRoots, root: &Path, arches: u32, layout: &DirLayout<'_>) -> Vec<String> {
+    // Only reference the directories that were actually splatted, eg. there
+    // are no headers if --libs-only was used
+    let in_image = |dirs: Vec<PathBuf>| -> Vec<PathBuf> {
+        dirs.into_iter()
+            .filter(|dir| dir.exists())
+            .map(|dir| match dir.strip_prefix(&roots.root) {
+                Ok(rel) => root.join(rel),
+                Err(_) => dir,
+            })
+            .collect()
+    };
+
+    let mut flags = "-Wno-unused-command-line-argument -fuse-ld=lld-link".to_owned();
+    for dir in in_image(roots.include_dirs(layout)) {
+        flags.push_str(" /imsvc");
+        flags.push_str(dir.as_str());
+    }
+
+    let mut env = Vec::new();
+    for arch in Arch::iter(arches) {
+        let triple = arch.as_triple().replace('-', "_");
+        let cargo_triple = triple.to_uppercase();
+
+        let rust_flags = in_image(roots.lib_dirs(layout, arch).to_vec())
+            .iter()
+            .map(|dir| format!("-Lnative={dir}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        env.extend([
+            format!("CC_{triple}=clang-cl"),
+            format!("CXX_{triple}=clang-cl"),
+            format!("AR_{triple}=llvm-lib"),
+            format!("CFLAGS_{triple}={flags}"),
+            format!("CXXFLAGS_{triple}={flags}"),
+            format!("CARGO_TARGET_{cargo_triple}_LINKER=lld-link"),
+            format!("CARGO_TARGET_{cargo_triple}_RUSTFLAGS={rust_flags}"),
+        ]);
+    }
+
+    env
+}
+
+/// Writes an [OCI image layout](https://github.com/opencontainers/image-spec/blob/main/image-layout.md)
+/// with a single layer containing the splat at the configured root, and the
+/// environment variables for using it with `cc` and cargo in its config, so
+/// that it can be used as a base layer or copied from in a container build.
+///
+/// The layer is deterministic, ie. every entry has the same owner and mtime,
+/// so splatting the same CRT and SDK results in the same digest.
+///
+/// Symlinks are added as is, so absolute symlinks are rejected, as their
+/// targets won't exist in the image.
+pub(crate) fn emit_oci_layer(
+    layer: &OciLayer,
+    roots: &SplatRoots,
+    arches: u32,
+    layout: &DirLayout<'_>,
+    crt_version: &str,
+    sdk_version: &str,
+) -> Result<(), Error> {
+    let blobs = layer.dir.join("blobs/sha256");
+
+    // Only the current image is kept, otherwise every splat would leave its
+    // layer behind
+    if blobs.exists() {
+        std::fs::remove_dir_all(&blobs)
+            .with_context(|| format!("unable to remove existing blobs {blobs}"))?;
+    }
+    std::fs::create_dir_all(&blobs).with_context(|| format!("unable to create {blobs}"))?;
+
+    let image_root = layer
+        .root
+        .strip_prefix("/")
+        .with_context(|| format!("OCI root '{}' is not an absolute path", layer.root))?;
+
+    let layer_tmp = blobs.join(".layer.tar");
+    let (layer_digest, layer_size) = {
+        let file = std::fs::File::create(&layer_tmp)
+            .with_context(|| format!("unable to create {layer_tmp}"))?;
+
+        let mut tar = tar::Builder::new(HashWriter {
+            inner: std::io::BufWriter::new(file),
+            hasher: sha2::Sha256::new(),
+            len: 0,
+        });
+        tar.mode(tar::HeaderMode::Deterministic);
+        tar.follow_symlinks(false);
+
+        // The parent directories of the root need their own entries
+        let mut parent = PathBuf::new();
+        for component in image_root.parent().into_iter().flat_map(|p| p.components()) {
+            parent.push(component);
+            tar.append_dir(&parent, &roots.root)
+                .with_context(|| format!("unable to add {parent} to the layer"))?;
+        }
+
+        for entry in walkdir::WalkDir::new(&roots.root)
+            .follow_links(false)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                roots.checkpoints.as_ref().map(|cp| cp.as_std_path()) != Some(entry.path())
+                    && entry.path() != roots.root.join(crate::splat::SPLAT_MARKER).as_std_path()
+            })
+        {
+            let entry = entry.context("unable to read splat directory entry")?;
+            let path = Path::from_path(entry.path())
+                .with_context(|| format!("{} is not a utf-8 path", entry.path().display()))?;
+            let rel = path.strip_prefix(&roots.root)?;
+
+            if entry.path_is_symlink() {
+                let target = std::fs::read_link(path)
+                    .with_context(|| format!("unable to read symlink {path}"))?;
+                anyhow::ensure!(
+                    target.is_relative(),
+                    "{path} is a symlink to the absolute path {}, which won't exist in the image",
+                    target.display()
+                );
+            }
+
+            let name = if rel.as_str().is_empty() {
+                image_root.to_owned()
+            } else {
+                image_root.join(rel)
+            };
+
+            tar.append_path_with_name(path, &name)
+                .with_context(|| format!("unable to add {path} to the layer"))?;
+        }
+
+        let mut hw = tar
+            .into_inner()
+            .with_context(|| format!("unable to finish {layer_tmp}"))?;
+        std::io::Write::flush(&mut hw).with_context(|| format!("unable to write {layer_tmp}"))?;
+
+        (
+            crate::util::Sha256(hw.hasher.finalize().into()).to_string(),
+            hw.len,
+        )
+    };
+
+    let layer_path = blobs.join(&layer_digest);
+    std::fs::rename(&layer_tmp, &layer_path)
+        .with_context(|| format!("unable to move {layer_tmp} to {layer_path}"))?;
+
+    // The layer only has files, but the config needs a platform, so we use the
+    // host's, which is where the splat will be used
+    let architecture = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "arm" => "arm",
+        other => other,
+    };
+
+    let config = ImageConfig {
+        architecture,
+        os: "linux",
+        config: ContainerConfig {
+            env: image_env(roots, &layer.root, arches, layout),
+        },
+        rootfs: RootFs {
+            kind: "layers",
+            diff_ids: vec![format!("sha256:{layer_digest}")],
+        },
+        history: vec![History {
+            created_by: format!(
+                "xwin {} splat (CRT {crt_version}, SDK {sdk_version})",
+                env!("CARGO_PKG_VERSION")
+            ),
+        }],
+    };
+    let config = write_blob(&blobs, CONFIG_MEDIA_TYPE, &serde_json::to_vec(&config)?)?;
+
+    let manifest = Manifest {
+        schema_version: 2,
+        media_type: MANIFEST_MEDIA_TYPE,
+        config,
+        layers: vec![Descriptor {
+            media_type: LAYER_MEDIA_TYPE,
+            digest: format!("sha256:{layer_digest}"),
+            size: layer_size,
+            annotations: None,
+        }],
+    };
+    let mut manifest = write_blob(&blobs, MANIFEST_MEDIA_TYPE, &serde_json::to_vec(&manifest)?)?;
+    manifest.annotations = Some(
+        [("org.opencontainers.image.ref.name", "latest".to_owned())]
+            .into_iter()
+            .collect(),
+    );
+
+    let index = Index {
+        schema_version: 2,
+        media_type: INDEX_MEDIA_TYPE,
+        manifests: vec![manifest],
+    };
+
+    let index_path = layer.dir.join("index.json");
+    std::fs::write(&index_path, serde_json::to_vec_pretty(&index)?)
+        .with_context(|| format!("unable to write {index_path}"))?;
+
+    let layout_path = layer.dir.join("oci-layout");
+    std::fs::write(&layout_path, r#"{"imageLayoutVersion":"1.0.0"}"#)
+        .with_context(|| format!("unable to write {layout_path}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The casing symlinks are only created on unix
+    #[cfg(unix)]
+    #[test]
+    fn layer_contents() {
+        let td = tempfile::tempdir().unwrap();
+        let dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let root = dir.join("splat");
+        let roots = SplatRoots {
+            checkpoints: Some(root.join(".xwin-checkpoints")),
+            ..crate::splat::test::test_roots(&root)
+        };
+
+        for (path, contents) in [
+            ("crt/include/vcruntime.h", "vcruntime"),
+            ("crt/lib/x64/msvcrt.lib", "msvcrt"),
+            ("sdk/include/um/Windows.h", "windows"),
+            (".xwin-checkpoints/crt.json", "{}"),
+            (crate::splat::SPLAT_MARKER, ""),
+        ] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        crate::symlink("Windows.h", &root.join("sdk/include/um/windows.h")).unwrap();
+
+        let layout = DirLayout {
+            winsysroot: false,
+            map: false,
+            merge_ucrt_include: false,
+            preserve_ms_arch_notation: true,
+            spectre: false,
+            sdk_version: "10.0.22621.0",
+        };
+        let layer = OciLayer {
+            dir: dir.join("oci"),
+            root: "/opt/xwin".into(),
+        };
+
+        let emit = || {
+            emit_oci_layer(
+                &layer,
+                &roots,
+                Arch::X86_64 as u32,
+                &layout,
+                "14.40.33807",
+                "10.0.22621.0",
+            )
+        };
+        emit().unwrap();
+
+        let blob = |digest: &str| {
+            std::fs::read(
+                layer
+                    .dir
+                    .join("blobs/sha256")
+                    .join(digest.strip_prefix("sha256:").unwrap()),
+            )
+            .unwrap()
+        };
+        let json = |bytes: &[u8]| serde_json::from_slice::<serde_json::Value>(bytes).unwrap();
+
+        let index = json(&std::fs::read(layer.dir.join("index.json")).unwrap());
+        let manifest = json(&blob(index["manifests"][0]["digest"].as_str().unwrap()));
+        let config = json(&blob(manifest["config"]["digest"].as_str().unwrap()));
+
+        let env: Vec<_> = config["config"]["Env"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|var| var.as_str().unwrap())
+            .collect();
+        assert!(env.contains(
+            &"CFLAGS_x86_64_pc_windows_msvc=-Wno-unused-command-line-argument -fuse-ld=lld-link /imsvc/opt/xwin/crt/include /imsvc/opt/xwin/sdk/include/um"
+        ));
+        assert!(env.contains(
+            &"CARGO_TARGET_X86_64_PC_WINDOWS_MSVC_RUSTFLAGS=-Lnative=/opt/xwin/crt/lib/x64"
+        ));
+
+        let layer_blob = blob(manifest["layers"][0]["digest"].as_str().unwrap());
+        let mut archive = tar::Archive::new(layer_blob.as_slice());
+        let entries: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                // Deterministic entries all have the same fixed mtime
+                assert_eq!(entry.header().mtime().unwrap(), 1153704088);
+                (
+                    entry.path().unwrap().to_str().unwrap().to_owned(),
+                    entry
+                        .link_name()
+                        .unwrap()
+                        .map(|target| target.to_str().unwrap().to_owned()),
+                )
+            })
+            .collect();
+
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "opt",
+                "opt/xwin",
+                "opt/xwin/crt",
+                "opt/xwin/crt/include",
+                "opt/xwin/crt/include/vcruntime.h",
+                "opt/xwin/crt/lib",
+                "opt/xwin/crt/lib/x64",
+                "opt/xwin/crt/lib/x64/msvcrt.lib",
+                "opt/xwin/sdk",
+                "opt/xwin/sdk/include",
+                "opt/xwin/sdk/include/um",
+                "opt/xwin/sdk/include/um/Windows.h",
+                "opt/xwin/sdk/include/um/windows.h",
+            ]
+        );
+        assert_eq!(entries.last().unwrap().1.as_deref(), Some("Windows.h"));
+
+        // Absolute symlinks would point outside of the image
+        crate::symlink(
+            root.join("crt/lib/x64/msvcrt.lib").as_str(),
+            &root.join("crt/lib/x64/MSVCRT.lib"),
+        )
+        .unwrap();
+        let err = emit().unwrap_err();
+        assert!(err.to_string().contains("absolute path"), "{err:#}");
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{Payload, PayloadKind};
+use serde::Serialize;
+
+/// A [CycloneDX](https://cyclonedx.org/specification/overview/) software bill
+/// of materials for the Microsoft components retrieved by xwin
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sbom {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: Metadata,
+    components: Vec<Component>,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    tools: Tools,
+}
+
+#[derive(Serialize)]
+struct Tools {
+    components: Vec<Component>,
+}
+
+#[derive(Serialize)]
+struct Component {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref", skip_serializing_if = "Option::is_none")]
+    bom_ref: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher: Option<&'static str>,
+    name: String,
+    version: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<Hash>,
+    #[serde(rename = "externalReferences", skip_serializing_if = "Vec::is_empty")]
+    external_references: Vec<ExternalReference>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<Property>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    components: Vec<Component>,
+}
+
+#[derive(Serialize)]
+struct Hash {
+    alg: &'static str,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct ExternalReference {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct Property {
+    name: &'static str,
+    value: String,
+}
+
+const PUBLISHER: &str = "Microsoft Corporation";
+
+impl Sbom {
+    /// Creates an SBOM with a component for the CRT and the SDK, each of which
+    /// contains a component for every payload, including its checksum and size
+    pub fn new<'p>(
+        crt_version: &str,
+        sdk_version: &str,
+        payloads: impl IntoIterator<Item = &'p Payload>,
+    ) -> Self {
+        let mut crt = Vec::new();
+        let mut sdk = Vec::new();
+
+        for payload in payloads {
+            let (parent, version) = match payload.kind {
+                PayloadKind::CrtHeaders
+                | PayloadKind::CrtLibs
+                | PayloadKind::AtlHeaders
+                | PayloadKind::AtlLibs
+                | PayloadKind::DiaSdk
+                | PayloadKind::CrtTools => (&mut crt, crt_version),
+                PayloadKind::SdkHeaders
+                | PayloadKind::SdkLibs
+                | PayloadKind::SdkStoreLibs
+                | PayloadKind::SdkMetadata
+                | PayloadKind::Ucrt => (&mut sdk, sdk_version),
+            };
+
+            let mut properties = vec![
+                Property {
+                    name: "xwin:kind",
+                    value: serde_json::to_value(payload.kind)
+                        .ok()
+                        .and_then(|kind| kind.as_str().map(String::from))
+                        .unwrap_or_default(),
+                },
+                Property {
+                    name: "xwin:size",
+                    value: payload.size.to_string(),
+                },
+            ];
+
+            if let Some(arch) = payload.target_arch {
+                properties.push(Property {
+                    name: "xwin:target_arch",
+                    value: arch.as_str().to_owned(),
+                });
+            }
+
+            if let Some(variant) = payload.variant {
+                properties.push(Property {
+                    name: "xwin:variant",
+                    value: variant.as_str().to_owned(),
+                });
+            }
+
+            parent.push(Component {
+                kind: "file",
+                bom_ref: Some(payload.filename.to_string()),
+                publisher: Some(PUBLISHER),
+                name: payload.filename.to_string(),
+                version: version.to_owned(),
+                hashes: vec![Hash {
+                    alg: "SHA-256",
+                    content: payload.sha256.to_string(),
+                }],
+                external_references: vec![ExternalReference {
+                    kind: "distribution",
+                    url: payload.url.clone(),
+                }],
+                properties,
+                components: Vec::new(),
+            });
+        }
+
+        let group = |bom_ref: &str, name: &str, version: &str, components| Component {
+            kind: "library",
+            bom_ref: Some(bom_ref.to_owned()),
+            publisher: Some(PUBLISHER),
+            name: name.to_owned(),
+            version: version.to_owned(),
+            hashes: Vec::new(),
+            external_references: Vec::new(),
+            properties: Vec::new(),
+            components,
+        };
+
+        Self {
+            bom_format: "CycloneDX",
+            spec_version: "1.5",
+            version: 1,
+            metadata: Metadata {
+                tools: Tools {
+                    components: vec![Component {
+                        kind: "application",
+                        bom_ref: None,
+                        publisher: None,
+                        name: "xwin".to_owned(),
+                        version: env!("CARGO_PKG_VERSION").to_owned(),
+                        hashes: Vec::new(),
+                        external_references: Vec::new(),
+                        properties: Vec::new(),
+                        components: Vec::new(),
+                    }],
+                },
+            },
+            components: vec![
+                group("crt", "Microsoft Visual C++ CRT", crt_version, crt),
+                group("sdk", "Windows SDK", sdk_version, sdk),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Arch, Variant};
+
+    #[test]
+    fn cyclonedx() {
+        let payload = |filename: &str, kind, target_arch, variant| Payload {
+            filename: filename.into(),
+            sha256: crate::util::Sha256([0xab; 32]),
+            url: format!("https://download.visualstudio.microsoft.com/{filename}"),
+            size: 42,
+            install_size: None,
+            kind,
+            target_arch,
+            variant,
+        };
+
+        let payloads = [
+            payload(
+                "Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base.vsix",
+                PayloadKind::CrtLibs,
+                Some(Arch::X86_64),
+                Some(Variant::Desktop),
+            ),
+            payload(
+                "Windows SDK Desktop Headers x86-x86_en-us.msi",
+                PayloadKind::SdkHeaders,
+                None,
+                None,
+            ),
+        ];
+
+        let sbom = serde_json::to_value(Sbom::new("14.38.17.8", "10.0.22621", &payloads)).unwrap();
+
+        assert_eq!(sbom["bomFormat"], "CycloneDX");
+        assert_eq!(sbom["specVersion"], "1.5");
+        assert_eq!(
+            sbom["metadata"]["tools"]["components"][0]["version"],
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let hash = "ab".repeat(32);
+        assert_eq!(
+            sbom["components"],
+            serde_json::json!([
+                {
+                    "type": "library",
+                    "bom-ref": "crt",
+                    "publisher": PUBLISHER,
+                    "name": "Microsoft Visual C++ CRT",
+                    "version": "14.38.17.8",
+                    "components": [{
+                        "type": "file",
+                        "bom-ref": "Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base.vsix",
+                        "publisher": PUBLISHER,
+                        "name": "Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base.vsix",
+                        "version": "14.38.17.8",
+                        "hashes": [{ "alg": "SHA-256", "content": hash }],
+                        "externalReferences": [{
+                            "type": "distribution",
+                            "url": "https://download.visualstudio.microsoft.com/Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base.vsix",
+                        }],
+                        "properties": [
+                            { "name": "xwin:kind", "value": "crt_libs" },
+                            { "name": "xwin:size", "value": "42" },
+                            { "name": "xwin:target_arch", "value": "x86_64" },
+                            { "name": "xwin:variant", "value": "desktop" },
+                        ],
+                    }],
+                },
+                {
+                    "type": "library",
+                    "bom-ref": "sdk",
+                    "publisher": PUBLISHER,
+                    "name": "Windows SDK",
+                    "version": "10.0.22621",
+                    "components": [{
+                        "type": "file",
+                        "bom-ref": "Windows SDK Desktop Headers x86-x86_en-us.msi",
+                        "publisher": PUBLISHER,
+                        "name": "Windows SDK Desktop Headers x86-x86_en-us.msi",
+                        "version": "10.0.22621",
+                        "hashes": [{ "alg": "SHA-256", "content": hash }],
+                        "externalReferences": [{
+                            "type": "distribution",
+                            "url": "https://download.visualstudio.microsoft.com/Windows SDK Desktop Headers x86-x86_en-us.msi",
+                        }],
+                        "properties": [
+                            { "name": "xwin:kind", "value": "sdk_headers" },
+                            { "name": "xwin:size", "value": "42" },
+                        ],
+                    }],
+                },
+            ])
+        );
+    }
+}
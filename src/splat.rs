@@ -1,9 +1,9 @@
-use crate::{symlink, Arch, Ctx, Error, Path, PathBuf, PayloadKind, SectionKind, Variant};
+use crate::{Arch, Error, Path, PathBuf, PayloadKind, SectionKind, Variant};
 use anyhow::Context as _;
 use rayon::prelude::*;
 use std::collections::BTreeMap;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct SplatConfig {
     pub include_debug_libs: bool,
     pub include_debug_symbols: bool,
@@ -11,23 +11,106 @@ pub struct SplatConfig {
     pub preserve_ms_arch_notation: bool,
     pub use_winsysroot_style: bool,
     pub output: PathBuf,
+    /// An optional relative path under `output` in which the CRT and SDK are
+    /// splatted, allowing multiple toolchains to coexist in the same output
+    pub prefix: Option<PathBuf>,
     pub map: Option<PathBuf>,
     pub copy: bool,
+    /// Only splat headers
+    pub headers_only: bool,
+    /// Only splat libraries
+    pub libs_only: bool,
+    /// Skips payloads that were successfully splatted by a previous run with
+    /// the same configuration, rather than starting from scratch
+    pub resume: bool,
+    /// Creates the `bin/Host<arch>/<arch>` directories in the /winsysroot
+    /// layout that clang-cl looks for when locating the linker
+    pub winsysroot_bin: bool,
+    /// Overrides the name of the `crt` directory in the default layout
+    pub crt_dir_name: Option<String>,
+    /// Overrides the name of the `sdk` directory in the default layout
+    pub sdk_dir_name: Option<String>,
+    /// If not empty, only the SDK libs whose names match one of the glob
+    /// patterns, as well as those needed by the Rust standard library, are
+    /// splatted
+    pub sdk_libs: Vec<String>,
+    /// If symlinks are disabled, creates copies of files with their alternate
+    /// names instead
+    pub symlink_fallback_copy: bool,
+    /// A command that is run with the system shell once the splat has
+    /// finished successfully
+    pub post_splat: Option<String>,
+    /// Symlinks each architecture directory to the other notation, eg.
+    /// `lib/x64` -> `x86_64`, so that both notations can be used
+    pub link_arch_dirs: bool,
+    /// If specified, a Bazel `cc_toolchain` for the splat is written to the
+    /// directory
+    pub emit_bazel: Option<PathBuf>,
+    /// If specified, an OCI image with the splat as its only layer is written
+    pub emit_oci_layer: Option<crate::OciLayer>,
+    /// If specified, a JSON description of the splat's directories and
+    /// versions is written to the path
+    pub emit_layout_json: Option<PathBuf>,
+    /// If specified, a GN args file pointing at the splat is written to the
+    /// path. Only supported with the /winsysroot layout
+    pub emit_gn_args: Option<PathBuf>,
+    /// If specified, only the CRT libs in [`CRT_LIB_CASINGS`] with one of
+    /// these names get their uppercase casing, otherwise all of them do
+    pub crt_lib_casings: Option<Vec<String>>,
+    /// If specified, every symlink in the splat and its target is written to
+    /// the path once the splat has finished
+    pub symlink_report: Option<PathBuf>,
+    /// Splats into the existing CRT and SDK directories rather than deleting
+    /// them first, only adding the files that aren't already present, eg. to
+    /// add the libraries of another architecture
+    pub add_arch: bool,
+    /// Rewrites every symlink in the splat to point at the absolute path of
+    /// its target, rather than the relative path that keeps the splat
+    /// relocatable
+    pub absolute_symlinks: bool,
+    /// If specified, the modification time of every file in the splat is set
+    /// to this time, so that the timestamps are the same regardless of when
+    /// and how each file was unpacked and splatted
+    pub mtime: Option<std::time::SystemTime>,
+    /// Places the UCRT headers directly in the SDK include directory,
+    /// alongside `um` and `shared`, rather than in an `ucrt` subdirectory, so
+    /// that one less include directory needs to be specified
+    pub merge_ucrt_include: bool,
+    /// Places the type libraries (`.tlb`) from the SDK libs in their own
+    /// `tlb/<arch>` directory in the SDK rather than alongside the libraries,
+    /// and always splats them, even if they don't match `sdk_libs`
+    pub separate_tlbs: bool,
+    /// Hashes every SDK header as it is splatted, so that headers with the
+    /// same path but different contents in different payloads are reported
+    /// as diagnostics. This is skipped by default as every header has to be
+    /// read again
+    pub detect_conflicting_headers: bool,
     //pub isolated: bool,
 }
 
 /// There is a massive amount of duplication between SDK headers for the Desktop
 /// and Store variants, so we keep track of them so we only splat one unique file
+#[derive(serde::Serialize, serde::Deserialize)]
 pub(crate) struct SdkHeaders {
     pub(crate) inner: BTreeMap<u64, PathBuf>,
     pub(crate) root: PathBuf,
+    /// The checksum of each header in `inner` as it was splatted, so that
+    /// headers with the same path but different contents in different
+    /// payloads can be reported, as only one of them ends up in the splat
+    #[serde(default)]
+    pub(crate) contents: BTreeMap<u64, crate::util::Sha256>,
+    /// The payload the headers were splatted from
+    #[serde(default)]
+    pub(crate) payload: PathBuf,
 }
 
 impl SdkHeaders {
-    fn new(root: PathBuf) -> Self {
+    fn new(root: PathBuf, payload: PathBuf) -> Self {
         Self {
             inner: BTreeMap::new(),
             root,
+            contents: BTreeMap::new(),
+            payload,
         }
     }
 
@@ -49,42 +132,435 @@ pub(crate) struct SplatRoots {
     pub root: PathBuf,
     pub crt: PathBuf,
     pub sdk: PathBuf,
-    src: PathBuf,
+    /// The DIA SDK, which isn't part of the CRT and so gets its own directory,
+    /// which is only created if it is splatted
+    pub dia: PathBuf,
+    pub src: PathBuf,
+    /// The directory checkpoints are written to, if resuming is enabled
+    pub checkpoints: Option<PathBuf>,
+}
+
+/// The options that determine where [`splat`] places the include and library
+/// directories, so that everything that points a compiler or linker at a
+/// splat agrees with it
+#[derive(Clone, Copy)]
+pub(crate) struct DirLayout<'v> {
+    /// The /winsysroot layout, which has versioned SDK directories
+    pub(crate) winsysroot: bool,
+    /// Map files always place the SDK headers in the versioned `Include`
+    /// directory, as that is where the casing symlinks are relative to
+    pub(crate) map: bool,
+    pub(crate) merge_ucrt_include: bool,
+    pub(crate) preserve_ms_arch_notation: bool,
+    /// The CRT libs are placed in `lib/spectre/<arch>`
+    pub(crate) spectre: bool,
+    pub(crate) sdk_version: &'v str,
+}
+
+impl DirLayout<'_> {
+    /// The name of the architecture directories
+    pub(crate) fn arch_dir(&self, arch: Arch) -> &'static str {
+        if self.preserve_ms_arch_notation {
+            arch.as_ms_str()
+        } else {
+            arch.as_str()
+        }
+    }
+}
+
+impl SplatRoots {
+    /// The roots of an existing splat in the default layout, ie. `<root>/crt`,
+    /// `<root>/sdk`, and `<root>/dia`
+    pub(crate) fn default_layout(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            crt: root.join("crt"),
+            sdk: root.join("sdk"),
+            dia: root.join("dia"),
+            src: PathBuf::new(),
+            checkpoints: None,
+        }
+    }
+
+    /// Detects the layout options an existing splat in the default layout was
+    /// splatted with. If an architecture is specified, its CRT libraries are
+    /// used to detect the arch notation and whether they are the Spectre ones
+    pub(crate) fn detect_layout(&self, arch: Option<Arch>) -> Result<DirLayout<'static>, Error> {
+        let mut layout = DirLayout {
+            winsysroot: false,
+            map: false,
+            merge_ucrt_include: false,
+            preserve_ms_arch_notation: false,
+            spectre: false,
+            sdk_version: "",
+        };
+
+        // With --merge-ucrt-include the ucrt headers, eg. corecrt.h, are
+        // directly in the SDK include directory instead of `include/ucrt`
+        let sdk_include = self.sdk_include(&layout);
+        layout.merge_ucrt_include =
+            !sdk_include.join("ucrt").exists() && sdk_include.join("corecrt.h").exists();
+
+        let Some(arch) = arch else {
+            return Ok(layout);
+        };
+
+        let crt_lib = self.crt.join("lib");
+        let (spectre, preserve_ms_arch_notation) = [false, true]
+            .into_iter()
+            .flat_map(|spectre| [(spectre, false), (spectre, true)])
+            .find(|&(spectre, preserve)| {
+                let mut dir = crt_lib.clone();
+                if spectre {
+                    dir.push("spectre");
+                }
+                dir.push(if preserve {
+                    arch.as_ms_str()
+                } else {
+                    arch.as_str()
+                });
+                dir.exists()
+            })
+            .with_context(|| format!("{crt_lib} doesn't contain the libraries for {arch}"))?;
+
+        layout.spectre = spectre;
+        layout.preserve_ms_arch_notation = preserve_ms_arch_notation;
+        Ok(layout)
+    }
+
+    /// The directories that are splatted into, which are the only ones that
+    /// are modified once the splat has finished, as the rest of the root may
+    /// belong to the user. The /winsysroot `bin` directory is in the CRT
+    pub(crate) fn splatted_dirs(&self) -> impl Iterator<Item = &Path> {
+        [self.crt.as_path(), self.sdk.as_path(), self.dia.as_path()]
+            .into_iter()
+            .filter(|dir| dir.exists())
+    }
+
+    /// The root of the SDK headers
+    fn sdk_include(&self, layout: &DirLayout<'_>) -> PathBuf {
+        if layout.map {
+            self.sdk.join("Include").join(layout.sdk_version)
+        } else if layout.winsysroot {
+            self.sdk.join("include").join(layout.sdk_version)
+        } else {
+            self.sdk.join("include")
+        }
+    }
+
+    /// The root of the SDK libraries
+    fn sdk_lib(&self, layout: &DirLayout<'_>) -> PathBuf {
+        if layout.winsysroot {
+            self.sdk.join("lib").join(layout.sdk_version)
+        } else {
+            self.sdk.join("lib")
+        }
+    }
+
+    /// The include directories of the splat, in the order clang-cl searches
+    /// them when using `/vctoolsdir` and `/winsdkdir`.
+    ///
+    /// Every directory is returned, whether it was splatted or not, eg. there
+    /// are no include directories if `--libs-only` was used
+    pub(crate) fn include_dirs(&self, layout: &DirLayout<'_>) -> Vec<PathBuf> {
+        let sdk_include = self.sdk_include(layout);
+
+        std::iter::once(self.crt.join("include"))
+            .chain(
+                ["ucrt", "shared", "um", "winrt", "cppwinrt"]
+                    .into_iter()
+                    .map(|dir| {
+                        if dir == "ucrt" && layout.merge_ucrt_include {
+                            sdk_include.clone()
+                        } else {
+                            sdk_include.join(dir)
+                        }
+                    }),
+            )
+            .collect()
+    }
+
+    /// The directories containing the CRT, SDK, and UCRT libraries for an
+    /// architecture, whether they were splatted or not
+    pub(crate) fn lib_dirs(&self, layout: &DirLayout<'_>, arch: Arch) -> [PathBuf; 3] {
+        let arch_dir = layout.arch_dir(arch);
+
+        let mut crt_lib = self.crt.join("lib");
+        if layout.spectre {
+            crt_lib.push("spectre");
+        }
+
+        let sdk_lib = self.sdk_lib(layout);
+
+        [
+            crt_lib.join(arch_dir),
+            sdk_lib.join("um").join(arch_dir),
+            sdk_lib.join("ucrt").join(arch_dir),
+        ]
+    }
+
+    /// The directory the SDK type libraries for an architecture are placed in
+    /// with [`SplatConfig::separate_tlbs`]
+    pub(crate) fn tlb_dir(&self, layout: &DirLayout<'_>, arch: Arch) -> PathBuf {
+        self.sdk.join("tlb").join(layout.arch_dir(arch))
+    }
+}
+
+/// Resolves a header, eg. `windows.h` or `winrt/Windows.Foundation.h`, the same
+/// way clang-cl would when searching the include directories of a splat in the
+/// default layout, following the symlinks for alternate casings.
+///
+/// Returns the path in the first include directory that contains the header,
+/// or `None` if it can't be resolved.
+pub fn resolve_include(splat_root: &Path, name: &str) -> Option<PathBuf> {
+    // Includes in the SDK use both separators
+    let name = name.replace('\\', "/");
+
+    let roots = SplatRoots::default_layout(splat_root);
+    let layout = roots.detect_layout(None).ok()?;
+
+    roots
+        .include_dirs(&layout)
+        .into_iter()
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// Written to the checkpoint directory after a payload has been successfully
+/// splatted, so that it can be skipped when the splat is resumed
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Checkpoint {
+    pub(crate) sha256: crate::util::Sha256,
+    /// The SDK headers that were splatted, which are needed to add the
+    /// symlinks for includes
+    pub(crate) sdk_headers: Option<SdkHeaders>,
+    /// The unpacked tree for CRT and ATL headers, which are also scanned for
+    /// includes
+    pub(crate) tree: Option<crate::unpack::FileTree>,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint for the payload, if it was previously splatted
+    pub(crate) fn read(dir: &Path, payload: &crate::Payload) -> Option<Self> {
+        let cp = std::fs::read(dir.join(&payload.filename)).ok()?;
+        let cp = serde_json::from_slice::<Self>(&cp).ok()?;
+        (cp.sha256 == payload.sha256).then_some(cp)
+    }
+
+    pub(crate) fn write(&self, dir: &Path, payload: &crate::Payload) -> Result<(), Error> {
+        let path = dir.join(&payload.filename);
+        std::fs::write(&path, serde_json::to_vec(self)?)
+            .with_context(|| format!("unable to write checkpoint {path}"))
+    }
+}
+
+/// How the alternate names for splatted files, eg. different casings, are
+/// created
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LinkKind {
+    Symlink,
+    /// A full copy of the file, used when symlinks are disabled
+    Copy,
+}
+
+impl SplatConfig {
+    /// How alternate names are created, or `None` if they aren't created
+    pub(crate) fn link_kind(&self) -> Option<LinkKind> {
+        if self.enable_symlinks {
+            Some(LinkKind::Symlink)
+        } else if self.symlink_fallback_copy {
+            Some(LinkKind::Copy)
+        } else {
+            None
+        }
+    }
+
+    /// The options that determine where the include and library directories
+    /// are placed when splatting with this configuration
+    pub(crate) fn dir_layout<'v>(&self, variants: u32, sdk_version: &'v str) -> DirLayout<'v> {
+        DirLayout {
+            winsysroot: self.use_winsysroot_style,
+            map: self.map.is_some(),
+            merge_ucrt_include: self.merge_ucrt_include,
+            preserve_ms_arch_notation: self.preserve_ms_arch_notation,
+            spectre: variants & Variant::Spectre as u32 != 0,
+            sdk_version,
+        }
+    }
+}
+
+/// Creates an alternate name for a file, replacing an existing symlink at the
+/// same path, as a resumed splat may be splatting a payload that was partially
+/// splatted before it was interrupted
+#[inline]
+fn make_link(kind: LinkKind, original: &str, link: &Path) -> Result<(), Error> {
+    match kind {
+        LinkKind::Symlink => crate::symlink(original, link),
+        LinkKind::Copy => crate::copy_link(original, link),
+    }
+}
+
+/// Written to the root of every splat, so that we know the directory is ours
+/// to delete the existing CRT and SDK directories in
+pub(crate) const SPLAT_MARKER: &str = ".xwin-splat";
+
+/// Ensures the output is either a previous splat, or only contains the
+/// directories a splat would create, so that a typo in the output doesn't
+/// delete the CRT and SDK directories of some unrelated directory
+fn check_output(root: &Path, owned: &[&Path]) -> Result<(), Error> {
+    if root.join(SPLAT_MARKER).exists() {
+        return Ok(());
+    }
+
+    // The CRT and SDK directories are nested in the /winsysroot layout, so
+    // it's their top level directories that are expected
+    let owned: Vec<_> = owned
+        .iter()
+        .filter_map(|dir| dir.strip_prefix(root).ok()?.iter().next())
+        .collect();
+
+    for entry in std::fs::read_dir(root).with_context(|| format!("unable to read {root}"))? {
+        let entry = entry.with_context(|| format!("unable to read {root}"))?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        anyhow::ensure!(
+            owned.contains(&name.as_ref()),
+            "refusing to splat to {root}, as it contains '{name}' and wasn't created by xwin, pass --allow-non-empty-output to splat to it anyways"
+        );
+    }
+
+    Ok(())
 }
 
+/// Prepares the splat directories. If `fingerprint` is specified, the splat
+/// is resumed if the previous run used the same fingerprint, otherwise the
+/// existing CRT and SDK directories are deleted, unless `keep_existing` is
+/// set, in which case they must already exist.
+///
+/// The CRT and SDK directories default to `crt` and `sdk` unless overridden
+/// via `dir_names`, which is ignored for the /winsysroot layout. The DIA SDK is
+/// always splatted to `dia`, or `DIA SDK` in the /winsysroot layout, the same
+/// as a Visual Studio installation.
+///
+/// The payloads are splatted from `src`, which is normally the unpack
+/// directory of the [`crate::Ctx`].
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn prep_splat(
-    ctx: std::sync::Arc<Ctx>,
+    src: PathBuf,
+    allow_non_empty_output: bool,
     root: &Path,
+    prefix: Option<&Path>,
     winroot: Option<&str>,
+    dir_names: (Option<&str>, Option<&str>),
+    fingerprint: Option<&str>,
+    keep_existing: bool,
 ) -> Result<SplatRoots, Error> {
+    let root = if let Some(prefix) = prefix {
+        anyhow::ensure!(
+            prefix.is_relative()
+                && prefix
+                    .components()
+                    .all(|comp| matches!(comp, camino::Utf8Component::Normal(_))),
+            "splat prefix '{prefix}' must be a relative path without any '.' or '..' components"
+        );
+
+        root.join(prefix)
+    } else {
+        root.to_owned()
+    };
+
     // Ensure we create the path first, you can't canonicalize a non-existant path
     if !root.exists() {
-        std::fs::create_dir_all(root)
+        std::fs::create_dir_all(&root)
             .with_context(|| format!("unable to create splat directory {root}"))?;
     }
 
-    let root = crate::util::canonicalize(root)?;
+    let root = crate::util::canonicalize(&root)?;
 
-    let (crt_root, sdk_root) = if let Some(crt_version) = winroot {
+    let (crt_root, sdk_root, dia_root) = if let Some(crt_version) = winroot {
         let mut crt = root.join("VC/Tools/MSVC");
         crt.push(crt_version);
 
         let mut sdk = root.join("Windows Kits");
         sdk.push("10");
 
-        (crt, sdk)
+        (crt, sdk, root.join("DIA SDK"))
     } else {
-        (root.join("crt"), root.join("sdk"))
+        let crt = dir_names.0.unwrap_or("crt");
+        let sdk = dir_names.1.unwrap_or("sdk");
+
+        for name in [crt, sdk] {
+            anyhow::ensure!(
+                matches!(
+                    Path::new(name).components().collect::<Vec<_>>().as_slice(),
+                    [camino::Utf8Component::Normal(_)]
+                ),
+                "splat directory name '{name}' must be a single path component"
+            );
+        }
+
+        anyhow::ensure!(
+            !crt.eq_ignore_ascii_case(sdk),
+            "the CRT and SDK directory names must be different"
+        );
+
+        anyhow::ensure!(
+            !crt.eq_ignore_ascii_case("dia") && !sdk.eq_ignore_ascii_case("dia"),
+            "'dia' can't be used as a splat directory name, as it is used by the DIA SDK"
+        );
+
+        (root.join(crt), root.join(sdk), root.join("dia"))
     };
 
-    if crt_root.exists() {
-        std::fs::remove_dir_all(&crt_root)
-            .with_context(|| format!("unable to delete existing CRT directory {crt_root}"))?;
+    let checkpoint_dir = root.join(".xwin-checkpoints");
+    let fingerprint_path = checkpoint_dir.join(".fingerprint");
+
+    if !allow_non_empty_output {
+        check_output(&root, &[&crt_root, &sdk_root, &dia_root, &checkpoint_dir])?;
     }
 
-    if sdk_root.exists() {
-        std::fs::remove_dir_all(&sdk_root)
-            .with_context(|| format!("unable to delete existing SDK directory {sdk_root}"))?;
+    let resuming = fingerprint
+        .is_some_and(|fp| std::fs::read_to_string(&fingerprint_path).is_ok_and(|prev| prev == fp));
+
+    if resuming {
+        tracing::info!("resuming previous splat in {root}");
+    } else if keep_existing {
+        anyhow::ensure!(
+            crt_root.exists() && sdk_root.exists(),
+            "unable to add to the splat in {root}, as it doesn't contain the CRT directory {crt_root} and the SDK directory {sdk_root}"
+        );
+
+        tracing::info!("adding to existing splat in {root}");
+    } else {
+        if crt_root.exists() {
+            std::fs::remove_dir_all(&crt_root)
+                .with_context(|| format!("unable to delete existing CRT directory {crt_root}"))?;
+        }
+
+        if sdk_root.exists() {
+            std::fs::remove_dir_all(&sdk_root)
+                .with_context(|| format!("unable to delete existing SDK directory {sdk_root}"))?;
+        }
+
+        if dia_root.exists() {
+            std::fs::remove_dir_all(&dia_root)
+                .with_context(|| format!("unable to delete existing DIA directory {dia_root}"))?;
+        }
+
+        // Checkpoints are only valid for the output they were written for
+        if checkpoint_dir.exists() {
+            std::fs::remove_dir_all(&checkpoint_dir).with_context(|| {
+                format!("unable to delete existing checkpoints {checkpoint_dir}")
+            })?;
+        }
+
+        if let Some(fp) = fingerprint {
+            std::fs::create_dir_all(&checkpoint_dir)
+                .with_context(|| format!("unable to create {checkpoint_dir}"))?;
+            std::fs::write(&fingerprint_path, fp)
+                .with_context(|| format!("unable to write {fingerprint_path}"))?;
+        }
     }
 
     std::fs::create_dir_all(&crt_root)
@@ -92,13 +568,17 @@ pub(crate) fn prep_splat(
     std::fs::create_dir_all(&sdk_root)
         .with_context(|| format!("unable to create SDK directory {sdk_root}"))?;
 
-    let src_root = ctx.work_dir.join("unpack");
+    let marker = root.join(SPLAT_MARKER);
+    std::fs::write(&marker, concat!("xwin ", env!("CARGO_PKG_VERSION"), "\n"))
+        .with_context(|| format!("unable to write {marker}"))?;
 
     Ok(SplatRoots {
         root,
         crt: crt_root,
         sdk: sdk_root,
-        src: src_root,
+        dia: dia_root,
+        src,
+        checkpoints: fingerprint.is_some().then_some(checkpoint_dir),
     })
 }
 
@@ -112,6 +592,7 @@ pub(crate) fn splat(
     sdk_version: &str,
     arches: u32,
     variants: u32,
+    include_sources: bool,
 ) -> Result<Option<SdkHeaders>, Error> {
     struct Mapping<'ft> {
         src: PathBuf,
@@ -122,6 +603,31 @@ pub(crate) fn splat(
         section: SectionKind,
     }
 
+    // User specified casings apply whether the map filters or not
+    let casings = map
+        .map(|map| &map.casings)
+        .filter(|casings| !casings.is_empty());
+    let map = map.filter(|map| map.has_filters());
+
+    let link_kind = config.link_kind();
+    // The casings and symlinks specified by the user are always created
+    let user_link_kind = link_kind.unwrap_or(LinkKind::Symlink);
+
+    let add_casings = |fname: &str, tar: &mut PathBuf| -> Result<(), Error> {
+        if let Some(names) = casings.and_then(|casings| casings.get(fname)) {
+            for name in names {
+                tar.pop();
+                tar.push(name);
+                make_link(user_link_kind, fname, tar)?;
+            }
+
+            tar.pop();
+            tar.push(fname);
+        }
+
+        Ok(())
+    };
+
     let mut src = roots.src.join(&item.payload.filename);
 
     // If we're moving files from the unpack directory, invalidate it immediately
@@ -129,7 +635,9 @@ pub(crate) fn splat(
     if !config.copy {
         src.push(".unpack");
         if let Err(e) = std::fs::remove_file(&src) {
-            tracing::warn!("Failed to remove {src}: {e}");
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove {src}: {e}");
+            }
         }
         src.pop();
     }
@@ -158,7 +666,7 @@ pub(crate) fn splat(
     let variant = item.payload.variant;
     let kind = item.payload.kind;
 
-    let mappings = match kind {
+    let mut mappings = match kind {
         PayloadKind::CrtHeaders | PayloadKind::AtlHeaders => {
             src.push("include");
             let tree = get_tree(&src)?;
@@ -356,7 +864,9 @@ pub(crate) fn splat(
                 target
             };
 
-            target.push("ucrt");
+            if !config.merge_ucrt_include {
+                target.push("ucrt");
+            }
 
             let mut mappings = vec![Mapping {
                 src: inc_src,
@@ -367,6 +877,34 @@ pub(crate) fn splat(
                 section: SectionKind::SdkHeader,
             }];
 
+            // Map files only list headers and libs, so sources are never
+            // splatted when using one
+            if include_sources && map.is_none() {
+                let src_src = src.join("source/ucrt");
+
+                match get_tree(&src_src) {
+                    Ok(tree) => {
+                        let mut target = roots.sdk.join("src");
+                        if config.use_winsysroot_style {
+                            target.push(sdk_version);
+                        }
+                        target.push("ucrt");
+
+                        mappings.push(Mapping {
+                            src: src_src,
+                            target,
+                            tree,
+                            kind,
+                            variant,
+                            section: SectionKind::SdkHeader,
+                        });
+                    }
+                    Err(err) => {
+                        tracing::warn!("unable to splat UCRT sources: {err:#}");
+                    }
+                }
+            }
+
             src.push("lib/ucrt");
 
             let mut target = roots.sdk.join("lib");
@@ -397,8 +935,106 @@ pub(crate) fn splat(
 
             mappings
         }
+        PayloadKind::CrtTools => {
+            // The tools are already in the `Host<host>/<target>` layout that
+            // clang-cl and Visual Studio expect, and aren't renamed to the
+            // arch notation used for the libs
+            src.push("bin");
+            let tree = get_tree(&src)?;
+
+            vec![Mapping {
+                src,
+                target: roots.crt.join("bin"),
+                tree,
+                kind,
+                variant,
+                section: SectionKind::CrtLib,
+            }]
+        }
+        PayloadKind::DiaSdk => {
+            let inc_src = src.join("include");
+            let tree = get_tree(&inc_src)?;
+
+            let mut mappings = vec![Mapping {
+                src: inc_src,
+                target: roots.dia.join("include"),
+                tree,
+                kind,
+                variant,
+                section: SectionKind::CrtHeader,
+            }];
+
+            src.push("lib");
+
+            for arch in Arch::iter(arches) {
+                let mut src = src.clone();
+                let mut target = roots.dia.join("lib");
+
+                // The x86 libs are placed directly in the lib root, and the
+                // other architectures use yet another naming scheme
+                match arch {
+                    Arch::X86 => {}
+                    Arch::X86_64 => src.push("amd64"),
+                    Arch::Aarch => src.push("arm"),
+                    Arch::Aarch64 => src.push("arm64"),
+                }
+
+                target.push(if config.preserve_ms_arch_notation {
+                    arch.as_ms_str()
+                } else {
+                    arch.as_str()
+                });
+
+                let tree = get_tree(&src)?;
+
+                mappings.push(Mapping {
+                    src,
+                    target,
+                    tree,
+                    kind,
+                    variant,
+                    section: SectionKind::CrtLib,
+                });
+            }
+
+            mappings
+        }
+        PayloadKind::SdkMetadata => {
+            // Map files only list headers and libs, so metadata is never
+            // splatted when using one
+            if map.is_some() {
+                tracing::warn!("ignoring WinRT metadata as a map file is being used");
+                vec![]
+            } else {
+                src.push("unionmetadata");
+                let tree = get_tree(&src)?;
+
+                // Unlike the headers and libs, tools always expect the metadata
+                // to be in a versioned directory
+                let mut target = roots.sdk.join("UnionMetadata");
+                target.push(sdk_version);
+
+                vec![Mapping {
+                    src,
+                    target,
+                    tree,
+                    kind,
+                    variant,
+                    section: SectionKind::SdkLib,
+                }]
+            }
+        }
     };
 
+    // Some payloads contain both headers and libs, so we need to filter the
+    // individual mappings as well as the payloads themselves. The metadata is
+    // neither, and is kept as long as it was requested
+    mappings.retain(|mapping| match mapping.section {
+        _ if mapping.kind == PayloadKind::SdkMetadata => true,
+        SectionKind::SdkHeader | SectionKind::CrtHeader => !config.libs_only,
+        SectionKind::SdkLib | SectionKind::CrtLib => !config.headers_only,
+    });
+
     let mut results = Vec::new();
 
     item.progress.reset();
@@ -412,6 +1048,14 @@ pub(crate) fn splat(
         tree: &'ft crate::unpack::FileTree,
     }
 
+    // The x86 DIA SDK libs live directly in the lib root, next to the
+    // directories for every other architecture, so we can't recurse into them
+    let is_flat = |mapping: &Mapping<'_>| {
+        mapping.kind == PayloadKind::DiaSdk
+            && matches!(mapping.section, SectionKind::CrtLib)
+            && mapping.src.file_name() == Some("lib")
+    };
+
     if let Some(map) = map {
         mappings
             .into_par_iter()
@@ -443,6 +1087,8 @@ pub(crate) fn splat(
                     }
                 };
 
+                let flat = is_flat(&mapping);
+
                 let mut dir_stack = vec![Dir {
                     src: mapping.src,
                     tar: mapping.target,
@@ -463,7 +1109,7 @@ pub(crate) fn splat(
                             format!("invalid path {tar}: doesn't begin with prefix {prefix}")
                         })?;
 
-                        if !section.filter.contains(unprefixed.as_str()) {
+                        if !section.keeps(unprefixed.as_str()) {
                             tar.pop();
                             continue;
                         }
@@ -476,7 +1122,10 @@ pub(crate) fn splat(
                             created_dir = true;
                         }
 
-                        if config.copy {
+                        // Files from the existing splat are left as they are
+                        if config.add_arch && tar.exists() {
+                            tracing::debug!("keeping existing {tar}");
+                        } else if config.copy {
                             std::fs::copy(&src_path, &tar)
                                 .with_context(|| format!("failed to copy {src_path} to {tar}"))?;
                         } else {
@@ -490,13 +1139,22 @@ pub(crate) fn splat(
                             for sl in symlinks {
                                 tar.pop();
                                 tar.push(sl);
-                                symlink(fname.as_str(), &tar)?;
+                                make_link(user_link_kind, fname.as_str(), &tar)?;
                             }
+
+                            tar.pop();
+                            tar.push(fname);
                         }
 
+                        add_casings(fname.as_str(), &mut tar)?;
+
                         tar.pop();
                     }
 
+                    if flat {
+                        continue;
+                    }
+
                     for (dir, dtree) in &tree.dirs {
                         dir_stack.push(Dir {
                             src: src.join(dir),
@@ -519,8 +1177,20 @@ pub(crate) fn splat(
         mappings
             .into_par_iter()
             .map(|mapping| -> Result<Option<SdkHeaders>, Error> {
-                let mut sdk_headers = (mapping.kind == PayloadKind::SdkHeaders)
-                    .then(|| SdkHeaders::new(mapping.target.clone()));
+                let mut sdk_headers = (mapping.kind == PayloadKind::SdkHeaders).then(|| {
+                    SdkHeaders::new(mapping.target.clone(), item.payload.filename.clone())
+                });
+                let flat = is_flat(&mapping);
+
+                // The SDK lib mappings always target the architecture directory
+                let tlb_dir = (config.separate_tlbs
+                    && matches!(
+                        mapping.kind,
+                        PayloadKind::SdkLibs | PayloadKind::SdkStoreLibs
+                    ))
+                .then(|| mapping.target.file_name())
+                .flatten()
+                .map(|arch| roots.sdk.join("tlb").join(arch));
 
                 let mut dir_stack = vec![Dir {
                     src: mapping.src,
@@ -542,6 +1212,23 @@ pub(crate) fn splat(
                             continue;
                         }
 
+                        let is_tlb = tlb_dir.is_some()
+                            && fname
+                                .extension()
+                                .is_some_and(|ext| ext.eq_ignore_ascii_case("tlb"));
+
+                        if !config.sdk_libs.is_empty()
+                            && matches!(
+                                mapping.kind,
+                                PayloadKind::SdkLibs | PayloadKind::SdkStoreLibs
+                            )
+                            && !is_tlb
+                            && !is_selected_sdk_lib(&config.sdk_libs, fname)
+                        {
+                            tracing::debug!("skipping {fname}");
+                            continue;
+                        }
+
                         let fname_str = fname.as_str();
                         if !include_debug_libs
                             && (mapping.kind == PayloadKind::CrtLibs
@@ -560,11 +1247,42 @@ pub(crate) fn splat(
                             }
                         }
 
+                        // Type libraries are placed in their own directory
+                        let mut tlb_tar;
+                        let tar = match tlb_dir.as_ref().filter(|_| is_tlb) {
+                            Some(tlb_dir) => {
+                                std::fs::create_dir_all(tlb_dir)
+                                    .with_context(|| format!("unable to create {tlb_dir}"))?;
+                                tlb_tar = tlb_dir.clone();
+                                &mut tlb_tar
+                            }
+                            None => &mut tar,
+                        };
+
                         tar.push(fname);
 
                         let src_path = src.join(fname);
 
-                        if config.copy {
+                        // The headers are hashed before they are placed, as the
+                        // same header can be splatted from other payloads at
+                        // the same time
+                        let contents = if config.detect_conflicting_headers
+                            && sdk_headers.is_some()
+                            && link_kind.is_some()
+                        {
+                            Some(
+                                std::fs::File::open(&src_path)
+                                    .and_then(crate::util::Sha256::digest_reader)
+                                    .with_context(|| format!("failed to read {src_path}"))?,
+                            )
+                        } else {
+                            None
+                        };
+
+                        // Files from the existing splat are left as they are
+                        if config.add_arch && tar.exists() {
+                            tracing::debug!("keeping existing {tar}");
+                        } else if config.copy {
                             std::fs::copy(&src_path, &tar)
                                 .with_context(|| format!("failed to copy {src_path} to {tar}"))?;
                         } else {
@@ -572,115 +1290,21 @@ pub(crate) fn splat(
                                 .with_context(|| format!("failed to move {src_path} to {tar}"))?;
                         }
 
-                        let kind = mapping.kind;
-
-                        let mut add_symlinks = || -> Result<(), Error> {
-                            match kind {
-                                // These are all internally consistent and lowercased, so if
-                                // a library is including them with different casing that is
-                                // kind of on them
-                                //
-                                // The SDK headers are also all over the place with casing
-                                // as well as being internally inconsistent, so we scan
-                                // them all for includes and add those that are referenced
-                                // incorrectly, but we wait until after all the of headers
-                                // have been unpacked before fixing them
-                                PayloadKind::CrtHeaders
-                                | PayloadKind::AtlHeaders
-                                | PayloadKind::Ucrt
-                                | PayloadKind::AtlLibs => {}
-
-                                PayloadKind::SdkHeaders => {
-                                    if let Some(sdk_headers) = &mut sdk_headers {
-                                        let rel_target_path =
-                                            sdk_headers.get_relative_path(&tar)?;
-
-                                        let rel_hash = calc_lower_hash(rel_target_path.as_str());
-
-                                        if sdk_headers.inner.insert(rel_hash, tar.clone()).is_some()
-                                        {
-                                            anyhow::bail!(
-                                                "found duplicate relative path when hashed"
-                                            );
-                                        }
-
-                                        if let Some(additional_name) = match fname_str {
-                                            // https://github.com/zeromq/libzmq/blob/3070a4b2461ec64129062907d915ed665d2ac126/src/precompiled.hpp#L73
-                                            "mstcpip.h" => Some("Mstcpip.h"),
-                                            // https://github.com/ponylang/ponyc/blob/8d41d6650b48b9733cd675df199588e6fccc6346/src/common/platform.h#L191
-                                            "basetsd.h" => Some("BaseTsd.h"),
-                                            _ => None,
-                                        } {
-                                            tar.pop();
-                                            tar.push(additional_name);
-
-                                            symlink(fname_str, &tar)?;
-                                        }
-                                    }
-                                }
-                                PayloadKind::CrtLibs => {
-                                    // While _most_ of the libs *stares at Microsoft.VisualC.STLCLR.dll* are lower case,
-                                    // sometimes when they are specified as linker arguments, crates will link with
-                                    // SCREAMING as if they are angry at the linker, so fix this in the few "common" cases.
-                                    // This list is probably not complete, but that's what PRs are for
-                                    if let Some(angry_lib) = match fname_str.strip_suffix(".lib") {
-                                        Some("libcmt") => Some("LIBCMT.lib"),
-                                        Some("msvcrt") => Some("MSVCRT.lib"),
-                                        Some("oldnames") => Some("OLDNAMES.lib"),
-                                        _ => None,
-                                    } {
-                                        tar.pop();
-                                        tar.push(angry_lib);
-
-                                        symlink(fname_str, &tar)?;
-                                    }
-                                }
-                                PayloadKind::SdkLibs | PayloadKind::SdkStoreLibs => {
-                                    // The SDK libraries are just completely inconsistent, but
-                                    // all usage I have ever seen just links them with lowercase
-                                    // names, so we just fix all of them to be lowercase.
-                                    // Note that we need to not only fix the name but also the
-                                    // extension, as for some inexplicable reason about half of
-                                    // them use an uppercase L for the extension. WTF. This also
-                                    // applies to the tlb files, so at least they are consistently
-                                    // inconsistent
-                                    if fname_str.contains(|c: char| c.is_ascii_uppercase()) {
-                                        tar.pop();
-                                        tar.push(fname_str.to_ascii_lowercase());
-
-                                        symlink(fname_str, &tar)?;
-                                    }
-
-                                    // There is also this: https://github.com/time-rs/time/blob/v0.3.2/src/utc_offset.rs#L454
-                                    // And this: https://github.com/webrtc-rs/util/blob/main/src/ifaces/ffi/windows/mod.rs#L33
-                                    if let Some(additional_name) = match fname_str {
-                                        "kernel32.Lib" => Some("Kernel32.lib"),
-                                        "iphlpapi.lib" => Some("Iphlpapi.lib"),
-                                        _ => None,
-                                    } {
-                                        tar.pop();
-                                        tar.push(additional_name);
-
-                                        symlink(fname_str, &tar)?;
-                                    }
-
-                                    // We also need to support SCREAMING case for the library names
-                                    // due to...reasons https://github.com/microsoft/windows-rs/blob/a27a74784ccf304ab362bf2416f5f44e98e5eecd/src/bindings.rs#L3772
-                                    if tar.extension() == Some("lib") {
-                                        tar.pop();
-                                        tar.push(fname_str.to_ascii_uppercase());
-                                        tar.set_extension("lib");
-
-                                        symlink(fname_str, &tar)?;
-                                    }
-                                }
-                            }
-
-                            Ok(())
-                        };
+                        if let Some(link_kind) = link_kind {
+                            add_file_symlinks(
+                                link_kind,
+                                mapping.kind,
+                                fname_str,
+                                tar,
+                                sdk_headers
+                                    .as_mut()
+                                    .map(|sdk_headers| (sdk_headers, contents)),
+                                config.crt_lib_casings.as_deref(),
+                            )?;
 
-                        if config.enable_symlinks {
-                            add_symlinks()?;
+                            tar.pop();
+                            tar.push(fname);
+                            add_casings(fname_str, tar)?;
                         }
 
                         tar.pop();
@@ -701,6 +1325,10 @@ pub(crate) fn splat(
                         continue;
                     }
 
+                    if flat {
+                        continue;
+                    }
+
                     for (dir, dtree) in &tree.dirs {
                         dir_stack.push(Dir {
                             src: src.join(dir),
@@ -729,11 +1357,12 @@ pub(crate) fn splat(
                     }
 
                     // https://github.com/llvm/llvm-project/blob/release/14.x/clang/lib/Driver/ToolChains/MSVC.cpp#L1102
-                    if config.enable_symlinks {
+                    // Copying the entire directory would be prohibitive
+                    if link_kind == Some(LinkKind::Symlink) {
                         let mut title_case = roots.sdk.clone();
                         title_case.push("Lib");
                         if !title_case.exists() {
-                            symlink("lib", &title_case)?;
+                            make_link(LinkKind::Symlink, "lib", &title_case)?;
                         }
                     }
                 }
@@ -750,11 +1379,12 @@ pub(crate) fn splat(
                     }
 
                     // https://github.com/llvm/llvm-project/blob/release/14.x/clang/lib/Driver/ToolChains/MSVC.cpp#L1340-L1346
-                    if config.enable_symlinks {
+                    // Copying the entire directory would be prohibitive
+                    if link_kind == Some(LinkKind::Symlink) {
                         let mut title_case = roots.sdk.clone();
                         title_case.push("Include");
                         if !title_case.exists() {
-                            symlink("include", &title_case)?;
+                            make_link(LinkKind::Symlink, "include", &title_case)?;
                         }
                     }
                 }
@@ -770,45 +1400,672 @@ pub(crate) fn splat(
     Ok(headers.into_iter().find_map(|headers| headers))
 }
 
-pub(crate) fn finalize_splat(
-    ctx: &Ctx,
-    sdk_version: Option<&str>,
-    roots: &SplatRoots,
-    sdk_headers: Vec<SdkHeaders>,
-    crt_headers: Option<crate::unpack::FileTree>,
-    atl_headers: Option<crate::unpack::FileTree>,
+/// The SDK libs that the Rust standard library links against, which are always
+/// splatted even if they don't match any of the `sdk_libs` patterns
+const REQUIRED_SDK_LIBS: &[&str] = &[
+    "advapi32",
+    "bcrypt",
+    "kernel32",
+    "ntdll",
+    "synchronization",
+    "userenv",
+    "ws2_32",
+];
+
+/// Whether the SDK lib matches one of the patterns, either by its full file
+/// name or without the `.lib` extension
+fn is_selected_sdk_lib(patterns: &[String], fname: &Path) -> bool {
+    let stem = fname
+        .extension()
+        .filter(|ext| ext.eq_ignore_ascii_case("lib"))
+        .and_then(|_| fname.file_stem());
+
+    let matches = |pattern: &str| {
+        crate::util::glob_match(pattern, fname.as_str())
+            || stem.is_some_and(|stem| crate::util::glob_match(pattern, stem))
+    };
+
+    patterns.iter().any(|pat| matches(pat)) || REQUIRED_SDK_LIBS.iter().any(|req| matches(req))
+}
+
+/// The uppercase casings added for the CRT libs, keyed by the name of the lib
+/// without the extension. This list is probably not complete, but that's what
+/// PRs are for
+pub const CRT_LIB_CASINGS: &[(&str, &str)] = &[
+    // The static CRT, ie. /MT
+    ("libcmt", "LIBCMT.lib"),
+    // The dynamic CRT, ie. /MD
+    ("msvcrt", "MSVCRT.lib"),
+    // Used by both
+    ("oldnames", "OLDNAMES.lib"),
+];
+
+/// Adds the symlinks for a single file that has been splatted to `tar`, which
+/// is left pointing at the last symlink that was created, if any
+///
+/// If `crt_lib_casings` is specified, only the [`CRT_LIB_CASINGS`] with one of
+/// the names are added
+fn add_file_symlinks(
+    link_kind: LinkKind,
+    kind: PayloadKind,
+    fname_str: &str,
+    tar: &mut PathBuf,
+    sdk_headers: Option<(&mut SdkHeaders, Option<crate::util::Sha256>)>,
+    crt_lib_casings: Option<&[String]>,
 ) -> Result<(), Error> {
-    let mut files: std::collections::HashMap<
-        _,
-        Header<'_>,
-        std::hash::BuildHasherDefault<twox_hash::XxHash64>,
-    > = Default::default();
+    match kind {
+        // These are all internally consistent and lowercased, so if
+        // a library is including them with different casing that is
+        // kind of on them
+        //
+        // The SDK headers are also all over the place with casing
+        // as well as being internally inconsistent, so we scan
+        // them all for includes and add those that are referenced
+        // incorrectly, but we wait until after all the of headers
+        // have been unpacked before fixing them
+        PayloadKind::CrtHeaders
+        | PayloadKind::AtlHeaders
+        | PayloadKind::Ucrt
+        | PayloadKind::AtlLibs
+        | PayloadKind::DiaSdk
+        | PayloadKind::SdkMetadata
+        | PayloadKind::CrtTools => {}
 
-    struct Header<'root> {
-        root: &'root SdkHeaders,
-        path: PathBuf,
-    }
+        PayloadKind::SdkHeaders => {
+            if let Some((sdk_headers, contents)) = sdk_headers {
+                let rel_target_path = sdk_headers.get_relative_path(tar)?;
 
-    fn compare_hashes(existing: &Path, new: &PathBuf) -> anyhow::Result<()> {
-        use crate::util::Sha256;
+                let rel_hash = calc_lower_hash(rel_target_path.as_str());
 
-        let existing_hash = Sha256::digest(&std::fs::read(existing)?);
-        let new_hash = Sha256::digest(&std::fs::read(new)?);
+                if sdk_headers.inner.insert(rel_hash, tar.clone()).is_some() {
+                    anyhow::bail!("found duplicate relative path when hashed");
+                }
 
-        anyhow::ensure!(
-            existing_hash == new_hash,
-            "2 files with same relative path were not equal: '{existing}' != '{new}'"
-        );
+                if let Some(contents) = contents {
+                    sdk_headers.contents.insert(rel_hash, contents);
+                }
 
-        Ok(())
-    }
+                if let Some(additional_name) = match fname_str {
+                    // https://github.com/zeromq/libzmq/blob/3070a4b2461ec64129062907d915ed665d2ac126/src/precompiled.hpp#L73
+                    "mstcpip.h" => Some("Mstcpip.h"),
+                    // https://github.com/ponylang/ponyc/blob/8d41d6650b48b9733cd675df199588e6fccc6346/src/common/platform.h#L191
+                    "basetsd.h" => Some("BaseTsd.h"),
+                    _ => None,
+                } {
+                    tar.pop();
+                    tar.push(additional_name);
+
+                    make_link(link_kind, fname_str, tar)?;
+                }
+            }
+        }
+        PayloadKind::CrtLibs => {
+            // While _most_ of the libs *stares at Microsoft.VisualC.STLCLR.dll* are lower case,
+            // sometimes when they are specified as linker arguments, crates will link with
+            // SCREAMING as if they are angry at the linker, so fix this in the few "common" cases.
+            let angry_lib = fname_str.strip_suffix(".lib").and_then(|stem| {
+                CRT_LIB_CASINGS
+                    .iter()
+                    .find(|(name, _)| *name == stem)
+                    .filter(|(name, _)| match crt_lib_casings {
+                        Some(selected) => selected.iter().any(|s| s == name),
+                        None => true,
+                    })
+                    .map(|(_, casing)| *casing)
+            });
 
-    for hdrs in &sdk_headers {
-        for (k, v) in &hdrs.inner {
+            if let Some(angry_lib) = angry_lib {
+                tar.pop();
+                tar.push(angry_lib);
+
+                make_link(link_kind, fname_str, tar)?;
+            }
+        }
+        PayloadKind::SdkLibs | PayloadKind::SdkStoreLibs => {
+            // The SDK libraries are just completely inconsistent, but
+            // all usage I have ever seen just links them with lowercase
+            // names, so we just fix all of them to be lowercase.
+            // Note that we need to not only fix the name but also the
+            // extension, as for some inexplicable reason about half of
+            // them use an uppercase L for the extension. WTF. This also
+            // applies to the tlb files, so at least they are consistently
+            // inconsistent
+            if fname_str.contains(|c: char| c.is_ascii_uppercase()) {
+                tar.pop();
+                tar.push(fname_str.to_ascii_lowercase());
+
+                make_link(link_kind, fname_str, tar)?;
+            }
+
+            // There is also this: https://github.com/time-rs/time/blob/v0.3.2/src/utc_offset.rs#L454
+            // And this: https://github.com/webrtc-rs/util/blob/main/src/ifaces/ffi/windows/mod.rs#L33
+            if let Some(additional_name) = match fname_str {
+                "kernel32.Lib" => Some("Kernel32.lib"),
+                "iphlpapi.lib" => Some("Iphlpapi.lib"),
+                _ => None,
+            } {
+                tar.pop();
+                tar.push(additional_name);
+
+                make_link(link_kind, fname_str, tar)?;
+            }
+
+            // We also need to support SCREAMING case for the library names
+            // due to...reasons https://github.com/microsoft/windows-rs/blob/a27a74784ccf304ab362bf2416f5f44e98e5eecd/src/bindings.rs#L3772
+            if tar.extension() == Some("lib") {
+                tar.pop();
+                tar.push(fname_str.to_ascii_uppercase());
+                tar.set_extension("lib");
+
+                make_link(link_kind, fname_str, tar)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Symlinks each architecture directory of the libraries to the notation that
+/// wasn't used, eg. `lib/x64` -> `x86_64`, so that tools that expect either
+/// notation can use the same splat
+pub(crate) fn link_arch_dirs(
+    roots: &SplatRoots,
+    arches: u32,
+    preserve_ms_arch_notation: bool,
+) -> Result<(), Error> {
+    let names: Vec<_> = Arch::iter(arches)
+        .map(|arch| {
+            if preserve_ms_arch_notation {
+                (arch.as_ms_str(), arch.as_str())
+            } else {
+                (arch.as_str(), arch.as_ms_str())
+            }
+        })
+        .filter(|(real, alternate)| real != alternate)
+        .collect();
+
+    for root in [&roots.crt, &roots.sdk] {
+        let mut links = Vec::new();
+
+        // Only libraries are split by architecture, so there's no need to walk
+        // the many thousands of headers
+        for entry in walkdir::WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|entry| {
+                !entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.eq_ignore_ascii_case("include"))
+            })
+        {
+            let entry = entry.with_context(|| format!("unable to walk {root}"))?;
+            if !entry.file_type().is_dir() {
+                continue;
+            }
+
+            let Some(dir) = Path::from_path(entry.path()) else {
+                continue;
+            };
+
+            let Some((real, alternate)) = names
+                .iter()
+                .find(|(real, _)| dir.file_name() == Some(*real))
+            else {
+                continue;
+            };
+
+            if dir
+                .components()
+                .any(|comp| comp.as_str().eq_ignore_ascii_case("lib"))
+            {
+                links.push((*real, dir.with_file_name(alternate)));
+            }
+        }
+
+        for (real, link) in links {
+            if link.exists() && !link.is_symlink() {
+                tracing::warn!("unable to link {link} to {real} as it already exists");
+                continue;
+            }
+
+            crate::symlink_on_windows_too(real, &link)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the `VC/Tools/MSVC/<ver>/bin/Host<arch>/<arch>` directories that
+/// clang-cl probes when using `/winsysroot`, one for each target architecture.
+/// Since the MSVC tools themselves are not redistributed, `link.exe` is only
+/// symlinked to `lld-link` if it is found in `PATH`. Unlike every other symlink
+/// in the splat, this one is absolute, as `lld-link` is outside of the splat.
+pub(crate) fn create_winsysroot_bin(roots: &SplatRoots, arches: u32) -> Result<(), Error> {
+    let host = match std::env::consts::ARCH {
+        "x86" => Arch::X86,
+        "x86_64" => Arch::X86_64,
+        "arm" => Arch::Aarch,
+        "aarch64" => Arch::Aarch64,
+        other => anyhow::bail!("unable to create winsysroot bin directories for host '{other}'"),
+    };
+
+    let lld_link = crate::util::find_in_path("lld-link")
+        .map(PathBuf::from_path_buf)
+        .transpose()
+        .map_err(|pb| anyhow::anyhow!("lld-link path {} is not utf-8", pb.display()))?;
+
+    if lld_link.is_none() {
+        tracing::warn!("unable to find lld-link in PATH, winsysroot bin directories will be empty");
+    }
+
+    let mut bin = roots.crt.join("bin");
+    bin.push(format!("Host{}", host.as_ms_str()));
+
+    for arch in Arch::iter(arches) {
+        let dir = bin.join(arch.as_ms_str());
+        std::fs::create_dir_all(&dir).with_context(|| format!("unable to create {dir}"))?;
+
+        let link_exe = dir.join("link.exe");
+
+        // The real linker is present if the tools were included
+        if link_exe.exists() && !link_exe.is_symlink() {
+            continue;
+        }
+
+        if let Some(lld_link) = &lld_link {
+            make_link(LinkKind::Symlink, lld_link.as_str(), &link_exe)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the user's post splat command with the system shell, with the location
+/// and versions of the splat in environment variables
+pub(crate) fn run_post_splat(
+    command: &str,
+    roots: &SplatRoots,
+    crt_version: &str,
+    sdk_version: &str,
+) -> Result<(), Error> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+
+    cmd.arg(command).envs([
+        ("XWIN_SPLAT_ROOT", roots.root.as_str()),
+        ("XWIN_CRT_DIR", roots.crt.as_str()),
+        ("XWIN_SDK_DIR", roots.sdk.as_str()),
+        ("XWIN_CRT_VERSION", crt_version),
+        ("XWIN_SDK_VERSION", sdk_version),
+    ]);
+
+    tracing::info!("running post splat command '{command}'");
+
+    let status = cmd
+        .status()
+        .with_context(|| format!("unable to run post splat command '{command}'"))?;
+    anyhow::ensure!(
+        status.success(),
+        "post splat command '{command}' failed with {status}"
+    );
+
+    Ok(())
+}
+
+/// Rewrites every relative symlink in the CRT and SDK to point at the absolute
+/// path of its target, for tools that are easier to use with absolute symlinks.
+///
+/// Every symlink xwin creates is relative to the directory it is in, so that
+/// the splat can be moved or archived, which this gives up.
+pub(crate) fn make_symlinks_absolute(roots: &SplatRoots) -> Result<(), Error> {
+    let mut links = Vec::new();
+
+    for dir in roots.splatted_dirs() {
+        for entry in walkdir::WalkDir::new(dir).min_depth(1) {
+            let entry = entry.with_context(|| format!("failed to walk {dir}"))?;
+            if entry.path_is_symlink() {
+                let link = PathBuf::from_path_buf(entry.into_path())
+                    .map_err(|pb| anyhow::anyhow!("path {} is not utf-8", pb.display()))?;
+                links.push(link);
+            }
+        }
+    }
+
+    for link in links {
+        let target =
+            std::fs::read_link(&link).with_context(|| format!("unable to read symlink {link}"))?;
+        let target = PathBuf::from_path_buf(target)
+            .map_err(|pb| anyhow::anyhow!("symlink target {} is not utf-8", pb.display()))?;
+
+        if target.is_absolute() {
+            continue;
+        }
+
+        // The target isn't canonicalized, as it may itself be a symlink, eg.
+        // an alternate casing of a header in a directory that is a symlink
+        let mut absolute = link
+            .parent()
+            .with_context(|| format!("{link} has no parent directory"))?
+            .to_owned();
+        for comp in target.components() {
+            match comp {
+                camino::Utf8Component::ParentDir => {
+                    absolute.pop();
+                }
+                camino::Utf8Component::Normal(name) => absolute.push(name),
+                _ => {}
+            }
+        }
+
+        crate::symlink_on_windows_too(absolute.as_str(), &link)?;
+    }
+
+    Ok(())
+}
+
+/// Sets the modification time of every file in the CRT and SDK to `mtime`.
+///
+/// Files moved from the unpack directory keep the time they were unpacked,
+/// which differs between payloads and runs, so build systems that compare
+/// timestamps would otherwise see the whole sysroot as changed after every
+/// splat. Symlinks themselves are skipped, as they are followed.
+pub(crate) fn set_mtimes(roots: &SplatRoots, mtime: std::time::SystemTime) -> Result<(), Error> {
+    let mut count = 0;
+
+    for dir in roots.splatted_dirs() {
+        for entry in walkdir::WalkDir::new(dir).min_depth(1) {
+            let entry = entry.with_context(|| format!("failed to walk {dir}"))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            std::fs::File::options()
+                .write(true)
+                .open(entry.path())
+                .and_then(|file| file.set_modified(mtime))
+                .with_context(|| {
+                    format!(
+                        "unable to set the modification time of {}",
+                        entry.path().display()
+                    )
+                })?;
+            count += 1;
+        }
+    }
+
+    tracing::debug!(
+        "set the modification time of {count} files in {}",
+        roots.root
+    );
+    Ok(())
+}
+
+/// Writes every symlink in the CRT and SDK to `path`, one `<link> -> <target>` per
+/// line with the link relative to the splat root, sorted bytewise, so that a
+/// copy or archive of the splat can be checked to have preserved them rather
+/// than dereferencing them, eg. against the output of
+/// `find <dir> -type l -printf '%P -> %l\n' | LC_ALL=C sort`.
+///
+/// Warns if any of the symlinks are dangling, or if `expect_symlinks` is set
+/// but there are none at all.
+pub(crate) fn write_symlink_report(
+    path: &Path,
+    roots: &SplatRoots,
+    expect_symlinks: bool,
+) -> Result<(), Error> {
+    let mut links = Vec::new();
+    let mut dangling = 0;
+
+    for entry in roots
+        .splatted_dirs()
+        .flat_map(|dir| walkdir::WalkDir::new(dir).min_depth(1))
+    {
+        let entry = entry.with_context(|| format!("failed to walk {}", roots.root))?;
+        if !entry.path_is_symlink() {
+            continue;
+        }
+
+        let link = Path::from_path(entry.path())
+            .with_context(|| format!("path {} is not utf-8", entry.path().display()))?;
+        let target =
+            std::fs::read_link(link).with_context(|| format!("unable to read symlink {link}"))?;
+
+        // exists follows the symlink
+        if !link.exists() {
+            tracing::warn!("symlink {link} -> {} is dangling", target.display());
+            dangling += 1;
+        }
+
+        links.push(format!(
+            "{} -> {}\n",
+            link.strip_prefix(&roots.root)?,
+            target.display()
+        ));
+    }
+
+    links.sort_unstable();
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("unable to create {parent}"))?;
+    }
+
+    std::fs::write(path, links.concat()).with_context(|| format!("unable to write {path}"))?;
+
+    tracing::info!("wrote {} symlinks in {} to {path}", links.len(), roots.root);
+
+    if expect_symlinks && links.is_empty() {
+        tracing::warn!(
+            "there are no symlinks in {} even though they are enabled, they may have been dereferenced by a copy or be unsupported by the file system",
+            roots.root
+        );
+    }
+
+    if dangling > 0 {
+        tracing::warn!("{dangling} of the symlinks in {} are dangling", roots.root);
+    }
+
+    Ok(())
+}
+
+/// Adds all of the symlinks to an existing splat directory that wasn't
+/// produced by this invocation, eg. one extracted from an archive. Any existing
+/// symlinks in the directory are removed and recreated.
+///
+/// Only the default layout, ie. `<root>/crt` and `<root>/sdk`, is supported.
+pub fn symlink_splat(root: &Path, draw_target: crate::util::ProgressTarget) -> Result<(), Error> {
+    let root = crate::util::canonicalize(root)?;
+
+    let roots = SplatRoots::default_layout(&root);
+
+    // Remove all existing symlinks first, as otherwise we would fail to create
+    // a symlink that already exists
+    for dir in [&roots.crt, &roots.sdk] {
+        anyhow::ensure!(
+            dir.is_dir(),
+            "expected splat directory '{dir}' does not exist"
+        );
+
+        for entry in walkdir::WalkDir::new(dir) {
+            let entry = entry.with_context(|| format!("failed to walk {dir}"))?;
+
+            if entry.path_is_symlink() {
+                std::fs::remove_file(entry.path()).with_context(|| {
+                    format!(
+                        "failed to remove existing symlink {}",
+                        entry.path().display()
+                    )
+                })?;
+            }
+        }
+    }
+
+    // Walks the files in the directory, optionally skipping a subdirectory
+    fn walk(
+        dir: &Path,
+        skip: Option<&str>,
+        mut on_file: impl FnMut(&Path) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let skip = skip.map(|skip| dir.join(skip));
+
+        for entry in walkdir::WalkDir::new(dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                skip.as_ref().map(|skip| skip.as_std_path()) != Some(entry.path())
+            })
+        {
+            let entry = entry.with_context(|| format!("failed to walk {dir}"))?;
+            let path = Path::from_path(entry.path())
+                .with_context(|| format!("path {} is not utf-8", entry.path().display()))?;
+
+            if entry.file_type().is_file() {
+                on_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut sdk_headers = SdkHeaders::new(roots.sdk.join("include"), PathBuf::new());
+
+    // The ucrt headers and libs don't get any symlinks
+    walk(&sdk_headers.root.clone(), Some("ucrt"), |path| {
+        let mut tar = path.to_owned();
+        add_file_symlinks(
+            LinkKind::Symlink,
+            PayloadKind::SdkHeaders,
+            path.file_name().unwrap(),
+            &mut tar,
+            Some((&mut sdk_headers, None)),
+            None,
+        )
+    })?;
+
+    walk(&roots.sdk.join("lib"), Some("ucrt"), |path| {
+        let mut tar = path.to_owned();
+        add_file_symlinks(
+            LinkKind::Symlink,
+            PayloadKind::SdkLibs,
+            path.file_name().unwrap(),
+            &mut tar,
+            None,
+            None,
+        )
+    })?;
+
+    // The CRT headers are only scanned for includes, so we only need to
+    // know the files, but the CRT libs need the angry symlinks
+    let mut crt_include = crate::unpack::FileTree::new();
+    walk(&roots.crt.join("include"), None, |path| {
+        if path.parent() == Some(&roots.crt.join("include")) {
+            crt_include
+                .files
+                .push((path.file_name().unwrap().into(), 0));
+        }
+        Ok(())
+    })?;
+
+    walk(&roots.crt.join("lib"), None, |path| {
+        let mut tar = path.to_owned();
+        add_file_symlinks(
+            LinkKind::Symlink,
+            PayloadKind::CrtLibs,
+            path.file_name().unwrap(),
+            &mut tar,
+            None,
+            None,
+        )
+    })?;
+
+    let mut crt_headers = crate::unpack::FileTree::new();
+    crt_headers.dirs.push(("include".into(), crt_include));
+
+    // The headers all come from the same directory, so they can't conflict
+    finalize_splat(
+        draw_target,
+        None,
+        &roots,
+        vec![sdk_headers],
+        Some(crt_headers),
+        None,
+        LinkKind::Symlink,
+    )?;
+
+    Ok(())
+}
+
+/// Adds the symlinks for the includes in the splatted headers, returning the
+/// SDK headers that were present in multiple payloads with different contents
+pub(crate) fn finalize_splat(
+    draw_target: crate::util::ProgressTarget,
+    sdk_version: Option<&str>,
+    roots: &SplatRoots,
+    sdk_headers: Vec<SdkHeaders>,
+    crt_headers: Option<crate::unpack::FileTree>,
+    atl_headers: Option<crate::unpack::FileTree>,
+    link_kind: LinkKind,
+) -> Result<Vec<crate::Diagnostic>, Error> {
+    let mut conflicts = Vec::new();
+    let mut files: std::collections::HashMap<
+        _,
+        Header<'_>,
+        std::hash::BuildHasherDefault<twox_hash::XxHash64>,
+    > = Default::default();
+
+    struct Header<'root> {
+        root: &'root SdkHeaders,
+        path: PathBuf,
+    }
+
+    fn compare_hashes(existing: &Path, new: &PathBuf) -> anyhow::Result<()> {
+        use crate::util::Sha256;
+
+        let existing_hash = Sha256::digest(&std::fs::read(existing)?);
+        let new_hash = Sha256::digest(&std::fs::read(new)?);
+
+        anyhow::ensure!(
+            existing_hash == new_hash,
+            "2 files with same relative path were not equal: '{existing}' != '{new}'"
+        );
+
+        Ok(())
+    }
+
+    for hdrs in &sdk_headers {
+        for (k, v) in &hdrs.inner {
             if let Some(existing) = files.get(k) {
                 // We already have a file with the same path, if they're the same
                 // as each other it's fine, but if they differ we have an issue
-                compare_hashes(&existing.path, v)?;
+                if existing.path != *v {
+                    compare_hashes(&existing.path, v)?;
+                } else if let (Some(existing_contents), Some(contents)) =
+                    (existing.root.contents.get(k), hdrs.contents.get(k))
+                {
+                    // Both payloads were splatted to the same file, so only
+                    // one of them was kept
+                    if existing_contents != contents {
+                        let path = v.strip_prefix(&roots.root).unwrap_or(v);
+                        tracing::warn!(
+                            "'{path}' differs between '{}' and '{}', only one of them was splatted",
+                            existing.root.payload,
+                            hdrs.payload
+                        );
+                        conflicts.push(crate::Diagnostic {
+                            kind: crate::DiagnosticKind::ConflictingHeader,
+                            id: path.to_string(),
+                            fallback: None,
+                            suggestions: Vec::new(),
+                            payloads: vec![existing.root.payload.clone(), hdrs.payload.clone()],
+                        });
+                    }
+                }
                 tracing::debug!("skipped {v}, a matching path already exists");
             } else {
                 files.insert(
@@ -822,6 +2079,8 @@ pub(crate) fn finalize_splat(
         }
     }
 
+    let has_sdk_headers = !sdk_headers.is_empty();
+
     let mut includes: std::collections::HashMap<
         _,
         _,
@@ -852,15 +2111,14 @@ pub(crate) fn finalize_splat(
 
     let regex = regex::bytes::Regex::new(r#"#include\s+(?:"|<)([^">]+)(?:"|>)?"#).unwrap();
 
-    let pb =
-        indicatif::ProgressBar::with_draw_target(Some(files.len() as u64), ctx.draw_target.into())
-            .with_style(
-                indicatif::ProgressStyle::default_bar()
-                    .template(
-                        "{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {pos}/{len}",
-                    )?
-                    .progress_chars("█▇▆▅▄▃▂▁  "),
-            );
+    let pb = indicatif::ProgressBar::with_draw_target(Some(files.len() as u64), draw_target.into())
+        .with_style(
+            indicatif::ProgressStyle::default_bar()
+                .template(
+                    "{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {pos}/{len}",
+                )?
+                .progress_chars("█▇▆▅▄▃▂▁  "),
+        );
 
     pb.set_prefix("symlinks");
     pb.set_message("🔍 SDK includes");
@@ -872,6 +2130,8 @@ pub(crate) fn finalize_splat(
         let contents =
             std::fs::read(&file.path).with_context(|| format!("unable to read {}", file.path))?;
 
+        let contents = active_code(&contents);
+
         for caps in regex.captures_iter(&contents) {
             let rel_path = std::str::from_utf8(&caps[1]).with_context(|| {
                 format!(
@@ -904,6 +2164,7 @@ pub(crate) fn finalize_splat(
             let path = cr.join(path);
             let contents =
                 std::fs::read(&path).with_context(|| format!("unable to read CRT {path}"))?;
+            let contents = active_code(&contents);
 
             for caps in regex.captures_iter(&contents) {
                 let rel_path = std::str::from_utf8(&caps[1]).with_context(|| {
@@ -931,6 +2192,7 @@ pub(crate) fn finalize_splat(
             let path = cr.join(path);
             let contents =
                 std::fs::read(&path).with_context(|| format!("unable to read ATL {path}"))?;
+            let contents = active_code(&contents);
 
             for caps in regex.captures_iter(&contents) {
                 let rel_path = std::str::from_utf8(&caps[1]).with_context(|| {
@@ -957,7 +2219,7 @@ pub(crate) fn finalize_splat(
                     let mut link = disk_file.path.clone();
                     link.pop();
                     link.push(include_name);
-                    symlink(disk_name, &link)?;
+                    make_link(link_kind, disk_name, &link)?;
                 }
                 _ => {}
             },
@@ -976,11 +2238,83 @@ pub(crate) fn finalize_splat(
         // target.push(sdk_version);
         // target.push("um/GL");
         // symlink("gl", &target)?;
-    } else {
-        symlink("gl", &roots.sdk.join("include/um/GL"))?;
+    } else if has_sdk_headers {
+        make_link(link_kind, "gl", &roots.sdk.join("include/um/GL"))?;
     }
 
-    Ok(())
+    Ok(conflicts)
+}
+
+/// Strips comments and `#if 0` blocks from a header so that includes within
+/// them aren't treated as real includes. This is not a real preprocessor, the
+/// only conditional that is evaluated is the literal `#if 0`, and string
+/// literals are not taken into account.
+fn active_code(contents: &[u8]) -> Vec<u8> {
+    let mut active = Vec::with_capacity(contents.len());
+    let mut line = Vec::new();
+    let mut in_comment = false;
+    // The nesting depth of conditionals inside an `#if 0` block, 0 if we
+    // aren't inside one
+    let mut disabled = 0usize;
+
+    for src_line in contents.split(|b| *b == b'\n') {
+        line.clear();
+
+        let mut i = 0;
+        while i < src_line.len() {
+            let rest = &src_line[i..];
+            if in_comment {
+                if rest.starts_with(b"*/") {
+                    in_comment = false;
+                    line.push(b' ');
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+            } else if rest.starts_with(b"/*") {
+                in_comment = true;
+                i += 2;
+            } else if rest.starts_with(b"//") {
+                break;
+            } else {
+                line.push(src_line[i]);
+                i += 1;
+            }
+        }
+
+        let directive = line
+            .trim_ascii_start()
+            .strip_prefix(b"#")
+            .map(|d| d.trim_ascii());
+
+        if disabled > 0 {
+            if let Some(directive) = directive {
+                if directive.starts_with(b"if") {
+                    disabled += 1;
+                } else if directive.starts_with(b"endif") {
+                    disabled -= 1;
+                } else if disabled == 1
+                    && (directive.starts_with(b"else") || directive.starts_with(b"elif"))
+                {
+                    disabled = 0;
+                }
+            }
+
+            continue;
+        }
+
+        if let Some(cond) = directive.and_then(|d| d.strip_prefix(b"if")) {
+            if cond.first().is_some_and(|c| c.is_ascii_whitespace()) && cond.trim_ascii() == b"0" {
+                disabled = 1;
+                continue;
+            }
+        }
+
+        active.extend_from_slice(&line);
+        active.push(b'\n');
+    }
+
+    active
 }
 
 use std::hash::Hasher;
@@ -995,3 +2329,1039 @@ fn calc_lower_hash(path: &str) -> u64 {
 
     hasher.finish()
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{Arch, Path, PayloadKind, SdkHeaders, SplatConfig, SplatRoots};
+
+    /// The roots of a splat in the default layout, unpacked to the same root
+    pub(crate) fn test_roots(root: &Path) -> SplatRoots {
+        SplatRoots {
+            src: root.to_owned(),
+            ..SplatRoots::default_layout(root)
+        }
+    }
+
+    /// A configuration for the default layout, with symlinks
+    fn test_config(output: &Path) -> SplatConfig {
+        SplatConfig {
+            enable_symlinks: true,
+            output: output.to_owned(),
+            copy: true,
+            ..Default::default()
+        }
+    }
+
+    /// Writes the files of a payload to its unpack directory in the source
+    /// root, then splats it
+    fn splat_files(
+        config: &SplatConfig,
+        roots: &SplatRoots,
+        kind: PayloadKind,
+        target_arch: Option<Arch>,
+        files: &[&str],
+        arches: u32,
+    ) -> Option<SdkHeaders> {
+        let contents: Vec<_> = files.iter().map(|file| (*file, *file)).collect();
+        splat_contents(
+            config,
+            roots,
+            None,
+            &format!("{kind:?}.vsix"),
+            kind,
+            target_arch,
+            &contents,
+            arches,
+        )
+    }
+
+    /// Writes the files, with the specified contents, of a payload to its
+    /// unpack directory in the source root, then splats it with the map
+    #[allow(clippy::too_many_arguments)]
+    fn splat_contents(
+        config: &SplatConfig,
+        roots: &SplatRoots,
+        map: Option<&crate::Map>,
+        filename: &str,
+        kind: PayloadKind,
+        target_arch: Option<Arch>,
+        files: &[(&str, &str)],
+        arches: u32,
+    ) -> Option<SdkHeaders> {
+        let unpack_dir = roots.src.join(filename);
+        for (file, contents) in files {
+            let path = unpack_dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+        }
+
+        let tree = crate::unpack::read_unpack_dir(unpack_dir).unwrap();
+        let item = crate::WorkItem {
+            progress: indicatif::ProgressBar::hidden(),
+            payload: std::sync::Arc::new(crate::Payload {
+                filename: filename.into(),
+                sha256: crate::util::Sha256([0; 32]),
+                url: String::new(),
+                size: 0,
+                install_size: None,
+                kind,
+                target_arch,
+                variant: None,
+            }),
+        };
+
+        super::splat(
+            config,
+            roots,
+            &item,
+            &tree,
+            map,
+            "10.0.22621.0",
+            arches,
+            crate::Variant::Desktop as u32,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn map_casings() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+        let files = [
+            ("include/ucrt/corecrt.h", ""),
+            ("lib/ucrt/x64/ucrt.lib", ""),
+            ("lib/ucrt/x64/libucrt.lib", ""),
+        ];
+
+        for filters in [false, true] {
+            let mut map: crate::Map =
+                toml::from_str("[casings]\n\"ucrt.lib\" = [\"UCRT.lib\", \"Ucrt.Lib\"]\n").unwrap();
+            if filters {
+                map.sdk.libs.filter.insert("glob:*/ucrt.lib".to_owned());
+            }
+
+            let roots = prep_splat(
+                root.join("unpack"),
+                false,
+                &output,
+                None,
+                None,
+                (None, None),
+                None,
+                false,
+            )
+            .unwrap();
+            splat_contents(
+                &test_config(&output),
+                &roots,
+                Some(&map),
+                "Ucrt.msi",
+                PayloadKind::Ucrt,
+                None,
+                &files,
+                Arch::X86_64 as u32,
+            );
+
+            let lib_dir = roots.sdk.join("lib/ucrt/x86_64");
+            for casing in ["UCRT.lib", "Ucrt.Lib"] {
+                assert_eq!(
+                    std::fs::read_link(lib_dir.join(casing)).unwrap(),
+                    std::path::Path::new("ucrt.lib"),
+                    "{casing}"
+                );
+            }
+
+            // A map with only casings doesn't filter the splatted files
+            assert_eq!(lib_dir.join("libucrt.lib").exists(), !filters);
+            assert_eq!(roots.sdk.join("include/ucrt/corecrt.h").exists(), !filters);
+            assert!(!lib_dir.join("LIBUCRT.lib").exists());
+        }
+    }
+
+    #[test]
+    fn symlink_existing_splat() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root =
+            crate::util::canonicalize(&PathBuf::from_path_buf(td.path().to_owned()).unwrap())
+                .unwrap();
+
+        assert!(symlink_splat(&root, crate::util::ProgressTarget::Hidden).is_err());
+
+        for (file, contents) in [
+            ("crt/include/vcruntime.h", "#include <WinSock2.h>\n"),
+            ("crt/lib/x86_64/libcmt.lib", ""),
+            ("sdk/include/ucrt/Corecrt.h", ""),
+            ("sdk/include/um/Windows.h", ""),
+            ("sdk/include/um/winsock2.h", ""),
+            ("sdk/lib/um/x86_64/kernel32.Lib", ""),
+            ("sdk/lib/ucrt/x86_64/Ucrt.lib", ""),
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        // Running it again replaces the symlinks from the previous run
+        for _ in 0..2 {
+            symlink_splat(&root, crate::util::ProgressTarget::Hidden).unwrap();
+        }
+
+        let link = |path: &str| {
+            std::fs::read_link(root.join(path))
+                .map(|target| target.to_str().unwrap().to_owned())
+                .ok()
+        };
+
+        assert_eq!(
+            link("sdk/include/um/windows.h").as_deref(),
+            Some("Windows.h")
+        );
+        // The include from the CRT header uses a different casing
+        assert_eq!(
+            link("sdk/include/um/WinSock2.h").as_deref(),
+            Some("winsock2.h")
+        );
+        for name in ["kernel32.lib", "Kernel32.lib", "KERNEL32.lib"] {
+            assert_eq!(
+                link(&format!("sdk/lib/um/x86_64/{name}")).as_deref(),
+                Some("kernel32.Lib"),
+                "{name}"
+            );
+        }
+        assert_eq!(
+            link("crt/lib/x86_64/LIBCMT.lib").as_deref(),
+            Some("libcmt.lib")
+        );
+
+        // The ucrt doesn't get any symlinks
+        assert!(!root.join("sdk/include/ucrt/corecrt.h").exists());
+        assert!(!root.join("sdk/lib/ucrt/x86_64/ucrt.lib").exists());
+    }
+
+    #[test]
+    fn winmd_metadata() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+
+        let roots = prep_splat(
+            root.join("unpack"),
+            false,
+            &output,
+            None,
+            None,
+            (None, None),
+            None,
+            false,
+        )
+        .unwrap();
+
+        // The metadata is neither headers nor libs, so is always splatted
+        let mut config = test_config(&output);
+        config.headers_only = true;
+
+        splat_files(
+            &config,
+            &roots,
+            PayloadKind::SdkMetadata,
+            None,
+            &["unionmetadata/Windows.winmd"],
+            Arch::X86_64 as u32,
+        );
+
+        // Tools expect the metadata in a versioned directory, even without
+        // --use-winsysroot-style
+        let winmd = roots.sdk.join("UnionMetadata/10.0.22621.0/Windows.winmd");
+        assert_eq!(
+            std::fs::read_to_string(&winmd).unwrap(),
+            "unionmetadata/Windows.winmd"
+        );
+        assert!(!roots
+            .sdk
+            .join("UnionMetadata/10.0.22621.0/windows.winmd")
+            .exists());
+
+        // But map files only list headers and libs
+        std::fs::remove_file(&winmd).unwrap();
+        let mut map = crate::Map::default();
+        map.sdk.headers.filter.insert("um/windows.h".to_owned());
+        splat_contents(
+            &config,
+            &roots,
+            Some(&map),
+            "SdkMetadata.vsix",
+            PayloadKind::SdkMetadata,
+            None,
+            &[],
+            Arch::X86_64 as u32,
+        );
+        assert!(!winmd.exists());
+    }
+
+    #[test]
+    fn conflicting_headers() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+
+        let roots = prep_splat(
+            root.join("unpack"),
+            false,
+            &output,
+            None,
+            None,
+            (None, None),
+            None,
+            false,
+        )
+        .unwrap();
+        let mut config = test_config(&output);
+
+        // The headers are only hashed if conflicts are being detected
+        let unhashed = splat_contents(
+            &config,
+            &roots,
+            None,
+            "a.msi",
+            PayloadKind::SdkHeaders,
+            None,
+            &[("include/um/Shared.h", "shared")],
+            Arch::X86_64 as u32,
+        )
+        .unwrap();
+        assert!(!unhashed.inner.is_empty());
+        assert!(unhashed.contents.is_empty());
+
+        config.detect_conflicting_headers = true;
+
+        let sdk_headers = [
+            ("a.msi", "shared"),
+            ("b.msi", "shared"),
+            ("c.msi", "different"),
+        ]
+        .into_iter()
+        .map(|(filename, contents)| {
+            splat_contents(
+                &config,
+                &roots,
+                None,
+                filename,
+                PayloadKind::SdkHeaders,
+                None,
+                &[
+                    ("include/um/Shared.h", contents),
+                    (&format!("include/um/{filename}.h"), filename),
+                ],
+                Arch::X86_64 as u32,
+            )
+            .unwrap()
+        })
+        .collect();
+
+        let conflicts = finalize_splat(
+            crate::util::ProgressTarget::Hidden,
+            None,
+            &roots,
+            sdk_headers,
+            None,
+            None,
+            LinkKind::Symlink,
+        )
+        .unwrap();
+
+        // Identical headers aren't conflicts, only the differing one is
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, crate::DiagnosticKind::ConflictingHeader);
+        assert_eq!(conflicts[0].id, "sdk/include/um/Shared.h");
+        assert_eq!(
+            conflicts[0].payloads,
+            [PathBuf::from("a.msi"), PathBuf::from("c.msi")]
+        );
+
+        // The lowercase link is still added for the header that was kept
+        assert!(roots.sdk.join("include/um/shared.h").exists());
+    }
+
+    #[test]
+    fn dia_root() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let src = root.join("unpack");
+        let output = root.join("splat");
+
+        let roots = prep_splat(src, false, &output, None, None, (None, None), None, false).unwrap();
+
+        splat_files(
+            &test_config(&output),
+            &roots,
+            PayloadKind::DiaSdk,
+            None,
+            &[
+                "include/dia2.h",
+                "lib/diaguids.lib",
+                "lib/amd64/diaguids.lib",
+                "lib/arm64/diaguids.lib",
+            ],
+            Arch::X86 as u32 | Arch::X86_64 as u32,
+        );
+
+        // The DIA SDK isn't part of the CRT
+        assert!(roots.dia.join("include/dia2.h").is_file());
+        assert!(!roots.crt.join("include/dia2.h").exists());
+        assert!(!roots.crt.join("lib").exists());
+
+        // The x86 libs are in the lib root, which isn't recursed into
+        assert!(roots.dia.join("lib/x86/diaguids.lib").is_file());
+        assert!(!roots.dia.join("lib/x86/amd64").exists());
+        assert!(roots.dia.join("lib/x86_64/diaguids.lib").is_file());
+        // Only the selected architectures are splatted
+        assert!(!roots.dia.join("lib/aarch64").exists());
+
+        // And is removed along with the CRT and SDK
+        prep_splat(
+            root.join("unpack"),
+            false,
+            &output,
+            None,
+            None,
+            (None, None),
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(!roots.dia.exists());
+
+        assert!(prep_splat(
+            root.join("unpack"),
+            false,
+            &output,
+            None,
+            None,
+            (Some("dia"), None),
+            None,
+            false,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn skips_inactive_includes() {
+        let header = br#"
+#include <windows.h>
+// #include <commented.h>
+/* #include <block.h>
+#include <multiline.h> */ #include "after_comment.h"
+#if 0
+#include <disabled.h>
+#ifdef NESTED
+#include <nested.h>
+#endif
+#else
+#include <enabled.h>
+#endif
+#if 0 // with a comment
+#include <disabled_again.h>
+#endif
+#if 01
+#include <not_zero.h>
+#endif
+"#;
+
+        let active = super::active_code(header);
+        let regex = regex::bytes::Regex::new(r#"#include\s+(?:"|<)([^">]+)(?:"|>)?"#).unwrap();
+        let includes: Vec<_> = regex
+            .captures_iter(&active)
+            .map(|caps| std::str::from_utf8(&caps[1]).unwrap().to_owned())
+            .collect();
+
+        assert_eq!(
+            includes,
+            ["windows.h", "after_comment.h", "enabled.h", "not_zero.h"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn arch_dir_links() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = test_roots(&root);
+
+        for dir in [
+            "crt/lib/x86_64",
+            "crt/lib/x86",
+            "sdk/lib/um/aarch64",
+            "sdk/include/x86_64",
+        ] {
+            std::fs::create_dir_all(roots.root.join(dir)).unwrap();
+        }
+
+        link_arch_dirs(
+            &roots,
+            Arch::X86 as u32 | Arch::X86_64 as u32 | Arch::Aarch64 as u32,
+            false,
+        )
+        .unwrap();
+
+        for (link, target) in [("crt/lib/x64", "x86_64"), ("sdk/lib/um/arm64", "aarch64")] {
+            let link = roots.root.join(link);
+            assert_eq!(std::fs::read_link(&link).unwrap().to_str(), Some(target));
+            assert!(link.is_dir());
+        }
+
+        // x86 is the same in both notations, and headers aren't split by
+        // architecture
+        assert!(!roots.root.join("crt/lib/x86").is_symlink());
+        assert!(!roots.root.join("sdk/include/x64").exists());
+    }
+
+    #[test]
+    fn refuses_unrelated_output() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let (crt, sdk) = (root.join("crt"), root.join("sdk"));
+
+        // Empty, or only containing the directories of a previous splat
+        check_output(&root, &[&crt, &sdk]).unwrap();
+        std::fs::create_dir(&crt).unwrap();
+        check_output(&root, &[&crt, &sdk]).unwrap();
+
+        std::fs::write(root.join("notes.txt"), "important").unwrap();
+        assert!(check_output(&root, &[&crt, &sdk]).is_err());
+
+        std::fs::write(root.join(SPLAT_MARKER), "").unwrap();
+        check_output(&root, &[&crt, &sdk]).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn absolute_symlinks() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let lib = root.join("sdk/lib");
+        std::fs::create_dir_all(lib.join("um")).unwrap();
+        std::fs::write(lib.join("um/kernel32.Lib"), "").unwrap();
+        std::os::unix::fs::symlink("kernel32.Lib", lib.join("um/kernel32.lib")).unwrap();
+        std::os::unix::fs::symlink(".", lib.join("10.0.22621")).unwrap();
+        // Anything outside of the CRT and SDK isn't ours to change
+        std::os::unix::fs::symlink("sdk/lib", root.join("libs")).unwrap();
+
+        make_symlinks_absolute(&SplatRoots::default_layout(&root)).unwrap();
+
+        assert_eq!(
+            std::fs::read_link(lib.join("um/kernel32.lib")).unwrap(),
+            lib.join("um/kernel32.Lib")
+        );
+        assert_eq!(std::fs::read_link(lib.join("10.0.22621")).unwrap(), lib);
+        assert!(lib.join("10.0.22621/um/kernel32.lib").exists());
+        assert_eq!(
+            std::fs::read_link(root.join("libs")).unwrap(),
+            std::path::Path::new("sdk/lib")
+        );
+    }
+
+    #[test]
+    fn uniform_mtimes() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let include = root.join("crt/include");
+        std::fs::create_dir_all(&include).unwrap();
+        for name in ["stdio.h", "vcruntime.h"] {
+            std::fs::write(include.join(name), "").unwrap();
+        }
+        // Anything outside of the CRT and SDK isn't ours to change
+        let notes = root.join("notes.txt");
+        std::fs::write(&notes, "").unwrap();
+
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        set_mtimes(&SplatRoots::default_layout(&root), mtime).unwrap();
+
+        for name in ["stdio.h", "vcruntime.h"] {
+            let md = std::fs::metadata(include.join(name)).unwrap();
+            assert_eq!(md.modified().unwrap(), mtime);
+        }
+        assert_ne!(std::fs::metadata(notes).unwrap().modified().unwrap(), mtime);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_report() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = test_roots(&root);
+
+        let um = roots.sdk.join("include/um");
+        std::fs::create_dir_all(&um).unwrap();
+        std::fs::write(um.join("Windows.h"), "").unwrap();
+        std::os::unix::fs::symlink("Windows.h", um.join("windows.h")).unwrap();
+        std::os::unix::fs::symlink("gone.h", um.join("Gone.h")).unwrap();
+        // Anything outside of the CRT and SDK isn't part of the splat
+        std::os::unix::fs::symlink("sdk", root.join("latest")).unwrap();
+
+        let report = root.join("report/symlinks.txt");
+        write_symlink_report(&report, &roots, true).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&report).unwrap(),
+            "sdk/include/um/Gone.h -> gone.h\nsdk/include/um/windows.h -> Windows.h\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn selected_crt_lib_casings() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let lib = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let selected = ["libcmt".to_owned(), "oldnames".to_owned()];
+
+        for fname in ["libcmt.lib", "msvcrt.lib", "oldnames.lib"] {
+            let mut tar = lib.join(fname);
+            std::fs::write(&tar, "").unwrap();
+
+            add_file_symlinks(
+                LinkKind::Symlink,
+                PayloadKind::CrtLibs,
+                fname,
+                &mut tar,
+                None,
+                Some(&selected),
+            )
+            .unwrap();
+        }
+
+        assert!(lib.join("LIBCMT.lib").is_symlink());
+        assert!(lib.join("OLDNAMES.lib").is_symlink());
+        assert!(!lib.join("MSVCRT.lib").exists());
+    }
+
+    /// The C++/WinRT projection headers are named after the namespaces they
+    /// project, so they need lower case aliases the same as every other SDK
+    /// header, and the includes between them need to resolve
+    #[cfg(unix)]
+    #[test]
+    fn cppwinrt_casing() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = test_roots(&root);
+
+        let include = roots.sdk.join("include");
+        let mut sdk_headers = SdkHeaders::new(include.clone(), PathBuf::new());
+
+        for (rel, contents) in [
+            ("um/Windows.h", ""),
+            ("um/gl/GL.h", ""),
+            ("cppwinrt/winrt/base.h", "#include <intrin.h>\n"),
+            (
+                "cppwinrt/winrt/Windows.Foundation.h",
+                "#include \"winrt/impl/Windows.Foundation.2.h\"\n",
+            ),
+            (
+                "cppwinrt/winrt/impl/Windows.Foundation.2.h",
+                "#include \"winrt/base.h\"\n#include \"winrt/impl/windows.foundation.0.h\"\n",
+            ),
+            ("cppwinrt/winrt/impl/Windows.Foundation.0.h", ""),
+        ] {
+            let mut tar = include.join(rel);
+            std::fs::create_dir_all(tar.parent().unwrap()).unwrap();
+            std::fs::write(&tar, contents).unwrap();
+
+            let fname = tar.file_name().unwrap().to_owned();
+            add_file_symlinks(
+                LinkKind::Symlink,
+                PayloadKind::SdkHeaders,
+                &fname,
+                &mut tar,
+                Some((&mut sdk_headers, None)),
+                None,
+            )
+            .unwrap();
+        }
+
+        finalize_splat(
+            crate::util::ProgressTarget::Hidden,
+            None,
+            &roots,
+            vec![sdk_headers],
+            None,
+            None,
+            LinkKind::Symlink,
+        )
+        .unwrap();
+
+        let winrt = include.join("cppwinrt/winrt");
+        for (link, target) in [
+            ("windows.foundation.h", "Windows.Foundation.h"),
+            ("impl/windows.foundation.2.h", "Windows.Foundation.2.h"),
+            ("impl/windows.foundation.0.h", "Windows.Foundation.0.h"),
+        ] {
+            let link = winrt.join(link);
+            assert!(link.is_symlink(), "{link} is not a symlink");
+            assert_eq!(std::fs::read_link(&link).unwrap().to_str(), Some(target));
+        }
+
+        // Headers that are already lower case don't need an alias
+        assert!(!winrt.join("base.h").is_symlink());
+
+        assert_eq!(
+            resolve_include(&roots.root, "windows.h"),
+            Some(include.join("um/windows.h"))
+        );
+        assert_eq!(
+            resolve_include(&roots.root, "winrt\\windows.foundation.h"),
+            Some(winrt.join("windows.foundation.h"))
+        );
+        assert_eq!(resolve_include(&roots.root, "winsock2.h"), None);
+    }
+
+    #[test]
+    fn layout_dirs() {
+        use super::*;
+
+        let roots = SplatRoots::default_layout(Path::new("/splat"));
+        let rel = |dirs: &[PathBuf]| -> Vec<String> {
+            dirs.iter()
+                .map(|dir| dir.strip_prefix("/splat").unwrap().to_string())
+                .collect()
+        };
+
+        let mut layout = DirLayout {
+            winsysroot: false,
+            map: false,
+            merge_ucrt_include: false,
+            preserve_ms_arch_notation: false,
+            spectre: false,
+            sdk_version: "10.0.22621.0",
+        };
+
+        assert_eq!(
+            rel(&roots.include_dirs(&layout)),
+            [
+                "crt/include",
+                "sdk/include/ucrt",
+                "sdk/include/shared",
+                "sdk/include/um",
+                "sdk/include/winrt",
+                "sdk/include/cppwinrt"
+            ]
+        );
+        assert_eq!(
+            rel(&roots.lib_dirs(&layout, Arch::Aarch64)),
+            [
+                "crt/lib/aarch64",
+                "sdk/lib/um/aarch64",
+                "sdk/lib/ucrt/aarch64"
+            ]
+        );
+        assert_eq!(
+            roots.tlb_dir(&layout, Arch::X86_64),
+            "/splat/sdk/tlb/x86_64"
+        );
+
+        layout.merge_ucrt_include = true;
+        layout.spectre = true;
+        layout.preserve_ms_arch_notation = true;
+        assert_eq!(
+            rel(&roots.include_dirs(&layout))[..3],
+            ["crt/include", "sdk/include", "sdk/include/shared"]
+        );
+        assert_eq!(
+            rel(&roots.lib_dirs(&layout, Arch::Aarch64)),
+            [
+                "crt/lib/spectre/arm64",
+                "sdk/lib/um/arm64",
+                "sdk/lib/ucrt/arm64"
+            ]
+        );
+
+        layout.merge_ucrt_include = false;
+        layout.spectre = false;
+        layout.winsysroot = true;
+        assert_eq!(
+            rel(&roots.include_dirs(&layout))[..2],
+            ["crt/include", "sdk/include/10.0.22621.0/ucrt"]
+        );
+        assert_eq!(
+            rel(&roots.lib_dirs(&layout, Arch::X86_64))[1..],
+            [
+                "sdk/lib/10.0.22621.0/um/x64",
+                "sdk/lib/10.0.22621.0/ucrt/x64"
+            ]
+        );
+
+        // Map files always use the versioned `Include` directory
+        layout.map = true;
+        assert_eq!(
+            rel(&roots.include_dirs(&layout))[1],
+            "sdk/Include/10.0.22621.0/ucrt"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn post_splat() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = SplatRoots::default_layout(&root);
+        let out = root.join("env.txt");
+
+        let vars = [
+            "XWIN_SPLAT_ROOT",
+            "XWIN_CRT_DIR",
+            "XWIN_SDK_DIR",
+            "XWIN_CRT_VERSION",
+            "XWIN_SDK_VERSION",
+        ]
+        .map(|var| format!("\"${var}\""))
+        .join(" ");
+
+        run_post_splat(
+            &format!("printf '%s\\n' {vars} > '{out}'"),
+            &roots,
+            "14.38.17.8",
+            "10.0.22621",
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            format!(
+                "{root}\n{}\n{}\n14.38.17.8\n10.0.22621\n",
+                roots.crt, roots.sdk
+            )
+        );
+
+        let err = run_post_splat("exit 3", &roots, "", "").unwrap_err();
+        assert!(err.to_string().contains("failed with exit status: 3"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tlb_dir() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = test_roots(&root);
+
+        let mut config = test_config(&root);
+        config.separate_tlbs = true;
+        // The type libraries aren't filtered by --sdk-libs
+        config.sdk_libs = vec!["kernel32".to_owned()];
+
+        splat_files(
+            &config,
+            &roots,
+            PayloadKind::SdkLibs,
+            Some(Arch::X86_64),
+            &[
+                "lib/um/x64/kernel32.Lib",
+                "lib/um/x64/user32.lib",
+                "lib/um/x64/MsHtml.Tlb",
+            ],
+            Arch::X86_64 as u32,
+        );
+
+        let lib_dir = roots.sdk.join("lib/um/x86_64");
+        let tlb_dir = roots.sdk.join("tlb/x86_64");
+
+        assert!(tlb_dir.join("MsHtml.Tlb").is_file());
+        assert!(tlb_dir.join("mshtml.tlb").is_symlink());
+        assert_eq!(
+            std::fs::read_to_string(tlb_dir.join("mshtml.tlb")).unwrap(),
+            "lib/um/x64/MsHtml.Tlb"
+        );
+
+        // The type libraries are only in the tlb directory, unlike the libs
+        for name in ["MsHtml.Tlb", "mshtml.tlb"] {
+            assert!(!lib_dir.join(name).exists());
+        }
+        assert!(lib_dir.join("kernel32.lib").is_symlink());
+        assert!(!lib_dir.join("user32.lib").exists());
+        assert!(!tlb_dir.join("kernel32.Lib").exists());
+    }
+
+    #[test]
+    fn add_arch() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+        let files = [
+            "include/ucrt/corecrt.h",
+            "lib/ucrt/x64/ucrt.lib",
+            "lib/ucrt/arm64/ucrt.lib",
+        ];
+
+        let roots = prep_splat(
+            root.join("unpack"),
+            false,
+            &output,
+            None,
+            None,
+            (None, None),
+            None,
+            false,
+        )
+        .unwrap();
+        let mut config = test_config(&output);
+        splat_files(
+            &config,
+            &roots,
+            PayloadKind::Ucrt,
+            None,
+            &files,
+            Arch::X86_64 as u32,
+        );
+
+        let header = roots.sdk.join("include/ucrt/corecrt.h");
+        let x64_lib = roots.sdk.join("lib/ucrt/x86_64/ucrt.lib");
+        let arm64_lib = roots.sdk.join("lib/ucrt/aarch64/ucrt.lib");
+        assert!(!arm64_lib.exists());
+
+        // Mark the existing files so it's clear they weren't splatted again
+        for path in [&header, &x64_lib] {
+            std::fs::write(path, "existing").unwrap();
+        }
+
+        let roots = prep_splat(
+            root.join("unpack"),
+            false,
+            &output,
+            None,
+            None,
+            (None, None),
+            None,
+            true,
+        )
+        .unwrap();
+        config.add_arch = true;
+        splat_files(
+            &config,
+            &roots,
+            PayloadKind::Ucrt,
+            None,
+            &files,
+            Arch::Aarch64 as u32,
+        );
+
+        for path in [&header, &x64_lib] {
+            assert_eq!(std::fs::read_to_string(path).unwrap(), "existing");
+        }
+        assert_eq!(
+            std::fs::read_to_string(&arm64_lib).unwrap(),
+            "lib/ucrt/arm64/ucrt.lib"
+        );
+    }
+
+    #[test]
+    fn merged_ucrt_include() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+
+        for merge_ucrt_include in [false, true] {
+            let roots = prep_splat(
+                root.join("unpack"),
+                false,
+                &output,
+                None,
+                None,
+                (None, None),
+                None,
+                false,
+            )
+            .unwrap();
+            std::fs::create_dir_all(roots.crt.join("lib/x86_64")).unwrap();
+
+            let mut config = test_config(&output);
+            config.merge_ucrt_include = merge_ucrt_include;
+            splat_files(
+                &config,
+                &roots,
+                PayloadKind::Ucrt,
+                None,
+                &["include/ucrt/corecrt.h", "lib/ucrt/x64/ucrt.lib"],
+                Arch::X86_64 as u32,
+            );
+
+            let sdk_include = roots.sdk.join("include");
+            let ucrt_include = if merge_ucrt_include {
+                sdk_include.clone()
+            } else {
+                sdk_include.join("ucrt")
+            };
+
+            // Both env and doctor use the detected include directories
+            let layout = roots.detect_layout(Some(Arch::X86_64)).unwrap();
+            assert_eq!(layout.merge_ucrt_include, merge_ucrt_include);
+            assert_eq!(
+                resolve_include(&output, "corecrt.h"),
+                Some(ucrt_include.join("corecrt.h"))
+            );
+            assert_eq!(
+                crate::splat_dirs(&output, Arch::X86_64)
+                    .unwrap()
+                    .include_dirs,
+                [ucrt_include]
+            );
+        }
+    }
+
+    #[test]
+    fn detects_layout() {
+        use super::*;
+
+        let td = tempfile::tempdir().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let roots = SplatRoots::default_layout(&root);
+
+        for dir in ["crt/lib/x64", "crt/lib/spectre/aarch64"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let layout = roots.detect_layout(None).unwrap();
+        assert!(!layout.spectre && !layout.preserve_ms_arch_notation);
+
+        let layout = roots.detect_layout(Some(Arch::X86_64)).unwrap();
+        assert!(!layout.spectre && layout.preserve_ms_arch_notation);
+
+        let layout = roots.detect_layout(Some(Arch::Aarch64)).unwrap();
+        assert!(layout.spectre && !layout.preserve_ms_arch_notation);
+
+        assert!(roots.detect_layout(Some(Arch::X86)).is_err());
+    }
+}
@@ -3,21 +3,90 @@ use anyhow::Context as _;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub(crate) struct UnpackMeta {
-    #[serde(serialize_with = "crate::util::serialize_sha256")]
     pub(crate) sha256: crate::util::Sha256,
     pub(crate) compressed: u64,
     pub(crate) decompressed: u64,
     pub(crate) num_files: u32,
+    /// Whether source files were unpacked as well
+    #[serde(default)]
+    pub(crate) sources: bool,
+    /// Whether only the default libraries were unpacked, see [`default_libs_only`]
+    #[serde(default)]
+    pub(crate) default_libs_only: bool,
+    /// Whether every file was unpacked, including those that are never
+    /// splatted, as is done when extracting a package
+    #[serde(default)]
+    pub(crate) unfiltered: bool,
 }
 
-#[derive(Debug)]
+/// Whether only the libraries that are linked by default, ie. those directly in
+/// `lib/<arch>`, are needed from the payload. This is the case for the Store
+/// variant of the CRT libraries when the Store isn't targeted, as it is only
+/// selected for eg. `oldnames.lib`, and the Store specific libraries in its
+/// subdirectories are never splatted.
+pub(crate) fn default_libs_only(payload: &crate::Payload, variants: u32) -> bool {
+    payload.kind == crate::PayloadKind::CrtLibs
+        && payload.variant == Some(crate::Variant::Store)
+        && variants & crate::Variant::Store as u32 == 0
+}
+
+/// A payload that has been unpacked into the cache
+pub struct Unpacked {
+    pub filename: PathBuf,
+    pub sha256: crate::util::Sha256,
+    pub num_files: u32,
+    /// The size of the downloaded payload
+    pub compressed: u64,
+    /// The total size of the unpacked files
+    pub decompressed: u64,
+}
+
+/// Lists the payloads that have been successfully unpacked in the specified
+/// cache directory, sorted by name. Payloads that were only partially unpacked
+/// are ignored.
+pub fn list_unpacked(cache_dir: &Path) -> Result<Vec<Unpacked>, Error> {
+    let unpack_dir = cache_dir.join("unpack");
+
+    let mut unpacked = Vec::new();
+    for entry in
+        std::fs::read_dir(&unpack_dir).with_context(|| format!("unable to read {unpack_dir}"))?
+    {
+        let entry = entry.with_context(|| format!("unable to read entry from {unpack_dir}"))?;
+        let Some(filename) = entry.file_name().to_str().map(PathBuf::from) else {
+            continue;
+        };
+
+        let meta_path = unpack_dir.join(&filename).join(".unpack");
+        let Ok(meta) = std::fs::read(&meta_path) else {
+            tracing::debug!("{filename} has not been fully unpacked");
+            continue;
+        };
+
+        let meta: UnpackMeta = serde_json::from_slice(&meta)
+            .with_context(|| format!("unable to deserialize {meta_path}"))?;
+
+        unpacked.push(Unpacked {
+            filename,
+            sha256: meta.sha256,
+            num_files: meta.num_files,
+            compressed: meta.compressed,
+            decompressed: meta.decompressed,
+        });
+    }
+
+    unpacked.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+    Ok(unpacked)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub(crate) struct FileTree {
     pub(crate) files: Vec<(PathBuf, u64)>,
     pub(crate) dirs: Vec<(PathBuf, FileTree)>,
 }
 
 impl FileTree {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             files: Vec::new(),
             dirs: Vec::new(),
@@ -45,16 +114,16 @@ impl FileTree {
     }
 
     pub(crate) fn stats(&self) -> (u32, u64) {
-        self.dirs.iter().fold(
-            (
-                self.files.len() as u32,
-                self.files.iter().map(|(_, size)| *size).sum(),
-            ),
-            |(num_files, size), tree| {
-                let stats = tree.1.stats();
-                (num_files + stats.0, size + stats.1)
-            },
-        )
+        let (mut num_files, mut size) = (0, 0);
+        let mut tree_stack = vec![self];
+
+        while let Some(tree) = tree_stack.pop() {
+            num_files += tree.files.len() as u32;
+            size += tree.files.iter().map(|(_, size)| *size).sum::<u64>();
+            tree_stack.extend(tree.dirs.iter().map(|(_, tree)| tree));
+        }
+
+        (num_files, size)
     }
 
     pub(crate) fn subtree(&self, path: &Path) -> Option<&FileTree> {
@@ -69,12 +138,32 @@ impl FileTree {
 
         Some(tree)
     }
+
+    fn subtree_mut(&mut self, path: &Path) -> Option<&mut FileTree> {
+        let mut tree = self;
+
+        for comp in path.iter() {
+            tree = &mut tree.dirs.iter_mut().find(|dir| dir.0 == comp)?.1;
+        }
+
+        Some(tree)
+    }
 }
 
-fn read_unpack_dir(root: PathBuf) -> Result<FileTree, Error> {
+pub(crate) fn read_unpack_dir(root: PathBuf) -> Result<FileTree, Error> {
     let mut root_tree = FileTree::new();
 
-    fn read(src: PathBuf, tree: &mut FileTree) -> Result<(), Error> {
+    // The contents of unpacked archives are untrusted, so we use an explicit
+    // stack rather than recursion to avoid overflowing with deeply nested
+    // directories. Each entry is the directory to read and its path relative
+    // to the root
+    let mut dir_stack = vec![(root, PathBuf::new())];
+
+    while let Some((src, rel)) = dir_stack.pop() {
+        let tree = root_tree
+            .subtree_mut(&rel)
+            .with_context(|| format!("unable to find tree for {src}"))?;
+
         for entry in std::fs::read_dir(&src).with_context(|| format!("unable to read {src}"))? {
             let entry = entry.with_context(|| format!("unable to read entry from {src}"))?;
 
@@ -96,10 +185,8 @@ fn read_unpack_dir(root: PathBuf) -> Result<FileTree, Error> {
             let ft = metadata.file_type();
 
             if ft.is_dir() {
-                let mut dir_tree = FileTree::new();
-                read(src.join(&src_name), &mut dir_tree)?;
-
-                tree.dirs.push((src_name, dir_tree));
+                dir_stack.push((src.join(&src_name), rel.join(&src_name)));
+                tree.dirs.push((src_name, FileTree::new()));
             } else if ft.is_file() {
                 tree.files.push((src_name, metadata.len()));
             } else if ft.is_symlink() {
@@ -109,24 +196,325 @@ fn read_unpack_dir(root: PathBuf) -> Result<FileTree, Error> {
                 );
             }
         }
+    }
+
+    Ok(root_tree)
+}
+
+/// Writes the raw tables that determine where each file in an MSI is unpacked
+/// to as JSON, so that unexpected layouts can be diagnosed without needing
+/// separate tools to inspect the MSI
+fn dump_msi_tables<R: std::io::Read + std::io::Seek>(
+    msi: &mut msi::Package<R>,
+    dir: &Path,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(dir).with_context(|| format!("unable to create {dir}"))?;
+
+    for table in ["Directory", "Component", "File", "Media"] {
+        let rows = msi
+            .select_rows(msi::Select::table(table))
+            .with_context(|| format!("MSI has no '{table}' table"))?;
+
+        let columns: Vec<_> = rows
+            .columns()
+            .iter()
+            .map(|col| col.name().to_owned())
+            .collect();
+
+        let rows: Vec<serde_json::Map<_, _>> = rows
+            .map(|row| {
+                columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let value = match &row[i] {
+                            msi::Value::Null => serde_json::Value::Null,
+                            msi::Value::Int(int) => (*int).into(),
+                            msi::Value::Str(s) => s.as_str().into(),
+                        };
+
+                        (name.clone(), value)
+                    })
+                    .collect()
+            })
+            .collect();
 
-        Ok(())
+        let path = dir.join(format!("{table}.json"));
+        std::fs::write(&path, serde_json::to_vec_pretty(&rows)?)
+            .with_context(|| format!("unable to write {path}"))?;
     }
 
-    read(root, &mut root_tree)?;
+    Ok(())
+}
+
+/// VSIX files are just a "specially" formatted zip file, all of the actual
+/// files we want are under "Contents", and are unpacked relative to their
+/// `lib` or `include` directory, or their `bin` directory for the tools.
+/// Unfiltered, every file is unpacked relative to "Contents" instead.
+fn vsix_path(name: &str, kind: crate::PayloadKind, unfiltered: bool) -> Option<PathBuf> {
+    let contents = name.strip_prefix("Contents/")?;
 
-    Ok(root_tree)
+    if unfiltered {
+        return Some(contents.into());
+    }
+
+    let roots: &[&str] = if kind == crate::PayloadKind::CrtTools {
+        &["bin"]
+    } else {
+        &["lib", "include"]
+    };
+
+    // The root must be a whole component, eg. `bin` in `libcmt.lib` is not
+    let path: PathBuf = Path::new(contents)
+        .components()
+        .skip_while(|comp| !roots.contains(&comp.as_str()))
+        .collect();
+
+    (!path.as_str().is_empty()).then_some(path)
+}
+
+/// Whether the name is a generated 8.3 short name, eg. `WINSOC~1.H`
+fn is_short_name(name: &str) -> bool {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    base.len() <= 8
+        && ext.len() <= 3
+        && base
+            .split_once('~')
+            .is_some_and(|(_, num)| !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// A file in an MSI, and the CAB it is stored in
+struct CabFile {
+    id: String,
+    /// The path the file is unpacked to, relative to the output directory
+    name: PathBuf,
+    size: u64,
+    sequence: u32,
+}
+
+/// Reads the `Directory`, `Component`, and `File` tables of an MSI to
+/// determine every file it installs and where it is unpacked to, sorted by
+/// the sequence number used to locate the CAB each file is stored in
+fn read_msi_files<R: std::io::Read + std::io::Seek>(
+    msi: &mut msi::Package<R>,
+    pkg: &Path,
+    include_sources: bool,
+    unfiltered: bool,
+) -> Result<Vec<CabFile>, Error> {
+    // Open source ftw https://gitlab.gnome.org/GNOME/msitools/-/blob/master/tools/msiextract.vala
+
+    // For some reason many filenames in the table(s) have a weird
+    // checksum(?) filename with an extension separated from the
+    // _actual_ filename with a `|` so we need to detect that and
+    // strip off just the real name we want
+    //
+    // This is really the `short|long` form, and some MSIs only have the
+    // short (8.3) name, which we still use as there is nothing else, but they
+    // are logged as they are almost certainly a packaging mistake that will
+    // trip up anything that includes or links the file by its real name
+    let fix_name = |name: &msi::Value| -> Result<String, Error> {
+        let name = name.as_str().context("filename is not a string")?;
+
+        let name = match name.split_once('|') {
+            Some((_short, long)) if !long.is_empty() => long,
+            Some((short, _)) => short,
+            None => name,
+        };
+
+        if is_short_name(name) {
+            tracing::warn!("'{name}' in {pkg} only has a short (8.3) name");
+        }
+
+        Ok(name.to_owned())
+    };
+
+    let components = {
+        #[derive(Debug)]
+        struct Dir {
+            id: String,
+            parent: Option<String>,
+            path: PathBuf,
+        }
+
+        // Collect the directories that can be referenced by a component
+        // that are reference by files. Ugh.
+        let mut directories: Vec<_> = msi
+            .select_rows(msi::Select::table("Directory"))
+            .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
+            .map(|row| -> Result<_, _> {
+                // Columns:
+                // 0 - Directory (name)
+                // 1 - Directory_Parent (name of parent)
+                // 2 - DefaultDir (location of directory on disk)
+                // ...
+                anyhow::ensure!(row.len() >= 3, "invalid row in 'Directory'");
+
+                Ok(Dir {
+                    id: row[0]
+                        .as_str()
+                        .context("directory name is not a string")?
+                        .to_owned(),
+                    // This can be `null`
+                    parent: row[1].as_str().map(String::from),
+                    path: fix_name(&row[2])?.into(),
+                })
+            })
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("unable to read directories for {pkg}"))?;
+
+        directories.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let components: std::collections::BTreeMap<_, _> = msi
+            .select_rows(msi::Select::table("Component"))
+            .with_context(|| format!("MSI {pkg} has no 'Directory' table"))?
+            .map(|row| -> Result<_, _> {
+                // Columns:
+                // 0 - Component (name, really, id)
+                // 1 - ComponentId
+                // 2 - Directory_ (directory id)
+                anyhow::ensure!(row.len() >= 3, "invalid row in 'Component'");
+
+                // The recursion depth for directory lookup is quite shallow
+                // typically, the full path to a file would be something like
+                // `Program Files/Windows Kits/10/Lib/10.0.19041.0/um/x64`
+                // but this a terrible path, so we massage it to instead be
+                // `lib/um/x64`
+                fn build_dir(dirs: &[Dir], id: &str, dir: &mut PathBuf) {
+                    #[allow(clippy::single_match_else)]
+                    let cur_dir = match dirs.binary_search_by(|d| d.id.as_str().cmp(id)) {
+                        Ok(i) => &dirs[i],
+                        Err(_) => {
+                            tracing::warn!("unable to find directory {id}");
+                            return;
+                        }
+                    };
+
+                    match cur_dir.path.file_name() {
+                        Some("Lib") => {
+                            dir.push("lib");
+                        }
+                        Some("Include") => {
+                            dir.push("include");
+                        }
+                        Some("Source") => {
+                            dir.push("source");
+                        }
+                        Some("UnionMetadata") => {
+                            dir.push("unionmetadata");
+                        }
+                        other => {
+                            if let Some(parent) = &cur_dir.parent {
+                                build_dir(dirs, parent, dir);
+                            }
+
+                            if let Some(other) = other {
+                                // Ignore the SDK version directory between
+                                // Lib/Include and the actual subdirs we care about
+                                if !other.starts_with(|c: char| c.is_ascii_digit()) {
+                                    dir.push(other);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let component_id = row[0]
+                    .as_str()
+                    .context("component id is not a string")?
+                    .to_owned();
+
+                let mut dir = PathBuf::new();
+                build_dir(
+                    &directories,
+                    row[2]
+                        .as_str()
+                        .context("component directory is not a string")?,
+                    &mut dir,
+                );
+
+                Ok((component_id, dir))
+            })
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("unable to read components for {pkg}"))?;
+
+        components
+    };
+
+    let mut files: Vec<_> = msi
+        .select_rows(msi::Select::table("File"))
+        .with_context(|| format!("MSI {} has no 'File' table", pkg))?
+        .filter_map(|row| -> Option<Result<_, Error>> {
+            // Columns:
+            // 0 - File Id (lookup in CAB)
+            // 1 - Component_ (target directory)
+            // 2 - FileName
+            // 3 - FileSize
+            // 4 - Version
+            // 5 - Language
+            // 6 - Attributes
+            // 7 - Sequence (determines which CAB file)
+            if row.len() < 8 {
+                return Some(Err(anyhow::anyhow!("invalid row in 'File'")));
+            }
+
+            #[allow(clippy::blocks_in_conditions)]
+            let (dir, fname, id, seq, size) = match || -> Result<_, Error> {
+                let fname = fix_name(&row[2])?;
+                let dir = components
+                    .get(row[1].as_str().context("component id was not a string")?)
+                    .with_context(|| format!("file {} referenced an unknown component", row[2]))?;
+
+                let size = row[3].as_int().context("size is not an integer")? as u64;
+                let id = row[0].as_str().context("File (id) is not a string")?;
+                let seq = row[7].as_int().context("sequence is not an integer")? as u32;
+
+                Ok((dir, fname, id, seq, size))
+            }() {
+                Ok(items) => items,
+                Err(e) => return Err(e).transpose(),
+            };
+
+            // Everything we care about is under one of the roots that
+            // `build_dir` maps, anything else is rooted at the MSI's `SourceDir`
+            let mut components = dir.components().map(|comp| comp.as_str());
+            match components.next() {
+                _ if unfiltered => {}
+                Some("source") if !include_sources => return None,
+                Some("SourceDir") if components.any(|comp| matches!(comp, "Catalogs" | "bin")) => {
+                    return None;
+                }
+                _ => {}
+            }
+
+            let cf = CabFile {
+                id: id.to_owned(),
+                name: dir.join(fname),
+                sequence: seq,
+                size,
+            };
+
+            Some(Ok(cf))
+        })
+        .collect::<Result<Vec<_>, Error>>()
+        .with_context(|| format!("unable to read 'File' metadata for {pkg}"))?;
+
+    files.sort_by(|a, b| a.sequence.cmp(&b.sequence));
+
+    Ok(files)
 }
 
 pub(crate) fn unpack(
     ctx: std::sync::Arc<Ctx>,
     item: &crate::WorkItem,
     contents: PayloadContents,
+    default_libs_only: bool,
+    unfiltered: bool,
 ) -> Result<FileTree, Error> {
     item.progress.reset();
     item.progress.set_message("📂 unpacking...");
 
-    let output_dir = match ctx.prep_unpack(&item.payload)? {
+    let output_dir = match ctx.prep_unpack(&item.payload, default_libs_only, unfiltered)? {
         crate::ctx::Unpack::Present { output_dir, .. } => {
             return read_unpack_dir(output_dir);
         }
@@ -134,6 +522,7 @@ pub(crate) fn unpack(
     };
 
     let pkg = &item.payload.filename;
+    let include_sources = ctx.include_sources || unfiltered;
 
     let (tree, compressed) = match contents {
         PayloadContents::Vsix(vsix) => {
@@ -142,20 +531,19 @@ pub(crate) fn unpack(
             let mut zip = zip::ZipArchive::new(std::io::Cursor::new(vsix))
                 .with_context(|| format!("invalid zip {pkg}"))?;
 
-            // VSIX files are just a "specially" formatted zip file, all
-            // of the actual files we want are under "Contents"
             let mut to_extract = Vec::new();
             let mut total_uncompressed = 0;
 
             for findex in 0..zip.len() {
                 let file = zip.by_index_raw(findex)?;
 
-                let fname = file.name();
+                if let Some(rel_path) = vsix_path(file.name(), item.payload.kind, unfiltered) {
+                    // lib/<arch>/<file>
+                    if default_libs_only && rel_path.components().count() != 3 {
+                        continue;
+                    }
 
-                if fname.starts_with("Contents/")
-                    && (fname.contains("lib") || fname.contains("include"))
-                {
-                    to_extract.push(findex);
+                    to_extract.push((findex, rel_path));
                     total_uncompressed += file.size();
                 }
             }
@@ -164,17 +552,9 @@ pub(crate) fn unpack(
 
             let mut total_compressed = 0;
 
-            for findex in to_extract {
+            for (findex, rel_path) in to_extract {
                 let mut file = zip.by_index(findex).unwrap();
-                let zip_path = Path::new(file.name());
-                let mut fs_path = output_dir.clone();
-
-                for comp in zip_path
-                    .components()
-                    .skip_while(|comp| comp.as_str() != "lib" && comp.as_str() != "include")
-                {
-                    fs_path.push(comp);
-                }
+                let fs_path = output_dir.join(&rel_path);
 
                 if let Some(parent) = fs_path.parent() {
                     if !parent.exists() {
@@ -199,8 +579,7 @@ pub(crate) fn unpack(
 
                 item.progress.inc(decompressed);
 
-                let tree_path = fs_path.strip_prefix(&output_dir).unwrap();
-                tree.push(tree_path, decompressed);
+                tree.push(&rel_path, decompressed);
 
                 total_compressed += file.compressed_size();
             }
@@ -211,127 +590,14 @@ pub(crate) fn unpack(
             let mut msi = msi::Package::open(std::io::Cursor::new(msi))
                 .with_context(|| format!("unable to read MSI from {pkg}"))?;
 
-            // Open source ftw https://gitlab.gnome.org/GNOME/msitools/-/blob/master/tools/msiextract.vala
-
-            // For some reason many filenames in the table(s) have a weird
-            // checksum(?) filename with an extension separated from the
-            // _actual_ filename with a `|` so we need to detect that and
-            // strip off just the real name we want
-            #[inline]
-            fn fix_name(name: &msi::Value) -> Result<&str, Error> {
-                let name = name.as_str().context("filename is not a string")?;
-
-                Ok(match name.find('|') {
-                    Some(ind) => &name[ind + 1..],
-                    None => name,
-                })
+            if ctx.keep_unpacked_raw {
+                let mut tables_dir = ctx.work_dir.join("msi-tables");
+                tables_dir.push(pkg);
+                dump_msi_tables(&mut msi, &tables_dir)
+                    .with_context(|| format!("unable to dump MSI tables for {pkg}"))?;
             }
 
-            let components = {
-                #[derive(Debug)]
-                struct Dir {
-                    id: String,
-                    parent: Option<String>,
-                    path: PathBuf,
-                }
-
-                // Collect the directories that can be referenced by a component
-                // that are reference by files. Ugh.
-                let mut directories: Vec<_> = msi
-                    .select_rows(msi::Select::table("Directory"))
-                    .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
-                    .map(|row| -> Result<_, _> {
-                        // Columns:
-                        // 0 - Directory (name)
-                        // 1 - Directory_Parent (name of parent)
-                        // 2 - DefaultDir (location of directory on disk)
-                        // ...
-                        anyhow::ensure!(row.len() >= 3, "invalid row in 'Directory'");
-
-                        Ok(Dir {
-                            id: row[0]
-                                .as_str()
-                                .context("directory name is not a string")?
-                                .to_owned(),
-                            // This can be `null`
-                            parent: row[1].as_str().map(String::from),
-                            path: fix_name(&row[2])?.into(),
-                        })
-                    })
-                    .collect::<Result<_, _>>()
-                    .with_context(|| format!("unable to read directories for {pkg}"))?;
-
-                directories.sort_by(|a, b| a.id.cmp(&b.id));
-
-                let components: std::collections::BTreeMap<_, _> = msi
-                    .select_rows(msi::Select::table("Component"))
-                    .with_context(|| format!("MSI {pkg} has no 'Directory' table"))?
-                    .map(|row| -> Result<_, _> {
-                        // Columns:
-                        // 0 - Component (name, really, id)
-                        // 1 - ComponentId
-                        // 2 - Directory_ (directory id)
-                        anyhow::ensure!(row.len() >= 3, "invalid row in 'Component'");
-
-                        // The recursion depth for directory lookup is quite shallow
-                        // typically, the full path to a file would be something like
-                        // `Program Files/Windows Kits/10/Lib/10.0.19041.0/um/x64`
-                        // but this a terrible path, so we massage it to instead be
-                        // `lib/um/x64`
-                        fn build_dir(dirs: &[Dir], id: &str, dir: &mut PathBuf) {
-                            #[allow(clippy::single_match_else)]
-                            let cur_dir = match dirs.binary_search_by(|d| d.id.as_str().cmp(id)) {
-                                Ok(i) => &dirs[i],
-                                Err(_) => {
-                                    tracing::warn!("unable to find directory {id}");
-                                    return;
-                                }
-                            };
-
-                            match cur_dir.path.file_name() {
-                                Some("Lib") => {
-                                    dir.push("lib");
-                                }
-                                Some("Include") => {
-                                    dir.push("include");
-                                }
-                                other => {
-                                    if let Some(parent) = &cur_dir.parent {
-                                        build_dir(dirs, parent, dir);
-                                    }
-
-                                    if let Some(other) = other {
-                                        // Ignore the SDK version directory between
-                                        // Lib/Include and the actual subdirs we care about
-                                        if !other.starts_with(|c: char| c.is_ascii_digit()) {
-                                            dir.push(other);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        let component_id = row[0]
-                            .as_str()
-                            .context("component id is not a string")?
-                            .to_owned();
-
-                        let mut dir = PathBuf::new();
-                        build_dir(
-                            &directories,
-                            row[2]
-                                .as_str()
-                                .context("component directory is not a string")?,
-                            &mut dir,
-                        );
-
-                        Ok((component_id, dir))
-                    })
-                    .collect::<Result<_, _>>()
-                    .with_context(|| format!("unable to read components for {pkg}"))?;
-
-                components
-            };
+            let files = read_msi_files(&mut msi, pkg, include_sources, unfiltered)?;
 
             struct Cab {
                 /// The max sequence number, each `File` in an MSI has a
@@ -339,6 +605,8 @@ pub(crate) fn unpack(
                 sequence: u32,
                 path: PathBuf,
                 cab: bytes::Bytes,
+                url: String,
+                sha256: crate::util::Sha256,
             }
 
             let cabs = {
@@ -353,6 +621,8 @@ pub(crate) fn unpack(
                         sequence: cab.sequence,
                         path: cab.path,
                         cab: cab.content,
+                        url: cab.url,
+                        sha256: cab.sha256,
                     });
                 }
 
@@ -363,79 +633,7 @@ pub(crate) fn unpack(
 
             anyhow::ensure!(!cabs.is_empty(), "no cab files were referenced by the MSI");
 
-            struct CabFile {
-                id: String,
-                name: PathBuf,
-                size: u64,
-                sequence: u32,
-            }
-
-            let (files, uncompressed) = {
-                let mut uncompressed = 0u64;
-                let mut files: Vec<_> = msi
-                    .select_rows(msi::Select::table("File"))
-                    .with_context(|| format!("MSI {} has no 'File' table", pkg))?
-                    .filter_map(|row| -> Option<Result<_, Error>> {
-                        // Columns:
-                        // 0 - File Id (lookup in CAB)
-                        // 1 - Component_ (target directory)
-                        // 2 - FileName
-                        // 3 - FileSize
-                        // 4 - Version
-                        // 5 - Language
-                        // 6 - Attributes
-                        // 7 - Sequence (determines which CAB file)
-                        if row.len() < 8 {
-                            return Some(Err(anyhow::anyhow!("invalid row in 'File'")));
-                        }
-
-                        #[allow(clippy::blocks_in_conditions)]
-                        let (dir, fname, id, seq, size) = match || -> Result<_, Error> {
-                            let fname = fix_name(&row[2])?;
-                            let dir = components
-                                .get(row[1].as_str().context("component id was not a string")?)
-                                .with_context(|| {
-                                    format!("file {} referenced an unknown component", row[2])
-                                })?;
-
-                            let size = row[3].as_int().context("size is not an integer")? as u64;
-                            let id = row[0].as_str().context("File (id) is not a string")?;
-                            let seq = row[7].as_int().context("sequence is not an integer")? as u32;
-
-                            Ok((dir, fname, id, seq, size))
-                        }() {
-                            Ok(items) => items,
-                            Err(e) => return Err(e).transpose(),
-                        };
-
-                        if let Some(camino::Utf8Component::Normal(
-                            "Catalogs" | "bin" | "Source" | "SourceDir",
-                        )) = dir
-                            .strip_prefix(&output_dir)
-                            .ok()
-                            .and_then(|rel| rel.components().next())
-                        {
-                            return None;
-                        }
-
-                        uncompressed += size;
-
-                        let cf = CabFile {
-                            id: id.to_owned(),
-                            name: dir.join(fname),
-                            sequence: seq,
-                            size,
-                        };
-
-                        Some(Ok(cf))
-                    })
-                    .collect::<Result<Vec<_>, Error>>()
-                    .with_context(|| format!("unable to read 'File' metadata for {pkg}"))?;
-
-                files.sort_by(|a, b| a.sequence.cmp(&b.sequence));
-
-                (files, uncompressed)
-            };
+            let uncompressed = files.iter().map(|file| file.size).sum();
 
             item.progress.set_length(uncompressed);
 
@@ -505,53 +703,91 @@ pub(crate) fn unpack(
 
             let tree = parking_lot::Mutex::new(FileTree::new());
 
-            chunks
-                .into_par_iter()
-                .map(|chunk| -> Result<(), Error> {
-                    let mut cab = cab::Cabinet::new(std::io::Cursor::new(chunk.cab)).unwrap();
+            struct Wrapper<'pb> {
+                pb: &'pb indicatif::ProgressBar,
+                uf: std::fs::File,
+            }
 
-                    let cab_path = &cabs[chunk.cab_index].path;
+            impl<'pb> std::io::Write for Wrapper<'pb> {
+                fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                    self.pb.inc(buf.len() as u64);
+                    self.uf.write(buf)
+                }
 
-                    for file in chunk.files {
-                        let mut cab_file = match cab.read_file(file.id.as_str()) {
-                            Ok(cf) => cf,
-                            Err(e) => Err(e).with_context(|| {
-                                format!("unable to read '{}' from {cab_path}", file.name)
-                            })?,
-                        };
+                fn flush(&mut self) -> std::io::Result<()> {
+                    self.uf.flush()
+                }
+            }
 
-                        let unpack_path = output_dir.join(&file.name);
+            type Cabinet = cab::Cabinet<std::io::Cursor<bytes::Bytes>>;
 
-                        if let Some(parent) = unpack_path.parent() {
-                            if !parent.exists() {
-                                std::fs::create_dir_all(parent)?;
-                            }
-                        }
+            let extract =
+                |cab: &mut Cabinet, file: &CabFile, cab_path: &Path| -> Result<u64, Error> {
+                    let mut cab_file = cab.read_file(file.id.as_str()).with_context(|| {
+                        format!("unable to read '{}' from {cab_path}", file.name)
+                    })?;
 
-                        let unpacked_file = std::fs::File::create(&unpack_path)?;
+                    let unpack_path = output_dir.join(&file.name);
 
-                        struct Wrapper<'pb> {
-                            pb: &'pb indicatif::ProgressBar,
-                            uf: std::fs::File,
+                    if let Some(parent) = unpack_path.parent() {
+                        if !parent.exists() {
+                            std::fs::create_dir_all(parent)?;
                         }
+                    }
 
-                        impl<'pb> std::io::Write for Wrapper<'pb> {
-                            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-                                self.pb.inc(buf.len() as u64);
-                                self.uf.write(buf)
-                            }
+                    let unpacked_file = std::fs::File::create(&unpack_path)?;
 
-                            fn flush(&mut self) -> std::io::Result<()> {
-                                self.uf.flush()
-                            }
-                        }
+                    std::io::copy(
+                        &mut cab_file,
+                        &mut Wrapper {
+                            pb: &item.progress,
+                            uf: unpacked_file,
+                        },
+                    )
+                    .with_context(|| format!("unable to extract '{}' from {cab_path}", file.name))
+                };
+
+            // A CAB is only downloaded again once, no matter how many of the
+            // chunks using it fail to extract
+            let refetched: Vec<parking_lot::Mutex<Option<bytes::Bytes>>> =
+                cabs.iter().map(|_| parking_lot::Mutex::new(None)).collect();
 
-                        let size = std::io::copy(
-                            &mut cab_file,
-                            &mut Wrapper {
-                                pb: &item.progress,
-                                uf: unpacked_file,
-                            },
+            let refetch = |cab_index: usize| -> Result<Cabinet, Error> {
+                let mut refetched = refetched[cab_index].lock();
+
+                let content = if let Some(content) = &*refetched {
+                    content.clone()
+                } else {
+                    let cab = &cabs[cab_index];
+                    let content = ctx.redownload(
+                        &cab.url,
+                        &cab.path,
+                        Some(cab.sha256.clone()),
+                        indicatif::ProgressBar::hidden(),
+                    )?;
+                    *refetched = Some(content.clone());
+                    content
+                };
+
+                cab::Cabinet::new(std::io::Cursor::new(content))
+                    .with_context(|| format!("CAB {} is invalid", cabs[cab_index].path))
+            };
+
+            chunks
+                .into_par_iter()
+                .map(|chunk| -> Result<(), Error> {
+                    let mut cab = cab::Cabinet::new(std::io::Cursor::new(chunk.cab)).unwrap();
+
+                    let cab_path = &cabs[chunk.cab_index].path;
+                    let mut retried = false;
+
+                    for file in chunk.files {
+                        let size = extract_retrying(
+                            &mut cab,
+                            (ctx.retry_cab_extraction && !retried).then_some(&mut retried),
+                            cab_path,
+                            |cab| extract(cab, &file, cab_path),
+                            || refetch(chunk.cab_index),
                         )?;
 
                         tree.lock().push(&file.name, size);
@@ -561,6 +797,11 @@ pub(crate) fn unpack(
                 })
                 .collect_into_vec(&mut results);
 
+            results
+                .into_iter()
+                .collect::<Result<(), _>>()
+                .with_context(|| format!("failed to unpack {pkg}"))?;
+
             (tree.into_inner(), uncompressed)
         }
     };
@@ -574,6 +815,14 @@ pub(crate) fn unpack(
 
     let (num_files, decompressed) = tree.stats();
 
+    // Don't mark the unpack as finished if there was nothing in it, so that
+    // the payload is unpacked again rather than later failing to find the
+    // subtrees we expect
+    anyhow::ensure!(
+        num_files > 0,
+        "unpacking {pkg} produced no files, the payload is likely a placeholder"
+    );
+
     ctx.finish_unpack(
         output_dir,
         UnpackMeta {
@@ -581,8 +830,532 @@ pub(crate) fn unpack(
             compressed,
             decompressed,
             num_files,
+            sources: include_sources,
+            default_libs_only,
+            unfiltered,
         },
     )?;
 
     Ok(tree)
 }
+
+/// Extracts a file from a CAB, and if that fails and a retry is allowed, gets
+/// the CAB again and extracts the file once more, marking the retry as used
+fn extract_retrying<C, T>(
+    cab: &mut C,
+    retried: Option<&mut bool>,
+    cab_path: &Path,
+    mut extract: impl FnMut(&mut C) -> Result<T, Error>,
+    refetch: impl FnOnce() -> Result<C, Error>,
+) -> Result<T, Error> {
+    match extract(cab) {
+        Ok(res) => Ok(res),
+        Err(err) => {
+            let Some(retried) = retried else {
+                return Err(err);
+            };
+
+            tracing::warn!("{err:#}, downloading {cab_path} again and retrying");
+
+            *retried = true;
+            *cab = refetch()?;
+            extract(cab)
+        }
+    }
+}
+
+/// A file that would be unpacked from a payload
+#[derive(serde::Serialize)]
+pub struct IndexedFile {
+    /// The filename of the payload the file is stored in
+    pub payload: PathBuf,
+    /// The uncompressed size of the file
+    pub size: u64,
+}
+
+/// Every file in a set of payloads, keyed by the path it is unpacked to,
+/// relative to the unpack directory of its payload. The same path can be
+/// present in multiple payloads, eg. headers shared by the Desktop and Store
+/// SDKs
+pub type FileIndex = std::collections::BTreeMap<PathBuf, Vec<IndexedFile>>;
+
+/// Downloads each payload and reads the files it contains, without
+/// unpacking them. For MSIs this only requires the MSI itself, as the file
+/// tables are stored in the MSI rather than the CABs with the actual contents
+pub fn index_payloads(ctx: &Ctx, items: &[crate::WorkItem]) -> Result<FileIndex, Error> {
+    use rayon::prelude::*;
+
+    let indexed = items
+        .par_iter()
+        .map(|item| -> Result<_, Error> {
+            let pkg = &item.payload.filename;
+
+            item.progress.set_message("📥 downloading..");
+            let contents = ctx.get_and_validate(
+                &item.payload.url,
+                pkg,
+                Some(item.payload.sha256.clone()),
+                Some(item.payload.size),
+                item.progress.clone(),
+            )?;
+
+            item.progress.set_message("📇 indexing...");
+            let files = if pkg.extension() == Some("msi") {
+                let mut msi = msi::Package::open(std::io::Cursor::new(contents))
+                    .with_context(|| format!("unable to read MSI from {pkg}"))?;
+
+                read_msi_files(&mut msi, pkg, ctx.include_sources, false)?
+                    .into_iter()
+                    .map(|file| (file.name, file.size))
+                    .collect()
+            } else {
+                let mut zip = zip::ZipArchive::new(std::io::Cursor::new(contents))
+                    .with_context(|| format!("invalid zip {pkg}"))?;
+
+                let mut files = Vec::new();
+                for findex in 0..zip.len() {
+                    let file = zip.by_index_raw(findex)?;
+
+                    if let Some(rel_path) = vsix_path(file.name(), item.payload.kind, false) {
+                        files.push((rel_path, file.size()));
+                    }
+                }
+
+                files
+            };
+
+            item.progress.finish_with_message("indexed");
+            Ok((pkg.clone(), files))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut index = FileIndex::new();
+    for (payload, files) in indexed {
+        for (path, size) in files {
+            index.entry(path).or_default().push(IndexedFile {
+                payload: payload.clone(),
+                size,
+            });
+        }
+    }
+
+    // Payloads are indexed in parallel, so sort to keep the output stable
+    for payloads in index.values_mut() {
+        payloads.sort_by(|a, b| a.payload.cmp(&b.payload));
+    }
+
+    Ok(index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vsix_paths() {
+        use crate::PayloadKind;
+
+        let tools = "Contents/VC/Tools/MSVC/14.38.33130/bin/Hostx64/arm64/cl.exe";
+        let lib = "Contents/VC/Tools/MSVC/14.38.33130/lib/arm64/libcmt.lib";
+
+        assert_eq!(
+            vsix_path(tools, PayloadKind::CrtTools, false).unwrap(),
+            "bin/Hostx64/arm64/cl.exe"
+        );
+        assert_eq!(
+            vsix_path(lib, PayloadKind::CrtLibs, false).unwrap(),
+            "lib/arm64/libcmt.lib"
+        );
+
+        // The roots are only matched against whole components, rather than
+        // anywhere in the path, eg. `bin` in `cabinet` or `lib` in `libcmt`
+        for (name, kind) in [
+            (
+                "Contents/VC/Tools/MSVC/14.38.33130/cabinet.txt",
+                PayloadKind::CrtTools,
+            ),
+            (
+                "Contents/VC/Tools/MSVC/14.38.33130/binaries/cl.exe",
+                PayloadKind::CrtTools,
+            ),
+            (
+                "Contents/VC/Tools/MSVC/14.38.33130/libcmt.lib",
+                PayloadKind::CrtLibs,
+            ),
+            (
+                "Contents/VC/Tools/MSVC/14.38.33130/include.txt",
+                PayloadKind::CrtHeaders,
+            ),
+            (lib, PayloadKind::CrtTools),
+            (tools, PayloadKind::CrtLibs),
+            // Files outside of the contents are never unpacked
+            ("extension.vsixmanifest", PayloadKind::CrtTools),
+        ] {
+            assert_eq!(vsix_path(name, kind, false), None, "{name}");
+        }
+
+        assert_eq!(
+            vsix_path(
+                "Contents/VC/Tools/MSVC/14.38.33130/cabinet.txt",
+                PayloadKind::CrtTools,
+                true
+            )
+            .unwrap(),
+            "VC/Tools/MSVC/14.38.33130/cabinet.txt"
+        );
+    }
+
+    #[test]
+    fn cab_extraction_retry() {
+        let cab_path = Path::new("a.cab");
+        // The "CAB" is the number of times the file can't be extracted from it
+        let extract = |cab: &mut u32| {
+            if *cab == 0 {
+                Ok("file")
+            } else {
+                *cab -= 1;
+                anyhow::bail!("corrupt")
+            }
+        };
+
+        // Without a retry the error is returned
+        let mut cab = 1;
+        assert!(extract_retrying(&mut cab, None, cab_path, extract, || Ok(0)).is_err());
+
+        // With one, the CAB is retrieved again and the file extracted from it
+        let mut retried = false;
+        let mut cab = 1;
+        assert_eq!(
+            extract_retrying(&mut cab, Some(&mut retried), cab_path, extract, || Ok(0)).unwrap(),
+            "file"
+        );
+        assert!(retried);
+
+        // But only once, even if the new CAB is also unusable
+        let mut retried = false;
+        let mut cab = 1;
+        assert!(
+            extract_retrying(&mut cab, Some(&mut retried), cab_path, extract, || Ok(1)).is_err()
+        );
+        assert!(retried);
+
+        // And the retry isn't used up if it isn't needed
+        let mut retried = false;
+        let mut cab = 0;
+        extract_retrying(
+            &mut cab,
+            Some(&mut retried),
+            cab_path,
+            extract,
+            || unreachable!(),
+        )
+        .unwrap();
+        assert!(!retried);
+
+        // Failing to retrieve the CAB again is also an error
+        let mut retried = false;
+        let mut cab = 1;
+        assert!(
+            extract_retrying(&mut cab, Some(&mut retried), cab_path, extract, || {
+                anyhow::bail!("offline")
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn store_default_libs() {
+        use std::io::Write as _;
+
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let ctx = std::sync::Arc::new(
+            Ctx::with_dir(
+                work_dir.clone(),
+                crate::util::ProgressTarget::Hidden,
+                ureq::agent(),
+                0,
+            )
+            .unwrap(),
+        );
+
+        let mut vsix = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for name in [
+            "Contents/VC/Tools/MSVC/14.40.33807/lib/x64/oldnames.lib",
+            "Contents/VC/Tools/MSVC/14.40.33807/lib/x64/store/msvcrt.lib",
+            "Contents/VC/Tools/MSVC/14.40.33807/lib/x64/uwp/vccorlib.lib",
+        ] {
+            vsix.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            vsix.write_all(name.as_bytes()).unwrap();
+        }
+        let vsix = bytes::Bytes::from(vsix.finish().unwrap().into_inner());
+
+        let payload = crate::Payload {
+            filename: "Microsoft.VC.14.40.17.10.CRT.x64.Store.base.vsix".into(),
+            sha256: crate::util::Sha256([0; 32]),
+            url: String::new(),
+            size: vsix.len() as u64,
+            install_size: None,
+            kind: crate::PayloadKind::CrtLibs,
+            target_arch: Some(crate::Arch::X86_64),
+            variant: Some(crate::Variant::Store),
+        };
+
+        assert!(default_libs_only(&payload, crate::Variant::Desktop as u32));
+        assert!(!default_libs_only(
+            &payload,
+            crate::Variant::Desktop as u32 | crate::Variant::Store as u32
+        ));
+
+        let item = crate::WorkItem {
+            progress: indicatif::ProgressBar::hidden(),
+            payload: std::sync::Arc::new(payload),
+        };
+
+        let unpack = |default_libs_only: bool| {
+            let tree = unpack(
+                ctx.clone(),
+                &item,
+                PayloadContents::Vsix(vsix.clone()),
+                default_libs_only,
+                false,
+            )
+            .unwrap();
+            let lib = tree.subtree(Path::new("lib/x64")).unwrap();
+            (lib.files.len(), lib.dirs.len())
+        };
+
+        // Only the libs directly in the arch directory are unpacked
+        assert_eq!(unpack(true), (1, 0));
+        // Which is not enough if the Store is targeted later, so the payload
+        // is unpacked again
+        assert!(matches!(
+            ctx.prep_unpack(&item.payload, false, false).unwrap(),
+            crate::ctx::Unpack::Needed(_)
+        ));
+        assert_eq!(unpack(false), (1, 2));
+        // But the full unpack is reused when only the default libs are needed
+        assert_eq!(unpack(true), (1, 2));
+    }
+
+    #[test]
+    fn payload_index() {
+        use std::io::Write as _;
+
+        let td = tempfile::tempdir().unwrap();
+        let work_dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let mut ctx = Ctx::with_dir(
+            work_dir.clone(),
+            crate::util::ProgressTarget::Hidden,
+            ureq::agent(),
+            0,
+        )
+        .unwrap();
+        ctx.offline = true;
+
+        // The payloads are cached, so are indexed without network access
+        let items: Vec<_> = [
+            (
+                "b.vsix",
+                &[
+                    "Contents/VC/Tools/MSVC/14.38.33130/include/vcruntime.h",
+                    "Contents/VC/Tools/MSVC/14.38.33130/lib/x64/libcmt.lib",
+                ][..],
+            ),
+            (
+                "a.vsix",
+                &[
+                    "Contents/VC/Tools/MSVC/14.38.33130/include/vcruntime.h",
+                    "Contents/VC/Tools/MSVC/14.38.33130/include/vcruntime_new.h",
+                    "Contents/VC/Tools/MSVC/14.38.33130/Licenses/license.rtf",
+                ][..],
+            ),
+        ]
+        .into_iter()
+        .map(|(filename, files)| {
+            let mut vsix = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for name in files {
+                vsix.start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                vsix.write_all(filename.as_bytes()).unwrap();
+            }
+            let vsix = vsix.finish().unwrap().into_inner();
+            std::fs::write(work_dir.join("dl").join(filename), &vsix).unwrap();
+
+            crate::WorkItem {
+                progress: indicatif::ProgressBar::hidden(),
+                payload: std::sync::Arc::new(crate::Payload {
+                    filename: filename.into(),
+                    sha256: crate::util::Sha256::digest(&vsix),
+                    url: String::new(),
+                    size: vsix.len() as u64,
+                    install_size: None,
+                    kind: crate::PayloadKind::CrtHeaders,
+                    target_arch: None,
+                    variant: None,
+                }),
+            }
+        })
+        .collect();
+
+        let index = index_payloads(&ctx, &items).unwrap();
+        let index: Vec<_> = index
+            .iter()
+            .map(|(path, files)| {
+                (
+                    path.as_str(),
+                    files
+                        .iter()
+                        .map(|file| (file.payload.as_str(), file.size))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+
+        // Files outside of the roots aren't indexed, and files in multiple
+        // payloads list each of them, in order
+        assert_eq!(
+            index,
+            [
+                ("include/vcruntime.h", vec![("a.vsix", 6), ("b.vsix", 6)]),
+                ("include/vcruntime_new.h", vec![("a.vsix", 6)]),
+                ("lib/x64/libcmt.lib", vec![("b.vsix", 6)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn msi_file_dirs() {
+        use msi::{Column, Insert, Value};
+
+        let mut msi = msi::Package::create(
+            msi::PackageType::Installer,
+            std::io::Cursor::new(Vec::new()),
+        )
+        .unwrap();
+
+        msi.create_table(
+            "Directory",
+            vec![
+                Column::build("Directory").primary_key().string(72),
+                Column::build("Directory_Parent").nullable().string(72),
+                Column::build("DefaultDir").string(255),
+            ],
+        )
+        .unwrap();
+        msi.create_table(
+            "Component",
+            vec![
+                Column::build("Component").primary_key().string(72),
+                Column::build("ComponentId").nullable().string(38),
+                Column::build("Directory_").string(72),
+            ],
+        )
+        .unwrap();
+        msi.create_table(
+            "File",
+            vec![
+                Column::build("File").primary_key().string(72),
+                Column::build("Component_").string(72),
+                Column::build("FileName").string(255),
+                Column::build("FileSize").int32(),
+                Column::build("Version").nullable().string(72),
+                Column::build("Language").nullable().string(20),
+                Column::build("Attributes").nullable().int16(),
+                Column::build("Sequence").int32(),
+            ],
+        )
+        .unwrap();
+
+        let dirs = [
+            ("TARGETDIR", None, "SourceDir"),
+            ("KitsDir", Some("TARGETDIR"), "Windows Kits"),
+            ("VersionDir", Some("KitsDir"), "10"),
+            ("LibDir", Some("VersionDir"), "Lib"),
+            ("LibVersionDir", Some("LibDir"), "10.0.22621.0"),
+            ("UmDir", Some("LibVersionDir"), "um"),
+            ("X64Dir", Some("UmDir"), "x64"),
+            ("CatalogsDir", Some("VersionDir"), "Catalogs"),
+            ("BinDir", Some("VersionDir"), "bin"),
+            ("SourceDir", Some("VersionDir"), "Source"),
+            ("UcrtDir", Some("SourceDir"), "ucrt"),
+        ];
+        msi.insert_rows(
+            Insert::into("Directory").rows(
+                dirs.iter()
+                    .map(|(id, parent, name)| {
+                        vec![
+                            Value::from(*id),
+                            parent.map_or(Value::Null, Value::from),
+                            Value::from(*name),
+                        ]
+                    })
+                    .collect(),
+            ),
+        )
+        .unwrap();
+
+        let files = [
+            ("X64Dir", "KERNEL~1.LIB|kernel32.Lib"),
+            ("CatalogsDir", "sdk.cat"),
+            ("BinDir", "rc.exe"),
+            ("UcrtDir", "strlen.c"),
+        ];
+        msi.insert_rows(
+            Insert::into("Component").rows(
+                files
+                    .iter()
+                    .map(|(dir, _)| vec![Value::from(*dir), Value::Null, Value::from(*dir)])
+                    .collect(),
+            ),
+        )
+        .unwrap();
+        msi.insert_rows(
+            Insert::into("File").rows(
+                files
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (dir, name))| {
+                        vec![
+                            Value::from(format!("file{i}")),
+                            Value::from(*dir),
+                            Value::from(*name),
+                            Value::from(1),
+                            Value::Null,
+                            Value::Null,
+                            Value::Null,
+                            Value::from(i as i32 + 1),
+                        ]
+                    })
+                    .collect(),
+            ),
+        )
+        .unwrap();
+
+        let mut names = |include_sources: bool, unfiltered: bool| {
+            read_msi_files(&mut msi, Path::new("test.msi"), include_sources, unfiltered)
+                .unwrap()
+                .into_iter()
+                .map(|cf| cf.name)
+                .collect::<Vec<_>>()
+        };
+
+        // The catalogs and tools are never unpacked
+        assert_eq!(names(false, false), ["lib/um/x64/kernel32.Lib"]);
+        assert_eq!(
+            names(true, false),
+            ["lib/um/x64/kernel32.Lib", "source/ucrt/strlen.c"]
+        );
+        // Unless everything is being extracted
+        assert_eq!(
+            names(false, true),
+            [
+                "lib/um/x64/kernel32.Lib",
+                "SourceDir/Windows Kits/Catalogs/sdk.cat",
+                "SourceDir/Windows Kits/bin/rc.exe",
+                "source/ucrt/strlen.c"
+            ]
+        );
+    }
+}
@@ -11,6 +11,138 @@ pub fn canonicalize(path: &Path) -> anyhow::Result<PathBuf> {
     .map_err(|pb| anyhow::anyhow!("canonicalized path {} is not utf-8", pb.display()))
 }
 
+/// Whether any error in the chain is an I/O error due to the disk being full,
+/// ie. `ENOSPC` or `ERROR_DISK_FULL`
+pub(crate) fn is_out_of_space(err: &Error) -> bool {
+    err.chain()
+        .filter_map(|err| err.downcast_ref::<std::io::Error>())
+        .any(|err| err.kind() == std::io::ErrorKind::StorageFull)
+}
+
+/// The broad category of an error, so that callers can decide how to handle
+/// it, eg. whether it is worth retrying
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A network request failed, which may succeed if retried
+    Network,
+    /// A download didn't match the checksum in the manifest
+    ChecksumMismatch,
+    /// The requested versions or packages aren't in the manifest, or the
+    /// resolved versions aren't the expected ones, which won't succeed if
+    /// retried
+    Selection,
+    /// The disk is full
+    OutOfSpace,
+    /// Any other error
+    Other,
+}
+
+impl ErrorKind {
+    /// Categorizes an error by the errors in its chain
+    pub fn of(err: &Error) -> Self {
+        if is_out_of_space(err) {
+            return Self::OutOfSpace;
+        }
+
+        if let Some(categorized) = err.downcast_ref::<Categorized>() {
+            return categorized.0;
+        }
+
+        // Client errors, eg. a 404 for a manifest version that doesn't exist,
+        // won't go away on their own
+        let transient = |code: u16| !(400..500).contains(&code) || matches!(code, 408 | 429);
+
+        let network = err.chain().any(|err| {
+            if let Some(err) = err.downcast_ref::<ureq::Error>() {
+                return match err {
+                    ureq::Error::StatusCode(code) => transient(*code),
+                    _ => true,
+                };
+            }
+
+            #[cfg(feature = "async")]
+            if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+                return match err.status() {
+                    Some(status) => transient(status.as_u16()),
+                    None => true,
+                };
+            }
+
+            false
+        });
+
+        if network {
+            Self::Network
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Creates an error message of this kind, which is found by [`Self::of`]
+    /// even when more context is added to it
+    pub fn msg(self, msg: impl Into<String>) -> Categorized {
+        Categorized(self, msg.into())
+    }
+}
+
+/// An error message with a known [`ErrorKind`], see [`ErrorKind::msg`]
+#[derive(Debug)]
+pub struct Categorized(ErrorKind, String);
+
+impl fmt::Display for Categorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+
+impl std::error::Error for Categorized {}
+
+/// Finds the first executable with the specified name in `PATH`
+pub(crate) fn find_in_path(tool: &str) -> Option<std::path::PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    let exe = format!("{tool}{}", std::env::consts::EXE_SUFFIX);
+
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(&exe))
+        .find(|path| path.is_file())
+}
+
+/// Matches a name against a glob pattern, ignoring ASCII case, where `*`
+/// matches any number of characters and `?` matches exactly one
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+
+    let (mut p, mut n) = (0, 0);
+    // The position of the last `*` in the pattern, and the position in the
+    // name it was matched at, so that we can backtrack to it
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(pc) if *pc == b'?' || pc.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => {
+                let Some((star, matched)) = backtrack else {
+                    return false;
+                };
+
+                p = star + 1;
+                n = matched + 1;
+                backtrack = Some((star, n));
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|pc| *pc == b'*')
+}
+
 #[derive(Copy, Clone)]
 pub enum ProgressTarget {
     Stdout,
@@ -89,6 +221,15 @@ impl std::str::FromStr for Sha256 {
     }
 }
 
+impl serde::Serialize for Sha256 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
 impl<'de> serde::Deserialize<'de> for Sha256 {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -115,13 +256,6 @@ impl<'de> serde::Deserialize<'de> for Sha256 {
     }
 }
 
-pub(crate) fn serialize_sha256<S>(hash: &Sha256, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    serializer.serialize_str(&hash.to_string())
-}
-
 impl Sha256 {
     pub fn digest(buffer: &[u8]) -> Self {
         use sha2::Digest;
@@ -132,6 +266,56 @@ impl Sha256 {
 
         Self(digest.into())
     }
+
+    /// Calculates the digest of everything read from `reader`, without
+    /// needing to hold all of it in memory at once
+    pub fn digest_reader(reader: impl std::io::Read) -> std::io::Result<Self> {
+        use sha2::Digest;
+        use std::io::BufRead;
+
+        let mut hasher = sha2::Sha256::new();
+
+        let mut reader = std::io::BufReader::new(reader);
+        loop {
+            let len = {
+                let buf = reader.fill_buf()?;
+                if buf.is_empty() {
+                    break;
+                }
+
+                hasher.update(buf);
+                buf.len()
+            };
+            reader.consume(len);
+        }
+
+        Ok(Self(hasher.finalize().into()))
+    }
+
+    /// Reads everything from `reader` into `buffer`, calculating the digest
+    /// of each chunk as it is read rather than in a second pass
+    pub fn read_digest(
+        mut reader: impl std::io::Read,
+        buffer: &mut Vec<u8>,
+    ) -> std::io::Result<Self> {
+        use sha2::Digest;
+
+        let mut hasher = sha2::Sha256::new();
+        let mut chunk = vec![0u8; 64 * 1024];
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            hasher.update(&chunk[..read]);
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+
+        Ok(Self(hasher.finalize().into()))
+    }
 }
 
 #[cfg(test)]
@@ -146,5 +330,65 @@ mod test {
         let hex = digest.to_string();
 
         assert_eq!(digest, hex.parse::<Sha256>().unwrap());
+        assert_eq!(digest, Sha256::digest_reader(&buffer[..]).unwrap());
+
+        let mut read = Vec::new();
+        assert_eq!(digest, Sha256::read_digest(&buffer[..], &mut read).unwrap());
+        assert_eq!(read, buffer);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn out_of_space() {
+        let err = anyhow::Error::from(std::io::Error::from_raw_os_error(28))
+            .context("failed to copy a to b");
+        assert!(is_out_of_space(&err));
+
+        let err = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context("failed to copy a to b");
+        assert!(!is_out_of_space(&err));
+    }
+
+    #[test]
+    fn error_kinds() {
+        use anyhow::Context as _;
+
+        let err = None::<()>
+            .with_context(|| ErrorKind::Selection.msg("CRT version '1.0' does not exist"))
+            .context("failed to select packages")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Selection);
+        assert!(err.to_string().starts_with("failed to select"));
+
+        let err = Err::<(), _>(ureq::Error::StatusCode(503))
+            .context("HTTP GET request for x failed")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Network);
+
+        let err = Err::<(), _>(ureq::Error::StatusCode(404))
+            .context("HTTP GET request for x failed")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Other);
+
+        let err = anyhow::Error::from(ErrorKind::ChecksumMismatch.msg("checksum mismatch"))
+            .context("failed to download x");
+        assert_eq!(ErrorKind::of(&err), ErrorKind::ChecksumMismatch);
+
+        assert_eq!(ErrorKind::of(&anyhow::anyhow!("oops")), ErrorKind::Other);
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("d3d12.lib", "D3D12.Lib"));
+        assert!(glob_match("d3d*", "d3d11.lib"));
+        assert!(glob_match("*32.lib", "kernel32.lib"));
+        assert!(glob_match("ws?_32*", "ws2_32.lib"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*a*b", "xaxxb"));
+
+        assert!(!glob_match("d3d*", "dxgi.lib"));
+        assert!(!glob_match("kernel32", "kernel32.lib"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("*a*b", "xaxxbc"));
     }
 }
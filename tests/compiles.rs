@@ -26,138 +26,147 @@ fn verify_compiles() {
     let pkg_manifest =
         xwin::manifest::get_package_manifest(&ctx, &manifest, hidden.clone()).unwrap();
 
-    let pruned = xwin::prune_pkg_list(
-        &pkg_manifest,
-        xwin::Arch::X86_64 as u32,
-        xwin::Variant::Desktop as u32,
-        false,
-        None,
-        None,
-    )
-    .unwrap();
-
     #[derive(Debug)]
     enum Style {
         Default,
         WinSysRoot,
     }
 
-    for style in [Style::Default, Style::WinSysRoot] {
-        let output_dir = ctx.work_dir.join(format!("{style:?}"));
-        if !output_dir.exists() {
-            std::fs::create_dir_all(&output_dir).unwrap();
-        }
-
-        if !cfg!(target_os = "windows") && matches!(style, Style::WinSysRoot) {
-            continue;
-        }
-
-        let op = xwin::Ops::Splat(xwin::SplatConfig {
-            include_debug_libs: false,
-            include_debug_symbols: false,
-            enable_symlinks: matches!(style, Style::Default),
-            preserve_ms_arch_notation: matches!(style, Style::WinSysRoot),
-            use_winsysroot_style: matches!(style, Style::WinSysRoot),
-            map: None,
-            copy: true,
-            output: output_dir.clone(),
-        });
-
-        ctx.clone()
-            .execute(
-                pkg_manifest.packages.clone(),
-                pruned
-                    .payloads
-                    .clone()
-                    .into_iter()
-                    .map(|payload| xwin::WorkItem {
-                        progress: hidden.clone(),
-                        payload: std::sync::Arc::new(payload),
-                    })
-                    .collect(),
-                pruned.crt_version.clone(),
-                pruned.sdk_version.clone(),
-                xwin::Arch::X86_64 as u32,
-                xwin::Variant::Desktop as u32,
-                op,
-            )
-            .unwrap();
-
-        if xwin::Path::new("tests/xwin-test/target").exists() {
-            std::fs::remove_dir_all("tests/xwin-test/target").expect("failed to remove target dir");
-        }
-
-        let mut cmd = std::process::Command::new("cargo");
-        cmd.args([
-            "build",
-            "--target",
-            "x86_64-pc-windows-msvc",
-            "--manifest-path",
-            "tests/xwin-test/Cargo.toml",
-        ]);
-
-        let od = xwin::util::canonicalize(&output_dir).unwrap();
-
-        let includes = match style {
-            Style::Default => {
-                cmd.env("RUSTFLAGS", format!("-C linker=lld-link -Lnative={od}/crt/lib/x86_64 -Lnative={od}/sdk/lib/um/x86_64 -Lnative={od}/sdk/lib/ucrt/x86_64"));
-                format!("-Wno-unused-command-line-argument -fuse-ld=lld-link /imsvc{od}/crt/include /imsvc{od}/sdk/include/ucrt /imsvc{od}/sdk/include/um /imsvc{od}/sdk/include/shared")
+    // Both targets need to be installed, eg. via `rustup target add`
+    for arch in [xwin::Arch::X86_64, xwin::Arch::Aarch64] {
+        let pruned = xwin::prune_pkg_list(
+            &pkg_manifest,
+            arch as u32,
+            xwin::Variant::Desktop as u32,
+            xwin::PruneOptions::default(),
+        )
+        .unwrap();
+
+        let target = format!("{}-pc-windows-msvc", arch.as_str());
+        let target_env = target.replace('-', "_");
+
+        for style in [Style::Default, Style::WinSysRoot] {
+            let output_dir = ctx.work_dir.join(format!("{arch}-{style:?}"));
+            if !output_dir.exists() {
+                std::fs::create_dir_all(&output_dir).unwrap();
             }
-            Style::WinSysRoot => {
-                const SEP: char = '\x1F';
-                cmd.env("CARGO_ENCODED_RUSTFLAGS", format!("-C{SEP}linker=lld-link{SEP}-Lnative={od}/VC/Tools/MSVC/{crt_version}/Lib/x64{SEP}-Lnative={od}/Windows Kits/10/Lib/{sdk_version}/um/x64{SEP}-Lnative={od}/Windows Kits/10/Lib/{sdk_version}/ucrt/x64", crt_version = &pruned.crt_version, sdk_version = &pruned.sdk_version));
 
-                format!("-Wno-unused-command-line-argument -fuse-ld=lld-link /winsysroot {od}")
+            if !cfg!(target_os = "windows") && matches!(style, Style::WinSysRoot) {
+                continue;
             }
-        };
 
-        let cc_env = [
-            ("CC_x86_64_pc_windows_msvc", "clang-cl"),
-            ("CXX_x86_64_pc_windows_msvc", "clang-cl"),
-            ("AR_x86_64_pc_windows_msvc", "llvm-lib"),
-            ("CFLAGS_x86_64_pc_windows_msvc", &includes),
-            ("CXXFLAGS_x86_64_pc_windows_msvc", &includes),
-        ];
+            let op = xwin::Ops::Splat(xwin::SplatConfig {
+                enable_symlinks: matches!(style, Style::Default),
+                preserve_ms_arch_notation: matches!(style, Style::WinSysRoot),
+                use_winsysroot_style: matches!(style, Style::WinSysRoot),
+                copy: true,
+                output: output_dir.clone(),
+                ..Default::default()
+            });
+
+            ctx.clone()
+                .execute(
+                    pkg_manifest.packages.clone(),
+                    pruned
+                        .payloads
+                        .clone()
+                        .into_iter()
+                        .map(|payload| xwin::WorkItem {
+                            progress: hidden.clone(),
+                            payload: std::sync::Arc::new(payload),
+                        })
+                        .collect(),
+                    pruned.crt_version.clone(),
+                    pruned.sdk_version.clone(),
+                    arch as u32,
+                    xwin::Variant::Desktop as u32,
+                    op,
+                )
+                .unwrap();
+
+            if xwin::Path::new("tests/xwin-test/target").exists() {
+                std::fs::remove_dir_all("tests/xwin-test/target")
+                    .expect("failed to remove target dir");
+            }
 
-        cmd.envs(cc_env);
+            let mut cmd = std::process::Command::new("cargo");
+            cmd.args([
+                "build",
+                "--target",
+                &target,
+                "--manifest-path",
+                "tests/xwin-test/Cargo.toml",
+            ]);
+
+            let od = xwin::util::canonicalize(&output_dir).unwrap();
+
+            let includes = match style {
+                Style::Default => {
+                    cmd.env("RUSTFLAGS", format!("-C linker=lld-link -Lnative={od}/crt/lib/{arch} -Lnative={od}/sdk/lib/um/{arch} -Lnative={od}/sdk/lib/ucrt/{arch}"));
+                    format!("-Wno-unused-command-line-argument -fuse-ld=lld-link /imsvc{od}/crt/include /imsvc{od}/sdk/include/ucrt /imsvc{od}/sdk/include/um /imsvc{od}/sdk/include/shared")
+                }
+                Style::WinSysRoot => {
+                    const SEP: char = '\x1F';
+                    cmd.env("CARGO_ENCODED_RUSTFLAGS", format!("-C{SEP}linker=lld-link{SEP}-Lnative={od}/VC/Tools/MSVC/{crt_version}/Lib/{ms_arch}{SEP}-Lnative={od}/Windows Kits/10/Lib/{sdk_version}/um/{ms_arch}{SEP}-Lnative={od}/Windows Kits/10/Lib/{sdk_version}/ucrt/{ms_arch}", crt_version = &pruned.crt_version, sdk_version = &pruned.sdk_version, ms_arch = arch.as_ms_str()));
+
+                    format!("-Wno-unused-command-line-argument -fuse-ld=lld-link /winsysroot {od}")
+                }
+            };
+
+            let cc_env = [
+                (format!("CC_{target_env}"), "clang-cl"),
+                (format!("CXX_{target_env}"), "clang-cl"),
+                (format!("AR_{target_env}"), "llvm-lib"),
+                (format!("CFLAGS_{target_env}"), &includes),
+                (format!("CXXFLAGS_{target_env}"), &includes),
+            ];
+
+            cmd.envs(cc_env);
+
+            assert!(
+                cmd.status().unwrap().success(),
+                "failed to build for {target}"
+            );
+
+            // Ignore the /vctoolsdir /winsdkdir test below on CI since it fails, I'm assuming
+            // due to the clang version in GHA being outdated, but don't have the will to
+            // look into it now
+            if !matches!(style, Style::Default) || std::env::var("CI").is_ok() {
+                continue;
+            }
 
-        assert!(cmd.status().unwrap().success());
+            std::fs::remove_dir_all("tests/xwin-test/target").expect("failed to remove target dir");
 
-        // Ignore the /vctoolsdir /winsdkdir test below on CI since it fails, I'm assuming
-        // due to the clang version in GHA being outdated, but don't have the will to
-        // look into it now
-        if !matches!(style, Style::Default) || std::env::var("CI").is_ok() {
-            return;
+            let mut cmd = std::process::Command::new("cargo");
+            cmd.args([
+                "build",
+                "--target",
+                &target,
+                "--manifest-path",
+                "tests/xwin-test/Cargo.toml",
+            ]);
+
+            let includes = format!(
+                "-Wno-unused-command-line-argument -fuse-ld=lld-link /vctoolsdir {od}/crt /winsdkdir {od}/sdk"
+            );
+            let libs = format!("-C linker=lld-link -Lnative={od}/crt/lib/{arch} -Lnative={od}/sdk/lib/um/{arch} -Lnative={od}/sdk/lib/ucrt/{arch}");
+
+            let cc_env = [
+                (format!("CC_{target_env}"), "clang-cl"),
+                (format!("CXX_{target_env}"), "clang-cl"),
+                (format!("AR_{target_env}"), "llvm-lib"),
+                (format!("CFLAGS_{target_env}"), &includes),
+                (format!("CXXFLAGS_{target_env}"), &includes),
+                ("RUSTFLAGS".to_owned(), &libs),
+            ];
+
+            cmd.envs(cc_env);
+
+            assert!(
+                cmd.status().unwrap().success(),
+                "failed to build for {target}"
+            );
         }
-
-        std::fs::remove_dir_all("tests/xwin-test/target").expect("failed to remove target dir");
-
-        let mut cmd = std::process::Command::new("cargo");
-        cmd.args([
-            "build",
-            "--target",
-            "x86_64-pc-windows-msvc",
-            "--manifest-path",
-            "tests/xwin-test/Cargo.toml",
-        ]);
-
-        let includes = format!(
-        "-Wno-unused-command-line-argument -fuse-ld=lld-link /vctoolsdir {od}/crt /winsdkdir {od}/sdk"
-    );
-        let libs = format!("-C linker=lld-link -Lnative={od}/crt/lib/x86_64 -Lnative={od}/sdk/lib/um/x86_64 -Lnative={od}/sdk/lib/ucrt/x86_64");
-
-        let cc_env = [
-            ("CC_x86_64_pc_windows_msvc", "clang-cl"),
-            ("CXX_x86_64_pc_windows_msvc", "clang-cl"),
-            ("AR_x86_64_pc_windows_msvc", "llvm-lib"),
-            ("CFLAGS_x86_64_pc_windows_msvc", &includes),
-            ("CXXFLAGS_x86_64_pc_windows_msvc", &includes),
-            ("RUSTFLAGS", &libs),
-        ];
-
-        cmd.envs(cc_env);
-
-        assert!(cmd.status().unwrap().success());
     }
 }
 
@@ -194,9 +203,7 @@ fn verify_compiles_minimized() {
         &pkg_manifest,
         xwin::Arch::X86_64 as u32,
         xwin::Variant::Desktop as u32,
-        false,
-        None,
-        None,
+        xwin::PruneOptions::default(),
     )
     .unwrap();
 
@@ -221,10 +228,16 @@ fn verify_compiles_minimized() {
         map: map_path.clone(),
         copy: true,
         splat_output: output_dir.clone(),
+        splat_prefix: None,
         manifest_path: "tests/xwin-test/Cargo.toml".into(),
+        release: false,
+        cargo_args: Vec::new(),
         target: "x86_64-pc-windows-msvc".into(),
         minimize_output: Some(filtered.clone()),
         preserve_strace: false,
+        strace_tmp_dir: None,
+        results_json: None,
+        strace_input: None,
     });
 
     ctx.execute(
@@ -24,9 +24,10 @@ fn verify_deterministic() {
         &pkg_manifest,
         xwin::Arch::X86_64 as u32,
         xwin::Variant::Desktop as u32,
-        true,
-        None,
-        None,
+        xwin::PruneOptions {
+            include_atl: true,
+            ..Default::default()
+        },
     )
     .unwrap();
 
@@ -36,14 +37,10 @@ fn verify_deterministic() {
     }
 
     let op = xwin::Ops::Splat(xwin::SplatConfig {
-        include_debug_libs: false,
-        include_debug_symbols: false,
         enable_symlinks: true,
-        preserve_ms_arch_notation: false,
-        use_winsysroot_style: false,
-        map: None,
         copy: true,
         output: output_dir.clone(),
+        ..Default::default()
     });
 
     ctx.execute(
@@ -100,7 +97,8 @@ fn verify_deterministic() {
         .filter_map(|entry| {
             let entry = entry.unwrap();
 
-            if entry.file_type().is_dir() {
+            // The marker contains the xwin version, which isn't part of the splat
+            if entry.file_type().is_dir() || entry.file_name() == ".xwin-splat" {
                 return None;
             }
 